@@ -18,7 +18,7 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     let raw_module = wat::parse_file("./wat/hello.wat").unwrap();
     let module = runtime
-        .compile_module::<DefaultProcessState>(raw_module)
+        .compile_module::<DefaultProcessState>(raw_module.into())
         .unwrap();
 
     c.bench_function("spawn process", |b| {
@@ -34,6 +34,7 @@ fn criterion_benchmark(c: &mut Criterion) {
                 "hello",
                 Vec::new(),
                 None,
+                None,
             )
             .await
             .unwrap()
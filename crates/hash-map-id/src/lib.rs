@@ -35,6 +35,15 @@ where
     pub fn get(&self, id: u64) -> Option<&T> {
         self.store.get(&id)
     }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.store.values()
+    }
+
+    /// Returns the IDs of all items, in no particular order.
+    pub fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.store.keys().copied()
+    }
 }
 
 impl<T> Default for HashMapId<T>
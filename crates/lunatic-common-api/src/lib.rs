@@ -1,6 +1,6 @@
 use anyhow::Result;
 use std::fmt::Display;
-use wasmtime::{Caller, Memory, Trap};
+use wasmtime::{Caller, FuncType, Linker, Memory, Trap, Val, ValType};
 
 // Get exported memory
 pub fn get_memory<T>(caller: &mut Caller<T>) -> std::result::Result<Memory, Trap> {
@@ -39,3 +39,117 @@ impl<T> IntoTrap<T> for Option<T> {
         }
     }
 }
+
+/// Defines the host function `module::name` with the signature `params -> results`, where `func`
+/// can return multiple values, e.g. an error code and a resource ID.
+///
+/// If the types are known at compile time, prefer [`Linker::func_wrap`] and return a tuple, it
+/// avoids boxing the values:
+///
+/// ```
+/// # use wasmtime::{Caller, Linker, Trap};
+/// # fn register<T>(linker: &mut Linker<T>) -> anyhow::Result<()> {
+/// // Imported as `(func (param i32 i32) (result i32 i32))`.
+/// linker.func_wrap(
+///     "example",
+///     "div_rem",
+///     |_caller: Caller<T>, a: u32, b: u32| -> Result<(u32, u32), Trap> {
+///         if b == 0 {
+///             return Err(Trap::new("division by zero"));
+///         }
+///         Ok((a / b, a % b))
+///     },
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// This helper is for signatures only known at runtime. `func` receives the parameters and
+/// returns the results as [`Val`]s. If it returns a different number of values or values of
+/// other types than `results`, the guest traps. Multiple results need the multi-value proposal,
+/// enabled in the default configuration of the runtime.
+pub fn func_wrap_multi<T, F>(
+    linker: &mut Linker<T>,
+    module: &str,
+    name: &str,
+    params: impl IntoIterator<Item = ValType>,
+    results: impl IntoIterator<Item = ValType>,
+    func: F,
+) -> Result<()>
+where
+    F: Fn(Caller<'_, T>, &[Val]) -> Result<Vec<Val>, Trap> + Send + Sync + 'static,
+{
+    let ty = FuncType::new(params, results);
+    let expected: Vec<ValType> = ty.results().collect();
+    let label = format!("{}::{}", module, name);
+    linker.func_new(module, name, ty, move |caller, params, results| {
+        let values = func(caller, params)?;
+        let types: Vec<ValType> = values.iter().map(Val::ty).collect();
+        if types != expected {
+            return Err(Trap::new(format!(
+                "{} returned {:?}, expected {:?}",
+                label, types, expected
+            )));
+        }
+        results.clone_from_slice(&values);
+        Ok(())
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::{Engine, Linker, Module, Store, Val, ValType};
+
+    use super::func_wrap_multi;
+
+    #[test]
+    fn host_function_returns_multiple_values() {
+        let engine = Engine::default();
+        let mut linker = Linker::new(&engine);
+        func_wrap_multi(
+            &mut linker,
+            "example",
+            "split",
+            [ValType::I64],
+            [ValType::I32, ValType::I32],
+            |_caller, params| {
+                let value = params[0].unwrap_i64();
+                Ok(vec![Val::I32((value >> 32) as i32), Val::I32(value as i32)])
+            },
+        )
+        .unwrap();
+        func_wrap_multi(
+            &mut linker,
+            "example",
+            "wrong",
+            [],
+            [ValType::I32, ValType::I64],
+            |_caller, _params| Ok(vec![Val::I32(1)]),
+        )
+        .unwrap();
+        let module = Module::new(
+            &engine,
+            r#"
+            (module
+                (import "example" "split" (func $split (param i64) (result i32 i32)))
+                (import "example" "wrong" (func $wrong (result i32 i64)))
+                (func (export "run") (result i32)
+                    (i32.sub (call $split (i64.const 0x0000000700000002))))
+                (func (export "wrong") (drop (drop (call $wrong)))))
+            "#,
+        )
+        .unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        let run = instance
+            .get_typed_func::<(), i32, _>(&mut store, "run")
+            .unwrap();
+        assert_eq!(run.call(&mut store, ()).unwrap(), 5);
+        let wrong = instance
+            .get_typed_func::<(), (), _>(&mut store, "wrong")
+            .unwrap();
+        let trap = wrong.call(&mut store, ()).unwrap_err();
+        assert!(trap.to_string().contains("example::wrong returned [I32]"));
+    }
+}
@@ -2,6 +2,7 @@ use std::{
     convert::TryInto,
     future::Future,
     io::{Read, Write},
+    sync::Arc,
     time::Duration,
 };
 
@@ -9,16 +10,22 @@ use anyhow::Result;
 use lunatic_common_api::{get_memory, IntoTrap};
 use lunatic_networking_api::NetworkingCtx;
 use lunatic_process_api::ProcessCtx;
-use wasmtime::{Caller, Linker, Trap};
+use wasmtime::{Caller, Linker, ResourceLimiter, Trap};
 
 use lunatic_process::{
-    message::{DataMessage, Message},
+    config::{IdleReceivePolicy, IdleReceiveTimeout, ProcessConfig},
+    fuel::{charge_host_call, HostCallCategory},
+    message::{DataMessage, Message, MAX_PRIORITY},
+    process_ref::ProcessRef,
     state::ProcessState,
-    Signal,
+    upgrade::upgrade_at_boundary,
+    Signal, WasmProcess,
 };
 
 // Register the mailbox APIs to the linker
-pub fn register<T: ProcessState + ProcessCtx<T> + NetworkingCtx + Send + 'static>(
+pub fn register<
+    T: ProcessState + ProcessCtx<T> + NetworkingCtx + ResourceLimiter + Send + 'static,
+>(
     linker: &mut Linker<T>,
 ) -> Result<()> {
     linker.func_wrap("lunatic::message", "create_data", create_data)?;
@@ -32,14 +39,28 @@ pub fn register<T: ProcessState + ProcessCtx<T> + NetworkingCtx + Send + 'static
     linker.func_wrap("lunatic::message", "push_tcp_stream", push_tcp_stream)?;
     linker.func_wrap("lunatic::message", "take_tcp_stream", take_tcp_stream)?;
     linker.func_wrap("lunatic::message", "send", send)?;
+    linker.func_wrap("lunatic::message", "send_to_named", send_to_named)?;
+    linker.func_wrap("lunatic::message", "try_send", try_send)?;
+    linker.func_wrap("lunatic::message", "send_multi", send_multi)?;
+    linker.func_wrap("lunatic::message", "subscribe", subscribe)?;
+    linker.func_wrap("lunatic::message", "unsubscribe", unsubscribe)?;
+    linker.func_wrap("lunatic::message", "publish", publish)?;
+    linker.func_wrap("lunatic::message", "set_reply_to", set_reply_to)?;
+    linker.func_wrap("lunatic::message", "take_reply_to", take_reply_to)?;
     linker.func_wrap2_async(
         "lunatic::message",
         "send_receive_skip_search",
         send_receive_skip_search,
     )?;
     linker.func_wrap3_async("lunatic::message", "receive", receive)?;
+    linker.func_wrap3_async("lunatic::message", "receive_system", receive_system)?;
+    linker.func_wrap("lunatic::message", "create_system_data", create_system_data)?;
+    linker.func_wrap("lunatic::message", "set_priority", set_priority)?;
+    linker.func_wrap("lunatic::message", "cancel_tag", cancel_tag)?;
     linker.func_wrap("lunatic::message", "push_udp_socket", push_udp_socket)?;
     linker.func_wrap("lunatic::message", "take_udp_socket", take_udp_socket)?;
+    linker.func_wrap("lunatic::message", "push_tcp_listener", push_tcp_listener)?;
+    linker.func_wrap("lunatic::message", "take_tcp_listener", take_tcp_listener)?;
 
     Ok(())
 }
@@ -124,7 +145,8 @@ fn create_data<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     tag: i64,
     buffer_capacity: u64,
-) {
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let tag = match tag {
         0 => None,
         tag => Some(tag),
@@ -134,6 +156,51 @@ fn create_data<T: ProcessState + ProcessCtx<T>>(
         .data_mut()
         .message_scratch_area()
         .replace(Message::Data(message));
+    Ok(())
+}
+
+// Creates a new system message, the same way as `create_data`.
+//
+// System messages are only received through `lunatic::message::receive_system`.
+fn create_system_data<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    tag: i64,
+    buffer_capacity: u64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let tag = match tag {
+        0 => None,
+        tag => Some(tag),
+    };
+    let message = DataMessage::new_system(tag, buffer_capacity as usize);
+    caller
+        .data_mut()
+        .message_scratch_area()
+        .replace(Message::Data(message));
+    Ok(())
+}
+
+// Sets the priority of the message in the scratch area. Messages with a higher priority are
+// received first, 0 is the default priority and 3 the highest.
+//
+// Traps:
+// * If the priority is higher than 3.
+// * If it's called without a data message being inside of the scratch area.
+fn set_priority<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    priority: u32,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    if priority > MAX_PRIORITY as u32 {
+        return Err(Trap::new(
+            "lunatic::message::set_priority: priority too high",
+        ));
+    }
+    match caller.data_mut().message_scratch_area() {
+        Some(Message::Data(data)) => data.priority = priority as u8,
+        _ => return Err(Trap::new("lunatic::message::set_priority: no data message")),
+    }
+    Ok(())
 }
 
 // Writes some data into the message buffer and returns how much data is written in bytes.
@@ -146,6 +213,7 @@ fn write_data<T: ProcessState + ProcessCtx<T>>(
     data_ptr: u32,
     data_len: u32,
 ) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let memory = get_memory(&mut caller)?;
     let mut message = caller
         .data_mut()
@@ -158,8 +226,8 @@ fn write_data<T: ProcessState + ProcessCtx<T>>(
         .or_trap("lunatic::message::write_data")?;
     let bytes = match &mut message {
         Message::Data(data) => data.write(buffer).or_trap("lunatic::message::write_data")?,
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
     // Put message back after writing to it.
@@ -178,6 +246,7 @@ fn read_data<T: ProcessState + ProcessCtx<T>>(
     data_ptr: u32,
     data_len: u32,
 ) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let memory = get_memory(&mut caller)?;
     let mut message = caller
         .data_mut()
@@ -190,8 +259,8 @@ fn read_data<T: ProcessState + ProcessCtx<T>>(
         .or_trap("lunatic::message::read_data")?;
     let bytes = match &mut message {
         Message::Data(data) => data.read(buffer).or_trap("lunatic::message::read_data")?,
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
     // Put message back after reading from it.
@@ -210,6 +279,7 @@ fn seek_data<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     index: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let mut message = caller
         .data_mut()
         .message_scratch_area()
@@ -217,8 +287,8 @@ fn seek_data<T: ProcessState + ProcessCtx<T>>(
         .or_trap("lunatic::message::seek_data")?;
     match &mut message {
         Message::Data(data) => data.seek(index as usize),
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
     Ok(())
@@ -229,6 +299,7 @@ fn seek_data<T: ProcessState + ProcessCtx<T>>(
 // Traps:
 // * If it's called without a message being inside of the scratch area.
 fn get_tag<T: ProcessState + ProcessCtx<T>>(mut caller: Caller<T>) -> Result<i64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let message = caller
         .data_mut()
         .message_scratch_area()
@@ -245,6 +316,7 @@ fn get_tag<T: ProcessState + ProcessCtx<T>>(mut caller: Caller<T>) -> Result<i64
 // Traps:
 // * If it's called without a data message being inside of the scratch area.
 fn data_size<T: ProcessState + ProcessCtx<T>>(mut caller: Caller<T>) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let message = caller
         .data_mut()
         .message_scratch_area()
@@ -252,8 +324,8 @@ fn data_size<T: ProcessState + ProcessCtx<T>>(mut caller: Caller<T>) -> Result<u
         .or_trap("lunatic::message::data_size")?;
     let bytes = match message {
         Message::Data(data) => data.size(),
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
 
@@ -272,6 +344,7 @@ fn push_process<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     process_id: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let process = caller
         .data_mut()
         .process_resources_mut()
@@ -284,8 +357,8 @@ fn push_process<T: ProcessState + ProcessCtx<T>>(
         .or_trap("lunatic::message::push_process")?;
     let index = match message {
         Message::Data(data) => data.add_process(process) as u64,
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
     Ok(index)
@@ -301,6 +374,7 @@ fn take_process<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     index: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let message = caller
         .data_mut()
         .message_scratch_area()
@@ -310,8 +384,8 @@ fn take_process<T: ProcessState + ProcessCtx<T>>(
         Message::Data(data) => data
             .take_process(index as usize)
             .or_trap("lunatic::message::take_process")?,
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
     Ok(caller.data_mut().process_resources_mut().add(process))
@@ -327,20 +401,44 @@ fn push_tcp_stream<T: ProcessState + ProcessCtx<T> + NetworkingCtx>(
     mut caller: Caller<T>,
     stream_id: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let stream = caller
         .data_mut()
         .tcp_stream_resources_mut()
         .remove(stream_id)
         .or_trap("lunatic::message::push_tcp_stream")?;
+    // The receiving process doesn't inherit the idle timeout or the shutdown directions of the
+    // stream, and the stream doesn't count towards the outbound connections or the draining
+    // listeners of this process anymore. An outbound connection keeps its tag, it counts towards
+    // the process that opened it until the last stream referring to it is dropped.
+    caller
+        .data_mut()
+        .tcp_stream_activity_mut()
+        .remove(&stream_id);
+    let outbound_tag = caller
+        .data_mut()
+        .outbound_tcp_streams_mut()
+        .remove(stream_id);
+    caller
+        .data_mut()
+        .tcp_stream_shutdowns_mut()
+        .remove(&stream_id);
+    caller
+        .data_mut()
+        .tcp_listener_drains_mut()
+        .stream_closed(stream_id);
     let message = caller
         .data_mut()
         .message_scratch_area()
         .as_mut()
         .or_trap("lunatic::message::push_tcp_stream")?;
     let index = match message {
-        Message::Data(data) => data.add_tcp_stream(stream) as u64,
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::Data(data) => match outbound_tag {
+            Some(tag) => data.add_outbound_tcp_stream(stream, tag) as u64,
+            None => data.add_tcp_stream(stream) as u64,
+        },
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
     Ok(index)
@@ -356,20 +454,25 @@ fn take_tcp_stream<T: ProcessState + ProcessCtx<T> + NetworkingCtx>(
     mut caller: Caller<T>,
     index: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let message = caller
         .data_mut()
         .message_scratch_area()
         .as_mut()
         .or_trap("lunatic::message::take_tcp_stream")?;
-    let tcp_stream = match message {
+    let (tcp_stream, outbound_tag) = match message {
         Message::Data(data) => data
-            .take_tcp_stream(index as usize)
+            .take_tagged_tcp_stream(index as usize)
             .or_trap("lunatic::message::take_tcp_stream")?,
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
-    Ok(caller.data_mut().tcp_stream_resources_mut().add(tcp_stream))
+    let id = caller.data_mut().tcp_stream_resources_mut().add(tcp_stream);
+    if let Some(tag) = outbound_tag {
+        caller.data_mut().outbound_tcp_streams_mut().insert(id, tag);
+    }
+    Ok(id)
 }
 
 // Sends the message to a process.
@@ -383,20 +486,357 @@ fn send<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     process_id: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let message = caller
         .data_mut()
         .message_scratch_area()
         .take()
         .or_trap("lunatic::message::send")?;
+    #[cfg(feature = "message-stats")]
+    let sender = caller.data().id();
     let process = caller
         .data_mut()
         .process_resources_mut()
         .get(process_id)
         .or_trap("lunatic::message::send")?;
+    #[cfg(feature = "message-stats")]
+    lunatic_process::message_stats::record(sender, process.id(), &message);
     process.send(Signal::Message(message));
     Ok(())
 }
 
+// Same as `send`, but reports if the message can't be delivered because the process finished.
+//
+// The message is consumed even if it can't be sent. This is the way to answer a request to the
+// process taken with `take_reply_to`, the requester could have given up and died.
+//
+// Returns:
+// * 0 on success
+// * 1 if the process finished and can't receive messages anymore
+//
+// Traps:
+// * If the process ID doesn't exist.
+// * If it's called before creating the next message.
+fn try_send<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    process_id: u64,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let message = caller
+        .data_mut()
+        .message_scratch_area()
+        .take()
+        .or_trap("lunatic::message::try_send")?;
+    #[cfg(feature = "message-stats")]
+    let sender = caller.data().id();
+    let process = caller
+        .data_mut()
+        .process_resources_mut()
+        .get(process_id)
+        .or_trap("lunatic::message::try_send")?;
+    #[cfg(feature = "message-stats")]
+    lunatic_process::message_stats::record(sender, process.id(), &message);
+    match ProcessRef::new(process.clone()).try_send_message(message) {
+        Ok(()) => Ok(0),
+        Err(_) => Ok(1),
+    }
+}
+
+// Sends a copy of the message to each process in the array of **process_ids_len** u64 process
+// IDs at **process_ids_ptr**, e.g. to publish an event to all subscribers. The data is copied by
+// the host, the guest encodes it only once.
+//
+// The message is consumed even if it can't be sent to any process. The delivery status of each
+// process is written to **status_ptr**, one byte per process in the same order:
+// * 0 if the message was delivered
+// * 1 if the process finished and can't receive messages anymore
+//
+// Returns the number of processes the message was delivered to.
+//
+// Traps:
+// * If any of the process IDs doesn't exist, nothing is sent then.
+// * If the message carries resources, they can't be shared by multiple processes.
+// * If it's called before creating the next message.
+// * If any memory outside the guest heap space is referenced.
+fn send_multi<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    process_ids_ptr: u32,
+    process_ids_len: u32,
+    status_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let message = caller
+        .data_mut()
+        .message_scratch_area()
+        .take()
+        .or_trap("lunatic::message::send_multi")?;
+    if message.try_clone().is_none() {
+        return Err(Trap::new(
+            "lunatic::message::send_multi: message carries resources",
+        ));
+    }
+    let memory = get_memory(&mut caller)?;
+    let process_ids: Vec<u64> = memory
+        .data(&caller)
+        .get(process_ids_ptr as usize..(process_ids_ptr + process_ids_len * 8) as usize)
+        .or_trap("lunatic::message::send_multi")?
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().expect("works")))
+        .collect();
+    // Resolve all processes first, so that nothing is sent if an ID is wrong.
+    let resources = caller.data_mut().process_resources_mut();
+    let processes = process_ids
+        .into_iter()
+        .map(|process_id| {
+            resources
+                .get(process_id)
+                .map(|process| ProcessRef::new(process.clone()))
+        })
+        .collect::<Option<Vec<_>>>()
+        .or_trap("lunatic::message::send_multi")?;
+
+    #[cfg(feature = "message-stats")]
+    let sender = caller.data().id();
+    let mut statuses = Vec::with_capacity(processes.len());
+    let mut message = Some(message);
+    for (i, process) in processes.iter().enumerate() {
+        // The last process gets the original, the others a copy.
+        let copy = match i + 1 == processes.len() {
+            true => message.take(),
+            false => message.as_ref().and_then(Message::try_clone),
+        }
+        .expect("checked above");
+        #[cfg(feature = "message-stats")]
+        lunatic_process::message_stats::record(sender, process.id(), &copy);
+        let status = match process.try_send_message(copy) {
+            Ok(()) => 0,
+            Err(_) => 1,
+        };
+        statuses.push(status);
+    }
+    memory
+        .write(&mut caller, status_ptr as usize, &statuses)
+        .or_trap("lunatic::message::send_multi")?;
+    Ok(statuses.iter().filter(|status| **status == 0).count() as u32)
+}
+
+// Subscribes the process to the topic named by the string at **topic_str_ptr**. It receives
+// all messages published to the topic afterwards, until it unsubscribes or finishes.
+//
+// Returns:
+// * 0 on success
+// * 1 if the process is already subscribed to the topic
+//
+// Traps:
+// * If the topic is not a valid utf8 string.
+// * If any memory outside the guest heap space is referenced.
+fn subscribe<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    topic_str_ptr: u32,
+    topic_str_len: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let topic = topic_name(&mut caller, topic_str_ptr, topic_str_len, "subscribe")?;
+    let id = caller.data().id();
+    let signal_mailbox = caller.data().signal_mailbox().0.clone();
+    let process = ProcessRef::new(Arc::new(WasmProcess::new(id, signal_mailbox)));
+    match caller.data().runtime().topics().subscribe(&topic, process) {
+        true => Ok(0),
+        false => Ok(1),
+    }
+}
+
+// Unsubscribes the process from the topic named by the string at **topic_str_ptr**. Messages
+// published before can still be in the mailbox.
+//
+// Returns:
+// * 0 on success
+// * 1 if the process wasn't subscribed to the topic
+//
+// Traps:
+// * If the topic is not a valid utf8 string.
+// * If any memory outside the guest heap space is referenced.
+fn unsubscribe<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    topic_str_ptr: u32,
+    topic_str_len: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let topic = topic_name(&mut caller, topic_str_ptr, topic_str_len, "unsubscribe")?;
+    let id = caller.data().id();
+    match caller.data().runtime().topics().unsubscribe(&topic, id) {
+        true => Ok(0),
+        false => Ok(1),
+    }
+}
+
+// Sends a copy of the message to all current subscribers of the topic named by the string at
+// **topic_str_ptr**, the caller included if it's subscribed. See `lunatic_process::topics` for
+// the ordering guarantees. Processes subscribing later don't get the message.
+//
+// The message is consumed even if the topic has no subscribers.
+//
+// Returns the number of processes the message was delivered to.
+//
+// Traps:
+// * If the topic is not a valid utf8 string.
+// * If the message carries resources, they can't be shared by multiple processes.
+// * If it's called before creating the next message.
+// * If any memory outside the guest heap space is referenced.
+fn publish<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    topic_str_ptr: u32,
+    topic_str_len: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let message = caller
+        .data_mut()
+        .message_scratch_area()
+        .take()
+        .or_trap("lunatic::message::publish")?;
+    if message.try_clone().is_none() {
+        return Err(Trap::new(
+            "lunatic::message::publish: message carries resources",
+        ));
+    }
+    let topic = topic_name(&mut caller, topic_str_ptr, topic_str_len, "publish")?;
+    let delivered = caller.data().runtime().topics().publish(&topic, &message);
+    #[cfg(feature = "message-stats")]
+    for receiver in &delivered {
+        lunatic_process::message_stats::record(caller.data().id(), *receiver, &message);
+    }
+    Ok(delivered.len() as u32)
+}
+
+fn topic_name<T: ProcessState>(
+    caller: &mut Caller<T>,
+    topic_str_ptr: u32,
+    topic_str_len: u32,
+    function: &str,
+) -> Result<String, Trap> {
+    let name = format!("lunatic::message::{}", function);
+    let memory = get_memory(caller)?;
+    let topic = memory
+        .data(&caller)
+        .get(topic_str_ptr as usize..(topic_str_ptr + topic_str_len) as usize)
+        .or_trap(&name)?;
+    let topic = std::str::from_utf8(topic).or_trap(&name)?;
+    Ok(topic.to_owned())
+}
+
+// Marks the process as the one the receiver of the message in the scratch area should reply
+// to, e.g. the sender itself (see `lunatic::process::this`). The handle stays in the resources of
+// the current process.
+//
+// The receiver gets the handle with `take_reply_to`. Correlate the reply by tagging the request
+// and waiting on the tag, the responder replies with the tag of the request.
+//
+// Traps:
+// * If the process ID doesn't exist.
+// * If no data message is in the scratch area.
+fn set_reply_to<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    process_id: u64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let process = caller
+        .data_mut()
+        .process_resources_mut()
+        .get(process_id)
+        .or_trap("lunatic::message::set_reply_to")?
+        .clone();
+    match caller.data_mut().message_scratch_area() {
+        Some(Message::Data(data)) => data.reply_to = Some(ProcessRef::new(process)),
+        _ => return Err(Trap::new("lunatic::message::set_reply_to: no data message")),
+    }
+    Ok(())
+}
+
+// Takes the process to reply to from the message in the scratch area and puts it into the
+// process' resources.
+//
+// Returns:
+// * 0 if the message has a reply-to process, its resource ID is written to **id_u64_ptr**
+// * 1 if the message has no reply-to process, or it was already taken
+//
+// Traps:
+// * If no data message is in the scratch area.
+// * If any memory outside the guest heap space is referenced.
+fn take_reply_to<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    id_u64_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let reply_to = match caller.data_mut().message_scratch_area() {
+        Some(Message::Data(data)) => data.reply_to.take(),
+        _ => {
+            return Err(Trap::new(
+                "lunatic::message::take_reply_to: no data message",
+            ))
+        }
+    };
+    let process = match reply_to {
+        Some(process) => process,
+        None => return Ok(1),
+    };
+    let id = caller
+        .data_mut()
+        .process_resources_mut()
+        .add(process.into_inner());
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(&mut caller, id_u64_ptr as usize, &id.to_le_bytes())
+        .or_trap("lunatic::message::take_reply_to")?;
+    Ok(0)
+}
+
+// Sends the message to the process registered under `name`.
+//
+// The lookup and the send happen in the same host call, the message can't end up at a process
+// that replaced the registered one in between. The send fails if the signal mailbox of the
+// process is already closed, a process that finishes after accepting the message drops it
+// unreceived. The message is consumed even if it can't be sent.
+//
+// Returns:
+// * 0 on success
+// * 1 if no process is registered under `name`
+// * 2 if the registered process finished and can't receive messages anymore
+//
+// Traps:
+// * If the name is not a valid utf8 string.
+// * If it's called with wrong data in the scratch area.
+// * If any memory outside the guest heap space is referenced.
+fn send_to_named<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    name_str_ptr: u32,
+    name_str_len: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let message = caller
+        .data_mut()
+        .message_scratch_area()
+        .take()
+        .or_trap("lunatic::message::send_to_named")?;
+    let memory = get_memory(&mut caller)?;
+    let name = memory
+        .data(&caller)
+        .get(name_str_ptr as usize..(name_str_ptr + name_str_len) as usize)
+        .or_trap("lunatic::message::send_to_named")?;
+    let name = std::str::from_utf8(name).or_trap("lunatic::message::send_to_named")?;
+
+    let registry = caller.data().registry();
+    let process = match registry.get(name) {
+        Some(process) => ProcessRef::new(process.clone()),
+        None => return Ok(1),
+    };
+    #[cfg(feature = "message-stats")]
+    lunatic_process::message_stats::record(caller.data().id(), process.id(), &message);
+    match process.try_send_message(message) {
+        Ok(()) => Ok(0),
+        Err(_) => Ok(2),
+    }
+}
+
 // Sends the message to a process and waits for a reply, but doesn't look through existing
 // messages in the mailbox queue while waiting. This is an optimization that only makes sense
 // with tagged messages. In a request/reply scenario we can tag the request message with an
@@ -406,7 +846,7 @@ fn send<T: ProcessState + ProcessCtx<T>>(
 // miss out on the incoming message before `receive` is called.
 //
 // If timeout is specified (value different from 0), the function will return on timeout
-// expiration with value 9027.
+// expiration with value 9027. The idle receive timeout of the process applies too, see `receive`.
 //
 // Returns:
 // * 0    if message arrived.
@@ -415,17 +855,21 @@ fn send<T: ProcessState + ProcessCtx<T>>(
 // Traps:
 // * If the process ID doesn't exist.
 // * If it's called with wrong data in the scratch area.
+// * If the idle receive timeout of the process runs out and its policy is to kill the process.
 fn send_receive_skip_search<T: ProcessState + ProcessCtx<T> + Send>(
     mut caller: Caller<T>,
     process_id: u64,
     timeout: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Message)?;
         let message = caller
             .data_mut()
             .message_scratch_area()
             .take()
             .or_trap("lunatic::message::send_receive_skip_search")?;
+        #[cfg(feature = "message-stats")]
+        let sender = caller.data().id();
         let mut _tags = [0; 1];
         let tags = if let Some(tag) = message.tag() {
             _tags = [tag];
@@ -438,16 +882,24 @@ fn send_receive_skip_search<T: ProcessState + ProcessCtx<T> + Send>(
             .process_resources_mut()
             .get(process_id)
             .or_trap("lunatic::message::send_receive_skip_search")?;
+        #[cfg(feature = "message-stats")]
+        lunatic_process::message_stats::record(sender, process.id(), &message);
         process.send(Signal::Message(message));
+        let idle = caller.data().config().get_idle_receive_timeout();
+        let (limit, idle_policy) = receive_limit(idle, timeout);
+        let _wait_guard = match limit {
+            None => caller.data().wait_guard(),
+            Some(_) => None,
+        };
         if let Some(message) = tokio::select! {
-            _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
+            _ = async_std::task::sleep(limit.unwrap_or_default()), if limit.is_some() => None,
             message = caller.data_mut().mailbox().pop_skip_search(tags) => Some(message)
         } {
             // Put the message into the scratch area
             caller.data_mut().message_scratch_area().replace(message);
             Ok(0)
         } else {
-            Ok(9027)
+            receive_timed_out(idle_policy, "lunatic::message::send_receive_skip_search")
         }
     })
 }
@@ -460,57 +912,191 @@ fn send_receive_skip_search<T: ProcessState + ProcessCtx<T> + Send>(
 // endian values.
 //
 // If timeout is specified (value different from 0), the function will return on timeout
-// expiration with value 9027.
+// expiration with value 9027. This allows the guest to run a fallback if no matching message
+// arrived in time, like `receive ... after` in Erlang. If a matching message arrives at the same
+// time as the timeout expires, the message wins. The timer only lives for the duration of the
+// call, a message arriving first cancels it. A message arriving after the timeout stays in the
+// mailbox for the next receive.
+//
+// If the process has an idle receive timeout that is shorter, the call waits only that long.
+// Depending on the policy, the call returns 9027 or the process is killed once it runs out.
 //
 // Once the message is received, functions like `lunatic::message::read_data()` can be used to
 // extract data out of it.
 //
+// If the process requested an upgrade with `lunatic::process::upgrade`, it happens before the
+// call waits for a message. A successful upgrade doesn't return, the process continues with the
+// new code.
+//
 // Returns:
 // * 0    if it's a data message.
 // * 1    if it's a signal turned into a message.
+// * 2    if the process was asked to shut down (killed with a grace period).
+// * 3    if the receive was cancelled by the host, no message is received.
 // * 9027 if call timed out.
 //
 // Traps:
 // * If **tag_ptr + (ciovec_array_len * 8) is outside the memory
-fn receive<T: ProcessState + ProcessCtx<T> + Send>(
+// * If the idle receive timeout of the process runs out and its policy is to kill the process.
+fn receive<T: ProcessState + ProcessCtx<T> + ResourceLimiter + Send + 'static>(
     mut caller: Caller<T>,
     tag_ptr: u32,
     tag_len: u32,
     timeout: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
-        let tags = if tag_len > 0 {
-            let memory = get_memory(&mut caller)?;
-            let buffer = memory
-                .data(&caller)
-                .get(tag_ptr as usize..(tag_ptr + tag_len * 8) as usize)
-                .or_trap("lunatic::message::receive")?;
-
-            // Gether all tags
-            let tags: Vec<i64> = buffer
-                .chunks_exact(8)
-                .map(|chunk| i64::from_le_bytes(chunk.try_into().expect("works")))
-                .collect();
-            Some(tags)
-        } else {
-            None
+        // The boundary between two messages, where a requested upgrade happens.
+        upgrade_at_boundary(&mut caller).await?;
+        receive_from(
+            &mut caller,
+            tag_ptr,
+            tag_len,
+            timeout,
+            false,
+            "lunatic::message::receive",
+        )
+        .await
+    })
+}
+
+// Same as `receive`, but takes the next system message out of the queue.
+//
+// System messages are created with `lunatic::message::create_system_data` and are never returned
+// by `receive`. This allows framework code to handle its own protocol messages without user code
+// seeing them.
+//
+// Returns:
+// * 0    if it's a data message.
+// * 3    if the receive was cancelled by the host, no message is received.
+// * 9027 if call timed out.
+//
+// Traps:
+// * If **tag_ptr + (ciovec_array_len * 8) is outside the memory
+// * If the idle receive timeout of the process runs out and its policy is to kill the process.
+fn receive_system<T: ProcessState + ProcessCtx<T> + Send>(
+    mut caller: Caller<T>,
+    tag_ptr: u32,
+    tag_len: u32,
+    timeout: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+    Box::new(async move {
+        receive_from(
+            &mut caller,
+            tag_ptr,
+            tag_len,
+            timeout,
+            true,
+            "lunatic::message::receive_system",
+        )
+        .await
+    })
+}
+
+// Receives the next user or system message into the scratch area.
+async fn receive_from<T: ProcessState + ProcessCtx<T> + Send>(
+    caller: &mut Caller<'_, T>,
+    tag_ptr: u32,
+    tag_len: u32,
+    timeout: u32,
+    system: bool,
+    name: &str,
+) -> Result<u32, Trap> {
+    charge_host_call(caller, HostCallCategory::Message)?;
+    let tags = if tag_len > 0 {
+        let memory = get_memory(caller)?;
+        let buffer = memory
+            .data(&caller)
+            .get(tag_ptr as usize..(tag_ptr + tag_len * 8) as usize)
+            .or_trap(name)?;
+
+        // Gether all tags
+        let tags: Vec<i64> = buffer
+            .chunks_exact(8)
+            .map(|chunk| i64::from_le_bytes(chunk.try_into().expect("works")))
+            .collect();
+        Some(tags)
+    } else {
+        None
+    };
+
+    let idle = caller.data().config().get_idle_receive_timeout();
+    let (limit, idle_policy) = receive_limit(idle, timeout);
+    // Without a timeout only a message can wake up the process.
+    let _wait_guard = match limit {
+        None => caller.data().wait_guard(),
+        Some(_) => None,
+    };
+    let mailbox = caller.data_mut().mailbox().clone();
+    // The host can cancel the receive with `Signal::CancelReceive`.
+    let pop = mailbox.pop_cancellable(tags.as_deref(), system);
+    // A message that is ready when the timeout expires is still received.
+    if let Some(message) = tokio::select! {
+        biased;
+        message = pop => Some(message),
+        _ = async_std::task::sleep(limit.unwrap_or_default()), if limit.is_some() => None,
+    } {
+        let message = match message {
+            Some(message) => message,
+            None => return Ok(3),
+        };
+        let result = match message {
+            Message::Data(_) => 0,
+            Message::LinkDied(_) => 1,
+            Message::Shutdown => 2,
         };
+        // Put the message into the scratch area
+        caller.data_mut().message_scratch_area().replace(message);
+        Ok(result)
+    } else {
+        receive_timed_out(idle_policy, name)
+    }
+}
 
-        if let Some(message) = tokio::select! {
-            _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
-            message = caller.data_mut().mailbox().pop(tags.as_deref()) => Some(message)
-        } {
-            let result = match message {
-                Message::Data(_) => 0,
-                Message::LinkDied(_) => 1,
-            };
-            // Put the message into the scratch area
-            caller.data_mut().message_scratch_area().replace(message);
-            Ok(result)
-        } else {
-            Ok(9027)
+// Returns how long a receive can wait for a message. If the idle receive timeout of the process
+// is shorter than the **timeout** of the call, it's used instead and its policy is returned too.
+fn receive_limit(
+    idle: Option<IdleReceiveTimeout>,
+    timeout: u32,
+) -> (Option<Duration>, Option<IdleReceivePolicy>) {
+    let timeout = match timeout {
+        0 => None,
+        timeout => Some(Duration::from_millis(timeout as u64)),
+    };
+    match idle {
+        Some(idle) if timeout.is_none_or(|timeout| idle.timeout < timeout) => {
+            (Some(idle.timeout), Some(idle.policy))
         }
-    })
+        _ => (timeout, None),
+    }
+}
+
+fn receive_timed_out(idle_policy: Option<IdleReceivePolicy>, name: &str) -> Result<u32, Trap> {
+    match idle_policy {
+        Some(IdleReceivePolicy::Kill) => Err(Trap::new(format!(
+            "{}: Process was blocked for longer than its idle receive timeout",
+            name
+        ))),
+        _ => Ok(9027),
+    }
+}
+
+// Gives up on all messages with **tag**.
+//
+// Messages with the tag that are already in the mailbox are removed and the next message with
+// the tag arriving later is dropped on arrival. This is useful in request/response patterns when
+// the process stopped waiting on a reply (e.g. timed out). Receiving on the same tag again starts
+// a new correlation.
+//
+// This is best-effort and only affects the mailbox of the calling process. The request could
+// already be processed by the responder.
+//
+// Returns the number of messages removed from the mailbox.
+fn cancel_tag<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    tag: i64,
+) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    Ok(caller.data_mut().mailbox().cancel_tag(tag) as u64)
 }
 
 // Adds a udp socket resource to the message that is currently in the scratch area and returns
@@ -523,19 +1109,22 @@ fn push_udp_socket<T: ProcessState + ProcessCtx<T> + NetworkingCtx>(
     mut caller: Caller<T>,
     socket_id: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let data = caller.data_mut();
     let socket = data
         .udp_resources_mut()
         .remove(socket_id)
         .or_trap("lunatic::message::push_udp_socket")?;
+    // Group memberships move together with the socket, but are not tracked by the receiver.
+    data.udp_multicast_groups_mut().remove(&socket_id);
     let message = data
         .message_scratch_area()
         .as_mut()
         .or_trap("lunatic::message::push_udp_socket")?;
     let index = match message {
         Message::Data(data) => data.add_udp_socket(socket) as u64,
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
     Ok(index)
@@ -551,6 +1140,7 @@ fn take_udp_socket<T: ProcessState + ProcessCtx<T> + NetworkingCtx>(
     mut caller: Caller<T>,
     index: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
     let message = caller
         .data_mut()
         .message_scratch_area()
@@ -560,9 +1150,119 @@ fn take_udp_socket<T: ProcessState + ProcessCtx<T> + NetworkingCtx>(
         Message::Data(data) => data
             .take_udp_socket(index as usize)
             .or_trap("lunatic::message::take_udp_socket")?,
-        Message::LinkDied(_) => {
-            return Err(Trap::new("Unexpected `Message::LinkDied` in scratch area"))
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
         }
     };
     Ok(caller.data_mut().udp_resources_mut().add(udp_socket))
 }
+
+// Adds a tcp listener resource to the message that is currently in the scratch area and returns
+// the new location of it. This will remove the listener from the current process' resources.
+//
+// This allows handing a listener over to a successor process without closing the bound socket.
+// Connections waiting in the backlog are accepted by the new owner. The sending process is blocked
+// during `tcp_accept`, so the handoff can't happen while one of its accepts is in progress. Accept
+// filters and idle timeouts of the listener are not moved, the new owner needs to set them again.
+//
+// Traps:
+// * If TCP listener ID doesn't exist
+// * If no data message is in the scratch area.
+fn push_tcp_listener<T: ProcessState + ProcessCtx<T> + NetworkingCtx>(
+    mut caller: Caller<T>,
+    listener_id: u64,
+) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let data = caller.data_mut();
+    let listener = data
+        .tcp_listener_resources_mut()
+        .remove(listener_id)
+        .or_trap("lunatic::message::push_tcp_listener")?;
+    data.tcp_listener_filters_mut().remove(&listener_id);
+    data.tcp_listener_idle_timeouts_mut().remove(&listener_id);
+    let message = data
+        .message_scratch_area()
+        .as_mut()
+        .or_trap("lunatic::message::push_tcp_listener")?;
+    let index = match message {
+        Message::Data(data) => data.add_tcp_listener(listener) as u64,
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
+        }
+    };
+    Ok(index)
+}
+
+// Takes the tcp listener from the message that is currently in the scratch area by index, puts
+// it into the process' resources and returns the resource ID.
+//
+// Traps:
+// * If index ID doesn't exist or matches the wrong resource (not a tcp listener).
+// * If no data message is in the scratch area.
+fn take_tcp_listener<T: ProcessState + ProcessCtx<T> + NetworkingCtx>(
+    mut caller: Caller<T>,
+    index: u64,
+) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Message)?;
+    let message = caller
+        .data_mut()
+        .message_scratch_area()
+        .as_mut()
+        .or_trap("lunatic::message::take_tcp_listener")?;
+    let listener = match message {
+        Message::Data(data) => data
+            .take_tcp_listener(index as usize)
+            .or_trap("lunatic::message::take_tcp_listener")?,
+        Message::LinkDied(_) | Message::Shutdown => {
+            return Err(Trap::new("Unexpected signal message in scratch area"))
+        }
+    };
+    Ok(caller.data_mut().tcp_listener_resources_mut().add(listener))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use lunatic_process::config::{IdleReceivePolicy, IdleReceiveTimeout};
+
+    use super::receive_limit;
+
+    #[test]
+    fn receive_limit_uses_the_shorter_timeout() {
+        let idle = Some(IdleReceiveTimeout {
+            timeout: Duration::from_millis(100),
+            policy: IdleReceivePolicy::Kill,
+        });
+        // Without an idle timeout the timeout of the call applies, 0 waits forever.
+        assert_eq!(receive_limit(None, 0), (None, None));
+        assert_eq!(
+            receive_limit(None, 50),
+            (Some(Duration::from_millis(50)), None)
+        );
+        // The idle timeout applies if the call waits forever or longer.
+        assert_eq!(
+            receive_limit(idle, 0),
+            (
+                Some(Duration::from_millis(100)),
+                Some(IdleReceivePolicy::Kill)
+            )
+        );
+        assert_eq!(
+            receive_limit(idle, 200),
+            (
+                Some(Duration::from_millis(100)),
+                Some(IdleReceivePolicy::Kill)
+            )
+        );
+        // Shorter or equal timeouts of the call time out as usual.
+        assert_eq!(
+            receive_limit(idle, 100),
+            (Some(Duration::from_millis(100)), None)
+        );
+        assert_eq!(
+            receive_limit(idle, 50),
+            (Some(Duration::from_millis(50)), None)
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::net::IpAddr;
+
+/// Source address allowlist of a TCP listener.
+///
+/// As long as the allowlist is empty all connections are accepted.
+#[derive(Debug, Default)]
+pub struct AcceptFilter {
+    allowed: Vec<(IpAddr, u8)>,
+    rejected: u64,
+}
+
+impl AcceptFilter {
+    /// Allows connections from all addresses inside the network `addr/prefix_len`.
+    ///
+    /// Returns `false` if the prefix length is too big for the address type.
+    pub fn allow(&mut self, addr: IpAddr, prefix_len: u8) -> bool {
+        let max_prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_prefix_len {
+            return false;
+        }
+        self.allowed.push((addr, prefix_len));
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.allowed.clear();
+    }
+
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        if self.allowed.is_empty() {
+            return true;
+        }
+        // IPv4 peers connecting to a dual-stack listener show up as IPv4-mapped IPv6 addresses.
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+            IpAddr::V4(_) => addr,
+        };
+        self.allowed
+            .iter()
+            .any(|(network, prefix_len)| in_network(addr, *network, *prefix_len))
+    }
+
+    pub fn record_rejected(&mut self) {
+        self.rejected += 1;
+    }
+
+    /// Number of connections closed by the host because they didn't pass the filter.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::AcceptFilter;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn prefix_len_is_checked_against_the_address_type() {
+        let mut filter = AcceptFilter::default();
+        assert!(filter.allow(ip("10.0.0.0"), 32));
+        assert!(!filter.allow(ip("10.0.0.0"), 33));
+        assert!(filter.allow(ip("fd00::"), 128));
+        assert!(!filter.allow(ip("fd00::"), 129));
+    }
+
+    #[test]
+    fn empty_filter_allows_everything() {
+        let mut filter = AcceptFilter::default();
+        assert!(filter.is_allowed(ip("192.0.2.1")));
+        filter.allow(ip("10.0.0.0"), 8);
+        assert!(!filter.is_allowed(ip("192.0.2.1")));
+        filter.clear();
+        assert!(filter.is_allowed(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn ipv4_networks() {
+        let mut filter = AcceptFilter::default();
+        filter.allow(ip("10.1.0.0"), 16);
+        filter.allow(ip("192.0.2.7"), 32);
+        assert!(filter.is_allowed(ip("10.1.0.0")));
+        assert!(filter.is_allowed(ip("10.1.255.255")));
+        assert!(!filter.is_allowed(ip("10.2.0.0")));
+        assert!(filter.is_allowed(ip("192.0.2.7")));
+        assert!(!filter.is_allowed(ip("192.0.2.8")));
+        // IPv4 networks don't match IPv6 addresses.
+        assert!(!filter.is_allowed(ip("fd00::")));
+
+        let mut any = AcceptFilter::default();
+        any.allow(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        assert!(any.is_allowed(ip("255.255.255.255")));
+        assert!(any.is_allowed(ip("0.0.0.0")));
+        assert!(!any.is_allowed(ip("::1")));
+    }
+
+    #[test]
+    fn ipv6_networks() {
+        let mut filter = AcceptFilter::default();
+        filter.allow(ip("2001:db8::"), 32);
+        filter.allow(ip("fd00::1"), 128);
+        assert!(filter.is_allowed(ip("2001:db8:ffff::1")));
+        assert!(!filter.is_allowed(ip("2001:db9::")));
+        assert!(filter.is_allowed(ip("fd00::1")));
+        assert!(!filter.is_allowed(ip("fd00::2")));
+        assert!(!filter.is_allowed(ip("10.0.0.1")));
+
+        let mut any = AcceptFilter::default();
+        any.allow(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0);
+        assert!(any.is_allowed(ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")));
+        assert!(any.is_allowed(ip("::")));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_match_ipv4_networks() {
+        let mut filter = AcceptFilter::default();
+        filter.allow(ip("192.0.2.0"), 24);
+        assert!(filter.is_allowed(ip("::ffff:192.0.2.10")));
+        assert!(!filter.is_allowed(ip("::ffff:198.51.100.1")));
+        // IPv4-compatible addresses are plain IPv6 addresses.
+        assert!(!filter.is_allowed(ip("::192.0.2.10")));
+    }
+}
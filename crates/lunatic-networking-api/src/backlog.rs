@@ -0,0 +1,130 @@
+//! Depth of the accept queue of TCP listeners.
+//!
+//! Connections that completed the handshake wait in the accept queue of the listener until they
+//! are accepted. A queue that keeps growing means the server can't keep up with new connections.
+//!
+//! How much the OS exposes differs per platform:
+//!
+//! | Platform   | Depth                                                                    |
+//! |------------|--------------------------------------------------------------------------|
+//! | Linux      | Exact, read with `TCP_INFO`.                                             |
+//! | Other unix | Estimate, 1 if at least one connection is waiting (polled) and 0 if not. |
+//! | Others     | Not supported, an error is returned.                                     |
+
+use std::io;
+
+use async_std::net::TcpListener;
+
+/// Returns the number of connections waiting to be accepted on the `listener`.
+pub fn accept_queue_depth(listener: &TcpListener) -> io::Result<u64> {
+    imp::accept_queue_depth(listener)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+    use std::mem::{size_of, MaybeUninit};
+    use std::os::unix::io::AsRawFd;
+
+    use async_std::net::TcpListener;
+
+    // Start of the kernel's `struct tcp_info`, the fields after `tcpi_sacked` aren't needed. The
+    // kernel copies at most the requested length.
+    #[repr(C)]
+    struct TcpInfoPrefix {
+        _flags: [u8; 8],
+        _rto: u32,
+        _ato: u32,
+        _snd_mss: u32,
+        _rcv_mss: u32,
+        // For listening sockets, the current length of the accept queue.
+        unacked: u32,
+        // For listening sockets, the configured backlog.
+        _sacked: u32,
+    }
+
+    pub(super) fn accept_queue_depth(listener: &TcpListener) -> io::Result<u64> {
+        let mut info = MaybeUninit::<TcpInfoPrefix>::zeroed();
+        let mut len = size_of::<TcpInfoPrefix>() as libc::socklen_t;
+        // SAFETY: `info` is valid for writes of `len` bytes and the descriptor is owned by the
+        // listener, which outlives the call.
+        let result = unsafe {
+            libc::getsockopt(
+                listener.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_INFO,
+                info.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: zero initialized and partly overwritten by the kernel.
+        let info = unsafe { info.assume_init() };
+        Ok(info.unacked as u64)
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod imp {
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    use async_std::net::TcpListener;
+
+    // A listener is readable if at least one connection is waiting, without telling how many.
+    pub(super) fn accept_queue_depth(listener: &TcpListener) -> io::Result<u64> {
+        let mut pollfd = libc::pollfd {
+            fd: listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: one valid `pollfd` is passed and the call doesn't block.
+        let result = unsafe { libc::poll(&mut pollfd, 1, 0) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((pollfd.revents & libc::POLLIN != 0) as u64)
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::io;
+
+    use async_std::net::TcpListener;
+
+    pub(super) fn accept_queue_depth(_listener: &TcpListener) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The accept queue depth is not available on this platform",
+        ))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::time::Duration;
+
+    use async_std::net::{TcpListener, TcpStream};
+
+    use super::accept_queue_depth;
+
+    #[async_std::test]
+    async fn counts_connections_waiting_to_be_accepted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(accept_queue_depth(&listener).unwrap(), 0);
+
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        assert_eq!(accept_queue_depth(&listener).unwrap(), 3);
+
+        let _accepted = listener.accept().await.unwrap();
+        assert_eq!(accept_queue_depth(&listener).unwrap(), 2);
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Throughput cap of a process, shared by all its sockets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandwidthLimit {
+    /// Sustained throughput in bytes per second. A value of 0 disables the limit.
+    pub bytes_per_second: u64,
+    /// Number of bytes that can be transferred at once after the sockets were idle. A value of 0
+    /// allows a burst of one second of traffic.
+    pub burst: u64,
+}
+
+/// Total number of bytes a process sent and received over all its sockets.
+///
+/// Only the payload is counted, protocol headers are not included. If the process has a
+/// [`BandwidthLimit`] the transferred bytes are also taken out of its token bucket.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetworkStats {
+    bytes_sent: u64,
+    bytes_received: u64,
+    bucket: Option<TokenBucket>,
+}
+
+impl NetworkStats {
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.bytes_sent = self.bytes_sent.saturating_add(bytes as u64);
+        if let Some(bucket) = self.bucket.as_mut() {
+            bucket.consume(bytes);
+        }
+    }
+
+    pub fn record_received(&mut self, bytes: usize) {
+        self.bytes_received = self.bytes_received.saturating_add(bytes as u64);
+        if let Some(bucket) = self.bucket.as_mut() {
+            bucket.consume(bytes);
+        }
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Returns how long the next transfer needs to wait to stay under the `limit`.
+    ///
+    /// A single transfer can go over the limit, the following ones are delayed until the debt is
+    /// paid off.
+    pub fn delay(&mut self, limit: Option<BandwidthLimit>) -> Duration {
+        match limit {
+            Some(limit) => self
+                .bucket
+                .get_or_insert_with(|| TokenBucket::new(limit))
+                .delay(),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    // Goes negative if a transfer takes more tokens than available.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: BandwidthLimit) -> Self {
+        let capacity = match limit.burst {
+            0 => limit.bytes_per_second,
+            burst => burst,
+        } as f64;
+        Self {
+            rate: limit.bytes_per_second as f64,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        self.refill();
+        self.tokens -= bytes as f64;
+    }
+
+    fn delay(&mut self) -> Duration {
+        self.refill();
+        if self.tokens >= 0.0 || self.rate == 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BandwidthLimit, NetworkStats};
+
+    #[test]
+    fn transfers_over_the_limit_are_delayed() {
+        let limit = Some(BandwidthLimit {
+            bytes_per_second: 1_000,
+            burst: 1_000,
+        });
+        let mut stats = NetworkStats::default();
+        assert!(stats.delay(limit).is_zero());
+        // The burst is used up, the remaining 2000 bytes take 2 seconds to pay off.
+        stats.record_sent(1_000);
+        stats.record_received(2_000);
+        let delay = stats.delay(limit).as_secs_f64();
+        assert!(delay > 1.9 && delay <= 2.0, "delay was {}", delay);
+        assert_eq!(stats.bytes_sent(), 1_000);
+        assert_eq!(stats.bytes_received(), 2_000);
+    }
+
+    #[test]
+    fn unlimited_is_never_delayed() {
+        let mut stats = NetworkStats::default();
+        stats.record_sent(usize::MAX);
+        assert!(stats.delay(None).is_zero());
+    }
+}
@@ -0,0 +1,96 @@
+//! Compatibility shims for modules built against an older networking API.
+//!
+//! Shims are only registered if they are enabled on the runtime and the module imports the old
+//! signature. They adapt the old call to the current implementation.
+//!
+//! | Host function | Old signature                       | Change                  | Removal |
+//! |---------------|-------------------------------------|-------------------------|---------|
+//! | `tcp_bind`    | `(i32 i32 i32 i32 i32 i32) i32`     | added `backlog` (0.9.x) | 0.11    |
+//! | `tcp_bind`    | `(i32 i32 i32 i32 i32 i32 i32) i32` | added `flags` (0.9.x)   | 0.11    |
+
+use std::future::Future;
+
+use anyhow::Result;
+use lunatic_error_api::ErrorCtx;
+use lunatic_process::state::ProcessState;
+use wasmtime::{Caller, ExternType, Linker, Module, Trap};
+
+use crate::{NetworkingCtx, TCP_BIND_REUSE_ADDRESS};
+
+// Before the flags were added, `SO_REUSEADDR` was always set on unix platforms, like the standard
+// library does.
+#[cfg(unix)]
+const LEGACY_BIND_FLAGS: u32 = TCP_BIND_REUSE_ADDRESS;
+#[cfg(not(unix))]
+const LEGACY_BIND_FLAGS: u32 = 0;
+
+// The shims replace the current host functions with the same name, `linker` must allow shadowing.
+pub fn register_compat<T: ProcessState + NetworkingCtx + ErrorCtx + Send + 'static>(
+    linker: &mut Linker<T>,
+    module: &Module,
+) -> Result<()> {
+    if imports_func(module, "tcp_bind", 6) {
+        linker.func_wrap6_async("lunatic::networking", "tcp_bind", tcp_bind_v0)?;
+    }
+    if imports_func(module, "tcp_bind", 7) {
+        linker.func_wrap7_async("lunatic::networking", "tcp_bind", tcp_bind_v1)?;
+    }
+    Ok(())
+}
+
+// Returns true if the module imports the networking function `name` taking `params` arguments.
+fn imports_func(module: &Module, name: &str, params: usize) -> bool {
+    module.imports().any(|import| {
+        import.module() == "lunatic::networking"
+            && import.name() == name
+            && matches!(import.ty(), ExternType::Func(func) if func.params().len() == params)
+    })
+}
+
+// `tcp_bind` before the backlog parameter was added, uses the default backlog.
+fn tcp_bind_v0<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
+    caller: Caller<T>,
+    addr_type: u32,
+    addr_u8_ptr: u32,
+    port: u32,
+    flow_info: u32,
+    scope_id: u32,
+    id_u64_ptr: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+    crate::tcp_bind(
+        caller,
+        addr_type,
+        addr_u8_ptr,
+        port,
+        flow_info,
+        scope_id,
+        0,
+        LEGACY_BIND_FLAGS,
+        id_u64_ptr,
+    )
+}
+
+// `tcp_bind` before the flags parameter was added.
+#[allow(clippy::too_many_arguments)]
+fn tcp_bind_v1<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
+    caller: Caller<T>,
+    addr_type: u32,
+    addr_u8_ptr: u32,
+    port: u32,
+    flow_info: u32,
+    scope_id: u32,
+    backlog: u32,
+    id_u64_ptr: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+    crate::tcp_bind(
+        caller,
+        addr_type,
+        addr_u8_ptr,
+        port,
+        flow_info,
+        scope_id,
+        backlog,
+        LEGACY_BIND_FLAGS,
+        id_u64_ptr,
+    )
+}
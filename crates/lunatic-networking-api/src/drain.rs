@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+type OnDrained = Box<dyn FnOnce() + Send + Sync>;
+
+/// Tracks the streams accepted on each TCP listener, to notify the process once a draining
+/// listener has no open connections left.
+///
+/// Streams count until they are closed or handed over to another process.
+#[derive(Default)]
+pub struct TcpListenerDrains {
+    // Listener each open accepted stream came from, keyed by the stream resource ID.
+    accepted: HashMap<u64, u64>,
+    // Callbacks of the draining listeners, keyed by the listener resource ID.
+    draining: HashMap<u64, OnDrained>,
+}
+
+impl TcpListenerDrains {
+    /// Records that `stream_id` was accepted on `listener_id`.
+    pub fn accepted(&mut self, listener_id: u64, stream_id: u64) {
+        self.accepted.insert(stream_id, listener_id);
+    }
+
+    /// Records that `clone_id` is a clone of `stream_id`, it keeps the connection open too.
+    pub fn cloned(&mut self, stream_id: u64, clone_id: u64) {
+        if let Some(listener_id) = self.accepted.get(&stream_id).copied() {
+            self.accepted.insert(clone_id, listener_id);
+        }
+    }
+
+    /// Calls `on_drained` once no stream accepted on `listener_id` is open anymore, right away
+    /// if there is none.
+    pub fn drain<F>(&mut self, listener_id: u64, on_drained: F)
+    where
+        F: FnOnce() + Send + Sync + 'static,
+    {
+        if self.open_streams(listener_id) == 0 {
+            on_drained();
+        } else {
+            self.draining.insert(listener_id, Box::new(on_drained));
+        }
+    }
+
+    /// Stops tracking `stream_id`, it was closed or handed over.
+    pub fn stream_closed(&mut self, stream_id: u64) {
+        let listener_id = match self.accepted.remove(&stream_id) {
+            Some(listener_id) => listener_id,
+            None => return,
+        };
+        if self.open_streams(listener_id) == 0 {
+            if let Some(on_drained) = self.draining.remove(&listener_id) {
+                on_drained();
+            }
+        }
+    }
+
+    /// Returns the number of open streams accepted on `listener_id`.
+    pub fn open_streams(&self, listener_id: u64) -> usize {
+        self.accepted
+            .values()
+            .filter(|listener| **listener == listener_id)
+            .count()
+    }
+}
+
+impl Debug for TcpListenerDrains {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpListenerDrains")
+            .field("accepted", &self.accepted)
+            .field("draining", &self.draining.keys())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::TcpListenerDrains;
+
+    #[test]
+    fn drained_once_all_streams_are_closed() {
+        let drained = Arc::new(AtomicUsize::new(0));
+        let on_drained = || {
+            let drained = drained.clone();
+            move || {
+                drained.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        let mut drains = TcpListenerDrains::default();
+        drains.accepted(1, 10);
+        drains.accepted(1, 11);
+        drains.accepted(2, 12);
+        drains.cloned(11, 13);
+        assert_eq!(drains.open_streams(1), 3);
+
+        drains.drain(1, on_drained());
+        drains.stream_closed(10);
+        drains.stream_closed(11);
+        // Another listener's stream doesn't matter.
+        drains.stream_closed(12);
+        assert_eq!(drained.load(Ordering::Relaxed), 0);
+        drains.stream_closed(13);
+        assert_eq!(drained.load(Ordering::Relaxed), 1);
+
+        // Nothing open, drained right away.
+        drains.drain(3, on_drained());
+        assert_eq!(drained.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_std::channel::{bounded, Sender};
+use async_std::net::TcpStream;
+use lunatic_process::executor;
+
+/// Idle timeout configured on a TCP listener.
+///
+/// Accepted connections without any read or write for longer than `timeout` are closed by the
+/// host, and a message with `tag` is sent to the process that accepted them.
+#[derive(Debug, Clone, Copy)]
+pub struct IdleTimeout {
+    pub timeout: Duration,
+    pub tag: i64,
+}
+
+/// Tracks the last read or write on an accepted TCP stream.
+///
+/// Dropping it stops the watchdog, without closing the stream.
+#[derive(Debug)]
+pub struct StreamActivity {
+    last_activity: Arc<Mutex<Instant>>,
+    _stop: Sender<()>,
+}
+
+impl StreamActivity {
+    /// Spawns a watchdog that shuts down the `stream` once it was idle for longer than `timeout`
+    /// and calls `on_idle` afterwards.
+    pub fn watch<F>(stream: TcpStream, timeout: Duration, on_idle: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let last_activity = Arc::new(Mutex::new(Instant::now()));
+        let (stop, stopped) = bounded::<()>(1);
+        let watched = last_activity.clone();
+        executor::spawn(async move {
+            loop {
+                let deadline = *watched.lock().expect("never poisoned") + timeout;
+                let now = Instant::now();
+                if deadline <= now {
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
+                    on_idle();
+                    return;
+                }
+                tokio::select! {
+                    _ = executor::sleep(deadline - now) => {},
+                    // The stream resource was dropped.
+                    _ = stopped.recv() => return,
+                }
+            }
+        });
+        Self {
+            last_activity,
+            _stop: stop,
+        }
+    }
+
+    /// Marks the stream as active.
+    pub fn touch(&self) {
+        *self.last_activity.lock().expect("never poisoned") = Instant::now();
+    }
+}
@@ -1,18 +1,39 @@
+pub mod accept_filter;
+pub mod backlog;
+pub mod bandwidth;
+pub mod compat;
 pub mod dns;
+pub mod drain;
+pub mod idle;
+pub mod multicast;
+pub mod outbound;
 
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::future::Future;
 use std::io::IoSlice;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::sync::Arc;
 use std::time::Duration;
 
+use accept_filter::AcceptFilter;
 use anyhow::Result;
 use async_std::io::{ReadExt, WriteExt};
 use async_std::net::{TcpListener, TcpStream, UdpSocket};
+use bandwidth::{BandwidthLimit, NetworkStats};
 use dns::DnsIterator;
+use drain::TcpListenerDrains;
 use hash_map_id::HashMapId;
+use idle::{IdleTimeout, StreamActivity};
 use lunatic_error_api::ErrorCtx;
+use lunatic_process::executor;
+use lunatic_process::fuel::{charge_host_call, HostCallCategory};
+use lunatic_process::message::{DataMessage, Message};
+use lunatic_process::state::ProcessState;
+use lunatic_process::Signal;
+use multicast::MulticastGroup;
+pub use outbound::OutboundTcpStreams;
+use socket2::{Domain, Protocol, Socket, Type};
 use wasmtime::{Caller, Linker};
 use wasmtime::{Memory, Trap};
 
@@ -22,22 +43,61 @@ pub type TcpListenerResources = HashMapId<TcpListener>;
 pub type TcpStreamResources = HashMapId<TcpStream>;
 pub type UdpResources = HashMapId<Arc<UdpSocket>>;
 pub type DnsResources = HashMapId<DnsIterator>;
+// Multicast groups joined through a UDP socket, keyed by the socket resource ID.
+pub type UdpMulticastGroups = HashMap<u64, Vec<MulticastGroup>>;
+// Source address filters of TCP listeners, keyed by the listener resource ID.
+pub type TcpListenerFilters = HashMap<u64, AcceptFilter>;
+// Idle timeouts of TCP listeners, keyed by the listener resource ID.
+pub type TcpListenerIdleTimeouts = HashMap<u64, IdleTimeout>;
+// Activity of accepted TCP streams with an idle timeout, keyed by the stream resource ID.
+pub type TcpStreamActivity = HashMap<u64, StreamActivity>;
+// Directions of TCP streams shut down with `tcp_shutdown`, keyed by the stream resource ID.
+pub type TcpStreamShutdowns = HashMap<u64, Shutdown>;
+
+pub trait NetworkingConfigCtx {
+    fn bandwidth_limit(&self) -> Option<BandwidthLimit>;
+    fn set_bandwidth_limit(&mut self, limit: Option<BandwidthLimit>);
+    fn max_outbound_connections(&self) -> Option<usize>;
+    fn set_max_outbound_connections(&mut self, max: Option<usize>);
+    /// Longest linger processes can set with `tcp_set_linger`, [`DEFAULT_MAX_TCP_LINGER`] by
+    /// default.
+    fn max_tcp_linger(&self) -> Duration;
+    fn set_max_tcp_linger(&mut self, max: Duration);
+}
 
 pub trait NetworkingCtx {
     fn tcp_listener_resources(&self) -> &TcpListenerResources;
     fn tcp_listener_resources_mut(&mut self) -> &mut TcpListenerResources;
+    fn tcp_listener_filters(&self) -> &TcpListenerFilters;
+    fn tcp_listener_filters_mut(&mut self) -> &mut TcpListenerFilters;
+    fn tcp_listener_idle_timeouts(&self) -> &TcpListenerIdleTimeouts;
+    fn tcp_listener_idle_timeouts_mut(&mut self) -> &mut TcpListenerIdleTimeouts;
+    fn tcp_stream_activity(&self) -> &TcpStreamActivity;
+    fn tcp_stream_activity_mut(&mut self) -> &mut TcpStreamActivity;
     fn tcp_stream_resources(&self) -> &TcpStreamResources;
     fn tcp_stream_resources_mut(&mut self) -> &mut TcpStreamResources;
+    fn outbound_tcp_streams(&self) -> &OutboundTcpStreams;
+    fn outbound_tcp_streams_mut(&mut self) -> &mut OutboundTcpStreams;
+    fn tcp_stream_shutdowns(&self) -> &TcpStreamShutdowns;
+    fn tcp_stream_shutdowns_mut(&mut self) -> &mut TcpStreamShutdowns;
+    fn tcp_listener_drains(&self) -> &TcpListenerDrains;
+    fn tcp_listener_drains_mut(&mut self) -> &mut TcpListenerDrains;
     fn udp_resources(&self) -> &UdpResources;
     fn udp_resources_mut(&mut self) -> &mut UdpResources;
     fn dns_resources(&self) -> &DnsResources;
     fn dns_resources_mut(&mut self) -> &mut DnsResources;
+    fn udp_multicast_groups(&self) -> &UdpMulticastGroups;
+    fn udp_multicast_groups_mut(&mut self) -> &mut UdpMulticastGroups;
+    fn network_stats(&self) -> &NetworkStats;
+    fn network_stats_mut(&mut self) -> &mut NetworkStats;
 }
 
 // Register the error APIs to the linker
-pub fn register<T: NetworkingCtx + ErrorCtx + Send + 'static>(
-    linker: &mut Linker<T>,
-) -> Result<()> {
+pub fn register<T>(linker: &mut Linker<T>) -> Result<()>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send + 'static,
+    T::Config: NetworkingConfigCtx,
+{
     linker.func_wrap4_async("lunatic::networking", "resolve", resolve)?;
     linker.func_wrap(
         "lunatic::networking",
@@ -45,15 +105,46 @@ pub fn register<T: NetworkingCtx + ErrorCtx + Send + 'static>(
         drop_dns_iterator,
     )?;
     linker.func_wrap("lunatic::networking", "resolve_next", resolve_next)?;
-    linker.func_wrap6_async("lunatic::networking", "tcp_bind", tcp_bind)?;
+    linker.func_wrap8_async("lunatic::networking", "tcp_bind", tcp_bind)?;
     linker.func_wrap(
         "lunatic::networking",
         "drop_tcp_listener",
         drop_tcp_listener,
     )?;
     linker.func_wrap("lunatic::networking", "tcp_local_addr", tcp_local_addr)?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "tcp_listener_queue_depth",
+        tcp_listener_queue_depth,
+    )?;
     linker.func_wrap("lunatic::networking", "udp_local_addr", udp_local_addr)?;
+    linker.func_wrap("lunatic::networking", "tcp_peer_addr", tcp_peer_addr)?;
     linker.func_wrap3_async("lunatic::networking", "tcp_accept", tcp_accept)?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "tcp_listener_allow",
+        tcp_listener_allow,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "tcp_listener_clear_allowlist",
+        tcp_listener_clear_allowlist,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "tcp_listener_rejected_count",
+        tcp_listener_rejected_count,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "tcp_listener_set_idle_timeout",
+        tcp_listener_set_idle_timeout,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "tcp_listener_drain",
+        tcp_listener_drain,
+    )?;
     linker.func_wrap7_async("lunatic::networking", "tcp_connect", tcp_connect)?;
     linker.func_wrap("lunatic::networking", "drop_tcp_stream", drop_tcp_stream)?;
     linker.func_wrap("lunatic::networking", "clone_tcp_stream", clone_tcp_stream)?;
@@ -63,7 +154,12 @@ pub fn register<T: NetworkingCtx + ErrorCtx + Send + 'static>(
         tcp_write_vectored,
     )?;
     linker.func_wrap5_async("lunatic::networking", "tcp_read", tcp_read)?;
+    linker.func_wrap6_async("lunatic::networking", "tcp_read_exact", tcp_read_exact)?;
     linker.func_wrap2_async("lunatic::networking", "tcp_flush", tcp_flush)?;
+    linker.func_wrap("lunatic::networking", "tcp_shutdown", tcp_shutdown)?;
+    linker.func_wrap("lunatic::networking", "tcp_set_linger", tcp_set_linger)?;
+    linker.func_wrap2_async("lunatic::networking", "stream_flush", tcp_flush)?;
+    linker.func_wrap2_async("lunatic::networking", "stream_close", stream_close)?;
     linker.func_wrap6_async("lunatic::networking", "udp_bind", udp_bind)?;
     linker.func_wrap("lunatic::networking", "drop_udp_socket", drop_udp_socket)?;
     linker.func_wrap5_async("lunatic::networking", "udp_receive", udp_receive)?;
@@ -90,8 +186,75 @@ pub fn register<T: NetworkingCtx + ErrorCtx + Send + 'static>(
         "get_udp_socket_ttl",
         get_udp_socket_ttl,
     )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "udp_join_multicast_v4",
+        udp_join_multicast_v4,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "udp_leave_multicast_v4",
+        udp_leave_multicast_v4,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "udp_join_multicast_v6",
+        udp_join_multicast_v6,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "udp_leave_multicast_v6",
+        udp_leave_multicast_v6,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "set_udp_socket_multicast_ttl_v4",
+        set_udp_socket_multicast_ttl_v4,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "get_udp_socket_multicast_ttl_v4",
+        get_udp_socket_multicast_ttl_v4,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "set_udp_socket_multicast_loop",
+        set_udp_socket_multicast_loop,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "get_udp_socket_multicast_loop",
+        get_udp_socket_multicast_loop,
+    )?;
     linker.func_wrap10_async("lunatic::networking", "udp_send_to", udp_send_to)?;
     linker.func_wrap5_async("lunatic::networking", "udp_send", udp_send)?;
+    linker.func_wrap("lunatic::networking", "bytes_sent", bytes_sent)?;
+    linker.func_wrap("lunatic::networking", "bytes_received", bytes_received)?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "config_set_bandwidth_limit",
+        config_set_bandwidth_limit,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "config_get_bandwidth_limit",
+        config_get_bandwidth_limit,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "config_set_max_outbound_connections",
+        config_set_max_outbound_connections,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "config_get_max_outbound_connections",
+        config_get_max_outbound_connections,
+    )?;
+    linker.func_wrap(
+        "lunatic::networking",
+        "config_get_bandwidth_burst",
+        config_get_bandwidth_burst,
+    )?;
 
     Ok(())
 }
@@ -107,7 +270,7 @@ pub fn register<T: NetworkingCtx + ErrorCtx + Send + 'static>(
 // Traps:
 // * If the name is not a valid utf8 string.
 // * If any memory outside the guest heap space is referenced.
-fn resolve<T: NetworkingCtx + ErrorCtx + Send>(
+fn resolve<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
     mut caller: Caller<T>,
     name_str_ptr: u32,
     name_str_len: u32,
@@ -115,6 +278,7 @@ fn resolve<T: NetworkingCtx + ErrorCtx + Send>(
     id_u64_ptr: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
         let mut buffer = vec![0; name_str_len as usize];
         let memory = get_memory(&mut caller)?;
         memory
@@ -161,10 +325,11 @@ fn resolve<T: NetworkingCtx + ErrorCtx + Send>(
 //
 // Traps:
 // * If the DNS iterator ID doesn't exist.
-fn drop_dns_iterator<T: NetworkingCtx>(
+fn drop_dns_iterator<T: ProcessState + NetworkingCtx>(
     mut caller: Caller<T>,
     dns_iter_id: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     caller
         .data_mut()
         .dns_resources_mut()
@@ -186,7 +351,7 @@ fn drop_dns_iterator<T: NetworkingCtx>(
 // Traps:
 // * If the DNS iterator ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn resolve_next<T: NetworkingCtx>(
+fn resolve_next<T: ProcessState + NetworkingCtx>(
     mut caller: Caller<T>,
     dns_iter_id: u64,
     addr_type_u32_ptr: u32,
@@ -195,6 +360,7 @@ fn resolve_next<T: NetworkingCtx>(
     flow_info_u32_ptr: u32,
     scope_id_u32_ptr: u32,
 ) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     let memory = get_memory(&mut caller)?;
     let dns_iter = caller
         .data_mut()
@@ -254,22 +420,42 @@ fn resolve_next<T: NetworkingCtx>(
 // Binding with a port number of 0 will request that the OS assigns a port to this listener. The
 // port allocated can be queried via the `tcp_local_addr` (TODO) method.
 //
+// **backlog** is the maximum number of pending connections waiting to be accepted. If it's 0 a
+// default of 128 is used. Values above the platform maximum are clamped to it.
+//
+// **flags** set socket options before binding:
+// * `TCP_BIND_REUSE_ADDRESS` (1) - `SO_REUSEADDR`, allows binding to a port of a listener that
+//   was just closed and still has connections in the `TIME_WAIT` state. Useful for restarting
+//   servers. On Windows it also allows taking over a port another socket is listening on.
+// * `TCP_BIND_REUSE_PORT` (2) - `SO_REUSEPORT`, allows multiple listeners (e.g. one per process)
+//   to bind the same address and port, if all of them set it. On Linux the kernel balances
+//   incoming connections between them. Only supported on unix platforms other than Solaris and
+//   illumos, elsewhere the bind fails with an error.
+//
 // Returns:
 // * 0 on success - The ID of the newly created TCP listener is written to **id_u64_ptr**
 // * 1 on error   - The error ID is written to **id_u64_ptr**
 //
 // Traps:
 // * If any memory outside the guest heap space is referenced.
-fn tcp_bind<T: NetworkingCtx + ErrorCtx + Send>(
+// * If **flags** contains unknown bits.
+#[allow(clippy::too_many_arguments)]
+fn tcp_bind<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
     mut caller: Caller<T>,
     addr_type: u32,
     addr_u8_ptr: u32,
     port: u32,
     flow_info: u32,
     scope_id: u32,
+    backlog: u32,
+    flags: u32,
     id_u64_ptr: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        if flags & !(TCP_BIND_REUSE_ADDRESS | TCP_BIND_REUSE_PORT) != 0 {
+            return Err(Trap::new("lunatic::networking::tcp_bind: Unknown flags"));
+        }
         let memory = get_memory(&mut caller)?;
         let socket_addr = socket_address(
             &caller,
@@ -280,7 +466,7 @@ fn tcp_bind<T: NetworkingCtx + ErrorCtx + Send>(
             flow_info,
             scope_id,
         )?;
-        let (tcp_listener_or_error_id, result) = match TcpListener::bind(socket_addr).await {
+        let (tcp_listener_or_error_id, result) = match bind_listener(socket_addr, backlog, flags) {
             Ok(listener) => (
                 caller.data_mut().tcp_listener_resources_mut().add(listener),
                 0,
@@ -299,22 +485,83 @@ fn tcp_bind<T: NetworkingCtx + ErrorCtx + Send>(
     })
 }
 
+/// `tcp_bind` flag setting `SO_REUSEADDR`.
+pub const TCP_BIND_REUSE_ADDRESS: u32 = 1;
+/// `tcp_bind` flag setting `SO_REUSEPORT`, only supported on some platforms.
+pub const TCP_BIND_REUSE_PORT: u32 = 2;
+
+// Backlog used if the guest doesn't specify one, same as the standard library uses.
+const DEFAULT_BACKLOG: u32 = 128;
+
+fn bind_listener(addr: SocketAddr, backlog: u32, flags: u32) -> std::io::Result<TcpListener> {
+    let backlog = match backlog {
+        0 => DEFAULT_BACKLOG,
+        backlog => backlog.min(max_backlog()),
+    };
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if flags & TCP_BIND_REUSE_ADDRESS != 0 {
+        socket.set_reuse_address(true)?;
+    }
+    if flags & TCP_BIND_REUSE_PORT != 0 {
+        set_reuse_port(&socket)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(backlog as i32)?;
+    socket.set_nonblocking(true)?;
+    Ok(std::net::TcpListener::from(socket).into())
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(socket: &Socket) -> std::io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_socket: &Socket) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+// The OS silently caps the backlog, but the value is still bounded by `c_int`.
+fn max_backlog() -> u32 {
+    #[cfg(target_os = "linux")]
+    if let Some(somaxconn) = std::fs::read_to_string("/proc/sys/net/core/somaxconn")
+        .ok()
+        .and_then(|value| value.trim().parse::<u32>().ok())
+    {
+        return somaxconn.min(i32::MAX as u32);
+    }
+    i32::MAX as u32
+}
+
 // Drops the TCP listener resource.
 //
 // Traps:
 // * If the TCP listener ID doesn't exist.
-fn drop_tcp_listener<T: NetworkingCtx>(
+fn drop_tcp_listener<T: ProcessState + NetworkingCtx>(
     mut caller: Caller<T>,
     tcp_listener_id: u64,
 ) -> Result<(), Trap> {
-    caller
-        .data_mut()
-        .tcp_listener_resources_mut()
-        .remove(tcp_listener_id)
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    close_tcp_listener(caller.data_mut(), tcp_listener_id)
         .or_trap("lunatic::networking::drop_tcp_listener")?;
     Ok(())
 }
 
+/// Removes the TCP listener and its settings from the resources of `state`.
+///
+/// Returns `None` if the TCP listener ID doesn't exist.
+pub fn close_tcp_listener<T: NetworkingCtx>(state: &mut T, tcp_listener_id: u64) -> Option<()> {
+    state.tcp_listener_resources_mut().remove(tcp_listener_id)?;
+    state.tcp_listener_filters_mut().remove(&tcp_listener_id);
+    state
+        .tcp_listener_idle_timeouts_mut()
+        .remove(&tcp_listener_id);
+    Some(())
+}
+
 // Returns the local address that this listener is bound to as an DNS iterator with just one
 // element.
 // * 0 on success - The local address that this listener is bound to is returned as an DNS
@@ -325,11 +572,12 @@ fn drop_tcp_listener<T: NetworkingCtx>(
 // Traps:
 // * If the tcp listener ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn tcp_local_addr<T: NetworkingCtx + ErrorCtx>(
+fn tcp_local_addr<T: ProcessState + NetworkingCtx + ErrorCtx>(
     mut caller: Caller<T>,
     tcp_listener_id: u64,
     id_u64_ptr: u32,
 ) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     let tcp_listener = caller
         .data()
         .tcp_listener_resources()
@@ -358,6 +606,102 @@ fn tcp_local_addr<T: NetworkingCtx + ErrorCtx>(
     Ok(result)
 }
 
+// Returns the number of connections waiting in the accept queue of the listener.
+//
+// The depth is exact on Linux. On other unix platforms it's only an estimate, 1 if at least one
+// connection is waiting and 0 if none is. Other platforms always return an error.
+//
+// Returns:
+// * 0 on success - The depth is written to **depth_or_error_id_u64_ptr**
+// * 1 on error   - The error ID is written to **depth_or_error_id_u64_ptr**
+//
+// Traps:
+// * If the tcp listener ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn tcp_listener_queue_depth<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    tcp_listener_id: u64,
+    depth_or_error_id_u64_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let tcp_listener = caller
+        .data()
+        .tcp_listener_resources()
+        .get(tcp_listener_id)
+        .or_trap("lunatic::network::tcp_listener_queue_depth: listener ID doesn't exist")?;
+    let (depth_or_error_id, result) = match backlog::accept_queue_depth(tcp_listener) {
+        Ok(depth) => (depth, 0),
+        Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
+    };
+
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(
+            &mut caller,
+            depth_or_error_id_u64_ptr as usize,
+            &depth_or_error_id.to_le_bytes(),
+        )
+        .or_trap("lunatic::network::tcp_listener_queue_depth")?;
+
+    Ok(result)
+}
+
+// Returns the address of the remote peer of this TCP stream as an DNS iterator with just one
+// element.
+//
+// Works for accepted and connected streams, also if the stream was received in a message from
+// another process.
+//
+// Returns:
+// * 0 on success - The peer address is returned as an DNS iterator with just one element and
+//                  written to **id_u64_ptr**.
+// * 1 on error   - The error ID is written to **id_u64_ptr**, e.g. if the peer disconnected.
+//
+// Traps:
+// * If the tcp stream ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn tcp_peer_addr<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    tcp_stream_id: u64,
+    id_u64_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let tcp_stream = caller
+        .data()
+        .tcp_stream_resources()
+        .get(tcp_stream_id)
+        .or_trap("lunatic::network::tcp_peer_addr: stream ID doesn't exist")?;
+    let (dns_iter_or_error_id, result) = match tcp_stream.peer_addr() {
+        Ok(socket_addr) => {
+            let dns_iter_id = caller
+                .data_mut()
+                .dns_resources_mut()
+                .add(DnsIterator::new(vec![socket_addr].into_iter()));
+            (dns_iter_id, 0)
+        }
+        Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
+    };
+
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(
+            &mut caller,
+            id_u64_ptr as usize,
+            &dns_iter_or_error_id.to_le_bytes(),
+        )
+        .or_trap("lunatic::network::tcp_peer_addr")?;
+
+    Ok(result)
+}
+
+// Accepts a new connection on the listener.
+//
+// If an allowlist was set up for the listener with `tcp_listener_allow`, connections coming from
+// other addresses are closed by the host and the call keeps waiting for the next one.
+//
+// If an idle timeout was set with `tcp_listener_set_idle_timeout`, the accepted stream is closed
+// by the host once it was idle for too long.
+//
 // Returns:
 // * 0 on success - The ID of the newly created TCP stream is written to **id_u64_ptr** and the
 //                  peer address is returned as an DNS iterator with just one element and written
@@ -367,22 +711,64 @@ fn tcp_local_addr<T: NetworkingCtx + ErrorCtx>(
 // Traps:
 // * If the tcp listener ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn tcp_accept<T: NetworkingCtx + ErrorCtx + Send>(
+fn tcp_accept<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
     mut caller: Caller<T>,
     listener_id: u64,
     id_u64_ptr: u32,
     socket_addr_id_ptr: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
-        let tcp_listener = caller
-            .data()
-            .tcp_listener_resources()
-            .get(listener_id)
-            .or_trap("lunatic::network::tcp_accept")?;
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        let accepted = loop {
+            let tcp_listener = caller
+                .data()
+                .tcp_listener_resources()
+                .get(listener_id)
+                .or_trap("lunatic::network::tcp_accept")?;
+            let (stream, socket_addr) = match tcp_listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(error) => break Err(error),
+            };
+            if let Some(filter) = caller
+                .data_mut()
+                .tcp_listener_filters_mut()
+                .get_mut(&listener_id)
+            {
+                if !filter.is_allowed(socket_addr.ip()) {
+                    filter.record_rejected();
+                    // Dropping the stream closes the connection.
+                    continue;
+                }
+            }
+            break Ok((stream, socket_addr));
+        };
 
-        let (tcp_stream_or_error_id, peer_addr_iter, result) = match tcp_listener.accept().await {
+        let (tcp_stream_or_error_id, peer_addr_iter, result) = match accepted {
             Ok((stream, socket_addr)) => {
+                let watched_stream = stream.clone();
                 let stream_id = caller.data_mut().tcp_stream_resources_mut().add(stream);
+                caller
+                    .data_mut()
+                    .tcp_listener_drains_mut()
+                    .accepted(listener_id, stream_id);
+                let idle_timeout = caller
+                    .data()
+                    .tcp_listener_idle_timeouts()
+                    .get(&listener_id)
+                    .copied();
+                if let Some(IdleTimeout { timeout, tag }) = idle_timeout {
+                    let this = caller.data().signal_mailbox().0.clone();
+                    let activity = StreamActivity::watch(watched_stream, timeout, move || {
+                        // Notify the process which stream was closed.
+                        let mut message = DataMessage::new(Some(tag), 8);
+                        message.buffer.extend_from_slice(&stream_id.to_le_bytes());
+                        let _ = this.try_send(Signal::Message(Message::Data(message)));
+                    });
+                    caller
+                        .data_mut()
+                        .tcp_stream_activity_mut()
+                        .insert(stream_id, activity);
+                }
                 let dns_iter_id = caller
                     .data_mut()
                     .dns_resources_mut()
@@ -415,6 +801,163 @@ fn tcp_accept<T: NetworkingCtx + ErrorCtx + Send>(
     })
 }
 
+// Adds the network **addr_u8_ptr**/**prefix_len** to the allowlist of the listener. Once the
+// allowlist contains at least one network, `tcp_accept` only returns connections coming from
+// addresses inside of it.
+//
+// Traps:
+// * If the tcp listener ID doesn't exist.
+// * If **addr_type** is neither 4 or 6.
+// * If **prefix_len** is bigger than the address length in bits.
+// * If any memory outside the guest heap space is referenced.
+fn tcp_listener_allow<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
+    listener_id: u64,
+    addr_type: u32,
+    addr_u8_ptr: u32,
+    prefix_len: u32,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    caller
+        .data()
+        .tcp_listener_resources()
+        .get(listener_id)
+        .or_trap("lunatic::networking::tcp_listener_allow")?;
+    let memory = get_memory(&mut caller)?;
+    let addr = socket_address(&caller, &memory, addr_type, addr_u8_ptr, 0, 0, 0)?.ip();
+    let allowed = caller
+        .data_mut()
+        .tcp_listener_filters_mut()
+        .entry(listener_id)
+        .or_default()
+        .allow(addr, prefix_len.try_into().unwrap_or(u8::MAX));
+    if !allowed {
+        return Err(Trap::new(
+            "lunatic::networking::tcp_listener_allow: prefix length too big",
+        ));
+    }
+    Ok(())
+}
+
+// Removes all networks from the allowlist of the listener, accepting connections from all
+// addresses again.
+//
+// Traps:
+// * If the tcp listener ID doesn't exist.
+fn tcp_listener_clear_allowlist<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
+    listener_id: u64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    caller
+        .data()
+        .tcp_listener_resources()
+        .get(listener_id)
+        .or_trap("lunatic::networking::tcp_listener_clear_allowlist")?;
+    if let Some(filter) = caller
+        .data_mut()
+        .tcp_listener_filters_mut()
+        .get_mut(&listener_id)
+    {
+        filter.clear();
+    }
+    Ok(())
+}
+
+// Returns the number of connections that were closed by the host because they didn't match the
+// allowlist of the listener.
+//
+// Traps:
+// * If the tcp listener ID doesn't exist.
+fn tcp_listener_rejected_count<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
+    listener_id: u64,
+) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    caller
+        .data()
+        .tcp_listener_resources()
+        .get(listener_id)
+        .or_trap("lunatic::networking::tcp_listener_rejected_count")?;
+    let rejected = caller
+        .data()
+        .tcp_listener_filters()
+        .get(&listener_id)
+        .map(|filter| filter.rejected())
+        .unwrap_or(0);
+    Ok(rejected)
+}
+
+// Sets the idle timeout for connections accepted on the listener from now on. If a connection
+// has no reads or writes for longer than **timeout_ms**, it's closed by the host and a message
+// with **tag** is sent to the process. The message contains the ID of the closed stream as a
+// little-endian u64. The stream ID stays valid until it's dropped, but all reads and writes will
+// fail.
+//
+// Streams that are sent to another process are not tracked anymore. A **timeout_ms** of 0
+// disables the idle timeout, which is the default.
+//
+// Traps:
+// * If the tcp listener ID doesn't exist.
+fn tcp_listener_set_idle_timeout<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
+    listener_id: u64,
+    timeout_ms: u64,
+    tag: i64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    caller
+        .data()
+        .tcp_listener_resources()
+        .get(listener_id)
+        .or_trap("lunatic::networking::tcp_listener_set_idle_timeout")?;
+    let idle_timeouts = caller.data_mut().tcp_listener_idle_timeouts_mut();
+    if timeout_ms == 0 {
+        idle_timeouts.remove(&listener_id);
+    } else {
+        let timeout = Duration::from_millis(timeout_ms);
+        idle_timeouts.insert(listener_id, IdleTimeout { timeout, tag });
+    }
+    Ok(())
+}
+
+// Stops accepting connections on the listener and sends a message with **tag** to the process
+// once all connections accepted on it are closed. The message contains the listener ID as a
+// little-endian u64.
+//
+// The listener is closed right away and its ID becomes invalid. Connection attempts from now on
+// are refused by the OS and connections waiting in the backlog are reset, they are never queued.
+// Accepted connections count until they are dropped, closed with `stream_close` or sent to
+// another process. If none is open, the message is sent right away.
+//
+// Traps:
+// * If the tcp listener ID doesn't exist.
+fn tcp_listener_drain<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
+    listener_id: u64,
+    tag: i64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    close_tcp_listener(caller.data_mut(), listener_id)
+        .or_trap("lunatic::networking::tcp_listener_drain")?;
+    let this = caller.data().signal_mailbox().0.clone();
+    caller
+        .data_mut()
+        .tcp_listener_drains_mut()
+        .drain(listener_id, move || {
+            let mut message = DataMessage::new(Some(tag), 8);
+            message.buffer.extend_from_slice(&listener_id.to_le_bytes());
+            let _ = this.try_send(Signal::Message(Message::Data(message)));
+        });
+    Ok(())
+}
+
+// If the process already has as many open outbound connections as its configuration allows
+// (see `config_set_max_outbound_connections`), no connection is attempted and an error is
+// returned. A connection counts once, no matter how many clones of the stream exist, until the
+// last of them is dropped. Streams sent to other processes, including the process itself, still
+// count until then. Failed connection attempts don't count.
+//
 // Returns:
 // * 0 on success - The ID of the newly created TCP stream is written to **id_ptr**.
 // * 1 on error   - The error ID is written to **id_ptr**
@@ -423,7 +966,7 @@ fn tcp_accept<T: NetworkingCtx + ErrorCtx + Send>(
 // * If **addr_type** is neither 4 or 6.
 // * If any memory outside the guest heap space is referenced.
 #[allow(clippy::too_many_arguments)]
-fn tcp_connect<T: NetworkingCtx + ErrorCtx + Send>(
+fn tcp_connect<T>(
     mut caller: Caller<T>,
     addr_type: u32,
     addr_u8_ptr: u32,
@@ -432,8 +975,13 @@ fn tcp_connect<T: NetworkingCtx + ErrorCtx + Send>(
     scope_id: u32,
     timeout: u32,
     id_u64_ptr: u32,
-) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send,
+    T::Config: NetworkingConfigCtx,
+{
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
         let memory = get_memory(&mut caller)?;
         let socket_addr = socket_address(
             &caller,
@@ -445,12 +993,35 @@ fn tcp_connect<T: NetworkingCtx + ErrorCtx + Send>(
             scope_id,
         )?;
 
+        if let Some(max) = caller.data().config().max_outbound_connections() {
+            if caller
+                .data_mut()
+                .outbound_tcp_streams_mut()
+                .open_connections()
+                >= max
+            {
+                let error = anyhow::anyhow!(
+                    "Resource exhausted: the process already has {} outbound connections open",
+                    max
+                );
+                let error_id = caller.data_mut().error_resources_mut().add(error);
+                memory
+                    .write(&mut caller, id_u64_ptr as usize, &error_id.to_le_bytes())
+                    .or_trap("lunatic::networking::tcp_connect")?;
+                return Ok(1);
+            }
+        }
+
         if let Some(result) = tokio::select! {
             _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
             result = TcpStream::connect(socket_addr) => Some(result)
         } {
             let (stream_or_error_id, result) = match result {
-                Ok(stream) => (caller.data_mut().tcp_stream_resources_mut().add(stream), 0),
+                Ok(stream) => {
+                    let id = caller.data_mut().tcp_stream_resources_mut().add(stream);
+                    caller.data_mut().outbound_tcp_streams_mut().connected(id);
+                    (id, 0)
+                }
                 Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
             };
 
@@ -473,26 +1044,41 @@ fn tcp_connect<T: NetworkingCtx + ErrorCtx + Send>(
 //
 // Traps:
 // * If the DNS iterator ID doesn't exist.
-fn drop_tcp_stream<T: NetworkingCtx>(
+fn drop_tcp_stream<T: ProcessState + NetworkingCtx>(
     mut caller: Caller<T>,
     tcp_stream_id: u64,
 ) -> Result<(), Trap> {
-    caller
-        .data_mut()
-        .tcp_stream_resources_mut()
-        .remove(tcp_stream_id)
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    close_tcp_stream(caller.data_mut(), tcp_stream_id)
         .or_trap("lunatic::networking::drop_tcp_stream")?;
     Ok(())
 }
 
-// Clones a TCP stream returning the ID of the clone.
+/// Removes the TCP stream from the resources of `state`.
+///
+/// Host functions operating on the stream work on a clone of it, so a read or write in progress
+/// finishes normally. The connection is closed once the last clone is dropped.
+///
+/// Returns `None` if the TCP stream ID doesn't exist.
+pub fn close_tcp_stream<T: NetworkingCtx>(state: &mut T, tcp_stream_id: u64) -> Option<()> {
+    state.tcp_stream_resources_mut().remove(tcp_stream_id)?;
+    state.tcp_stream_activity_mut().remove(&tcp_stream_id);
+    state.outbound_tcp_streams_mut().remove(tcp_stream_id);
+    state.tcp_stream_shutdowns_mut().remove(&tcp_stream_id);
+    state.tcp_listener_drains_mut().stream_closed(tcp_stream_id);
+    Some(())
+}
+
+// Clones a TCP stream returning the ID of the clone. The clone refers to the same connection, it
+// doesn't count again towards the outbound connections.
 //
 // Traps:
 // * If the stream ID doesn't exist.
-fn clone_tcp_stream<T: NetworkingCtx>(
+fn clone_tcp_stream<T: ProcessState + NetworkingCtx>(
     mut caller: Caller<T>,
     tcp_stream_id: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     let stream = caller
         .data()
         .tcp_stream_resources()
@@ -500,6 +1086,14 @@ fn clone_tcp_stream<T: NetworkingCtx>(
         .or_trap("lunatic::networking::clone_process")?
         .clone();
     let id = caller.data_mut().tcp_stream_resources_mut().add(stream);
+    if let Some(tag) = caller.data().outbound_tcp_streams().get(tcp_stream_id) {
+        let tag = tag.clone();
+        caller.data_mut().outbound_tcp_streams_mut().insert(id, tag);
+    }
+    caller
+        .data_mut()
+        .tcp_listener_drains_mut()
+        .cloned(tcp_stream_id, id);
     Ok(id)
 }
 
@@ -513,15 +1107,29 @@ fn clone_tcp_stream<T: NetworkingCtx>(
 // Traps:
 // * If the stream ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn tcp_write_vectored<T: NetworkingCtx + ErrorCtx + Send>(
+fn tcp_write_vectored<T>(
     mut caller: Caller<T>,
     stream_id: u64,
     ciovec_array_ptr: u32,
     ciovec_array_len: u32,
     timeout: u32,
     opaque_ptr: u32,
-) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send,
+    T::Config: NetworkingConfigCtx,
+{
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        if let Err(error) = check_shutdown(caller.data(), stream_id, Shutdown::Write) {
+            let error_id = caller.data_mut().error_resources_mut().add(error.into());
+            let memory = get_memory(&mut caller)?;
+            memory
+                .write(&mut caller, opaque_ptr as usize, &error_id.to_le_bytes())
+                .or_trap("lunatic::networking::tcp_write_vectored")?;
+            return Ok(1);
+        }
+        let delay = bandwidth_delay(&mut caller);
         let memory = get_memory(&mut caller)?;
         let buffer = memory
             .data(&caller)
@@ -555,10 +1163,16 @@ fn tcp_write_vectored<T: NetworkingCtx + ErrorCtx + Send>(
         // Check for timeout
         if let Some(result) = tokio::select! {
             _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
-            result = stream.write_vectored(vec_slices.as_slice()) => Some(result)
+            result = throttled(delay, stream.write_vectored(vec_slices.as_slice())) => Some(result)
         } {
+            if result.is_ok() {
+                touch_stream(&caller, stream_id);
+            }
             let (opaque, return_) = match result {
-                Ok(bytes) => (bytes as u64, 0),
+                Ok(bytes) => {
+                    caller.data_mut().network_stats_mut().record_sent(bytes);
+                    (bytes as u64, 0)
+                }
                 Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
             };
 
@@ -584,21 +1198,35 @@ fn tcp_write_vectored<T: NetworkingCtx + ErrorCtx + Send>(
 // Traps:
 // * If the stream ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn tcp_read<T: NetworkingCtx + ErrorCtx + Send>(
+fn tcp_read<T>(
     mut caller: Caller<T>,
     stream_id: u64,
     buffer_ptr: u32,
     buffer_len: u32,
     timeout: u32,
     opaque_ptr: u32,
-) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send,
+    T::Config: NetworkingConfigCtx,
+{
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        let delay = bandwidth_delay(&mut caller);
         let mut stream = caller
             .data()
             .tcp_stream_resources()
             .get(stream_id)
             .or_trap("lunatic::network::tcp_read")?
             .clone();
+        if let Err(error) = check_shutdown(caller.data(), stream_id, Shutdown::Read) {
+            let error_id = caller.data_mut().error_resources_mut().add(error.into());
+            let memory = get_memory(&mut caller)?;
+            memory
+                .write(&mut caller, opaque_ptr as usize, &error_id.to_le_bytes())
+                .or_trap("lunatic::networking::tcp_read")?;
+            return Ok(1);
+        }
 
         let memory = get_memory(&mut caller)?;
         let buffer = memory
@@ -609,10 +1237,16 @@ fn tcp_read<T: NetworkingCtx + ErrorCtx + Send>(
         // Check for timeout first
         if let Some(result) = tokio::select! {
             _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
-            result = stream.read(buffer) => Some(result)
+            result = throttled(delay, stream.read(buffer)) => Some(result)
         } {
+            if result.is_ok() {
+                touch_stream(&caller, stream_id);
+            }
             let (opaque, return_) = match result {
-                Ok(bytes) => (bytes as u64, 0),
+                Ok(bytes) => {
+                    caller.data_mut().network_stats_mut().record_received(bytes);
+                    (bytes as u64, 0)
+                }
                 Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
             };
 
@@ -628,7 +1262,122 @@ fn tcp_read<T: NetworkingCtx + ErrorCtx + Send>(
     })
 }
 
-// Flushes this output stream, ensuring that all intermediately buffered contents reach their
+// Reads data from TCP stream until the buffer is full or the peer closed the connection.
+//
+// The timeout applies to the whole call, not to each read.
+//
+// The number of bytes read is written to **read_ptr** in all cases. On success it's less than
+// **buffer_len** if the stream reached EOF first. On an error or a timeout the bytes read before
+// are in the buffer and consumed from the stream, so the caller can continue from there.
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+// * 9027 if the operation timed out
+//
+// Traps:
+// * If the stream ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn tcp_read_exact<T>(
+    mut caller: Caller<T>,
+    stream_id: u64,
+    buffer_ptr: u32,
+    buffer_len: u32,
+    timeout: u32,
+    read_ptr: u32,
+    error_id_ptr: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send,
+    T::Config: NetworkingConfigCtx,
+{
+    Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        let delay = bandwidth_delay(&mut caller);
+        let mut stream = caller
+            .data()
+            .tcp_stream_resources()
+            .get(stream_id)
+            .or_trap("lunatic::network::tcp_read_exact")?
+            .clone();
+        let mut read = 0;
+        let result = match check_shutdown(caller.data(), stream_id, Shutdown::Read) {
+            Err(error) => Some(Err(error)),
+            Ok(()) => {
+                let memory = get_memory(&mut caller)?;
+                let buffer = memory
+                    .data_mut(&mut caller)
+                    .get_mut(buffer_ptr as usize..(buffer_ptr + buffer_len) as usize)
+                    .or_trap("lunatic::networking::tcp_read_exact")?;
+                let read_exact = async {
+                    while read < buffer.len() {
+                        match stream.read(&mut buffer[read..]).await {
+                            Ok(0) => break,
+                            Ok(bytes) => read += bytes,
+                            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                            Err(error) => return Err(error),
+                        }
+                    }
+                    Ok(())
+                };
+                tokio::select! {
+                    _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
+                    result = throttled(delay, read_exact) => Some(result)
+                }
+            }
+        };
+
+        if read > 0 {
+            touch_stream(&caller, stream_id);
+            caller.data_mut().network_stats_mut().record_received(read);
+        }
+        let (error_id, return_) = match result {
+            Some(Ok(())) => (0, 0),
+            Some(Err(error)) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
+            // Call timed out
+            None => (0, 9027),
+        };
+
+        let memory = get_memory(&mut caller)?;
+        memory
+            .write(&mut caller, read_ptr as usize, &(read as u64).to_le_bytes())
+            .or_trap("lunatic::networking::tcp_read_exact")?;
+        memory
+            .write(&mut caller, error_id_ptr as usize, &error_id.to_le_bytes())
+            .or_trap("lunatic::networking::tcp_read_exact")?;
+        Ok(return_)
+    })
+}
+
+// Returns an error if **direction** of the stream was shut down with `tcp_shutdown`.
+fn check_shutdown<T: NetworkingCtx>(
+    state: &T,
+    stream_id: u64,
+    direction: Shutdown,
+) -> std::io::Result<()> {
+    match state.tcp_stream_shutdowns().get(&stream_id) {
+        Some(shutdown) if *shutdown == Shutdown::Both || *shutdown == direction => {
+            let operation = match direction {
+                Shutdown::Write => "writing",
+                _ => "reading",
+            };
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                format!("The TCP stream was shut down for {}", operation),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Marks the stream as active, if it has an idle timeout.
+fn touch_stream<T: ProcessState + NetworkingCtx>(caller: &Caller<T>, stream_id: u64) {
+    if let Some(activity) = caller.data().tcp_stream_activity().get(&stream_id) {
+        activity.touch();
+    }
+}
+
+// Flushes this output stream, ensuring that all intermediately buffered contents reach their
 // destination.
 //
 // Returns:
@@ -638,12 +1387,13 @@ fn tcp_read<T: NetworkingCtx + ErrorCtx + Send>(
 // Traps:
 // * If the stream ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn tcp_flush<T: NetworkingCtx + ErrorCtx + Send>(
+fn tcp_flush<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
     mut caller: Caller<T>,
     stream_id: u64,
     error_id_ptr: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
         let mut stream = caller
             .data()
             .tcp_stream_resources()
@@ -664,6 +1414,207 @@ fn tcp_flush<T: NetworkingCtx + ErrorCtx + Send>(
     })
 }
 
+// Shuts down the read, write or both directions of the TCP stream, without closing it.
+//
+// Shutting down the write direction (a half-close) signals EOF to the peer, while the response
+// can still be read. Afterwards reading from a direction that was shut down, or writing to it,
+// fails with an error instead of reaching the socket. This only applies to the stream ID, clones
+// of the stream see the behavior of the OS.
+//
+// Directions:
+// * 0 - Read
+// * 1 - Write
+// * 2 - Both
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+//
+// Traps:
+// * If the stream ID doesn't exist.
+// * If the direction is unknown.
+// * If any memory outside the guest heap space is referenced.
+fn tcp_shutdown<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    stream_id: u64,
+    direction: u32,
+    error_id_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let direction = match direction {
+        0 => Shutdown::Read,
+        1 => Shutdown::Write,
+        2 => Shutdown::Both,
+        _ => {
+            return Err(Trap::new(
+                "lunatic::networking::tcp_shutdown: Unknown direction",
+            ))
+        }
+    };
+    let stream = caller
+        .data()
+        .tcp_stream_resources()
+        .get(stream_id)
+        .or_trap("lunatic::networking::tcp_shutdown")?;
+    let (error_id, result) = match stream.shutdown(direction) {
+        Ok(()) => {
+            let shutdowns = caller.data_mut().tcp_stream_shutdowns_mut();
+            let shutdown = match shutdowns.get(&stream_id) {
+                Some(earlier) if *earlier != direction => Shutdown::Both,
+                _ => direction,
+            };
+            shutdowns.insert(stream_id, shutdown);
+            (0, 0)
+        }
+        Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
+    };
+
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(&mut caller, error_id_ptr as usize, &error_id.to_le_bytes())
+        .or_trap("lunatic::networking::tcp_shutdown")?;
+    Ok(result)
+}
+
+// Sets the `SO_LINGER` option of the TCP stream, it decides what happens to unsent data once the
+// stream is closed:
+// * -1 - Default, closing returns right away and the OS sends the data in the background.
+// * 0  - Closing discards unsent data and resets the connection (RST), the peer gets a
+//        "connection reset" error instead of EOF. Useful to get rid of misbehaving peers and to
+//        avoid the `TIME_WAIT` state.
+// * n  - Closing waits up to **linger_secs** seconds for the data to be sent, before resetting
+//        the connection.
+//
+// The stream is only closed once it and all its clones are dropped. A zero linger resets the
+// connection on all platforms, even after a `tcp_shutdown`.
+//
+// How a positive linger behaves depends on the platform. On Linux, the host thread dropping the
+// last handle blocks until the data is sent or the linger passed, even though the stream is
+// non-blocking. Other platforms don't block on non-blocking sockets and may keep sending the data
+// in the background like with the default. To keep the blocking short, **linger_secs** can't
+// exceed the `max_tcp_linger` of the process configuration (5 seconds by default).
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**, also if **linger_secs** exceeds
+//                  the maximum
+//
+// Traps:
+// * If the stream ID doesn't exist.
+// * If **linger_secs** is below -1.
+// * If any memory outside the guest heap space is referenced.
+fn tcp_set_linger<T>(
+    mut caller: Caller<T>,
+    stream_id: u64,
+    linger_secs: i64,
+    error_id_ptr: u32,
+) -> Result<u32, Trap>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx,
+    T::Config: NetworkingConfigCtx,
+{
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let linger = match linger_secs {
+        -1 => None,
+        secs if secs >= 0 => Some(Duration::from_secs(secs as u64)),
+        _ => {
+            return Err(Trap::new(
+                "lunatic::networking::tcp_set_linger: Invalid linger duration",
+            ))
+        }
+    };
+    let stream = caller
+        .data()
+        .tcp_stream_resources()
+        .get(stream_id)
+        .or_trap("lunatic::networking::tcp_set_linger")?;
+    let max_linger_secs = caller
+        .data()
+        .config()
+        .max_tcp_linger()
+        .as_secs()
+        .min(MAX_TCP_LINGER_SECS);
+    let result = if linger.is_some_and(|linger| linger.as_secs() > max_linger_secs) {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "The linger duration can't exceed {} seconds",
+                max_linger_secs
+            ),
+        ))
+    } else {
+        socket2::SockRef::from(stream).set_linger(linger)
+    };
+    let (error_id, result) = match result {
+        Ok(()) => (0, 0),
+        Err(error) => {
+            let error = anyhow::Error::new(error).context("Can't set SO_LINGER");
+            (caller.data_mut().error_resources_mut().add(error), 1)
+        }
+    };
+
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(&mut caller, error_id_ptr as usize, &error_id.to_le_bytes())
+        .or_trap("lunatic::networking::tcp_set_linger")?;
+    Ok(result)
+}
+
+/// Longest linger processes can set by default, see [`NetworkingConfigCtx::max_tcp_linger`].
+pub const DEFAULT_MAX_TCP_LINGER: Duration = Duration::from_secs(5);
+
+/// Longest linger the platform supports, in seconds. `l_linger` is an `u_short` on Windows and a
+/// `c_int` elsewhere.
+#[cfg(windows)]
+pub const MAX_TCP_LINGER_SECS: u64 = u16::MAX as u64;
+#[cfg(not(windows))]
+pub const MAX_TCP_LINGER_SECS: u64 = i32::MAX as u64;
+
+// Flushes all buffered data and shuts the TCP stream down, waiting for both operations to
+// finish. The stream ID is invalidated even if the flush or shutdown fails, any later use of it
+// will trap.
+//
+// Other clones of the same stream will also not be able to read or write anymore.
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+//
+// Traps:
+// * If the stream ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn stream_close<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
+    mut caller: Caller<T>,
+    stream_id: u64,
+    error_id_ptr: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+    Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        let mut stream = caller
+            .data()
+            .tcp_stream_resources()
+            .get(stream_id)
+            .or_trap("lunatic::networking::stream_close")?
+            .clone();
+        close_tcp_stream(caller.data_mut(), stream_id);
+
+        let result = match stream.flush().await {
+            Ok(()) => stream.shutdown(Shutdown::Both),
+            Err(error) => Err(error),
+        };
+        let (error_id, result) = match result {
+            Ok(()) => (0, 0),
+            Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
+        };
+
+        let memory = get_memory(&mut caller)?;
+        memory
+            .write(&mut caller, error_id_ptr as usize, &error_id.to_le_bytes())
+            .or_trap("lunatic::networking::stream_close")?;
+        Ok(result)
+    })
+}
+
 // Creates a new UDP socket, which will be bound to the specified address. The returned socket
 // is ready for receiving messages.
 //
@@ -677,7 +1628,7 @@ fn tcp_flush<T: NetworkingCtx + ErrorCtx + Send>(
 // Traps:
 // * If **addr_type** is neither 4 or 6.
 // * If any memory outside the guest heap space is referenced.
-fn udp_bind<T: NetworkingCtx + ErrorCtx + Send>(
+fn udp_bind<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
     mut caller: Caller<T>,
     addr_type: u32,
     addr_u8_ptr: u32,
@@ -687,6 +1638,7 @@ fn udp_bind<T: NetworkingCtx + ErrorCtx + Send>(
     id_u64_ptr: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
         let memory = get_memory(&mut caller)?;
         let socket_addr = socket_address(
             &caller,
@@ -721,20 +1673,36 @@ fn udp_bind<T: NetworkingCtx + ErrorCtx + Send>(
 
 // Drops the UdpSocket resource.
 //
+// If this was the last reference to the socket, all multicast groups joined through it are left.
+//
 // Traps:
 // * If the UDP socket ID doesn't exist.
-fn drop_udp_socket<T: NetworkingCtx>(
+fn drop_udp_socket<T: ProcessState + NetworkingCtx>(
     mut caller: Caller<T>,
     udp_socket_id: u64,
 ) -> Result<(), Trap> {
-    caller
-        .data_mut()
-        .udp_resources_mut()
-        .remove(udp_socket_id)
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    close_udp_socket(caller.data_mut(), udp_socket_id)
         .or_trap("lunatic::networking::drop_udp_socket")?;
     Ok(())
 }
 
+/// Removes the UDP socket from the resources of `state`, leaving its multicast groups if it was
+/// the last reference to the socket.
+///
+/// Returns `None` if the UDP socket ID doesn't exist.
+pub fn close_udp_socket<T: NetworkingCtx>(state: &mut T, udp_socket_id: u64) -> Option<()> {
+    let socket = state.udp_resources_mut().remove(udp_socket_id)?;
+    let groups = state.udp_multicast_groups_mut().remove(&udp_socket_id);
+    if let (Some(groups), 1) = (groups, Arc::strong_count(&socket)) {
+        for group in groups {
+            // The socket is going away, there is nobody left to report the error to.
+            let _ = group.leave(&socket);
+        }
+    }
+    Some(())
+}
+
 // Reads data from the connected udp socket and writes it to the given buffer. This method will
 // fail if the socket is not connected.
 //
@@ -746,15 +1714,21 @@ fn drop_udp_socket<T: NetworkingCtx>(
 // Traps:
 // * If the socket ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn udp_receive<T: NetworkingCtx + ErrorCtx + Send>(
+fn udp_receive<T>(
     mut caller: Caller<T>,
     socket_id: u64,
     buffer_ptr: u32,
     buffer_len: u32,
     timeout: u32,
     opaque_ptr: u32,
-) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send,
+    T::Config: NetworkingConfigCtx,
+{
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        let delay = bandwidth_delay(&mut caller);
         let memory = get_memory(&mut caller)?;
         let (memory_slice, state) = memory.data_and_store_mut(&mut caller);
 
@@ -770,10 +1744,13 @@ fn udp_receive<T: NetworkingCtx + ErrorCtx + Send>(
         // Check for timeout first
         if let Some(result) = tokio::select! {
             _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
-            result = socket.recv(buffer) => Some(result)
+            result = throttled(delay, socket.recv(buffer)) => Some(result)
         } {
             let (opaque, return_) = match result {
-                Ok(bytes) => (bytes as u64, 0),
+                Ok(bytes) => {
+                    caller.data_mut().network_stats_mut().record_received(bytes);
+                    (bytes as u64, 0)
+                }
                 Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
             };
 
@@ -801,7 +1778,7 @@ fn udp_receive<T: NetworkingCtx + ErrorCtx + Send>(
 // Traps:
 // * If the stream ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn udp_receive_from<T: NetworkingCtx + ErrorCtx + Send>(
+fn udp_receive_from<T>(
     mut caller: Caller<T>,
     socket_id: u64,
     buffer_ptr: u32,
@@ -809,8 +1786,14 @@ fn udp_receive_from<T: NetworkingCtx + ErrorCtx + Send>(
     timeout: u32,
     opaque_ptr: u32,
     dns_iter_ptr: u32,
-) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send,
+    T::Config: NetworkingConfigCtx,
+{
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        let delay = bandwidth_delay(&mut caller);
         let memory = get_memory(&mut caller)?;
         let (memory_slice, state) = memory.data_and_store_mut(&mut caller);
 
@@ -826,10 +1809,13 @@ fn udp_receive_from<T: NetworkingCtx + ErrorCtx + Send>(
         // Check for timeout first
         if let Some(result) = tokio::select! {
             _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
-            result = socket.recv_from(buffer) => Some(result)
+            result = throttled(delay, socket.recv_from(buffer)) => Some(result)
         } {
             let (opaque, socket_result, return_) = match result {
-                Ok((bytes, socket)) => (bytes as u64, Some(socket), 0),
+                Ok((bytes, socket)) => {
+                    caller.data_mut().network_stats_mut().record_received(bytes);
+                    (bytes as u64, Some(socket), 0)
+                }
                 Err(error) => (
                     caller.data_mut().error_resources_mut().add(error.into()),
                     None,
@@ -877,7 +1863,7 @@ fn udp_receive_from<T: NetworkingCtx + ErrorCtx + Send>(
 // Traps:
 // * If any memory outside the guest heap space is referenced.
 #[allow(clippy::too_many_arguments)]
-fn udp_connect<T: NetworkingCtx + ErrorCtx + Send>(
+fn udp_connect<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
     mut caller: Caller<T>,
     udp_socket_id: u64,
     addr_type: u32,
@@ -889,6 +1875,7 @@ fn udp_connect<T: NetworkingCtx + ErrorCtx + Send>(
     id_u64_ptr: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
         // Get the memory and the socket being connected to
         let memory = get_memory(&mut caller)?;
         let socket_addr = socket_address(
@@ -930,10 +1917,11 @@ fn udp_connect<T: NetworkingCtx + ErrorCtx + Send>(
 //
 // Traps:
 // * If the stream ID doesn't exist.
-fn clone_udp_socket<T: NetworkingCtx>(
+fn clone_udp_socket<T: ProcessState + NetworkingCtx>(
     mut caller: Caller<T>,
     udp_socket_id: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     let stream = caller
         .data()
         .udp_resources()
@@ -949,11 +1937,12 @@ fn clone_udp_socket<T: NetworkingCtx>(
 // Traps:
 // * If the socket ID doesn't exist.
 // * If set_broadcast traps.
-fn set_udp_socket_broadcast<T: NetworkingCtx>(
-    caller: Caller<T>,
+fn set_udp_socket_broadcast<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
     udp_socket_id: u64,
     broadcast: u32,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     caller
         .data()
         .udp_resources()
@@ -969,10 +1958,11 @@ fn set_udp_socket_broadcast<T: NetworkingCtx>(
 // Traps:
 // * If the socket ID doesn't exist.
 // * If broadcast traps.
-fn get_udp_socket_broadcast<T: NetworkingCtx>(
-    caller: Caller<T>,
+fn get_udp_socket_broadcast<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
     udp_socket_id: u64,
 ) -> Result<i32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     let socket = caller
         .data()
         .udp_resources()
@@ -992,11 +1982,12 @@ fn get_udp_socket_broadcast<T: NetworkingCtx>(
 // Traps:
 // * If the socket ID doesn't exist.
 // * If set_ttl traps.
-fn set_udp_socket_ttl<T: NetworkingCtx>(
-    caller: Caller<T>,
+fn set_udp_socket_ttl<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
     udp_socket_id: u64,
     ttl: u32,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     caller
         .data()
         .udp_resources()
@@ -1012,10 +2003,11 @@ fn set_udp_socket_ttl<T: NetworkingCtx>(
 // Traps:
 // * If the socket ID doesn't exist.
 // * If ttl() traps.
-fn get_udp_socket_ttl<T: NetworkingCtx>(
-    caller: Caller<T>,
+fn get_udp_socket_ttl<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
     udp_socket_id: u64,
 ) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     let result = caller
         .data()
         .udp_resources()
@@ -1027,6 +2019,321 @@ fn get_udp_socket_ttl<T: NetworkingCtx>(
     Ok(result)
 }
 
+// Joins the IPv4 multicast group **multiaddr_u8_ptr** on the interface **interface_u8_ptr**.
+// Both addresses are 4 bytes long. If the interface is `0.0.0.0` the OS picks an appropriate one.
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+//
+// Traps:
+// * If the socket ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn udp_join_multicast_v4<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    udp_socket_id: u64,
+    multiaddr_u8_ptr: u32,
+    interface_u8_ptr: u32,
+    error_id_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let memory = get_memory(&mut caller)?;
+    let group = MulticastGroup::V4 {
+        multiaddr: read_ipv4(&caller, &memory, multiaddr_u8_ptr)?,
+        interface: read_ipv4(&caller, &memory, interface_u8_ptr)?,
+    };
+    multicast_membership(
+        caller,
+        memory,
+        udp_socket_id,
+        group,
+        true,
+        error_id_ptr,
+        "lunatic::networking::udp_join_multicast_v4",
+    )
+}
+
+// Leaves the IPv4 multicast group **multiaddr_u8_ptr** on the interface **interface_u8_ptr**.
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+//
+// Traps:
+// * If the socket ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn udp_leave_multicast_v4<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    udp_socket_id: u64,
+    multiaddr_u8_ptr: u32,
+    interface_u8_ptr: u32,
+    error_id_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let memory = get_memory(&mut caller)?;
+    let group = MulticastGroup::V4 {
+        multiaddr: read_ipv4(&caller, &memory, multiaddr_u8_ptr)?,
+        interface: read_ipv4(&caller, &memory, interface_u8_ptr)?,
+    };
+    multicast_membership(
+        caller,
+        memory,
+        udp_socket_id,
+        group,
+        false,
+        error_id_ptr,
+        "lunatic::networking::udp_leave_multicast_v4",
+    )
+}
+
+// Joins the IPv6 multicast group **multiaddr_u8_ptr** (16 bytes) on the interface with the index
+// **interface**. If the index is 0 the OS picks an appropriate interface.
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+//
+// Traps:
+// * If the socket ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn udp_join_multicast_v6<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    udp_socket_id: u64,
+    multiaddr_u8_ptr: u32,
+    interface: u32,
+    error_id_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let memory = get_memory(&mut caller)?;
+    let group = MulticastGroup::V6 {
+        multiaddr: read_ipv6(&caller, &memory, multiaddr_u8_ptr)?,
+        interface,
+    };
+    multicast_membership(
+        caller,
+        memory,
+        udp_socket_id,
+        group,
+        true,
+        error_id_ptr,
+        "lunatic::networking::udp_join_multicast_v6",
+    )
+}
+
+// Leaves the IPv6 multicast group **multiaddr_u8_ptr** on the interface with the index
+// **interface**.
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+//
+// Traps:
+// * If the socket ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn udp_leave_multicast_v6<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    udp_socket_id: u64,
+    multiaddr_u8_ptr: u32,
+    interface: u32,
+    error_id_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let memory = get_memory(&mut caller)?;
+    let group = MulticastGroup::V6 {
+        multiaddr: read_ipv6(&caller, &memory, multiaddr_u8_ptr)?,
+        interface,
+    };
+    multicast_membership(
+        caller,
+        memory,
+        udp_socket_id,
+        group,
+        false,
+        error_id_ptr,
+        "lunatic::networking::udp_leave_multicast_v6",
+    )
+}
+
+// Joins or leaves a multicast group and keeps track of the groups joined by the socket.
+fn multicast_membership<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    memory: Memory,
+    udp_socket_id: u64,
+    group: MulticastGroup,
+    join: bool,
+    error_id_ptr: u32,
+    trap_context: &str,
+) -> Result<u32, Trap> {
+    let socket = caller
+        .data()
+        .udp_resources()
+        .get(udp_socket_id)
+        .or_trap(trap_context)?
+        .clone();
+
+    let result = if !group.is_multicast() {
+        Err(anyhow::anyhow!("{} is not a multicast address", group))
+    } else if join {
+        group
+            .join(&socket)
+            .map_err(|error| anyhow::Error::new(error).context(format!("Can't join {}", group)))
+    } else {
+        group
+            .leave(&socket)
+            .map_err(|error| anyhow::Error::new(error).context(format!("Can't leave {}", group)))
+    };
+
+    let (error_id, result) = match result {
+        Ok(()) => {
+            let groups = caller
+                .data_mut()
+                .udp_multicast_groups_mut()
+                .entry(udp_socket_id)
+                .or_default();
+            if join {
+                groups.push(group);
+            } else {
+                groups.retain(|joined| *joined != group);
+            }
+            (0, 0)
+        }
+        Err(error) => (caller.data_mut().error_resources_mut().add(error), 1),
+    };
+    memory
+        .write(&mut caller, error_id_ptr as usize, &error_id.to_le_bytes())
+        .or_trap(trap_context)?;
+    Ok(result)
+}
+
+// Sets the value of the IP_MULTICAST_TTL option for this socket. It indicates the number of
+// network hops IPv4 multicast packets are allowed to take.
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+//
+// Traps:
+// * If the socket ID doesn't exist.
+// * If any memory outside the guest heap space is referenced.
+fn set_udp_socket_multicast_ttl_v4<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    udp_socket_id: u64,
+    ttl: u32,
+    error_id_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let result = caller
+        .data()
+        .udp_resources()
+        .get(udp_socket_id)
+        .or_trap("lunatic::networking::set_udp_socket_multicast_ttl_v4")?
+        .set_multicast_ttl_v4(ttl);
+    let (error_id, result) = match result {
+        Ok(()) => (0, 0),
+        Err(error) => {
+            let error = anyhow::Error::new(error).context("Can't set the multicast TTL");
+            (caller.data_mut().error_resources_mut().add(error), 1)
+        }
+    };
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(&mut caller, error_id_ptr as usize, &error_id.to_le_bytes())
+        .or_trap("lunatic::networking::set_udp_socket_multicast_ttl_v4")?;
+    Ok(result)
+}
+
+// Gets the value of the IP_MULTICAST_TTL option for this socket.
+//
+// Traps:
+// * If the socket ID doesn't exist.
+// * If multicast_ttl_v4() traps.
+fn get_udp_socket_multicast_ttl_v4<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
+    udp_socket_id: u64,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let result = caller
+        .data()
+        .udp_resources()
+        .get(udp_socket_id)
+        .or_trap("lunatic::networking::get_udp_socket_multicast_ttl_v4")?
+        .multicast_ttl_v4()
+        .or_trap("lunatic::networking::get_udp_socket_multicast_ttl_v4")?;
+    Ok(result)
+}
+
+// Sets the value of the IP_MULTICAST_LOOP (**ip_version** 4) or IPV6_MULTICAST_LOOP
+// (**ip_version** 6) option for this socket. If enabled, multicast packets sent from this socket
+// are also delivered back to the local host.
+//
+// Returns:
+// * 0 on success
+// * 1 on error   - The error ID is written to **error_id_ptr**
+//
+// Traps:
+// * If the socket ID doesn't exist.
+// * If **ip_version** is neither 4 or 6.
+// * If any memory outside the guest heap space is referenced.
+fn set_udp_socket_multicast_loop<T: ProcessState + NetworkingCtx + ErrorCtx>(
+    mut caller: Caller<T>,
+    udp_socket_id: u64,
+    ip_version: u32,
+    enabled: u32,
+    error_id_ptr: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let socket = caller
+        .data()
+        .udp_resources()
+        .get(udp_socket_id)
+        .or_trap("lunatic::networking::set_udp_socket_multicast_loop")?;
+    let result = match ip_version {
+        4 => socket.set_multicast_loop_v4(enabled > 0),
+        6 => socket.set_multicast_loop_v6(enabled > 0),
+        _ => return Err(Trap::new("Unsupported IP version")),
+    };
+    let (error_id, result) = match result {
+        Ok(()) => (0, 0),
+        Err(error) => {
+            let error = anyhow::Error::new(error)
+                .context(format!("Can't set the IPv{} multicast loop", ip_version));
+            (caller.data_mut().error_resources_mut().add(error), 1)
+        }
+    };
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(&mut caller, error_id_ptr as usize, &error_id.to_le_bytes())
+        .or_trap("lunatic::networking::set_udp_socket_multicast_loop")?;
+    Ok(result)
+}
+
+// Gets the value of the IP_MULTICAST_LOOP (**ip_version** 4) or IPV6_MULTICAST_LOOP
+// (**ip_version** 6) option for this socket.
+//
+// Traps:
+// * If the socket ID doesn't exist.
+// * If **ip_version** is neither 4 or 6.
+// * If multicast_loop_v4() or multicast_loop_v6() traps.
+fn get_udp_socket_multicast_loop<T: ProcessState + NetworkingCtx>(
+    mut caller: Caller<T>,
+    udp_socket_id: u64,
+    ip_version: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let socket = caller
+        .data()
+        .udp_resources()
+        .get(udp_socket_id)
+        .or_trap("lunatic::networking::get_udp_socket_multicast_loop")?;
+    let enabled = match ip_version {
+        4 => socket.multicast_loop_v4(),
+        6 => socket.multicast_loop_v6(),
+        _ => return Err(Trap::new("Unsupported IP version")),
+    }
+    .or_trap("lunatic::networking::get_udp_socket_multicast_loop")?;
+    Ok(enabled as u32)
+}
+
 // Sends data on the socket to the given address.
 //
 // Returns:
@@ -1038,7 +2345,7 @@ fn get_udp_socket_ttl<T: NetworkingCtx>(
 // * If the stream ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
 #[allow(clippy::too_many_arguments)]
-fn udp_send_to<T: NetworkingCtx + ErrorCtx + Send>(
+fn udp_send_to<T>(
     mut caller: Caller<T>,
     socket_id: u64,
     buffer_ptr: u32,
@@ -1050,8 +2357,14 @@ fn udp_send_to<T: NetworkingCtx + ErrorCtx + Send>(
     scope_id: u32,
     timeout: u32,
     opaque_ptr: u32,
-) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send,
+    T::Config: NetworkingConfigCtx,
+{
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        let delay = bandwidth_delay(&mut caller);
         let memory = get_memory(&mut caller)?;
         let socket_addr = socket_address(
             &caller,
@@ -1077,10 +2390,13 @@ fn udp_send_to<T: NetworkingCtx + ErrorCtx + Send>(
         // Check for timeout
         if let Some(result) = tokio::select! {
             _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
-            result = stream.send_to(buffer, socket_addr) => Some(result)
+            result = throttled(delay, stream.send_to(buffer, socket_addr)) => Some(result)
         } {
             let (opaque, return_) = match result {
-                Ok(bytes) => (bytes as u64, 0),
+                Ok(bytes) => {
+                    caller.data_mut().network_stats_mut().record_sent(bytes);
+                    (bytes as u64, 0)
+                }
                 Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
             };
 
@@ -1109,15 +2425,21 @@ fn udp_send_to<T: NetworkingCtx + ErrorCtx + Send>(
 // Traps:
 // * If the stream ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn udp_send<T: NetworkingCtx + ErrorCtx + Send>(
+fn udp_send<T>(
     mut caller: Caller<T>,
     socket_id: u64,
     buffer_ptr: u32,
     buffer_len: u32,
     timeout: u32,
     opaque_ptr: u32,
-) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + NetworkingCtx + ErrorCtx + Send,
+    T::Config: NetworkingConfigCtx,
+{
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Networking)?;
+        let delay = bandwidth_delay(&mut caller);
         let memory = get_memory(&mut caller)?;
 
         let buffer = memory
@@ -1135,10 +2457,13 @@ fn udp_send<T: NetworkingCtx + ErrorCtx + Send>(
         // Check for timeout
         if let Some(result) = tokio::select! {
             _ = async_std::task::sleep(Duration::from_millis(timeout as u64)), if timeout != 0 => None,
-            result = stream.send(buffer) => Some(result)
+            result = throttled(delay, stream.send(buffer)) => Some(result)
         } {
             let (opaque, return_) = match result {
-                Ok(bytes) => (bytes as u64, 0),
+                Ok(bytes) => {
+                    caller.data_mut().network_stats_mut().record_sent(bytes);
+                    (bytes as u64, 0)
+                }
                 Err(error) => (caller.data_mut().error_resources_mut().add(error.into()), 1),
             };
 
@@ -1164,11 +2489,12 @@ fn udp_send<T: NetworkingCtx + ErrorCtx + Send>(
 // Traps:
 // * If the udp socket ID doesn't exist.
 // * If any memory outside the guest heap space is referenced.
-fn udp_local_addr<T: NetworkingCtx + ErrorCtx + Send>(
+fn udp_local_addr<T: ProcessState + NetworkingCtx + ErrorCtx + Send>(
     mut caller: Caller<T>,
     udp_socket_id: u64,
     id_u64_ptr: u32,
 ) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
     let udp_socket = caller
         .data()
         .udp_resources()
@@ -1197,7 +2523,7 @@ fn udp_local_addr<T: NetworkingCtx + ErrorCtx + Send>(
     Ok(result)
 }
 
-fn socket_address<T: NetworkingCtx>(
+fn socket_address<T: ProcessState + NetworkingCtx>(
     caller: &Caller<T>,
     memory: &Memory,
     addr_type: u32,
@@ -1226,3 +2552,214 @@ fn socket_address<T: NetworkingCtx>(
         _ => return Err(Trap::new("Unsupported address type in socket_address*")),
     })
 }
+
+// Returns the total number of bytes this process sent over all its TCP streams and UDP sockets.
+fn bytes_sent<T: ProcessState + NetworkingCtx>(mut caller: Caller<T>) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    Ok(caller.data().network_stats().bytes_sent())
+}
+
+// Returns the total number of bytes this process received over all its TCP streams and UDP
+// sockets.
+fn bytes_received<T: ProcessState + NetworkingCtx>(mut caller: Caller<T>) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    Ok(caller.data().network_stats().bytes_received())
+}
+
+// Returns how long the next transfer of the process needs to wait to stay under its bandwidth
+// limit.
+fn bandwidth_delay<T>(caller: &mut Caller<T>) -> Duration
+where
+    T: ProcessState + NetworkingCtx,
+    T::Config: NetworkingConfigCtx,
+{
+    let limit = caller.data().config().bandwidth_limit();
+    caller.data_mut().network_stats_mut().delay(limit)
+}
+
+// Waits for `delay` before starting the transfer, so that the timeout of the host function also
+// covers the time spent throttled.
+async fn throttled<F: Future>(delay: Duration, transfer: F) -> F::Output {
+    if !delay.is_zero() {
+        executor::sleep(delay).await;
+    }
+    transfer.await
+}
+
+// Sets the bandwidth limit shared by all sockets of processes spawned from this configuration.
+//
+// A `bytes_per_second` value of 0 indicates no limit. A `burst` value of 0 allows a burst of one
+// second of traffic.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_set_bandwidth_limit<T>(
+    mut caller: Caller<T>,
+    config_id: u64,
+    bytes_per_second: u64,
+    burst: u64,
+) -> Result<(), Trap>
+where
+    T: ProcessState,
+    T::Config: NetworkingConfigCtx,
+{
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let limit = match bytes_per_second {
+        0 => None,
+        bytes_per_second => Some(BandwidthLimit {
+            bytes_per_second,
+            burst,
+        }),
+    };
+    caller
+        .data_mut()
+        .config_resources_mut()
+        .get_mut(config_id)
+        .or_trap("lunatic::networking::config_set_bandwidth_limit: Config ID doesn't exist")?
+        .set_bandwidth_limit(limit);
+    Ok(())
+}
+
+// Returns the bandwidth limit of a configuration in bytes per second.
+//
+// A value of 0 indicates no limit.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_get_bandwidth_limit<T>(mut caller: Caller<T>, config_id: u64) -> Result<u64, Trap>
+where
+    T: ProcessState,
+    T::Config: NetworkingConfigCtx,
+{
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let limit = caller
+        .data()
+        .config_resources()
+        .get(config_id)
+        .or_trap("lunatic::networking::config_get_bandwidth_limit: Config ID doesn't exist")?
+        .bandwidth_limit();
+    Ok(limit.map(|limit| limit.bytes_per_second).unwrap_or(0))
+}
+
+// Sets the maximum number of outbound TCP connections (opened with `tcp_connect`) each process
+// spawned from this configuration can have open at the same time.
+//
+// A value of 0 indicates no limit. Accepted connections are not limited.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_set_max_outbound_connections<T>(
+    mut caller: Caller<T>,
+    config_id: u64,
+    max: u64,
+) -> Result<(), Trap>
+where
+    T: ProcessState,
+    T::Config: NetworkingConfigCtx,
+{
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let max = match max {
+        0 => None,
+        max => Some(max as usize),
+    };
+    caller
+        .data_mut()
+        .config_resources_mut()
+        .get_mut(config_id)
+        .or_trap(
+            "lunatic::networking::config_set_max_outbound_connections: Config ID doesn't exist",
+        )?
+        .set_max_outbound_connections(max);
+    Ok(())
+}
+
+// Returns the maximum number of outbound TCP connections of a configuration.
+//
+// A value of 0 indicates no limit.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_get_max_outbound_connections<T>(
+    mut caller: Caller<T>,
+    config_id: u64,
+) -> Result<u64, Trap>
+where
+    T: ProcessState,
+    T::Config: NetworkingConfigCtx,
+{
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let max = caller
+        .data()
+        .config_resources()
+        .get(config_id)
+        .or_trap(
+            "lunatic::networking::config_get_max_outbound_connections: Config ID doesn't exist",
+        )?
+        .max_outbound_connections();
+    Ok(max.unwrap_or(0) as u64)
+}
+
+// Returns the burst size of the bandwidth limit of a configuration in bytes.
+//
+// Returns 0 if the configuration has no limit.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_get_bandwidth_burst<T>(mut caller: Caller<T>, config_id: u64) -> Result<u64, Trap>
+where
+    T: ProcessState,
+    T::Config: NetworkingConfigCtx,
+{
+    charge_host_call(&mut caller, HostCallCategory::Networking)?;
+    let limit = caller
+        .data()
+        .config_resources()
+        .get(config_id)
+        .or_trap("lunatic::networking::config_get_bandwidth_burst: Config ID doesn't exist")?
+        .bandwidth_limit();
+    Ok(limit.map(|limit| limit.burst).unwrap_or(0))
+}
+
+fn read_ipv4<T: ProcessState + NetworkingCtx>(
+    caller: &Caller<T>,
+    memory: &Memory,
+    addr_u8_ptr: u32,
+) -> Result<Ipv4Addr, Trap> {
+    let ip = memory
+        .data(caller)
+        .get(addr_u8_ptr as usize..(addr_u8_ptr + 4) as usize)
+        .or_trap("lunatic::network::read_ipv4")?;
+    Ok(<Ipv4Addr as From<[u8; 4]>>::from(
+        ip.try_into().expect("exactly 4 bytes"),
+    ))
+}
+
+fn read_ipv6<T: ProcessState + NetworkingCtx>(
+    caller: &Caller<T>,
+    memory: &Memory,
+    addr_u8_ptr: u32,
+) -> Result<Ipv6Addr, Trap> {
+    let ip = memory
+        .data(caller)
+        .get(addr_u8_ptr as usize..(addr_u8_ptr + 16) as usize)
+        .or_trap("lunatic::network::read_ipv6")?;
+    Ok(<Ipv6Addr as From<[u8; 16]>>::from(
+        ip.try_into().expect("exactly 16 bytes"),
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{bind_listener, TCP_BIND_REUSE_PORT};
+
+    #[test]
+    fn reuse_port_shares_the_address() {
+        let any: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let first = bind_listener(any, 0, TCP_BIND_REUSE_PORT).unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(bind_listener(addr, 0, TCP_BIND_REUSE_PORT).is_ok());
+        assert!(bind_listener(addr, 0, 0).is_err());
+    }
+}
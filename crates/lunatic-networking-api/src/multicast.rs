@@ -0,0 +1,67 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use async_std::net::UdpSocket;
+
+/// A multicast group joined by a UDP socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MulticastGroup {
+    V4 {
+        multiaddr: Ipv4Addr,
+        interface: Ipv4Addr,
+    },
+    V6 {
+        multiaddr: Ipv6Addr,
+        interface: u32,
+    },
+}
+
+impl MulticastGroup {
+    pub fn join(&self, socket: &UdpSocket) -> std::io::Result<()> {
+        match self {
+            MulticastGroup::V4 {
+                multiaddr,
+                interface,
+            } => socket.join_multicast_v4(*multiaddr, *interface),
+            MulticastGroup::V6 {
+                multiaddr,
+                interface,
+            } => socket.join_multicast_v6(multiaddr, *interface),
+        }
+    }
+
+    pub fn leave(&self, socket: &UdpSocket) -> std::io::Result<()> {
+        match self {
+            MulticastGroup::V4 {
+                multiaddr,
+                interface,
+            } => socket.leave_multicast_v4(*multiaddr, *interface),
+            MulticastGroup::V6 {
+                multiaddr,
+                interface,
+            } => socket.leave_multicast_v6(multiaddr, *interface),
+        }
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        match self {
+            MulticastGroup::V4 { multiaddr, .. } => multiaddr.is_multicast(),
+            MulticastGroup::V6 { multiaddr, .. } => multiaddr.is_multicast(),
+        }
+    }
+}
+
+impl fmt::Display for MulticastGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MulticastGroup::V4 {
+                multiaddr,
+                interface,
+            } => write!(f, "{} on interface {}", multiaddr, interface),
+            MulticastGroup::V6 {
+                multiaddr,
+                interface,
+            } => write!(f, "{} on interface index {}", multiaddr, interface),
+        }
+    }
+}
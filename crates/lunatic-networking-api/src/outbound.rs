@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use lunatic_process::message::OutboundTag;
+
+/// Outbound TCP connections of a process, opened with `tcp_connect`.
+///
+/// Every stream resource referring to an outbound connection carries the connection's
+/// [`OutboundTag`], clones of the stream and streams sent to other processes included. A
+/// connection counts towards the process that opened it until the last of these streams is
+/// dropped, no matter which process holds it.
+#[derive(Debug, Default)]
+pub struct OutboundTcpStreams {
+    // Tags of this process' stream resources, keyed by the resource ID.
+    streams: HashMap<u64, OutboundTag>,
+    // Tags of the connections opened by this process.
+    opened: Vec<OutboundTag>,
+}
+
+impl OutboundTcpStreams {
+    /// Tags the stream `id` of a connection opened by this process.
+    pub fn connected(&mut self, id: u64) {
+        let tag = OutboundTag::new();
+        self.opened.push(tag.clone());
+        self.streams.insert(id, tag);
+    }
+
+    /// Tags the stream `id` with the tag of an existing connection.
+    pub fn insert(&mut self, id: u64, tag: OutboundTag) {
+        self.streams.insert(id, tag);
+    }
+
+    /// Returns the tag of the stream `id`, `None` if it's not an outbound connection.
+    pub fn get(&self, id: u64) -> Option<&OutboundTag> {
+        self.streams.get(&id)
+    }
+
+    /// Removes the tag of the stream `id` once the stream is dropped or moved to a message.
+    pub fn remove(&mut self, id: u64) -> Option<OutboundTag> {
+        self.streams.remove(&id)
+    }
+
+    /// Returns the number of connections opened by this process that are still open.
+    pub fn open_connections(&mut self) -> usize {
+        self.opened.retain(OutboundTag::in_use);
+        self.opened.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_count_until_the_last_tagged_stream_is_dropped() {
+        let mut outbound = OutboundTcpStreams::default();
+        outbound.connected(1);
+        let tag = outbound.get(1).unwrap().clone();
+        outbound.insert(2, tag);
+        outbound.remove(1);
+        assert_eq!(outbound.open_connections(), 1);
+        // Moved to a message, still alive.
+        let in_message = outbound.remove(2).unwrap();
+        assert_eq!(outbound.open_connections(), 1);
+        drop(in_message);
+        assert_eq!(outbound.open_connections(), 0);
+    }
+}
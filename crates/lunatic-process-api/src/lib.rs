@@ -7,15 +7,28 @@ use std::{
 
 use anyhow::{anyhow, Result};
 use hash_map_id::HashMapId;
+use log::LevelFilter;
 use lunatic_common_api::{get_memory, IntoTrap};
 use lunatic_error_api::ErrorCtx;
 use lunatic_process::{
-    config::ProcessConfig, mailbox::MessageMailbox, message::Message,
-    runtimes::wasmtime::WasmtimeCompiledModule, state::ProcessState, wasm::spawn_wasm, Process,
-    Signal, WasmProcess,
+    config::{
+        IdleReceivePolicy, IdleReceiveTimeout, ProcessConfig, UNIT_OF_COMPUTE_IN_INSTRUCTIONS,
+    },
+    executor,
+    fuel::{charge_host_call, HostCallCategory},
+    mailbox::MessageMailbox,
+    message::Message,
+    process_ref::ProcessRef,
+    random::{ProcessRng, RandomSource},
+    runtimes::wasmtime::WasmtimeCompiledModule,
+    state::ProcessState,
+    upgrade::{Upgrade, STATE_TRANSFER_EXPORT},
+    wasm::{spawn_wasm, spawn_wasm_and_await_ready, spawn_wasm_detached, ReadyTimeout},
+    Process, Signal, WasmProcess,
 };
 use lunatic_wasi_api::LunaticWasiCtx;
-use wasmtime::{Caller, Linker, ResourceLimiter, Trap, Val};
+use rand::RngCore;
+use wasmtime::{Caller, Linker, ResourceLimiter, Trap, Val, ValType};
 
 pub type ProcessResources = HashMapId<Arc<dyn Process>>;
 pub type ModuleResources<T> = HashMapId<WasmtimeCompiledModule<T>>;
@@ -36,6 +49,7 @@ pub trait ProcessCtx<S: ProcessState> {
     fn module_resources_mut(&mut self) -> &mut ModuleResources<S>;
     fn process_resources(&self) -> &ProcessResources;
     fn process_resources_mut(&mut self) -> &mut ProcessResources;
+    fn rng(&mut self) -> &mut ProcessRng;
 }
 
 // Register the process APIs to the linker
@@ -45,7 +59,7 @@ where
     for<'a> &'a T: Send,
     T::Config: ProcessConfigCtx,
 {
-    linker.func_wrap("lunatic::process", "compile_module", compile_module)?;
+    linker.func_wrap3_async("lunatic::process", "compile_module", compile_module)?;
     linker.func_wrap("lunatic::process", "drop_module", drop_module)?;
     linker.func_wrap("lunatic::process", "create_config", create_config)?;
     linker.func_wrap("lunatic::process", "drop_config", drop_config)?;
@@ -69,6 +83,41 @@ where
         "config_get_max_fuel",
         config_get_max_fuel,
     )?;
+    linker.func_wrap(
+        "lunatic::process",
+        "config_set_host_call_cost",
+        config_set_host_call_cost,
+    )?;
+    linker.func_wrap(
+        "lunatic::process",
+        "config_get_host_call_cost",
+        config_get_host_call_cost,
+    )?;
+    linker.func_wrap(
+        "lunatic::process",
+        "config_set_idle_receive_timeout",
+        config_set_idle_receive_timeout,
+    )?;
+    linker.func_wrap(
+        "lunatic::process",
+        "config_get_idle_receive_timeout",
+        config_get_idle_receive_timeout,
+    )?;
+    linker.func_wrap(
+        "lunatic::process",
+        "config_get_idle_receive_policy",
+        config_get_idle_receive_policy,
+    )?;
+    linker.func_wrap(
+        "lunatic::process",
+        "config_set_random_seed",
+        config_set_random_seed,
+    )?;
+    linker.func_wrap(
+        "lunatic::process",
+        "config_use_system_random",
+        config_use_system_random,
+    )?;
     linker.func_wrap(
         "lunatic::process",
         "config_can_compile_modules",
@@ -112,6 +161,31 @@ where
     linker.func_wrap("lunatic::process", "link", link)?;
     linker.func_wrap("lunatic::process", "unlink", unlink)?;
     linker.func_wrap("lunatic::process", "kill", kill)?;
+    linker.func_wrap("lunatic::process", "kill_with_grace", kill_with_grace)?;
+    linker.func_wrap("lunatic::process", "set_log_level", set_log_level)?;
+
+    linker.func_wrap("lunatic::process", "fuel_remaining", fuel_remaining)?;
+    linker.func_wrap("lunatic::process", "memory_available", memory_available)?;
+    linker.func_wrap("lunatic::process", "uptime_ms", uptime_ms)?;
+    linker.func_wrap("lunatic::process", "ready", ready)?;
+    linker.func_wrap("lunatic::process", "upgrade", upgrade)?;
+    linker.func_wrap10_async("lunatic::process", "spawn_named", spawn_named)?;
+    linker.func_wrap7_async("lunatic::process", "spawn_detached", spawn_detached)?;
+    linker.func_wrap9_async(
+        "lunatic::process",
+        "spawn_and_await_ready",
+        spawn_and_await_ready,
+    )?;
+    linker.func_wrap("lunatic::process", "set_name", set_name)?;
+    linker.func_wrap("lunatic::process", "name_size", name_size)?;
+    linker.func_wrap("lunatic::process", "name", name)?;
+    linker.func_wrap("lunatic::process", "forward_os_signals", forward_os_signals)?;
+    linker.func_wrap("lunatic::process", "checkpoint_save", checkpoint_save)?;
+    linker.func_wrap("lunatic::process", "checkpoint_load", checkpoint_load)?;
+    linker.func_wrap("lunatic::process", "checkpoint_delete", checkpoint_delete)?;
+    linker.func_wrap("lunatic::process", "random_u64", random_u64)?;
+    linker.func_wrap("lunatic::process", "random_bytes", random_bytes)?;
+    linker.func_wrap("lunatic::process", "child_count", child_count)?;
 
     Ok(())
 }
@@ -119,7 +193,8 @@ where
 // Compile a new WebAssembly module.
 //
 // The `spawn` function can be used to spawn new processes from the module.
-// Module compilation can be a CPU intensive task.
+// Module compilation can be a CPU intensive task, it runs on a blocking thread and the process
+// waits for it without blocking others.
 //
 // Returns:
 // *  0 on success - The ID of the newly created module is written to **id_ptr**
@@ -130,31 +205,35 @@ fn compile_module<T>(
     module_data_ptr: u32,
     module_data_len: u32,
     id_ptr: u32,
-) -> Result<i32, Trap>
+) -> Box<dyn Future<Output = Result<i32, Trap>> + Send + '_>
 where
-    T: ProcessState + ProcessCtx<T> + ErrorCtx,
+    T: ProcessState + ProcessCtx<T> + ErrorCtx + Send + 'static,
     T::Config: ProcessConfigCtx,
 {
-    // TODO: Module compilation is CPU intensive and should be done on the blocking task thread pool.
-    if !caller.data().config().can_compile_modules() {
-        return Ok(-1);
-    }
+    Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Process)?;
+        if !caller.data().config().can_compile_modules() {
+            return Ok(-1);
+        }
 
-    let mut module = vec![0; module_data_len as usize];
-    let memory = get_memory(&mut caller)?;
-    memory
-        .read(&caller, module_data_ptr as usize, module.as_mut_slice())
-        .or_trap("lunatic::process::compile_module")?;
+        let mut module = vec![0; module_data_len as usize];
+        let memory = get_memory(&mut caller)?;
+        memory
+            .read(&caller, module_data_ptr as usize, module.as_mut_slice())
+            .or_trap("lunatic::process::compile_module")?;
 
-    let (mod_or_error_id, result) = match caller.data().runtime().compile_module(module) {
-        Ok(module) => (caller.data_mut().module_resources_mut().add(module), 0),
-        Err(error) => (caller.data_mut().error_resources_mut().add(error), 1),
-    };
+        let runtime = caller.data().runtime().clone();
+        let compiled = executor::unblock(move || runtime.compile_module(module.into())).await;
+        let (mod_or_error_id, result) = match compiled {
+            Ok(module) => (caller.data_mut().module_resources_mut().add(module), 0),
+            Err(error) => (caller.data_mut().error_resources_mut().add(error), 1),
+        };
 
-    memory
-        .write(&mut caller, id_ptr as usize, &mod_or_error_id.to_le_bytes())
-        .or_trap("lunatic::process::compile_module")?;
-    Ok(result)
+        memory
+            .write(&mut caller, id_ptr as usize, &mod_or_error_id.to_le_bytes())
+            .or_trap("lunatic::process::compile_module")?;
+        Ok(result)
+    })
 }
 
 // Drops the module from resources.
@@ -165,6 +244,7 @@ fn drop_module<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     module_id: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     caller
         .data_mut()
         .module_resources_mut()
@@ -180,16 +260,17 @@ fn drop_module<T: ProcessState + ProcessCtx<T>>(
 // Returns:
 // * ID of newly created configuration in case of success
 // * -1 in case the process doesn't have permission to create new configurations
-fn create_config<T>(mut caller: Caller<T>) -> i64
+fn create_config<T>(mut caller: Caller<T>) -> Result<i64, Trap>
 where
     T: ProcessState + ProcessCtx<T>,
     T::Config: ProcessConfigCtx,
 {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     if !caller.data().config().can_create_configs() {
-        return -1;
+        return Ok(-1);
     }
     let config = T::Config::default();
-    caller.data_mut().config_resources_mut().add(config) as i64
+    Ok(caller.data_mut().config_resources_mut().add(config) as i64)
 }
 
 // Drops the configuration from resources.
@@ -200,6 +281,7 @@ fn drop_config<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     config_id: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     caller
         .data_mut()
         .config_resources_mut()
@@ -218,6 +300,7 @@ fn config_set_max_memory<T: ProcessState + ProcessCtx<T>>(
     config_id: u64,
     max_memory: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let max_memory = usize::try_from(max_memory)
         .or_trap("lunatic::process::config_set_max_memory: max_memory exceeds platform max")?;
     caller
@@ -234,9 +317,10 @@ fn config_set_max_memory<T: ProcessState + ProcessCtx<T>>(
 // Traps:
 // * If the config ID doesn't exist.
 fn config_get_max_memory<T: ProcessState + ProcessCtx<T>>(
-    caller: Caller<T>,
+    mut caller: Caller<T>,
     config_id: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let max_memory = caller
         .data()
         .config_resources()
@@ -257,6 +341,7 @@ fn config_set_max_fuel<T: ProcessState + ProcessCtx<T>>(
     config_id: u64,
     max_fuel: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let max_fuel = match max_fuel {
         0 => None,
         max_fuel => Some(max_fuel),
@@ -278,9 +363,10 @@ fn config_set_max_fuel<T: ProcessState + ProcessCtx<T>>(
 // Traps:
 // * If the config ID doesn't exist.
 fn config_get_max_fuel<T: ProcessState + ProcessCtx<T>>(
-    caller: Caller<T>,
+    mut caller: Caller<T>,
     config_id: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let max_fuel = caller
         .data()
         .config_resources()
@@ -293,15 +379,199 @@ fn config_get_max_fuel<T: ProcessState + ProcessCtx<T>>(
     }
 }
 
+// Sets the fuel charged for each host call of **category** on a configuration. The cost is in
+// raw fuel units, one unit corresponds to one executed instruction.
+//
+// Categories:
+// * 0 - `lunatic::process`
+// * 1 - `lunatic::message`
+// * 2 - `lunatic::networking`
+// * 3 - `lunatic::timer`
+// * 4 - `lunatic::registry`
+//
+// Traps:
+// * If the config ID doesn't exist.
+// * If the category doesn't exist.
+fn config_set_host_call_cost<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    config_id: u64,
+    category: u32,
+    cost: u64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let category = HostCallCategory::try_from(category)
+        .ok()
+        .or_trap("lunatic::process::config_set_host_call_cost: Unknown category")?;
+    caller
+        .data_mut()
+        .config_resources_mut()
+        .get_mut(config_id)
+        .or_trap("lunatic::process::config_set_host_call_cost: Config ID doesn't exist")?
+        .set_host_call_cost(category, cost);
+    Ok(())
+}
+
+// Returns the fuel charged for each host call of **category** on a configuration. See
+// `config_set_host_call_cost` for the list of categories.
+//
+// Traps:
+// * If the config ID doesn't exist.
+// * If the category doesn't exist.
+fn config_get_host_call_cost<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    config_id: u64,
+    category: u32,
+) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let category = HostCallCategory::try_from(category)
+        .ok()
+        .or_trap("lunatic::process::config_get_host_call_cost: Unknown category")?;
+    let cost = caller
+        .data()
+        .config_resources()
+        .get(config_id)
+        .or_trap("lunatic::process::config_get_host_call_cost: Config ID doesn't exist")?
+        .get_host_call_cost(category);
+    Ok(cost)
+}
+
+// Limits how long processes spawned from a configuration can stay blocked on a single receive.
+// This catches processes waiting forever on a reply that got lost.
+//
+// A **timeout_ms** of 0 removes the limit.
+//
+// Policies:
+// * 0 - The receive returns as timed out, the same way as if the call had a timeout.
+// * 1 - The process is killed.
+//
+// Traps:
+// * If the config ID doesn't exist.
+// * If the policy doesn't exist.
+fn config_set_idle_receive_timeout<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    config_id: u64,
+    timeout_ms: u64,
+    policy: u32,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let policy = match policy {
+        0 => IdleReceivePolicy::Timeout,
+        1 => IdleReceivePolicy::Kill,
+        _ => {
+            return Err(Trap::new(
+                "lunatic::process::config_set_idle_receive_timeout: Unknown policy",
+            ))
+        }
+    };
+    let timeout = match timeout_ms {
+        0 => None,
+        timeout_ms => Some(IdleReceiveTimeout {
+            timeout: Duration::from_millis(timeout_ms),
+            policy,
+        }),
+    };
+    caller
+        .data_mut()
+        .config_resources_mut()
+        .get_mut(config_id)
+        .or_trap("lunatic::process::config_set_idle_receive_timeout: Config ID doesn't exist")?
+        .set_idle_receive_timeout(timeout);
+    Ok(())
+}
+
+// Returns the idle receive timeout of a configuration in milliseconds, 0 if there is none.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_get_idle_receive_timeout<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    config_id: u64,
+) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let timeout = caller
+        .data()
+        .config_resources()
+        .get(config_id)
+        .or_trap("lunatic::process::config_get_idle_receive_timeout: Config ID doesn't exist")?
+        .get_idle_receive_timeout();
+    Ok(timeout.map_or(0, |timeout| timeout.timeout.as_millis() as u64))
+}
+
+// Returns the idle receive policy of a configuration, see `config_set_idle_receive_timeout`.
+// If there is no idle receive timeout 0 is returned.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_get_idle_receive_policy<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    config_id: u64,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let timeout = caller
+        .data()
+        .config_resources()
+        .get(config_id)
+        .or_trap("lunatic::process::config_get_idle_receive_policy: Config ID doesn't exist")?
+        .get_idle_receive_timeout();
+    match timeout.map(|timeout| timeout.policy) {
+        None | Some(IdleReceivePolicy::Timeout) => Ok(0),
+        Some(IdleReceivePolicy::Kill) => Ok(1),
+    }
+}
+
+// Makes processes spawned from a configuration draw random values from a pseudo-random generator
+// seeded with **seed**. Processes with the same seed get the same random values, including the
+// ones returned by WASI's `random_get`.
+//
+// This is meant for reproducible tests only. Seeded random values are predictable and must never
+// be used for cryptography.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_set_random_seed<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    config_id: u64,
+    seed: u64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    caller
+        .data_mut()
+        .config_resources_mut()
+        .get_mut(config_id)
+        .or_trap("lunatic::process::config_set_random_seed: Config ID doesn't exist")?
+        .set_random_source(RandomSource::Seeded(seed));
+    Ok(())
+}
+
+// Makes processes spawned from a configuration draw random values from the entropy source of the
+// operating system. This is the default.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn config_use_system_random<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    config_id: u64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    caller
+        .data_mut()
+        .config_resources_mut()
+        .get_mut(config_id)
+        .or_trap("lunatic::process::config_use_system_random: Config ID doesn't exist")?
+        .set_random_source(RandomSource::System);
+    Ok(())
+}
+
 // Returns 1 if processes spawned from this configuration can compile Wasm modules, otherwise 0.
 //
 // Traps:
 // * If the config ID doesn't exist.
-fn config_can_compile_modules<T>(caller: Caller<T>, config_id: u64) -> Result<u32, Trap>
+fn config_can_compile_modules<T>(mut caller: Caller<T>, config_id: u64) -> Result<u32, Trap>
 where
     T: ProcessState + ProcessCtx<T>,
     T::Config: ProcessConfigCtx,
 {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let can = caller
         .data()
         .config_resources()
@@ -325,6 +595,7 @@ where
     T: ProcessState + ProcessCtx<T>,
     T::Config: ProcessConfigCtx,
 {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     caller
         .data_mut()
         .config_resources_mut()
@@ -339,11 +610,12 @@ where
 //
 // Traps:
 // * If the config ID doesn't exist.
-fn config_can_create_configs<T>(caller: Caller<T>, config_id: u64) -> Result<u32, Trap>
+fn config_can_create_configs<T>(mut caller: Caller<T>, config_id: u64) -> Result<u32, Trap>
 where
     T: ProcessState + ProcessCtx<T>,
     T::Config: ProcessConfigCtx,
 {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let can = caller
         .data()
         .config_resources()
@@ -367,6 +639,7 @@ where
     T: ProcessState + ProcessCtx<T>,
     T::Config: ProcessConfigCtx,
 {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     caller
         .data_mut()
         .config_resources_mut()
@@ -380,11 +653,12 @@ where
 //
 // Traps:
 // * If the config ID doesn't exist.
-fn config_can_spawn_processes<T>(caller: Caller<T>, config_id: u64) -> Result<u32, Trap>
+fn config_can_spawn_processes<T>(mut caller: Caller<T>, config_id: u64) -> Result<u32, Trap>
 where
     T: ProcessState + ProcessCtx<T>,
     T::Config: ProcessConfigCtx,
 {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let can = caller
         .data()
         .config_resources()
@@ -408,6 +682,7 @@ where
     T: ProcessState + ProcessCtx<T>,
     T::Config: ProcessConfigCtx,
 {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     caller
         .data_mut()
         .config_resources_mut()
@@ -424,7 +699,8 @@ where
 // is going to get a signal back with the value used as the tag.
 //
 // If *config_id* or *module_id* have the value -1, the same module/config is used as in the
-// process calling this function.
+// process calling this function. If the calling process follows a reloadable runtime config, the
+// latest version of it is used instead of the config of the calling process.
 //
 // The function arguments are passed as an array with the following structure:
 // [0 byte = type ID; 1..17 bytes = value as u128, ...]
@@ -445,14 +721,199 @@ where
 // * If any memory outside the guest heap space is referenced.
 #[allow(clippy::too_many_arguments)]
 fn spawn<T>(
+    caller: Caller<T>,
+    link: i64,
+    config_id: i64,
+    module_id: i64,
+    func_str_ptr: u32,
+    func_str_len: u32,
+    params_ptr: u32,
+    params_len: u32,
+    id_ptr: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + ProcessCtx<T> + ErrorCtx + LunaticWasiCtx + ResourceLimiter + Send + 'static,
+    for<'a> &'a T: Send,
+    T::Config: ProcessConfigCtx,
+{
+    spawn_named(
+        caller,
+        link,
+        config_id,
+        module_id,
+        func_str_ptr,
+        func_str_len,
+        params_ptr,
+        params_len,
+        0,
+        0,
+        id_ptr,
+    )
+}
+
+// Same as `spawn`, but gives the new process the name stored at **name_str_ptr**. The name is
+// only used for diagnostics and doesn't need to be unique. A **name_str_len** of 0 spawns the
+// process without a name.
+//
+// Returns:
+// * 0 on success - The ID of the newly created process is written to **id_ptr**
+// * 1 on error   - The error ID is written to **id_ptr**
+//
+// Traps:
+// * If the module ID doesn't exist.
+// * If the function or name string is not a valid utf8 string.
+// * If the params array is in a wrong format.
+// * If any memory outside the guest heap space is referenced.
+#[allow(clippy::too_many_arguments)]
+fn spawn_named<T>(
+    caller: Caller<T>,
+    link: i64,
+    config_id: i64,
+    module_id: i64,
+    func_str_ptr: u32,
+    func_str_len: u32,
+    params_ptr: u32,
+    params_len: u32,
+    name_str_ptr: u32,
+    name_str_len: u32,
+    id_ptr: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + ProcessCtx<T> + ErrorCtx + LunaticWasiCtx + ResourceLimiter + Send + 'static,
+    for<'a> &'a T: Send,
+    T::Config: ProcessConfigCtx,
+{
+    spawn_process(
+        caller,
+        link,
+        false,
+        config_id,
+        module_id,
+        func_str_ptr,
+        func_str_len,
+        params_ptr,
+        params_len,
+        name_str_ptr,
+        name_str_len,
+        None,
+        id_ptr,
+    )
+}
+
+// Same as `spawn`, but the new process is detached. It's never linked to the caller and can't
+// be linked to any process later, link signals are ignored by it. The death of the caller or of
+// any other process doesn't affect it and its own death isn't reported to anyone.
+//
+// Returns:
+// * 0 on success - The ID of the newly created process is written to **id_ptr**
+// * 1 on error   - The error ID is written to **id_ptr**
+//
+// Traps:
+// * If the module ID doesn't exist.
+// * If the function string is not a valid utf8 string.
+// * If the params array is in a wrong format.
+// * If any memory outside the guest heap space is referenced.
+#[allow(clippy::too_many_arguments)]
+fn spawn_detached<T>(
+    caller: Caller<T>,
+    config_id: i64,
+    module_id: i64,
+    func_str_ptr: u32,
+    func_str_len: u32,
+    params_ptr: u32,
+    params_len: u32,
+    id_ptr: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + ProcessCtx<T> + ErrorCtx + LunaticWasiCtx + ResourceLimiter + Send + 'static,
+    for<'a> &'a T: Send,
+    T::Config: ProcessConfigCtx,
+{
+    spawn_process(
+        caller,
+        0,
+        true,
+        config_id,
+        module_id,
+        func_str_ptr,
+        func_str_len,
+        params_ptr,
+        params_len,
+        0,
+        0,
+        None,
+        id_ptr,
+    )
+}
+
+// Same as `spawn`, but only returns once the new process called `lunatic::process::ready`, e.g.
+// after binding its listener. If it isn't ready within **timeout_ms** milliseconds it's unlinked
+// and killed, a linked spawner doesn't die with it. A **timeout_ms** of 0 waits without timeout.
+//
+// Returns:
+// * 0 on success - The ID of the newly created process is written to **id_ptr**
+// * 1 on error   - The error ID is written to **id_ptr**, also if the process failed or finished
+//                  before it was ready
+// * 2 on timeout - The error ID is written to **id_ptr**
+//
+// Traps:
+// * If the module ID doesn't exist.
+// * If the function string is not a valid utf8 string.
+// * If the params array is in a wrong format.
+// * If any memory outside the guest heap space is referenced.
+#[allow(clippy::too_many_arguments)]
+fn spawn_and_await_ready<T>(
+    caller: Caller<T>,
+    link: i64,
+    config_id: i64,
+    module_id: i64,
+    func_str_ptr: u32,
+    func_str_len: u32,
+    params_ptr: u32,
+    params_len: u32,
+    timeout_ms: u64,
+    id_ptr: u32,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + ProcessCtx<T> + ErrorCtx + LunaticWasiCtx + ResourceLimiter + Send + 'static,
+    for<'a> &'a T: Send,
+    T::Config: ProcessConfigCtx,
+{
+    spawn_process(
+        caller,
+        link,
+        false,
+        config_id,
+        module_id,
+        func_str_ptr,
+        func_str_len,
+        params_ptr,
+        params_len,
+        0,
+        0,
+        match timeout_ms {
+            0 => Some(None),
+            timeout_ms => Some(Some(Duration::from_millis(timeout_ms))),
+        },
+        id_ptr,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_process<T>(
     mut caller: Caller<T>,
     link: i64,
+    detached: bool,
     config_id: i64,
     module_id: i64,
     func_str_ptr: u32,
     func_str_len: u32,
     params_ptr: u32,
     params_len: u32,
+    name_str_ptr: u32,
+    name_str_len: u32,
+    // Waits until the process is ready if set, with an optional timeout.
+    await_ready: Option<Option<Duration>>,
     id_ptr: u32,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
 where
@@ -461,6 +922,7 @@ where
     T::Config: ProcessConfigCtx,
 {
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Process)?;
         if !caller.data().config().can_spawn_processes() {
             return Err(anyhow!("Process doesn't have permissions to spawn sub-processes").into());
         }
@@ -470,15 +932,21 @@ where
             return Err(anyhow!("Cannot spawn process during module initialization").into());
         }
 
-        let config = match config_id {
-            -1 => state.config().clone(),
-            config_id => Arc::new(
-                caller
-                    .data()
-                    .config_resources()
-                    .get(config_id as u64)
-                    .or_trap("lunatic::process::spawn: Config ID doesn't exist")?
-                    .clone(),
+        let (config, config_source) = match config_id {
+            -1 => match state.config_source() {
+                Some(source) => (source.load(), Some(source.clone())),
+                None => (state.config().clone(), None),
+            },
+            config_id => (
+                Arc::new(
+                    caller
+                        .data()
+                        .config_resources()
+                        .get(config_id as u64)
+                        .or_trap("lunatic::process::spawn: Config ID doesn't exist")?
+                        .clone(),
+                ),
+                None,
             ),
         };
 
@@ -515,6 +983,17 @@ where
                 Ok(result)
             })
             .collect::<Result<Vec<_>>>()?;
+        let name = match name_str_len {
+            0 => None,
+            _ => {
+                let name = memory
+                    .data(&caller)
+                    .get(name_str_ptr as usize..(name_str_ptr + name_str_len) as usize)
+                    .or_trap("lunatic::process::spawn")?;
+                let name = std::str::from_utf8(name).or_trap("lunatic::process::spawn")?;
+                Some(name.to_owned())
+            }
+        };
         // Should processes be linked together?
         let link: Option<(Option<i64>, Arc<dyn Process>)> = match link {
             0 => None,
@@ -528,7 +1007,18 @@ where
 
         let runtime = caller.data().runtime().clone();
         let registry = caller.data().registry().clone();
-        let mut state = T::new(runtime.clone(), module.clone(), config, registry)?;
+        let mut state = match T::new(runtime.clone(), module.clone(), config, registry) {
+            Ok(state) => state,
+            // The state can refuse the module, e.g. if the module asks for capabilities the
+            // config doesn't grant.
+            Err(error) => {
+                let error_id = caller.data_mut().error_resources_mut().add(error);
+                memory
+                    .write(&mut caller, id_ptr as usize, &error_id.to_le_bytes())
+                    .or_trap("lunatic::process::spawn")?;
+                return Ok(1);
+            }
+        };
 
         // Inherit stdout and stderr streams if they are redirected by the parent.
         let stdout = if let Some(stdout) = caller.data().get_stdout() {
@@ -542,7 +1032,7 @@ where
             // If stderr is same as stdout, use same `next_stream`.
             if let Some((stdout, next_stream)) = stdout {
                 if &stdout == stderr {
-                    state.set_stderr(next_stream);
+                    state.set_stderr(next_stream.with_kind(stderr.kind()));
                 } else {
                     state.set_stderr(stderr.next());
                 }
@@ -550,12 +1040,36 @@ where
                 state.set_stderr(stderr.next());
             }
         }
+        state.set_name(name);
+        state.set_config_source(config_source);
+        if let Some(extensions) = caller.data().extensions() {
+            state.set_extensions(extensions.clone());
+        }
 
-        let (proc_or_error_id, result) =
-            match spawn_wasm(runtime, module, state, function, params, link).await {
-                Ok((_, process)) => (caller.data_mut().process_resources_mut().add(process), 0),
-                Err(error) => (caller.data_mut().error_resources_mut().add(error), 1),
-            };
+        // Tracked before the child runs, it could finish right away.
+        let (parent_id, child_id) = (caller.data().id(), state.id());
+        let process_tree = runtime.process_tree().clone();
+        process_tree.spawned(parent_id, child_id);
+        let spawned = match (detached, await_ready) {
+            (true, _) => spawn_wasm_detached(runtime, module, state, function, params, None).await,
+            (false, Some(timeout)) => {
+                spawn_wasm_and_await_ready(
+                    runtime, module, state, function, params, link, None, timeout,
+                )
+                .await
+            }
+            (false, None) => spawn_wasm(runtime, module, state, function, params, link, None).await,
+        };
+        if spawned.is_err() {
+            process_tree.finished(child_id);
+        }
+        let (proc_or_error_id, result) = match spawned {
+            Ok((_, process)) => (caller.data_mut().process_resources_mut().add(process), 0),
+            Err(error) => {
+                let result = if error.is::<ReadyTimeout>() { 2 } else { 1 };
+                (caller.data_mut().error_resources_mut().add(error), result)
+            }
+        };
         memory
             .write(
                 &mut caller,
@@ -576,6 +1090,7 @@ fn drop_process<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     process_id: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     caller
         .data_mut()
         .process_resources_mut()
@@ -592,6 +1107,7 @@ fn clone_process<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     process_id: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let process = caller
         .data()
         .process_resources()
@@ -605,12 +1121,14 @@ fn clone_process<T: ProcessState + ProcessCtx<T>>(
 // lunatic::process::sleep_ms(millis: u64)
 //
 // Suspend process for `millis`.
-fn sleep_ms<T: ProcessState + ProcessCtx<T>>(
-    _: Caller<T>,
+fn sleep_ms<T: ProcessState + ProcessCtx<T> + Send>(
+    mut caller: Caller<T>,
     millis: u64,
-) -> Box<dyn Future<Output = ()> + Send + '_> {
+) -> Box<dyn Future<Output = Result<(), Trap>> + Send + '_> {
     Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Process)?;
         async_std::task::sleep(Duration::from_millis(millis)).await;
+        Ok(())
     })
 }
 
@@ -621,24 +1139,30 @@ fn sleep_ms<T: ProcessState + ProcessCtx<T>>(
 // 2. `trap != 0` the process will die and notify all linked processes of its death.
 //
 // The default behaviour for a newly spawned process is 2.
-fn die_when_link_dies<T: ProcessState + ProcessCtx<T>>(mut caller: Caller<T>, trap: u32) {
+fn die_when_link_dies<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    trap: u32,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     caller
         .data_mut()
         .signal_mailbox()
         .0
         .try_send(Signal::DieWhenLinkDies(trap != 0))
         .expect("The signal is sent to itself and the receiver must exist at this point");
+    Ok(())
 }
 
 // Create a process handle to itself and return resource ID.
-fn this<T: ProcessState + ProcessCtx<T>>(mut caller: Caller<T>) -> u64 {
+fn this<T: ProcessState + ProcessCtx<T>>(mut caller: Caller<T>) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let id = caller.data().id();
     let signal_mailbox = caller.data().signal_mailbox().clone();
     let process = WasmProcess::new(id, signal_mailbox.0);
-    caller
+    Ok(caller
         .data_mut()
         .process_resources_mut()
-        .add(Arc::new(process))
+        .add(Arc::new(process)))
 }
 
 // Returns UUID of a process as u128_ptr.
@@ -651,6 +1175,7 @@ fn id<T: ProcessState + ProcessCtx<T>>(
     process_id: u64,
     u128_ptr: u32,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let id = caller
         .data()
         .process_resources()
@@ -675,31 +1200,13 @@ fn link<T: ProcessState + ProcessCtx<T>>(
     tag: i64,
     process_id: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
     let tag = match tag {
         0 => None,
         tag => Some(tag),
     };
-    // Create handle to itself
-    let id = caller.data().id();
-    let signal_mailbox = caller.data().signal_mailbox().clone();
-    let this_process = WasmProcess::new(id, signal_mailbox.0);
-
-    // Send link signal to other process
-    let process = caller
-        .data()
-        .process_resources()
-        .get(process_id)
-        .or_trap("lunatic::process::link")?
-        .clone();
-    process.send(Signal::Link(tag, Arc::new(this_process)));
-
-    // Send link signal to itself
-    caller
-        .data_mut()
-        .signal_mailbox()
-        .0
-        .try_send(Signal::Link(tag, process))
-        .expect("The signal is sent to itself and the receiver must exist at this point");
+    let process = process_ref(&caller, process_id, "lunatic::process::link")?;
+    this_ref(&caller).link(tag, &process);
     Ok(())
 }
 
@@ -711,42 +1218,413 @@ fn unlink<T: ProcessState + ProcessCtx<T>>(
     mut caller: Caller<T>,
     process_id: u64,
 ) -> Result<(), Trap> {
-    // Create handle to itself
-    let id = caller.data().id();
-    let signal_mailbox = caller.data().signal_mailbox().clone();
-    let this_process = WasmProcess::new(id, signal_mailbox.0);
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let process = process_ref(&caller, process_id, "lunatic::process::unlink")?;
+    this_ref(&caller).unlink(&process);
+    Ok(())
+}
 
-    // Send unlink signal to other process
-    let process = caller
-        .data()
-        .process_resources()
-        .get(process_id)
-        .or_trap("lunatic::process::link")?
-        .clone();
-    process.send(Signal::UnLink(Arc::new(this_process)));
+// Send a Kill signal to **process_id**.
+//
+// Traps:
+// * If the process ID doesn't exist.
+fn kill<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    process_id: u64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    process_ref(&caller, process_id, "lunatic::process::kill")?.kill();
+    Ok(())
+}
 
-    // Send unlink signal to itself
-    caller
-        .data_mut()
-        .signal_mailbox()
-        .0
-        .try_send(Signal::UnLink(process))
-        .expect("The signal is sent to itself and the receiver must exist at this point");
+// Asks **process_id** to stop by sending it a shutdown message. If it's still running after
+// **grace_ms** milliseconds, it's killed. A grace period of 0 kills it immediately, like `kill`.
+//
+// Traps:
+// * If the process ID doesn't exist.
+fn kill_with_grace<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    process_id: u64,
+    grace_ms: u64,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    process_ref(&caller, process_id, "lunatic::process::kill_with_grace")?
+        .kill_with_grace(Duration::from_millis(grace_ms));
     Ok(())
 }
 
-// Send a Kill signal to **process_id**.
+// Sets the log level of **process_id**. It takes effect once the process handles the signal.
+//
+// Levels: -1 = default level, 0 = off, 1 = error, 2 = warn, 3 = info, 4 = debug, 5 = trace.
 //
 // Traps:
 // * If the process ID doesn't exist.
-fn kill<T: ProcessState + ProcessCtx<T>>(caller: Caller<T>, process_id: u64) -> Result<(), Trap> {
-    // Send kill signal to process
+// * If the level is not one of the above.
+fn set_log_level<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    process_id: u64,
+    level: i32,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let level = match level {
+        -1 => None,
+        0 => Some(LevelFilter::Off),
+        1 => Some(LevelFilter::Error),
+        2 => Some(LevelFilter::Warn),
+        3 => Some(LevelFilter::Info),
+        4 => Some(LevelFilter::Debug),
+        5 => Some(LevelFilter::Trace),
+        _ => return Err(Trap::new("lunatic::process::set_log_level: invalid level")),
+    };
+    process_ref(&caller, process_id, "lunatic::process::set_log_level")?.set_log_level(level);
+    Ok(())
+}
+
+// Returns a handle to the process **process_id** of the caller, trapping with `name` if it
+// doesn't exist.
+fn process_ref<T: ProcessState + ProcessCtx<T>>(
+    caller: &Caller<T>,
+    process_id: u64,
+    name: &str,
+) -> Result<ProcessRef, Trap> {
     let process = caller
         .data()
         .process_resources()
         .get(process_id)
-        .or_trap("lunatic::process::link")?
+        .or_trap(name)?;
+    Ok(ProcessRef::new(process.clone()))
+}
+
+// Returns a handle to the calling process.
+fn this_ref<T: ProcessState + ProcessCtx<T>>(caller: &Caller<T>) -> ProcessRef {
+    let id = caller.data().id();
+    let signal_mailbox = caller.data().signal_mailbox().0.clone();
+    ProcessRef::new(Arc::new(WasmProcess::new(id, signal_mailbox)))
+}
+
+// Returns the amount of fuel the process can still consume before it traps. One unit of fuel
+// roughly corresponds to one executed instruction. If the process has no fuel limit `u64::MAX` is
+// returned.
+//
+// The value is a snapshot, it goes down as soon as the guest continues executing.
+fn fuel_remaining<T: ProcessState>(mut caller: Caller<T>) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let remaining = match caller.data().config().get_max_fuel() {
+        Some(max_fuel) => {
+            let max_fuel = max_fuel.saturating_mul(UNIT_OF_COMPUTE_IN_INSTRUCTIONS);
+            let consumed = caller.fuel_consumed().unwrap_or(0);
+            max_fuel.saturating_sub(consumed)
+        }
+        None => u64::MAX,
+    };
+    Ok(remaining)
+}
+
+// Requests to replace the code of the running process with the function `func_str` of another
+// module, keeping the process ID, mailbox, links, registered name and all resources.
+//
+// The upgrade happens at the next `lunatic::message::receive`, after the current message was
+// handled. At that point the `state_len` bytes at `state_ptr` are handed over to the new instance
+// through its `lunatic_upgrade_state` export, the old code is unwound and the process continues
+// with the new function, which can't take parameters or return values. If the upgrade fails at
+// that point, the old code keeps running and the receive continues normally. See
+// `lunatic_process::upgrade` for the whole contract. A second request replaces the first one.
+//
+// Returns:
+// * 0 if the upgrade was requested
+// * 1 if the module doesn't export a function with this name and type `() -> ()`
+// * 2 if **state_len** is not 0 and the module doesn't export a function `lunatic_upgrade_state`
+//     of type `(i32) -> i32`
+//
+// Traps:
+// * If the module ID doesn't exist.
+// * If the function name is not a valid utf8 string.
+// * If any memory outside the guest heap space is referenced.
+fn upgrade<T>(
+    mut caller: Caller<T>,
+    module_id: u64,
+    func_str_ptr: u32,
+    func_str_len: u32,
+    state_ptr: u32,
+    state_len: u32,
+) -> Result<u32, Trap>
+where
+    T: ProcessState + ProcessCtx<T>,
+{
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let module = caller
+        .data()
+        .module_resources()
+        .get(module_id)
+        .or_trap("lunatic::process::upgrade: Module ID doesn't exist")?
         .clone();
-    process.send(Signal::Kill);
+    let memory = get_memory(&mut caller)?;
+    let func_str = memory
+        .data(&caller)
+        .get(func_str_ptr as usize..(func_str_ptr + func_str_len) as usize)
+        .or_trap("lunatic::process::upgrade")?;
+    let function = std::str::from_utf8(func_str)
+        .or_trap("lunatic::process::upgrade")?
+        .to_string();
+    memory
+        .data(&caller)
+        .get(state_ptr as usize..state_ptr as usize + state_len as usize)
+        .or_trap("lunatic::process::upgrade")?;
+
+    let func_type = |name: &str| {
+        module.exports().find_map(|export| match export.ty() {
+            wasmtime::ExternType::Func(ty) if export.name() == name => Some(ty),
+            _ => None,
+        })
+    };
+    match func_type(&function) {
+        Some(ty) if ty.params().len() == 0 && ty.results().len() == 0 => {}
+        _ => return Ok(1),
+    }
+    if state_len > 0 {
+        let i32_to_i32 = |ty: wasmtime::FuncType| {
+            ty.params().eq([ValType::I32]) && ty.results().eq([ValType::I32])
+        };
+        if !func_type(STATE_TRANSFER_EXPORT).is_some_and(i32_to_i32) {
+            return Ok(2);
+        }
+    }
+    caller.data_mut().set_pending_upgrade(Upgrade::Requested {
+        module,
+        function,
+        state: (state_ptr, state_len),
+    });
+    Ok(0)
+}
+
+// Reports that the process finished its initialization to the spawner waiting for it, if it was
+// spawned with `spawn_wasm_and_await_ready`. Calling it again, or if nobody is waiting, does
+// nothing.
+fn ready<T: ProcessState>(mut caller: Caller<T>) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    if let Some(notifier) = caller.data_mut().take_ready_notifier() {
+        // The spawner may have given up waiting already.
+        let _ = notifier.try_send(());
+    }
+    Ok(())
+}
+
+// Returns the number of milliseconds since the process was spawned. It's measured with a
+// monotonic clock and not affected by changes of the system time.
+fn uptime_ms<T: ProcessState>(mut caller: Caller<T>) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    Ok(caller.data().uptime().as_millis() as u64)
+}
+
+// Returns the number of children spawned by this process that are still alive, detached ones
+// included. If **transitive** is not 0, their children and so on are counted too. Children of a
+// process that finished are not counted as descendants anymore.
+//
+// The count is a consistent snapshot, but processes can finish or be spawned right after.
+fn child_count<T: ProcessState>(mut caller: Caller<T>, transitive: u32) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let id = caller.data().id();
+    Ok(caller
+        .data()
+        .runtime()
+        .process_tree()
+        .child_count(id, transitive != 0))
+}
+
+// Returns the number of bytes the memory of the process can still grow, before it reaches the
+// configured maximum.
+//
+// The value is a snapshot, it goes down as soon as the guest memory grows.
+//
+// Traps:
+// * If the guest doesn't export its memory.
+fn memory_available<T: ProcessState>(mut caller: Caller<T>) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let memory = get_memory(&mut caller)?;
+    let used = memory.data_size(&caller);
+    let max_memory = caller.data().config().get_max_memory();
+    Ok(max_memory.saturating_sub(used) as u64)
+}
+
+// Sets the name of this process, it's used in diagnostics and doesn't need to be unique. A
+// **name_str_len** of 0 removes the name.
+//
+// Traps:
+// * If the name is not a valid utf8 string.
+// * If any memory outside the guest heap space is referenced.
+fn set_name<T: ProcessState>(
+    mut caller: Caller<T>,
+    name_str_ptr: u32,
+    name_str_len: u32,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let name = match name_str_len {
+        0 => None,
+        _ => {
+            let memory = get_memory(&mut caller)?;
+            let name = memory
+                .data(&caller)
+                .get(name_str_ptr as usize..(name_str_ptr + name_str_len) as usize)
+                .or_trap("lunatic::process::set_name")?;
+            let name = std::str::from_utf8(name).or_trap("lunatic::process::set_name")?;
+            Some(name.to_owned())
+        }
+    };
+    caller.data_mut().set_name(name);
+    Ok(())
+}
+
+// Returns the size in bytes of this process' name, or 0 if it doesn't have a name.
+fn name_size<T: ProcessState>(mut caller: Caller<T>) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    Ok(caller.data().name().map(|name| name.len()).unwrap_or(0) as u32)
+}
+
+// Writes the name of this process to **name_str_ptr**. The `name_size` function can be used to
+// reserve enough space for it.
+//
+// Traps:
+// * If any memory outside the guest heap space is referenced.
+fn name<T: ProcessState>(mut caller: Caller<T>, name_str_ptr: u32) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let name = caller.data().name().unwrap_or_default().to_owned();
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(&mut caller, name_str_ptr as usize, name.as_bytes())
+        .or_trap("lunatic::process::name")?;
+    Ok(())
+}
+
+// Opts this process in or out of receiving the OS signals the runtime forwards (e.g. `SIGTERM`).
+// A forwarded signal arrives as a shutdown message. An **enable** value of 0 opts out.
+//
+// Returns:
+// * 0 on success
+// * 1 if the runtime doesn't forward OS signals
+fn forward_os_signals<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    enable: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let forwarder = match caller.data().runtime().signal_forwarder() {
+        Some(forwarder) => forwarder.clone(),
+        None => return Ok(1),
+    };
+    if enable != 0 {
+        let id = caller.data().id();
+        let signal_mailbox = caller.data().signal_mailbox().0.clone();
+        forwarder.register(Arc::new(WasmProcess::new(id, signal_mailbox)));
+    } else {
+        forwarder.unregister(caller.data().id());
+    }
+    Ok(0)
+}
+
+// Saves a checkpoint of the job this process belongs to, replacing the previous one. The job ID
+// is assigned by the host.
+//
+// Returns:
+// * 0 on success
+// * 1 if the process wasn't spawned with a job ID
+// * 2 if the checkpoint is too large for the checkpoint store, the previous one is kept
+//
+// Traps:
+// * If any memory outside the guest heap space is referenced.
+fn checkpoint_save<T: ProcessState>(
+    mut caller: Caller<T>,
+    data_ptr: u32,
+    data_len: u32,
+) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let job_id = match caller.data().config().get_job_id() {
+        Some(job_id) => job_id.to_owned(),
+        None => return Ok(1),
+    };
+    let memory = get_memory(&mut caller)?;
+    let data = memory
+        .data(&caller)
+        .get(data_ptr as usize..(data_ptr + data_len) as usize)
+        .or_trap("lunatic::process::checkpoint_save")?;
+    if caller.data().runtime().checkpoints().save(&job_id, data) {
+        Ok(0)
+    } else {
+        Ok(2)
+    }
+}
+
+// Loads the last checkpoint of the job this process belongs to.
+//
+// The first **buffer_len** bytes of the checkpoint are written to the buffer. Calling it with a
+// **buffer_len** of 0 returns the size without copying anything.
+//
+// Returns:
+// * The size of the checkpoint in bytes, also if it didn't fit into the buffer.
+// * -1 if the process wasn't spawned with a job ID or the job has no checkpoint yet.
+//
+// Traps:
+// * If any memory outside the guest heap space is referenced.
+fn checkpoint_load<T: ProcessState>(
+    mut caller: Caller<T>,
+    buffer_ptr: u32,
+    buffer_len: u32,
+) -> Result<i64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let checkpoint = match caller.data().config().get_job_id() {
+        Some(job_id) => caller.data().runtime().checkpoints().load(job_id),
+        None => None,
+    };
+    let checkpoint = match checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => return Ok(-1),
+    };
+    let len = checkpoint.len().min(buffer_len as usize);
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(&mut caller, buffer_ptr as usize, &checkpoint[..len])
+        .or_trap("lunatic::process::checkpoint_load")?;
+    Ok(checkpoint.len() as i64)
+}
+
+// Deletes the checkpoint of the job this process belongs to, once the job is done.
+//
+// Returns:
+// * 0 on success
+// * 1 if the process wasn't spawned with a job ID or the job has no checkpoint
+fn checkpoint_delete<T: ProcessState>(mut caller: Caller<T>) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let removed = match caller.data().config().get_job_id() {
+        Some(job_id) => caller.data().runtime().checkpoints().remove(job_id),
+        None => false,
+    };
+    if removed {
+        Ok(0)
+    } else {
+        Ok(1)
+    }
+}
+
+// Returns a random value from the random source of this process.
+fn random_u64<T: ProcessState + ProcessCtx<T>>(mut caller: Caller<T>) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    Ok(caller.data_mut().rng().next_u64())
+}
+
+// Fills **len** bytes starting at **ptr** with random values from the random source of this
+// process.
+//
+// Traps:
+// * If any memory outside the guest heap space is referenced.
+fn random_bytes<T: ProcessState + ProcessCtx<T>>(
+    mut caller: Caller<T>,
+    ptr: u32,
+    len: u32,
+) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let memory = get_memory(&mut caller)?;
+    let (memory_slice, state) = memory.data_and_store_mut(&mut caller);
+    let buffer = memory_slice
+        .get_mut(ptr as usize..(ptr as usize + len as usize))
+        .or_trap("lunatic::process::random_bytes")?;
+    state.rng().fill_bytes(buffer);
     Ok(())
 }
@@ -0,0 +1,123 @@
+/*!
+Checkpoints of long-running processes.
+
+A process spawned with a job ID (see [`ProcessConfig::set_job_id`](crate::config::ProcessConfig))
+can save an opaque blob under this ID from time to time. If it's killed and a new process is
+spawned for the same job, the new one loads the last checkpoint and resumes from there instead
+of starting over.
+
+Job IDs are assigned by the host only, guests can't pick the job they save to or load from.
+
+The [`CheckpointStore`] keeps the checkpoints in memory, they survive processes but not the
+runtime. Both the size of each checkpoint and the size of all checkpoints together are limited,
+a checkpoint that doesn't fit is refused instead of evicting the ones of other jobs.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Default limit of a single checkpoint, 1 MiB.
+pub const DEFAULT_MAX_CHECKPOINT_SIZE: usize = 1024 * 1024;
+/// Default limit of all checkpoints of a store together, 64 MiB.
+pub const DEFAULT_MAX_TOTAL_SIZE: usize = 64 * 1024 * 1024;
+
+/// Last checkpoint of each job.
+///
+/// Clones share the same checkpoints.
+#[derive(Clone, Debug)]
+pub struct CheckpointStore {
+    checkpoints: Arc<Mutex<Checkpoints>>,
+    max_checkpoint_size: usize,
+    max_total_size: usize,
+}
+
+#[derive(Debug, Default)]
+struct Checkpoints {
+    by_job: HashMap<String, Arc<[u8]>>,
+    total_size: usize,
+}
+
+impl Default for CheckpointStore {
+    fn default() -> Self {
+        Self::with_limits(DEFAULT_MAX_CHECKPOINT_SIZE, DEFAULT_MAX_TOTAL_SIZE)
+    }
+}
+
+impl CheckpointStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a store that refuses checkpoints larger than `max_checkpoint_size` bytes, or that
+    /// would grow all checkpoints together above `max_total_size` bytes.
+    pub fn with_limits(max_checkpoint_size: usize, max_total_size: usize) -> Self {
+        Self {
+            checkpoints: Arc::default(),
+            max_checkpoint_size,
+            max_total_size,
+        }
+    }
+
+    /// Replaces the checkpoint of the job. Returns `false` and keeps the previous checkpoint if
+    /// the new one doesn't fit into the limits of the store.
+    pub fn save(&self, job_id: &str, data: &[u8]) -> bool {
+        if data.len() > self.max_checkpoint_size {
+            return false;
+        }
+        let mut checkpoints = self.checkpoints.lock().expect("never poisoned");
+        let previous_size = checkpoints.by_job.get(job_id).map_or(0, |data| data.len());
+        let total_size = checkpoints.total_size - previous_size + data.len();
+        if total_size > self.max_total_size {
+            return false;
+        }
+        checkpoints.total_size = total_size;
+        checkpoints.by_job.insert(job_id.to_owned(), data.into());
+        true
+    }
+
+    /// Returns the last checkpoint of the job.
+    pub fn load(&self, job_id: &str) -> Option<Arc<[u8]>> {
+        let checkpoints = self.checkpoints.lock().expect("never poisoned");
+        checkpoints.by_job.get(job_id).cloned()
+    }
+
+    /// Removes the checkpoint of a finished job. Returns `false` if there was none.
+    pub fn remove(&self, job_id: &str) -> bool {
+        let mut checkpoints = self.checkpoints.lock().expect("never poisoned");
+        match checkpoints.by_job.remove(job_id) {
+            Some(data) => {
+                checkpoints.total_size -= data.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the size of all checkpoints together in bytes.
+    pub fn total_size(&self) -> usize {
+        self.checkpoints.lock().expect("never poisoned").total_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoints_are_limited() {
+        let store = CheckpointStore::with_limits(4, 6);
+        assert!(!store.save("a", b"12345"));
+        assert!(store.save("a", b"1234"));
+        assert!(!store.save("b", b"123"));
+        assert!(store.save("b", b"12"));
+        // Replacing a checkpoint only counts the difference.
+        assert!(store.save("a", b"12"));
+        assert!(store.save("b", b"1234"));
+        assert_eq!(store.total_size(), 6);
+        assert!(store.remove("a"));
+        assert_eq!(store.total_size(), 4);
+        assert_eq!(store.load("b").as_deref(), Some(&b"1234"[..]));
+    }
+}
@@ -1,4 +1,11 @@
-use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{fuel::HostCallCategory, random::RandomSource};
 
 // One unit of fuel represents around 100k instructions.
 pub const UNIT_OF_COMPUTE_IN_INSTRUCTIONS: u64 = 100_000;
@@ -12,8 +19,9 @@ pub const UNIT_OF_COMPUTE_IN_INSTRUCTIONS: u64 = 100_000;
 /// the process. This host functions are the ones that consider specific configuration while
 /// performing operations.
 ///
-/// However, two properties of a process are enforced by the runtime (maximum memory and maximum
-/// fuel usage). This two properties need to be part of every configuration.
+/// However, some properties of a process are enforced by the runtime (e.g. maximum memory, maximum
+/// fuel usage and the mailbox limit). These properties need to be part of every configuration, a
+/// configuration can't silently drop a limit the host sets.
 ///
 /// `ProcessConfig` must be serializable in case it is used to spawn processes on other nodes.
 pub trait ProcessConfig: Clone + Serialize + DeserializeOwned {
@@ -21,4 +29,113 @@ pub trait ProcessConfig: Clone + Serialize + DeserializeOwned {
     fn get_max_fuel(&self) -> Option<u64>;
     fn set_max_memory(&mut self, max_memory: usize);
     fn get_max_memory(&self) -> usize;
+    /// Sets the fuel charged for each host call of the `category`, see [`crate::fuel`].
+    fn set_host_call_cost(&mut self, category: HostCallCategory, cost: u64);
+    fn get_host_call_cost(&self, category: HostCallCategory) -> u64;
+    /// Sets how long processes can block on a receive.
+    fn set_idle_receive_timeout(&mut self, timeout: Option<IdleReceiveTimeout>);
+    fn get_idle_receive_timeout(&self) -> Option<IdleReceiveTimeout>;
+    /// Sets where processes get random values from, see [`crate::random`].
+    fn set_random_source(&mut self, source: RandomSource);
+    fn get_random_source(&self) -> RandomSource;
+    /// Sets the function processes start with, if the entry they are spawned with doesn't exist.
+    fn set_fallback_entry(&mut self, entry: Option<String>);
+    fn get_fallback_entry(&self) -> Option<&str>;
+    /// Sets how many messages the mailbox of processes can hold, see [`MailboxLimit`].
+    fn set_mailbox_limit(&mut self, limit: Option<MailboxLimit>);
+    fn get_mailbox_limit(&self) -> Option<MailboxLimit>;
+    /// Sets the job processes save their checkpoints under, see [`crate::checkpoint`]. Only the
+    /// host assigns job IDs.
+    fn set_job_id(&mut self, job_id: Option<String>);
+    fn get_job_id(&self) -> Option<&str>;
+    /// Sets how many bytes of memory are grown and touched right after instantiation, see
+    /// [`WasmtimeInstance::prefault_memory`](crate::runtimes::wasmtime::WasmtimeInstance::prefault_memory).
+    fn set_prefault_memory(&mut self, size: Option<usize>);
+    fn get_prefault_memory(&self) -> Option<usize>;
+    /// Sets the guest path of the private temp directory each process gets, `None` disables it.
+    fn set_temp_dir(&mut self, guest_path: Option<String>);
+    fn get_temp_dir(&self) -> Option<&str>;
+}
+
+/// A runtime-wide configuration that can be replaced without restarting the runtime.
+///
+/// Processes that follow it (see
+/// [`ProcessState::set_config_source`](crate::state::ProcessState::set_config_source)) use the
+/// latest configuration for the processes they spawn without an explicit one. Replacing the
+/// configuration only affects processes spawned afterwards, **running processes keep the
+/// configuration they were spawned with**, including their limits and permissions.
+///
+/// Clones share the same configuration.
+#[derive(Debug, Default)]
+pub struct ReloadableConfig<C> {
+    current: Arc<RwLock<Arc<C>>>,
+}
+
+impl<C> ReloadableConfig<C> {
+    pub fn new(config: C) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// Returns the current configuration.
+    pub fn load(&self) -> Arc<C> {
+        self.current.read().expect("never poisoned").clone()
+    }
+
+    /// Replaces the configuration for processes spawned from now on.
+    pub fn store(&self, config: C) {
+        *self.current.write().expect("never poisoned") = Arc::new(config);
+    }
+}
+
+// Derived `Clone` would require `C: Clone`.
+impl<C> Clone for ReloadableConfig<C> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+        }
+    }
+}
+
+/// Limits how long a process can stay blocked on a receive.
+///
+/// Each receive call waits at most `timeout` for a message, even if the call itself has no
+/// timeout or a longer one. The time is measured per call, so receiving a message starts over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdleReceiveTimeout {
+    pub timeout: Duration,
+    pub policy: IdleReceivePolicy,
+}
+
+/// What happens once a process was blocked on a receive for longer than its idle timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdleReceivePolicy {
+    /// The receive returns as if it timed out, the guest can react to it.
+    Timeout,
+    /// The process is killed.
+    Kill,
+}
+
+/// Limits how many messages can wait in the mailbox of a process.
+///
+/// Only data and signal messages count towards the limit, system messages are never dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailboxLimit {
+    pub capacity: usize,
+    pub overflow: MailboxOverflow,
+}
+
+/// What happens to a message arriving at a full mailbox.
+///
+/// Sending a message never blocks the sender and doesn't wait for the receiving process, so
+/// there is no backpressure policy. The sender isn't told either if its message was dropped, the
+/// receiving mailbox only counts the dropped messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MailboxOverflow {
+    /// The arriving message is dropped.
+    DropNewest,
+    /// The oldest queued message is dropped to make room. A message that is already handed over
+    /// to a waiting receive is not in the queue anymore and never dropped.
+    DropOldest,
 }
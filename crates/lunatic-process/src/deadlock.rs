@@ -0,0 +1,313 @@
+/*!
+Heuristic detection of processes that wait on each other forever.
+
+A process blocked in a receive without a timeout can only continue once another process sends it
+a message. The [`DeadlockDetector`] keeps track of such processes, together with the processes
+they hold handles to and could send a message to. Every scan interval it looks for cycles of
+processes that were all blocked for at least one whole interval and reports them. If two
+processes both wait on a message and only hold the handle of each other, neither of them can be
+the one to send it.
+
+Processes that have timers pending are never considered blocked, a timer could wake them up.
+
+## False positives
+
+The detector only sees a part of the picture, it's meant as a development aid:
+
+* A process outside of the cycle that holds a handle to one of the blocked processes, or finds it
+  through the registry, could still send it a message.
+* A process handling external IO (e.g. a TCP connection) and forwarding data as messages can
+  wake up the cycle at any time, even after being idle for longer than the scan interval.
+* Handles can also be in flight, inside of a message that wasn't received yet.
+
+Use a scan interval well above the longest expected idle time of the application and prefer
+[`DeadlockAction::Report`] if external IO is involved.
+*/
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use uuid::Uuid;
+
+use crate::{executor, Process, Signal};
+
+type DeadlockCallback = Arc<dyn Fn(&[Uuid]) + Send + Sync>;
+
+/// What happens to the processes of a detected cycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeadlockAction {
+    /// The cycle is reported once.
+    Report,
+    /// The cycle is reported and all processes in it are killed. Linked processes see them
+    /// die with a failure.
+    Kill,
+}
+
+/// Scans blocked processes for cycles.
+///
+/// Clones share the same state. The scans run on a background task that stops once all clones
+/// are dropped.
+#[derive(Clone)]
+pub struct DeadlockDetector {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    interval: Duration,
+    action: DeadlockAction,
+    callback: DeadlockCallback,
+    waiting: Mutex<HashMap<Uuid, Waiting>>,
+}
+
+struct Waiting {
+    process: Arc<dyn Process>,
+    peers: Vec<Uuid>,
+    since: Instant,
+    reported: bool,
+}
+
+impl DeadlockDetector {
+    /// Starts scanning every `interval`. The callback receives the IDs of the processes in each
+    /// detected cycle.
+    pub fn new<F>(interval: Duration, action: DeadlockAction, callback: F) -> Self
+    where
+        F: Fn(&[Uuid]) + Send + Sync + 'static,
+    {
+        let inner = Arc::new(Inner {
+            interval,
+            action,
+            callback: Arc::new(callback),
+            waiting: Mutex::new(HashMap::new()),
+        });
+        let weak = Arc::downgrade(&inner);
+        executor::spawn(scan_loop(weak, interval));
+        Self { inner }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.inner.interval
+    }
+
+    pub fn action(&self) -> DeadlockAction {
+        self.inner.action
+    }
+
+    /// Marks `process` as blocked on its mailbox until the returned guard is dropped.
+    ///
+    /// `peers` are the IDs of the processes it holds handles to.
+    pub fn waiting(&self, process: Arc<dyn Process>, peers: Vec<Uuid>) -> WaitGuard {
+        let id = process.id();
+        let waiting = Waiting {
+            process,
+            peers,
+            since: Instant::now(),
+            reported: false,
+        };
+        let mut all = self.inner.waiting.lock().expect("never poisoned");
+        all.insert(id, waiting);
+        WaitGuard {
+            detector: Arc::downgrade(&self.inner),
+            id,
+        }
+    }
+
+    /// Returns the cycles of processes that have been blocked for at least one scan interval.
+    ///
+    /// Cycles that were already reported are included too.
+    pub fn scan(&self) -> Vec<Vec<Uuid>> {
+        let all = self.inner.waiting.lock().expect("never poisoned");
+        find_cycles(&all, self.inner.interval)
+    }
+}
+
+impl Debug for DeadlockDetector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeadlockDetector")
+            .field("interval", &self.inner.interval)
+            .field("action", &self.inner.action)
+            .finish()
+    }
+}
+
+/// Keeps a process marked as blocked while it's alive.
+pub struct WaitGuard {
+    detector: Weak<Inner>,
+    id: Uuid,
+}
+
+impl Drop for WaitGuard {
+    fn drop(&mut self) {
+        if let Some(inner) = self.detector.upgrade() {
+            let mut all = inner.waiting.lock().expect("never poisoned");
+            all.remove(&self.id);
+        }
+    }
+}
+
+async fn scan_loop(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        executor::sleep(interval).await;
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let mut all = inner.waiting.lock().expect("never poisoned");
+        for cycle in find_cycles(&all, inner.interval) {
+            // Don't report a cycle each interval.
+            if cycle.iter().all(|id| all[id].reported) {
+                continue;
+            }
+            warn!("Processes {:?} are deadlocked", cycle);
+            for id in cycle.iter() {
+                let waiting = all.get_mut(id).expect("cycle is made of waiting processes");
+                waiting.reported = true;
+                if inner.action == DeadlockAction::Kill {
+                    waiting.process.send(Signal::Kill(Duration::ZERO));
+                }
+            }
+            let callback = inner.callback.clone();
+            executor::spawn_blocking(move || callback(&cycle));
+        }
+    }
+}
+
+// Returns the strongly connected components with more than one process, of the graph formed by
+// processes blocked for at least `interval` and the handles they hold to each other.
+fn find_cycles(all: &HashMap<Uuid, Waiting>, interval: Duration) -> Vec<Vec<Uuid>> {
+    let now = Instant::now();
+    let blocked: Vec<Uuid> = all
+        .iter()
+        .filter(|(_, waiting)| now.duration_since(waiting.since) >= interval)
+        .map(|(id, _)| *id)
+        .collect();
+    let index: HashMap<Uuid, usize> = blocked.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    let edges: Vec<Vec<usize>> = blocked
+        .iter()
+        .map(|id| {
+            all[id]
+                .peers
+                .iter()
+                .filter(|peer| *peer != id)
+                .filter_map(|peer| index.get(peer).copied())
+                .collect()
+        })
+        .collect();
+    let mut reversed = vec![Vec::new(); blocked.len()];
+    for (from, to) in edges.iter().enumerate() {
+        for to in to {
+            reversed[*to].push(from);
+        }
+    }
+
+    // Kosaraju, first pass: order the nodes by DFS finishing time.
+    let mut visited = vec![false; blocked.len()];
+    let mut order = Vec::with_capacity(blocked.len());
+    for start in 0..blocked.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![(start, 0)];
+        while let Some((node, next)) = stack.last_mut() {
+            if let Some(&to) = edges[*node].get(*next) {
+                *next += 1;
+                if !visited[to] {
+                    visited[to] = true;
+                    stack.push((to, 0));
+                }
+            } else {
+                order.push(*node);
+                stack.pop();
+            }
+        }
+    }
+
+    // Second pass: each DFS on the reversed graph collects one component.
+    let mut assigned = vec![false; blocked.len()];
+    let mut cycles = Vec::new();
+    for start in order.into_iter().rev() {
+        if assigned[start] {
+            continue;
+        }
+        assigned[start] = true;
+        let mut component = Vec::new();
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            component.push(blocked[node]);
+            for &from in reversed[node].iter() {
+                if !assigned[from] {
+                    assigned[from] = true;
+                    stack.push(from);
+                }
+            }
+        }
+        if component.len() > 1 {
+            component.sort_unstable();
+            cycles.push(component);
+        }
+    }
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{DeadlockAction, DeadlockDetector};
+    use crate::Process;
+
+    fn blocked_process() -> Arc<dyn Process> {
+        let (_join, process) = crate::spawn(|_this, _mailbox| async move {
+            async_std::task::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        Arc::new(process)
+    }
+
+    #[async_std::test]
+    async fn only_cycles_of_blocked_processes_are_found() {
+        let detector =
+            DeadlockDetector::new(Duration::from_millis(10), DeadlockAction::Report, |_| {});
+        let (a, b, c) = (blocked_process(), blocked_process(), blocked_process());
+        let _a = detector.waiting(a.clone(), vec![b.id()]);
+        let _b = detector.waiting(b.clone(), vec![a.id(), c.id()]);
+        // `c` is stuck, but it can't wake up `b` and is not part of the cycle.
+        let _c = detector.waiting(c.clone(), vec![]);
+        assert!(detector.scan().is_empty());
+
+        async_std::task::sleep(Duration::from_millis(20)).await;
+        let mut expected = vec![a.id(), b.id()];
+        expected.sort_unstable();
+        assert_eq!(detector.scan(), vec![expected]);
+
+        // A process that isn't blocked anymore breaks the cycle.
+        drop(_a);
+        assert!(detector.scan().is_empty());
+    }
+
+    #[async_std::test]
+    async fn deadlocked_processes_are_killed() {
+        let (sender, receiver) = async_std::channel::unbounded();
+        let detector = DeadlockDetector::new(
+            Duration::from_millis(10),
+            DeadlockAction::Kill,
+            move |ids| {
+                sender.try_send(ids.len()).unwrap();
+            },
+        );
+        let (join_a, a) = crate::spawn(|_this, _mailbox| async move {
+            async_std::task::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        let (a, b): (Arc<dyn Process>, _) = (Arc::new(a), blocked_process());
+        let _a = detector.waiting(a.clone(), vec![b.id()]);
+        let _b = detector.waiting(b.clone(), vec![a.id()]);
+        assert_eq!(receiver.recv().await.unwrap(), 2);
+        assert!(join_a.await.is_err());
+    }
+}
@@ -0,0 +1,321 @@
+/*!
+Deterministic scheduling of processes for reproducible tests.
+
+By default processes are spawned onto the multi-threaded `async_std` executor and the order in
+which they make progress depends on the OS scheduler. This makes tests exercising the interaction
+of multiple processes flaky.
+
+The [`DeterministicScheduler`] is a single-threaded executor that can be used instead. While a
+future is driven by [`DeterministicScheduler::block_on`], all processes spawned from it (directly
+or transitively) are placed on the same thread and stepped one at a time. If multiple processes
+are ready to run, the next one is picked by a pseudo-random generator seeded with a user provided
+value. Running the same code with the same seed results in the same interleaving of processes and
+the same order of message delivery.
+
+**This mode is intended for testing, not for production throughput.** All processes share one
+thread. Wake-ups coming from outside of the executor (timers, networking) still depend on the
+wall-clock and can't be made deterministic by the scheduler.
+*/
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Wake, Waker},
+};
+
+thread_local! {
+    // The scheduler driving the current thread, if any.
+    static CURRENT: RefCell<Option<Spawner>> = const { RefCell::new(None) };
+}
+
+// Task ID reserved for the future passed to `block_on`.
+const MAIN_TASK: usize = 0;
+
+type LocalTask = Pin<Box<dyn Future<Output = ()>>>;
+
+/// A single-threaded executor that steps processes in a fixed, seeded order.
+///
+/// ## Example:
+///
+/// ```no_run
+/// use lunatic_process::deterministic::DeterministicScheduler;
+///
+/// let scheduler = DeterministicScheduler::new(1337);
+/// scheduler.block_on(async {
+///     let (join, _process) = lunatic_process::spawn(|_this, _mailbox| async move { Ok(()) });
+///     join.await.unwrap();
+/// });
+/// ```
+pub struct DeterministicScheduler {
+    seed: u64,
+}
+
+impl DeterministicScheduler {
+    /// Create a new scheduler. The same `seed` always results in the same scheduling order.
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Runs the future to completion on the current thread.
+    ///
+    /// All processes spawned while the future is running are also driven by this scheduler. Once
+    /// the future finishes, processes that are still running are dropped.
+    ///
+    /// ## Panics
+    ///
+    /// If it's called from inside another `block_on` call.
+    pub fn block_on<F: Future>(&self, fut: F) -> F::Output {
+        let shared = Arc::new(Shared::default());
+        let spawner = Spawner {
+            shared: shared.clone(),
+            spawned: Rc::new(RefCell::new(Vec::new())),
+            next_id: Rc::new(Cell::new(MAIN_TASK + 1)),
+        };
+        let _guard = CurrentGuard::enter(spawner.clone());
+
+        let mut rng = XorShift::new(self.seed);
+        let mut tasks: HashMap<usize, LocalTask> = HashMap::new();
+        let main_waker = Waker::from(Arc::new(TaskWaker {
+            id: MAIN_TASK,
+            shared: shared.clone(),
+        }));
+        tokio::pin!(fut);
+        shared.wake(MAIN_TASK);
+
+        loop {
+            // Take over tasks spawned during the last step.
+            tasks.extend(spawner.spawned.borrow_mut().drain(..));
+
+            let id = {
+                let mut ready = shared.ready.lock().expect("never poisoned");
+                // If nothing is ready wait for an external wake-up (timers, networking, ...).
+                while ready.is_empty() {
+                    ready = shared.condvar.wait(ready).expect("never poisoned");
+                }
+                // `BTreeSet` iterates in order, so the choice only depends on the rng state.
+                let index = rng.next() as usize % ready.len();
+                let id = *ready.iter().nth(index).expect("index is in bounds");
+                ready.remove(&id);
+                id
+            };
+
+            if id == MAIN_TASK {
+                let mut context = Context::from_waker(&main_waker);
+                if let Poll::Ready(output) = fut.as_mut().poll(&mut context) {
+                    return output;
+                }
+            } else if let Some(task) = tasks.get_mut(&id) {
+                let waker = Waker::from(Arc::new(TaskWaker {
+                    id,
+                    shared: shared.clone(),
+                }));
+                let mut context = Context::from_waker(&waker);
+                if task.as_mut().poll(&mut context).is_ready() {
+                    tasks.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+/// Returns `true` if the current thread is driven by a [`DeterministicScheduler`].
+pub fn is_active() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+// Spawns the future onto the scheduler driving the current thread.
+//
+// Panics if the current thread is not driven by a scheduler, check with `is_active()` first.
+pub(crate) fn spawn<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let state = Arc::new(Mutex::new(JoinState {
+        output: None,
+        waker: None,
+    }));
+    let task_state = state.clone();
+    let task = async move {
+        let output = fut.await;
+        let mut state = task_state.lock().expect("never poisoned");
+        state.output = Some(output);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    };
+    CURRENT.with(|current| {
+        let current = current.borrow();
+        let spawner = current
+            .as_ref()
+            .expect("must be called from inside `DeterministicScheduler::block_on`");
+        let id = spawner.next_id.get();
+        spawner.next_id.set(id + 1);
+        spawner.spawned.borrow_mut().push((id, Box::pin(task)));
+        spawner.shared.wake(id);
+    });
+    JoinHandle { state }
+}
+
+/// Awaits the output of a task spawned onto a [`DeterministicScheduler`].
+pub(crate) struct JoinHandle<T> {
+    state: Arc<Mutex<JoinState<T>>>,
+}
+
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().expect("never poisoned");
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Spawner {
+    shared: Arc<Shared>,
+    spawned: Rc<RefCell<Vec<(usize, LocalTask)>>>,
+    next_id: Rc<Cell<usize>>,
+}
+
+// State that can be accessed by wakers from other threads.
+#[derive(Default)]
+struct Shared {
+    ready: Mutex<BTreeSet<usize>>,
+    condvar: Condvar,
+}
+
+impl Shared {
+    fn wake(&self, id: usize) {
+        self.ready.lock().expect("never poisoned").insert(id);
+        self.condvar.notify_one();
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    shared: Arc<Shared>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.shared.wake(self.id);
+    }
+}
+
+// Makes sure that the thread is released from the scheduler, even if a task panics.
+struct CurrentGuard;
+
+impl CurrentGuard {
+    fn enter(spawner: Spawner) -> Self {
+        CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            assert!(
+                current.is_none(),
+                "`DeterministicScheduler::block_on` can't be nested"
+            );
+            *current = Some(spawner);
+        });
+        CurrentGuard
+    }
+}
+
+impl Drop for CurrentGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().take());
+    }
+}
+
+// Small and fast pseudo-random generator (xorshift64*), good enough to pick the next task.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // The state of a xorshift generator must never be 0.
+        Self(if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        })
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::DeterministicScheduler;
+
+    // Spawns a few processes that interleave writes into a shared log.
+    fn run(seed: u64) -> Vec<usize> {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let scheduler = DeterministicScheduler::new(seed);
+        scheduler.block_on(async {
+            let mut joins = Vec::new();
+            for i in 0..4 {
+                let log = log.clone();
+                let (join, process) = crate::spawn(move |_this, _mailbox| async move {
+                    for _ in 0..5 {
+                        log.lock().unwrap().push(i);
+                        async_std::task::yield_now().await;
+                    }
+                    Ok(())
+                });
+                joins.push((join, process));
+            }
+            for (join, _process) in joins {
+                join.await.unwrap();
+            }
+        });
+        let log = log.lock().unwrap().clone();
+        log
+    }
+
+    #[test]
+    fn same_seed_same_order() {
+        let first = run(42);
+        assert_eq!(first.len(), 20);
+        for _ in 0..10 {
+            assert_eq!(run(42), first);
+        }
+    }
+
+    #[test]
+    fn processes_are_driven_by_scheduler() {
+        let scheduler = DeterministicScheduler::new(7);
+        let result = scheduler.block_on(async {
+            assert!(super::is_active());
+            let (join, _process) = crate::spawn(|_this, _mailbox| async move {
+                assert!(super::is_active());
+                Ok(())
+            });
+            join.await
+        });
+        assert!(result.is_ok());
+        assert!(!super::is_active());
+    }
+}
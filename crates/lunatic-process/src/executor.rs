@@ -0,0 +1,176 @@
+/*!
+The async runtime driving processes and background tasks.
+
+By default everything is spawned onto the `async_std` executor. Embedders that build their
+application on a different runtime can install their own [`Executor`] with [`set_executor`],
+before spawning the first process. With the `tokio-executor` feature, [`TokioExecutor`] is
+provided for running on tokio.
+
+Channels used for mailboxes and the IO types of the host functions are not tied to an executor
+and work with all of them.
+
+## Scheduling
+
+A process is a single task of the executor. It isn't bound to a thread: every time it yields
+(on a receive, on IO or when it used up a slice of its fuel) it can resume on any worker thread.
+With the work-stealing executors of `async_std` and tokio, idle threads take over queued tasks
+from busy ones, so there is nothing to rebalance on the lunatic side. Embedders that need a
+different placement of processes can implement it in their own [`Executor`].
+*/
+
+use std::{future::Future, pin::Pin, sync::Arc, sync::OnceLock, time::Duration};
+
+use async_std::channel::bounded;
+
+use crate::{JoinHandle, JoinHandleInner};
+
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Primitives of an async runtime.
+pub trait Executor: Send + Sync + 'static {
+    /// Runs `future` in the background, until it finishes.
+    fn spawn(&self, future: BoxFuture);
+    /// Runs `f` on a thread where blocking is allowed.
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>);
+    /// Returns a future resolving after `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture;
+}
+
+static EXECUTOR: OnceLock<Arc<dyn Executor>> = OnceLock::new();
+
+/// Installs the executor used for all processes and background tasks spawned afterwards.
+///
+/// The executor can only be set once. Returns it back if one was already installed.
+pub fn set_executor<E: Executor>(executor: E) -> Result<(), E> {
+    let mut executor = Some(executor);
+    EXECUTOR.get_or_init(|| Arc::new(executor.take().expect("only taken once")));
+    match executor {
+        Some(executor) => Err(executor),
+        None => Ok(()),
+    }
+}
+
+/// Spawns a background task on the installed executor.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let inner = match EXECUTOR.get() {
+        Some(executor) => {
+            let (sender, receiver) = bounded(1);
+            executor.spawn(Box::pin(async move {
+                let _ = sender.send(future.await).await;
+            }));
+            JoinHandleInner::Executor(receiver)
+        }
+        None => JoinHandleInner::AsyncStd(async_std::task::spawn(future)),
+    };
+    JoinHandle { inner }
+}
+
+/// Runs `f` on a thread of the installed executor where blocking is allowed, without waiting on
+/// it.
+pub fn spawn_blocking<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    match EXECUTOR.get() {
+        Some(executor) => executor.spawn_blocking(Box::new(f)),
+        None => {
+            async_std::task::spawn_blocking(f);
+        }
+    }
+}
+
+/// Runs `f` on a thread of the installed executor where blocking is allowed and resolves to its
+/// result.
+pub async fn unblock<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (sender, receiver) = bounded(1);
+    spawn_blocking(move || {
+        let _ = sender.try_send(f());
+    });
+    receiver.recv().await.expect("the blocking task panicked")
+}
+
+/// Resolves after `duration`, using the timers of the installed executor.
+pub async fn sleep(duration: Duration) {
+    match EXECUTOR.get() {
+        Some(executor) => executor.sleep(duration).await,
+        None => async_std::task::sleep(duration).await,
+    }
+}
+
+/// Spawns onto the `async_std` executor. Used if no other executor is installed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsyncStdExecutor;
+
+impl Executor for AsyncStdExecutor {
+    fn spawn(&self, future: BoxFuture) {
+        async_std::task::spawn(future);
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        async_std::task::spawn_blocking(f);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture {
+        Box::pin(async_std::task::sleep(duration))
+    }
+}
+
+/// Spawns onto a tokio runtime.
+#[cfg(feature = "tokio-executor")]
+#[derive(Clone, Debug)]
+pub struct TokioExecutor {
+    handle: tokio::runtime::Handle,
+}
+
+#[cfg(feature = "tokio-executor")]
+impl TokioExecutor {
+    /// Uses the runtime of the current context.
+    ///
+    /// ## Panics
+    ///
+    /// If it's called outside of a tokio runtime.
+    pub fn current() -> Self {
+        Self::from_handle(tokio::runtime::Handle::current())
+    }
+
+    pub fn from_handle(handle: tokio::runtime::Handle) -> Self {
+        Self { handle }
+    }
+}
+
+#[cfg(feature = "tokio-executor")]
+impl Executor for TokioExecutor {
+    fn spawn(&self, future: BoxFuture) {
+        self.handle.spawn(future);
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        self.handle.spawn_blocking(f);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture {
+        // `tokio::time::sleep` needs to be created inside of the runtime.
+        let _guard = self.handle.enter();
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Installing an executor is tested in `tests/executor.rs`, it can't be undone.
+
+    #[async_std::test]
+    async fn unblock_returns_the_result() {
+        let thread = std::thread::current().id();
+        let other = super::unblock(move || std::thread::current().id() != thread).await;
+        assert!(other);
+    }
+}
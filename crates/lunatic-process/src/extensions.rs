@@ -0,0 +1,85 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+    sync::Arc,
+};
+
+/// Values embedders attach to the state of a process, keyed by their type, e.g. a database pool
+/// or the tenant a process belongs to.
+///
+/// Host functions look them up with [`ProcessState::extensions`], without the signature of
+/// [`ProcessState::new`] changing. Set them on the state of a process before spawning it, the
+/// processes it spawns inherit them. Clones share the values.
+///
+/// [`ProcessState::extensions`]: crate::state::ProcessState::extensions
+/// [`ProcessState::new`]: crate::state::ProcessState::new
+#[derive(Clone, Default)]
+pub struct Extensions {
+    values: Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `value`, replacing the value of the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        Arc::make_mut(&mut self.values).insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Removes the value of type `T`, returns `false` if there was none.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> bool {
+        Arc::make_mut(&mut self.values)
+            .remove(&TypeId::of::<T>())
+            .is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("values", &self.values.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Extensions;
+
+    #[derive(Debug, PartialEq)]
+    struct TenantId(u64);
+
+    #[test]
+    fn values_are_keyed_by_type() {
+        let mut extensions = Extensions::new();
+        extensions.insert(TenantId(1));
+        extensions.insert("pool".to_owned());
+        extensions.insert(TenantId(2));
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions.get::<TenantId>(), Some(&TenantId(2)));
+
+        // Changing a clone doesn't affect the original.
+        let mut inherited = extensions.clone();
+        assert!(inherited.remove::<String>());
+        assert!(!inherited.remove::<String>());
+        assert_eq!(inherited.get::<String>(), None);
+        assert_eq!(extensions.get::<String>().map(String::as_str), Some("pool"));
+    }
+}
@@ -0,0 +1,152 @@
+/*!
+Fuel charged for host calls.
+
+WebAssembly code consumes fuel for each executed instruction, but time spent inside of host
+functions is free. A guest doing many cheap host calls, or host calls doing a lot of work (e.g.
+IO), would use more resources than its fuel limit suggests. Processes can be configured to pay a
+fixed amount of fuel for each host call, depending on the [`HostCallCategory`] of the call.
+
+Costs are expressed in raw fuel units, one unit corresponds to one executed instruction. All costs
+are zero by default. WASI calls are not charged.
+*/
+
+use log::Level;
+use serde::{Deserialize, Serialize};
+use wasmtime::{Caller, Trap};
+
+use crate::{config::ProcessConfig, state::ProcessState};
+
+/// Group of host functions sharing the same cost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostCallCategory {
+    /// `lunatic::process`
+    Process,
+    /// `lunatic::message`
+    Message,
+    /// `lunatic::networking`
+    Networking,
+    /// `lunatic::timer`
+    Timer,
+    /// `lunatic::registry`
+    Registry,
+}
+
+impl TryFrom<u32> for HostCallCategory {
+    type Error = ();
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(HostCallCategory::Process),
+            1 => Ok(HostCallCategory::Message),
+            2 => Ok(HostCallCategory::Networking),
+            3 => Ok(HostCallCategory::Timer),
+            4 => Ok(HostCallCategory::Registry),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Fuel charged per host call, for each category.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostCallCosts {
+    pub process: u64,
+    pub message: u64,
+    pub networking: u64,
+    pub timer: u64,
+    pub registry: u64,
+}
+
+impl HostCallCosts {
+    pub fn get(&self, category: HostCallCategory) -> u64 {
+        match category {
+            HostCallCategory::Process => self.process,
+            HostCallCategory::Message => self.message,
+            HostCallCategory::Networking => self.networking,
+            HostCallCategory::Timer => self.timer,
+            HostCallCategory::Registry => self.registry,
+        }
+    }
+
+    pub fn set(&mut self, category: HostCallCategory, cost: u64) {
+        match category {
+            HostCallCategory::Process => self.process = cost,
+            HostCallCategory::Message => self.message = cost,
+            HostCallCategory::Networking => self.networking = cost,
+            HostCallCategory::Timer => self.timer = cost,
+            HostCallCategory::Registry => self.registry = cost,
+        }
+    }
+}
+
+/// Takes the cost of a host call of `category` from the fuel of the calling process.
+///
+/// The fuel is handed to the store in slices of
+/// [`UNIT_OF_COMPUTE_IN_INSTRUCTIONS`](crate::config::UNIT_OF_COMPUTE_IN_INSTRUCTIONS). The cost
+/// is taken from the slices the process didn't get yet, so a cost larger than a slice is charged
+/// in full. If the process can't pay for the call, all of its fuel is used up and it traps.
+pub fn charge_host_call<T: ProcessState>(
+    caller: &mut Caller<T>,
+    category: HostCallCategory,
+) -> Result<(), Trap> {
+    let state = caller.data();
+    state.log_level().log(
+        Level::Trace,
+        format_args!(
+            "Process {} calls a {:?} host function",
+            state.id(),
+            category
+        ),
+    );
+    let cost = state.config().get_host_call_cost(category);
+    if cost == 0 {
+        return Ok(());
+    }
+    let max_fuel = state.config().get_max_fuel();
+    let (injections, fuel_per_injection) = state.runtime().fuel_injections(max_fuel);
+    let charge_error = |error| Trap::new(format!("Can't charge fuel for host call: {}", error));
+    // Fuel left in the current slice.
+    let remaining = caller.consume_fuel(0).map_err(charge_error)?;
+    let consumed = caller.fuel_consumed().unwrap_or(0);
+    // Fuel left in the current and all following slices.
+    let left = injections
+        .saturating_mul(fuel_per_injection)
+        .saturating_sub(consumed);
+    // Fuel left in the following slices once the call is paid.
+    let following = match left.checked_sub(remaining.saturating_add(cost)) {
+        Some(following) => following,
+        None => {
+            // Counts all fuel as consumed, so that the process is reported as out of fuel.
+            caller
+                .add_fuel(left)
+                .and_then(|()| caller.consume_fuel(left))
+                .map_err(charge_error)?;
+            return Err(Trap::new("Process ran out of fuel paying for a host call"));
+        }
+    };
+    // The cost is charged without touching the current slice, the following slices shrink by it
+    // instead. Fuel not filling a whole slice is moved to the current one.
+    caller
+        .add_fuel(cost + following % fuel_per_injection)
+        .and_then(|()| caller.consume_fuel(cost))
+        .map_err(charge_error)?;
+    caller.out_of_fuel_async_yield(following / fuel_per_injection, fuel_per_injection);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostCallCategory, HostCallCosts};
+
+    #[test]
+    fn costs_are_set_per_category() {
+        let mut costs = HostCallCosts::default();
+        costs.set(HostCallCategory::Networking, 500);
+        assert_eq!(costs.get(HostCallCategory::Networking), 500);
+        assert_eq!(costs.get(HostCallCategory::Message), 0);
+        assert_eq!(
+            HostCallCategory::try_from(2),
+            Ok(HostCallCategory::Networking)
+        );
+        assert!(HostCallCategory::try_from(5).is_err());
+    }
+}
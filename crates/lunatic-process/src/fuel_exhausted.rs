@@ -0,0 +1,62 @@
+use std::{fmt::Debug, sync::Arc};
+
+use uuid::Uuid;
+
+use crate::executor;
+
+type FuelExhaustedHook = Arc<dyn Fn(&FuelExhausted) + Send + Sync>;
+
+/// A process that used up its fuel limit and trapped, passed to fuel-exhausted hooks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuelExhausted {
+    pub id: Uuid,
+    /// Fuel consumed by the process, in instructions.
+    pub fuel_consumed: u64,
+    /// The fuel limit of the process in instructions, see
+    /// [`ProcessConfig::set_max_fuel`](crate::config::ProcessConfig::set_max_fuel).
+    pub fuel_limit: u64,
+}
+
+/// Hooks called each time a process runs out of fuel, e.g. to charge compute credits or alert on
+/// processes that keep hitting their limit.
+///
+/// Only processes with a fuel limit can run out of fuel. The hooks run one after another, in the
+/// order they were added. They are dispatched on the blocking thread pool and never hold up the
+/// process, which fails as usual.
+#[derive(Clone, Default)]
+pub struct FuelExhaustedHooks {
+    hooks: Arc<Vec<FuelExhaustedHook>>,
+}
+
+impl FuelExhaustedHooks {
+    pub fn add<F>(&mut self, hook: F)
+    where
+        F: Fn(&FuelExhausted) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.hooks).push(Arc::new(hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub(crate) fn dispatch(&self, exhausted: FuelExhausted) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let hooks = self.hooks.clone();
+        executor::spawn_blocking(move || {
+            for hook in hooks.iter() {
+                hook(&exhausted);
+            }
+        });
+    }
+}
+
+impl Debug for FuelExhaustedHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FuelExhaustedHooks")
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
@@ -0,0 +1,298 @@
+/*!
+Current fuel consumption of processes.
+
+The fuel consumed by a process only grows, it tells how much work a process did over its whole
+life. [`FuelRates`] tracks how much fuel each process consumed per second over a sliding window
+instead, to tell which processes are busy right now, e.g. for autoscaling or throttling.
+
+Processes spawned after the tracker is installed on the runtime are sampled each time they yield
+to refuel, after every [unit of compute](crate::config::UNIT_OF_COMPUTE_IN_INSTRUCTIONS) (or
+every step in step mode), and once they finish. Each sample records the fuel the store consumed
+since the previous one. A process only yields to refuel once its fuel is used up, so sampling is
+cheap, but the resolution of the rate is one unit of compute: fuel consumed since the last yield
+is counted at the next one. A process waiting on IO or a message doesn't consume fuel and its rate
+drops to 0 once the window passed.
+*/
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::reentrancy::HostCalls;
+
+// The window is split into this many buckets, older buckets fall out of the window as a whole.
+const BUCKETS_PER_WINDOW: u32 = 16;
+
+/// Fuel consumption rates of the running processes.
+///
+/// Clones share the same processes.
+#[derive(Clone)]
+pub struct FuelRates {
+    window: Duration,
+    processes: Arc<Mutex<HashMap<Uuid, Arc<Mutex<Samples>>>>>,
+}
+
+impl FuelRates {
+    /// Averages the consumption over `window`, at least 1 millisecond.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.max(Duration::from_millis(1)),
+            processes: Arc::default(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Returns the fuel consumed per second by the process over the window, or `None` if the
+    /// process isn't running or was spawned before the tracker was installed.
+    ///
+    /// If the process runs for less than the window, the rate is averaged over its run time.
+    pub fn rate(&self, id: Uuid) -> Option<u64> {
+        let samples = self
+            .processes
+            .lock()
+            .expect("never poisoned")
+            .get(&id)
+            .cloned()?;
+        let rate = samples
+            .lock()
+            .expect("never poisoned")
+            .rate(self.window, Instant::now());
+        Some(rate)
+    }
+
+    /// Returns the rates of all tracked processes, the busiest first.
+    pub fn snapshot(&self) -> Vec<(Uuid, u64)> {
+        let now = Instant::now();
+        let processes = self.processes.lock().expect("never poisoned");
+        let mut rates: Vec<_> = processes
+            .iter()
+            .map(|(id, samples)| {
+                let rate = samples
+                    .lock()
+                    .expect("never poisoned")
+                    .rate(self.window, now);
+                (*id, rate)
+            })
+            .collect();
+        rates.sort_by_key(|(_, rate)| Reverse(*rate));
+        rates
+    }
+
+    // Starts tracking the process, until the meter is dropped. `fuel_consumed` is the fuel the
+    // process consumed before, it's not counted.
+    pub(crate) fn track(&self, id: Uuid, fuel_consumed: u64) -> FuelMeter {
+        let samples = Arc::new(Mutex::new(Samples::new(Instant::now())));
+        self.processes
+            .lock()
+            .expect("never poisoned")
+            .insert(id, samples.clone());
+        FuelMeter {
+            rates: self.clone(),
+            id,
+            samples,
+            recorded: Mutex::new(fuel_consumed),
+        }
+    }
+}
+
+impl Default for FuelRates {
+    /// Averages over the last 10 seconds.
+    fn default() -> Self {
+        Self::new(Duration::from_secs(10))
+    }
+}
+
+impl std::fmt::Debug for FuelRates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FuelRates")
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+// Fuel consumed by one process, summed up per bucket.
+struct Samples {
+    started: Instant,
+    // Start of the bucket and the fuel consumed in it, oldest first.
+    buckets: VecDeque<(Instant, u64)>,
+}
+
+impl Samples {
+    fn new(started: Instant) -> Self {
+        Self {
+            started,
+            buckets: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, fuel: u64, window: Duration, now: Instant) {
+        while let Some((start, _)) = self.buckets.front() {
+            if now.duration_since(*start) > window {
+                self.buckets.pop_front();
+            } else {
+                break;
+            }
+        }
+        match self.buckets.back_mut() {
+            Some((start, sum)) if now.duration_since(*start) < window / BUCKETS_PER_WINDOW => {
+                *sum += fuel
+            }
+            _ => self.buckets.push_back((now, fuel)),
+        }
+    }
+
+    fn rate(&self, window: Duration, now: Instant) -> u64 {
+        let fuel: u64 = self
+            .buckets
+            .iter()
+            .filter(|(start, _)| now.duration_since(*start) <= window)
+            .map(|(_, fuel)| fuel)
+            .sum();
+        let span = now
+            .duration_since(self.started)
+            .min(window)
+            .max(window / BUCKETS_PER_WINDOW);
+        (fuel as f64 / span.as_secs_f64()) as u64
+    }
+}
+
+// Samples the fuel consumption of a process, it's removed from the tracker once dropped.
+pub(crate) struct FuelMeter {
+    rates: FuelRates,
+    id: Uuid,
+    samples: Arc<Mutex<Samples>>,
+    // Fuel consumed by the process up to the last sample.
+    recorded: Mutex<u64>,
+}
+
+impl FuelMeter {
+    // Drives `call`, recording each fuel yield. Works like `StepMode::drive`, each fuel yield
+    // injects `fuel_per_yield`.
+    pub(crate) fn drive<F: Future>(
+        &self,
+        fuel_at_yield: u64,
+        fuel_per_yield: u64,
+        host_calls: HostCalls,
+        call: F,
+    ) -> Metered<'_, F> {
+        Metered {
+            meter: self,
+            fuel_at_yield,
+            fuel_per_yield,
+            host_calls,
+            call: Box::pin(call),
+        }
+    }
+
+    // Records the fuel consumed since the last sample, `fuel_consumed` is the total of the
+    // process.
+    pub(crate) fn record(&self, fuel_consumed: u64) {
+        let mut recorded = self.recorded.lock().expect("never poisoned");
+        let fuel = fuel_consumed.saturating_sub(*recorded);
+        *recorded = (*recorded).max(fuel_consumed);
+        if fuel == 0 {
+            return;
+        }
+        let window = self.rates.window;
+        self.samples
+            .lock()
+            .expect("never poisoned")
+            .record(fuel, window, Instant::now());
+    }
+}
+
+impl Drop for FuelMeter {
+    fn drop(&mut self) {
+        self.rates
+            .processes
+            .lock()
+            .expect("never poisoned")
+            .remove(&self.id);
+    }
+}
+
+pub(crate) struct Metered<'a, F> {
+    meter: &'a FuelMeter,
+    // Fuel consumed by the store at the next fuel yield.
+    fuel_at_yield: u64,
+    fuel_per_yield: u64,
+    host_calls: HostCalls,
+    call: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Metered<'_, F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let poll = self.call.as_mut().poll(cx);
+        // Outside of host functions the process only pauses if it used up its fuel.
+        if poll.is_pending() && !self.host_calls.running() {
+            self.meter.record(self.fuel_at_yield);
+            self.fuel_at_yield += self.fuel_per_yield;
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use uuid::Uuid;
+
+    use super::{FuelRates, Samples};
+
+    #[test]
+    fn meter_records_fuel_since_the_last_sample() {
+        let rates = FuelRates::new(Duration::from_secs(60));
+        let id = Uuid::new_v4();
+        // Fuel consumed before the tracking started isn't counted.
+        let meter = rates.track(id, 100);
+        meter.record(100);
+        meter.record(250);
+        // Readings can't go back.
+        meter.record(200);
+        meter.record(300);
+        let fuel: u64 = meter
+            .samples
+            .lock()
+            .unwrap()
+            .buckets
+            .iter()
+            .map(|(_, fuel)| fuel)
+            .sum();
+        assert_eq!(fuel, 200);
+        drop(meter);
+        assert_eq!(rates.rate(id), None);
+    }
+
+    #[test]
+    fn rate_covers_only_the_window() {
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut samples = Samples::new(start);
+        // 1000 fuel in the first second.
+        for millis in 0..10 {
+            samples.record(100, window, start + Duration::from_millis(millis * 100));
+        }
+        assert_eq!(samples.rate(window, start + Duration::from_secs(1)), 1000);
+        // Averaged over the whole window once the process runs long enough.
+        assert_eq!(samples.rate(window, start + Duration::from_secs(10)), 100);
+        // Idle for longer than the window.
+        assert_eq!(samples.rate(window, start + Duration::from_secs(12)), 0);
+        samples.record(500, window, start + Duration::from_secs(20));
+        assert_eq!(samples.rate(window, start + Duration::from_secs(20)), 50);
+        assert_eq!(samples.buckets.len(), 1);
+    }
+}
@@ -0,0 +1,203 @@
+/*!
+Health of the runtime, for liveness and readiness checks of embedders.
+
+A [`HealthMonitor`] answers three questions:
+
+* Is the executor responsive? A heartbeat task scheduled on the executor wakes up every
+  heartbeat interval. If it fell behind by more than the stall threshold, the executor is
+  considered stalled, e.g. because a blocking call is hogging its threads.
+* Does spawning work? Each check spawns a process that exits right away and waits for it.
+* Is the memory within bounds? The resident memory of the host process is compared to an
+  optional limit. It's only measured on Linux, elsewhere the memory is always reported within
+  bounds.
+
+The monitor only produces a [`Health`] report, serving it (e.g. over HTTP) is up to the embedder.
+*/
+
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use crate::executor;
+
+/// Result of a health check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Health {
+    /// The heartbeat task ran within the stall threshold.
+    pub executor_responsive: bool,
+    /// Time since the heartbeat task last ran.
+    pub heartbeat_age: Duration,
+    /// A process could be spawned and finished within the stall threshold.
+    pub spawn_working: bool,
+    /// Resident memory of the host process in bytes, if it can be measured.
+    pub memory_used: Option<usize>,
+    /// The memory is below the limit, or there is no limit.
+    pub memory_within_bounds: bool,
+}
+
+impl Health {
+    /// Returns `true` if all checks passed.
+    pub fn is_healthy(&self) -> bool {
+        self.executor_responsive && self.spawn_working && self.memory_within_bounds
+    }
+}
+
+/// Checks the health of the runtime.
+///
+/// Clones share the same heartbeat. The heartbeat task stops once all clones are dropped.
+#[derive(Clone, Debug)]
+pub struct HealthMonitor {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    heartbeat_interval: Duration,
+    stall_threshold: Duration,
+    memory_limit: Option<usize>,
+    last_heartbeat: Mutex<Instant>,
+}
+
+impl HealthMonitor {
+    /// Starts a heartbeat every `heartbeat_interval`. The executor is considered stalled if the
+    /// heartbeat is late by more than `stall_threshold`.
+    pub fn new(
+        heartbeat_interval: Duration,
+        stall_threshold: Duration,
+        memory_limit: Option<usize>,
+    ) -> Self {
+        let inner = Arc::new(Inner {
+            heartbeat_interval,
+            stall_threshold,
+            memory_limit,
+            last_heartbeat: Mutex::new(Instant::now()),
+        });
+        executor::spawn(heartbeat_loop(Arc::downgrade(&inner), heartbeat_interval));
+        Self { inner }
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.inner.heartbeat_interval
+    }
+
+    pub fn stall_threshold(&self) -> Duration {
+        self.inner.stall_threshold
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.inner.memory_limit
+    }
+
+    /// Runs all checks.
+    ///
+    /// Takes at most the stall threshold, if spawning a process doesn't finish in time.
+    pub async fn check(&self) -> Health {
+        let heartbeat_age = self.heartbeat_age();
+        let executor_responsive =
+            heartbeat_age <= self.inner.heartbeat_interval + self.inner.stall_threshold;
+        let spawn_working = self.check_spawn().await;
+        let memory_used = resident_memory();
+        let memory_within_bounds = match (memory_used, self.inner.memory_limit) {
+            (Some(used), Some(limit)) => used <= limit,
+            _ => true,
+        };
+        Health {
+            executor_responsive,
+            heartbeat_age,
+            spawn_working,
+            memory_used,
+            memory_within_bounds,
+        }
+    }
+
+    /// Returns the time since the heartbeat task last ran.
+    pub fn heartbeat_age(&self) -> Duration {
+        self.inner
+            .last_heartbeat
+            .lock()
+            .expect("never poisoned")
+            .elapsed()
+    }
+
+    async fn check_spawn(&self) -> bool {
+        let (join, _process) = crate::spawn(|_, _| async { Ok::<(), anyhow::Error>(()) });
+        tokio::select! {
+            result = join => result.is_ok(),
+            _ = executor::sleep(self.inner.stall_threshold) => false,
+        }
+    }
+}
+
+async fn heartbeat_loop(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        executor::sleep(interval).await;
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        *inner.last_heartbeat.lock().expect("never poisoned") = Instant::now();
+    }
+}
+
+// Returns the resident set size of the host process.
+#[cfg(target_os = "linux")]
+fn resident_memory() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::HealthMonitor;
+
+    #[async_std::test]
+    async fn late_heartbeat_is_reported_as_stall() {
+        let monitor = HealthMonitor::new(
+            Duration::from_millis(10),
+            Duration::from_secs(5),
+            Some(usize::MAX),
+        );
+        // The heartbeat keeps running, it beats again after the monitor was created.
+        let created = Instant::now();
+        let beat = async {
+            while monitor.heartbeat_age() >= created.elapsed() {
+                async_std::task::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        async_std::future::timeout(Duration::from_secs(5), beat)
+            .await
+            .unwrap();
+        let health = monitor.check().await;
+        assert!(health.is_healthy(), "{:?}", health);
+
+        // Pretend the heartbeat task didn't get to run for a while. Instants before the start of
+        // the clock can't be faked.
+        let stalled = HealthMonitor::new(Duration::from_secs(60), Duration::from_millis(500), None);
+        if let Some(stalled_at) = Instant::now().checked_sub(Duration::from_secs(61)) {
+            *stalled.inner.last_heartbeat.lock().unwrap() = stalled_at;
+            let health = stalled.check().await;
+            assert!(!health.executor_responsive);
+            assert!(health.spawn_working);
+            assert!(!health.is_healthy());
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let over_limit =
+                HealthMonitor::new(Duration::from_secs(60), Duration::from_millis(500), Some(0));
+            let health = over_limit.check().await;
+            assert!(health.memory_used.is_some());
+            assert!(!health.memory_within_bounds);
+        }
+    }
+}
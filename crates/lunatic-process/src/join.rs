@@ -0,0 +1,129 @@
+/*!
+Waiting on a group of processes.
+
+[`join_any`] resolves as soon as the first process of a group finishes, like
+`futures::future::select_all`. [`join_all`] collects the results of all of them. Both work on the
+[`JoinHandle`]s returned when a process is spawned, so the result of each process carries its
+outcome: the state if it finished, or the reason of the failure (trap, kill signal, failed link).
+
+```no_run
+use lunatic_process::join::join_any_and_kill;
+
+# async fn example() {
+// Ask three replicas, take the first answer.
+let replicas = (0..3)
+    .map(|_| lunatic_process::spawn(|_this, _mailbox| async move { Ok(()) }))
+    .collect();
+let (index, answer) = join_any_and_kill(replicas).await;
+# }
+```
+*/
+
+use std::{future::Future, pin::Pin, task::Poll, time::Duration};
+
+use crate::{JoinHandle, Process, Signal};
+
+/// Waits until one of the processes finishes.
+///
+/// Returns the index of the finished process, its result and the handles of the processes still
+/// running, in the original order without the finished one. If more than one process is done
+/// already, the one with the lowest index is returned.
+///
+/// ## Panics
+///
+/// If `handles` is empty.
+pub async fn join_any<T>(mut handles: Vec<JoinHandle<T>>) -> (usize, T, Vec<JoinHandle<T>>) {
+    assert!(!handles.is_empty(), "join_any called with no handles");
+    let (index, result) = std::future::poll_fn(|cx| {
+        for (index, handle) in handles.iter_mut().enumerate() {
+            if let Poll::Ready(result) = Pin::new(handle).poll(cx) {
+                return Poll::Ready((index, result));
+            }
+        }
+        Poll::Pending
+    })
+    .await;
+    handles.remove(index);
+    (index, result, handles)
+}
+
+/// Waits until one of the processes finishes and kills all others.
+///
+/// The others are sent a `Kill` signal without a grace period, this doesn't wait for them to die.
+///
+/// ## Panics
+///
+/// If `processes` is empty.
+pub async fn join_any_and_kill<T, P: Process>(processes: Vec<(JoinHandle<T>, P)>) -> (usize, T) {
+    let (handles, processes): (Vec<_>, Vec<_>) = processes.into_iter().unzip();
+    let (index, result, _) = join_any(handles).await;
+    processes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != index)
+        .for_each(|(_, process)| process.send(Signal::Kill(Duration::ZERO)));
+    (index, result)
+}
+
+/// Waits until all processes finish and returns their results, in the order of `handles`.
+pub async fn join_all<T>(handles: Vec<JoinHandle<T>>) -> Vec<T> {
+    // Processes run in the background, awaiting them one after another doesn't delay any of them.
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(handle.await);
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{join_all, join_any, join_any_and_kill};
+    use crate::Process;
+
+    #[async_std::test]
+    async fn first_finished_process_wins() {
+        let sleeper = |millis: u64, fail: bool| {
+            crate::spawn(move |_this, _mailbox| async move {
+                async_std::task::sleep(Duration::from_millis(millis)).await;
+                if fail {
+                    Err(anyhow::anyhow!("failed after {}ms", millis))
+                } else {
+                    Ok(millis)
+                }
+            })
+        };
+
+        let (processes, handles): (Vec<_>, Vec<_>) = [(5_000, false), (10, false), (20, true)]
+            .into_iter()
+            .map(|(millis, fail)| {
+                let (join, process) = sleeper(millis, fail);
+                (process, join)
+            })
+            .unzip();
+        let (index, result, rest) = join_any(handles).await;
+        assert_eq!((index, result.unwrap()), (1, 10));
+        assert_eq!(rest.len(), 2);
+        // The failure is surfaced, the slow one is still running.
+        let (index, result, _) = join_any(rest).await;
+        assert_eq!(index, 1);
+        assert_eq!(result.unwrap_err().to_string(), "failed after 20ms");
+        assert!(processes[0].is_alive());
+
+        // Dropping the process handles would close their signal mailboxes, keep them around.
+        let (handles, _processes): (Vec<_>, Vec<_>) = vec![sleeper(20, false), sleeper(10, true)]
+            .into_iter()
+            .unzip();
+        let results = join_all(handles).await;
+        assert_eq!(results[0].as_ref().unwrap(), &20);
+        assert!(results[1].is_err());
+
+        let replicas = vec![sleeper(5_000, false), sleeper(10, false)];
+        let slow = replicas[0].1.clone();
+        let (index, result) = join_any_and_kill(replicas).await;
+        assert_eq!((index, result.unwrap()), (1, 10));
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        assert!(!slow.is_alive());
+    }
+}
@@ -1,21 +1,68 @@
+pub mod checkpoint;
 pub mod config;
+pub mod deadlock;
+pub mod deterministic;
+pub mod executor;
+pub mod extensions;
+pub mod fuel;
+pub mod fuel_exhausted;
+pub mod fuel_rate;
+pub mod health;
+pub mod join;
+pub mod link_cascade;
+pub mod live;
+pub mod logging;
 pub mod mailbox;
+pub mod memory_watch;
 pub mod message;
+#[cfg(feature = "message-stats")]
+pub mod message_stats;
+pub mod namespaces;
+pub mod os_signal;
+pub mod post_mortem;
+pub mod process_ref;
+pub mod process_tree;
+pub mod random;
+pub mod reentrancy;
+pub mod restart;
 pub mod runtimes;
+#[cfg(feature = "signal-trace")]
+pub mod signal_trace;
 pub mod state;
+pub mod step;
+pub mod topics;
+pub mod topology;
+pub mod trap_location;
+pub mod upgrade;
 pub mod wasm;
 
-use std::{collections::HashMap, fmt::Debug, future::Future, hash::Hash, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Result};
-use log::{debug, log_enabled, trace, warn, Level};
+use log::{debug, log_enabled, trace, warn, Level, LevelFilter};
 
 use async_std::channel::{unbounded, Receiver, Sender};
-use async_std::task::JoinHandle;
+use async_std::stream::Stream;
 
 use uuid::Uuid;
 
-use crate::{mailbox::MessageMailbox, message::Message};
+use crate::{
+    logging::ProcessLogLevel,
+    mailbox::MessageMailbox,
+    message::Message,
+    post_mortem::{PostMortemHooks, ProcessDeath},
+    topology::Topology,
+    trap_location::TrapLocation,
+};
 
 /// The `Process` is the main abstraction in lunatic.
 ///
@@ -28,6 +75,21 @@ use crate::{mailbox::MessageMailbox, message::Message};
 pub trait Process: Send + Sync {
     fn id(&self) -> Uuid;
     fn send(&self, signal: Signal);
+    /// Returns `false` if the process can't receive signals anymore, because it finished.
+    fn is_alive(&self) -> bool {
+        true
+    }
+    /// Sends `signal` unless the process finished, then it's returned back boxed.
+    ///
+    /// Processes with a signal mailbox should override it, so that the process can't finish
+    /// between the check and the send.
+    fn try_send(&self, signal: Signal) -> Result<(), Box<Signal>> {
+        if !self.is_alive() {
+            return Err(Box::new(signal));
+        }
+        self.send(signal);
+        Ok(())
+    }
 }
 
 impl Debug for dyn Process {
@@ -46,8 +108,10 @@ impl Hash for dyn Process {
 pub enum Signal {
     // Messages can contain opaque data.
     Message(Message),
-    // When received, the process should stop immediately.
-    Kill,
+    // When received, the process should stop. If the grace period is zero the process stops
+    // immediately, otherwise a `Message::Shutdown` is delivered to it first and the process is
+    // only stopped if it's still running after the grace period.
+    Kill(Duration),
     // Change behaviour of what happens if a linked process dies.
     DieWhenLinkDies(bool),
     // Sent from a process that wants to be linked. In case of a death the tag will be returned
@@ -58,28 +122,43 @@ pub enum Signal {
     // Sent to linked processes when the link dies. Contains the tag used when the link was
     // established. Depending on the value of `die_when_link_dies` (default is `true`) and
     // the death reason, the receiving process will turn this signal into a message or the
-    // process will immediately die as well.
-    LinkDied(Uuid, Option<i64>, DeathReason),
+    // process will immediately die as well. The last field is the depth of the cascade the
+    // death is part of, see `link_cascade`.
+    LinkDied(Uuid, Option<i64>, DeathReason, u32),
+    // Sets the log level of the process, `None` switches back to the default level.
+    SetLogLevel(Option<LevelFilter>),
+    // Detaches the process from all others. Existing links are dropped and from now on `Link`
+    // and `LinkDied` signals are ignored, so the death of another process never affects it and
+    // its own death is never reported.
+    Detach,
+    // Unblocks a receive of the guest without delivering a message, see
+    // `MessageMailbox::cancel_receive`.
+    CancelReceive,
 }
 
 impl Debug for Signal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Message(_) => write!(f, "Message"),
-            Self::Kill => write!(f, "Kill"),
+            Self::Kill(grace) => write!(f, "Kill {:?}", grace),
             Self::DieWhenLinkDies(_) => write!(f, "DieWhenLinkDies"),
             Self::Link(_, _) => write!(f, "Link"),
             Self::UnLink(_) => write!(f, "UnLink"),
-            Self::LinkDied(_, _, reason) => write!(f, "LinkDied {:?}", reason),
+            Self::LinkDied(_, _, reason, _) => write!(f, "LinkDied {:?}", reason),
+            Self::SetLogLevel(level) => write!(f, "SetLogLevel {:?}", level),
+            Self::Detach => write!(f, "Detach"),
+            Self::CancelReceive => write!(f, "CancelReceive"),
         }
     }
 }
 
 // The reason of a process' death
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum DeathReason {
     // Process finished normaly.
     Normal,
+    // Process called `proc_exit` with a non-zero exit code. This is not treated as a failure.
+    Exited(i32),
     Failure,
 }
 
@@ -115,12 +194,24 @@ impl Process for WasmProcess {
         self.id
     }
     fn send(&self, signal: Signal) {
+        #[cfg(feature = "signal-trace")]
+        signal_trace::record(self.id, &signal);
         // If the receiver doesn't exist or is closed, just ignore it and drop the `signal`.
         // lunatic can't guarantee that a message was successfully seen by the receiving side even
         // if this call succeeds. We deliberately don't expose this API, as it would not make sense
         // to relay on it and could signal wrong guarantees to users.
         let _ = self.signal_mailbox.try_send(signal);
     }
+    fn is_alive(&self) -> bool {
+        !self.signal_mailbox.is_closed()
+    }
+    fn try_send(&self, signal: Signal) -> Result<(), Box<Signal>> {
+        #[cfg(feature = "signal-trace")]
+        signal_trace::record(self.id, &signal);
+        self.signal_mailbox
+            .try_send(signal)
+            .map_err(|error| Box::new(error.into_inner()))
+    }
 }
 
 /// Turns a `Future` into a process, enabling signals (e.g. kill).
@@ -139,25 +230,55 @@ impl Process for WasmProcess {
 /// In case of success, the process state `S` is returned. It's not possible to return the process
 /// state in case of failure because of limitations in the Wasmtime API:
 /// https://github.com/bytecodealliance/wasmtime/issues/2986
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn new<F, S, R>(
     fut: F,
     id: Uuid,
+    name: Option<String>,
     signal_mailbox: Receiver<Signal>,
     message_mailbox: MessageMailbox,
+    post_mortem: PostMortemHooks,
+    log_level: ProcessLogLevel,
+    topology: Option<Topology>,
+    max_cascade_depth: Option<u32>,
 ) -> Result<S>
 where
     R: Into<ExecutionResult<S>>,
     F: Future<Output = R> + Send + 'static,
 {
-    trace!("Process {} spawned", id);
+    // Used in logs, the name is taken from the time the process was spawned.
+    let label = match name {
+        Some(ref name) => format!("{} ({})", id, name),
+        None => id.to_string(),
+    };
+    trace!("Process {} spawned", label);
+    if let Some(topology) = &topology {
+        topology.spawned(id, name.clone());
+    }
+    let started = Instant::now();
+    let death = |reason, killed, fuel_consumed, memory_size| ProcessDeath {
+        id,
+        name: name.clone(),
+        reason,
+        killed,
+        uptime: started.elapsed(),
+        fuel_consumed,
+        memory_size,
+    };
     tokio::pin!(fut);
 
     // Defines what happens if one of the linked processes dies.
     // If the value is set to false, instead of dying too the process will receive a message about
     // the linked process' death.
     let mut die_when_link_dies = true;
+    // Set once the process receives a `Detach` signal, links are ignored from then on.
+    let mut detached = false;
     // Process linked to this one
     let mut links = HashMap::new();
+    // Depth of the cascade if the process dies because a linked process failed.
+    let mut cascade_depth = 0;
+    // Set if the process received a `Kill` signal with a grace period, it's killed at this time.
+    let mut kill_deadline: Option<Instant> = None;
     // TODO: Maybe wrapping this in some kind of `std::panic::catch_unwind` wold be a good idea,
     //       to protect against panics in host function calls that unwind through Wasm code.
     //       Currently a panic would just kill the task, but not notify linked processes.
@@ -170,44 +291,104 @@ where
                     Ok(Signal::Message(message)) => message_mailbox.push(message),
                     Ok(Signal::DieWhenLinkDies(value)) => die_when_link_dies = value,
                     // Put process into list of linked processes
-                    Ok(Signal::Link(tag, proc)) => { links.insert(proc.id(), (proc, tag)); },
+                    Ok(Signal::Link(_, _)) if detached => {},
+                    Ok(Signal::Link(tag, proc)) => {
+                        if let Some(topology) = &topology {
+                            topology.linked(id, proc.id(), tag);
+                        }
+                        links.insert(proc.id(), (proc, tag));
+                    },
                     // Remove process from list
-                    Ok(Signal::UnLink(proc)) => { links.remove(&proc.id()); }
+                    Ok(Signal::UnLink(proc)) => {
+                        if let Some(topology) = &topology {
+                            topology.unlinked(id, proc.id());
+                        }
+                        links.remove(&proc.id());
+                    }
+                    Ok(Signal::SetLogLevel(level)) => log_level.set(level),
+                    Ok(Signal::CancelReceive) => { message_mailbox.cancel_receive(); }
+                    Ok(Signal::Detach) => {
+                        detached = true;
+                        if let Some(topology) = &topology {
+                            topology.unlinked_all(id);
+                        }
+                        links.clear();
+                    }
                     // Exit loop and don't poll anymore the future if Signal::Kill received.
-                    Ok(Signal::Kill) => break Finished::KillSignal,
+                    Ok(Signal::Kill(grace)) if grace.is_zero() => break Finished::KillSignal,
+                    // Ask the process to stop and give it some time to clean up.
+                    Ok(Signal::Kill(grace)) => {
+                        let deadline = Instant::now() + grace;
+                        match kill_deadline {
+                            // An earlier kill already asked the process to stop, it only can
+                            // make the deadline shorter.
+                            Some(earlier) => kill_deadline = Some(earlier.min(deadline)),
+                            None => {
+                                message_mailbox.push(Message::Shutdown);
+                                kill_deadline = Some(deadline);
+                            }
+                        }
+                    }
                     // Depending if `die_when_link_dies` is set, process will die or turn the
                     // signal into a message
-                    Ok(Signal::LinkDied(id, tag, reason)) => {
-                        links.remove(&id);
+                    Ok(Signal::LinkDied(_, _, _, _)) if detached => {},
+                    Ok(Signal::LinkDied(link_id, tag, reason, depth)) => {
+                        if let Some(topology) = &topology {
+                            topology.unlinked(id, link_id);
+                        }
+                        links.remove(&link_id);
                         match reason {
                             DeathReason::Failure => {
-                                if die_when_link_dies {
+                                if die_when_link_dies
+                                    && max_cascade_depth.is_none_or(|max| depth < max)
+                                {
                                     // Even this was not a **kill** signal it has the same effect on
                                     // this process and should be propagated as such.
+                                    cascade_depth = depth + 1;
                                     break Finished::KillSignal
                                 } else {
+                                    if die_when_link_dies {
+                                        warn!(
+                                            "Process {} stops a cascade of link failures at depth {}",
+                                            label, depth
+                                        );
+                                    }
                                     let message = Message::LinkDied(tag);
                                     message_mailbox.push(message);
                                 }
                             },
                             // In case a linked process finishes normally, don't do anything.
-                            DeathReason::Normal => {},
+                            DeathReason::Normal | DeathReason::Exited(_) => {},
                         }
                     },
                     Err(_) => unreachable!("The process holds the sending side and is not closed")
                 }
             }
+            // The grace period of a `Kill` signal expired.
+            _ = kill_timeout(kill_deadline), if kill_deadline.is_some() => {
+                break Finished::KillSignal;
+            }
             // Run process
             output = &mut fut => { break Finished::Normal(output); }
         }
     };
+    if let Some(topology) = &topology {
+        topology.finished(id);
+    }
+    message_mailbox.release_job();
     match result {
         Finished::Normal(result) => {
             let result = result.into();
+            let (fuel_consumed, memory_size) = (result.fuel_consumed, result.memory_size);
             if let Some(failure) = result.failure() {
+                let location = match result.trap_location() {
+                    Some(location) => format!(" at {}", location),
+                    None => String::new(),
+                };
                 warn!(
-                    "Process {} failed, notifying: {} links {}",
-                    id,
+                    "Process {} failed{}, notifying: {} links {}",
+                    label,
+                    location,
                     links.len(),
                     // If the log level is WARN instruct user how to display the stacktrace
                     if !log_enabled!(Level::Debug) {
@@ -217,34 +398,67 @@ where
                     }
                 );
                 debug!("{}", failure);
+                #[cfg(feature = "signal-trace")]
+                signal_trace::record_death(id, DeathReason::Failure);
                 // Notify all links that we finished with an error
                 links.iter().for_each(|(_, (proc, tag))| {
-                    proc.send(Signal::LinkDied(id, *tag, DeathReason::Failure));
+                    proc.send(Signal::LinkDied(id, *tag, DeathReason::Failure, 0));
                 });
+                post_mortem.dispatch(death(
+                    DeathReason::Failure,
+                    false,
+                    fuel_consumed,
+                    memory_size,
+                ));
                 Err(anyhow!(failure.to_string()))
             } else {
+                let reason = match result.exit_code() {
+                    Some(code) if code != 0 => {
+                        debug!("Process {} exited with code {}", label, code);
+                        DeathReason::Exited(code)
+                    }
+                    _ => DeathReason::Normal,
+                };
+                #[cfg(feature = "signal-trace")]
+                signal_trace::record_death(id, reason);
                 // Notify all links that we finished normally
                 links.iter().for_each(|(_, (proc, tag))| {
-                    proc.send(Signal::LinkDied(id, *tag, DeathReason::Normal));
+                    proc.send(Signal::LinkDied(id, *tag, reason, 0));
                 });
+                post_mortem.dispatch(death(reason, false, fuel_consumed, memory_size));
                 Ok(result.state())
             }
         }
         Finished::KillSignal => {
             warn!(
                 "Process {} was killed, notifying: {} links",
-                id,
+                label,
                 links.len()
             );
+            #[cfg(feature = "signal-trace")]
+            signal_trace::record_death(id, DeathReason::Failure);
             // Notify all links that we finished because of a kill signal
             links.iter().for_each(|(_, (proc, tag))| {
-                proc.send(Signal::LinkDied(id, *tag, DeathReason::Failure));
+                proc.send(Signal::LinkDied(
+                    id,
+                    *tag,
+                    DeathReason::Failure,
+                    cascade_depth,
+                ));
             });
+            post_mortem.dispatch(death(DeathReason::Failure, true, None, None));
             Err(anyhow!("Process received Kill signal"))
         }
     }
 }
 
+// Resolves once the deadline is reached.
+async fn kill_timeout(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        executor::sleep(deadline.saturating_duration_since(Instant::now())).await;
+    }
+}
+
 /// A process spawned from a native Rust closure.
 #[derive(Clone, Debug)]
 pub struct NativeProcess {
@@ -280,21 +494,90 @@ where
         signal_mailbox: signal_sender,
     };
     let fut = func(process.clone(), message_mailbox.clone());
-    let join = async_std::task::spawn(new(fut, id, signal_mailbox, message_mailbox));
+    let join = spawn_task(new(
+        fut,
+        id,
+        None,
+        signal_mailbox,
+        message_mailbox,
+        PostMortemHooks::default(),
+        ProcessLogLevel::default(),
+        None,
+        None,
+    ));
     (join, process)
 }
 
+/// A handle that can be awaited to get the result of a spawned process.
+///
+/// Dropping the handle detaches the process, it will keep running in the background.
+pub struct JoinHandle<T> {
+    inner: JoinHandleInner<T>,
+}
+
+enum JoinHandleInner<T> {
+    AsyncStd(async_std::task::JoinHandle<T>),
+    Deterministic(deterministic::JoinHandle<T>),
+    // Receives the output of a task spawned on a custom executor.
+    Executor(Receiver<T>),
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.inner {
+            JoinHandleInner::AsyncStd(handle) => Pin::new(handle).poll(cx),
+            JoinHandleInner::Deterministic(handle) => Pin::new(handle).poll(cx),
+            JoinHandleInner::Executor(receiver) => match Pin::new(receiver).poll_next(cx) {
+                Poll::Ready(Some(output)) => Poll::Ready(output),
+                Poll::Ready(None) => panic!("The task was dropped before finishing"),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+// Spawns the task that drives a process.
+//
+// If the current thread is driven by a `DeterministicScheduler` the task is placed onto it,
+// otherwise it's spawned onto the installed executor.
+pub(crate) fn spawn_task<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if deterministic::is_active() {
+        let inner = JoinHandleInner::Deterministic(deterministic::spawn(fut));
+        JoinHandle { inner }
+    } else {
+        executor::spawn(fut)
+    }
+}
+
 impl Process for NativeProcess {
     fn id(&self) -> Uuid {
         self.id
     }
     fn send(&self, signal: Signal) {
+        #[cfg(feature = "signal-trace")]
+        signal_trace::record(self.id, &signal);
         // If the receiver doesn't exist or is closed, just ignore it and drop the `signal`.
         // lunatic can't guarantee that a message was successfully seen by the receiving side even
         // if this call succeeds. We deliberately don't expose this API, as it would not make sense
         // to relay on it and could signal wrong guarantees to users.
         let _ = self.signal_mailbox.try_send(signal);
     }
+    fn is_alive(&self) -> bool {
+        !self.signal_mailbox.is_closed()
+    }
+    fn try_send(&self, signal: Signal) -> Result<(), Box<Signal>> {
+        #[cfg(feature = "signal-trace")]
+        signal_trace::record(self.id, &signal);
+        self.signal_mailbox
+            .try_send(signal)
+            .map_err(|error| Box::new(error.into_inner()))
+    }
 }
 
 // Contains the result of a process execution.
@@ -303,6 +586,9 @@ impl Process for NativeProcess {
 pub struct ExecutionResult<T> {
     state: T,
     result: ResultValue,
+    fuel_consumed: Option<u64>,
+    memory_size: Option<usize>,
+    trap_location: Option<TrapLocation>,
 }
 
 impl<T> ExecutionResult<T> {
@@ -315,6 +601,27 @@ impl<T> ExecutionResult<T> {
         }
     }
 
+    // Returns where in the guest code the process trapped, if it failed because of a trap.
+    pub fn trap_location(&self) -> Option<&TrapLocation> {
+        self.trap_location.as_ref()
+    }
+
+    // Returns the exit code if the process finished by calling `proc_exit`.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.result {
+            ResultValue::Exited(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    // Returns `true` if the process finished normally or called `proc_exit` with code 0.
+    //
+    // A non-zero exit code doesn't fail the process (links are not notified of a failure), but
+    // embedders running a process like a command can use this to tell both apart.
+    pub fn is_success(&self) -> bool {
+        matches!(self.result, ResultValue::Ok | ResultValue::Exited(0))
+    }
+
     // Returns the process state
     pub fn state(self) -> T {
         self.state
@@ -331,10 +638,16 @@ where
             Ok(t) => ExecutionResult {
                 state: t,
                 result: ResultValue::Ok,
+                fuel_consumed: None,
+                memory_size: None,
+                trap_location: None,
             },
             Err(e) => ExecutionResult {
                 state: T::default(),
                 result: ResultValue::Failed(e.to_string()),
+                fuel_consumed: None,
+                memory_size: None,
+                trap_location: None,
             },
         }
     }
@@ -343,6 +656,53 @@ where
 #[derive(PartialEq, Eq)]
 pub enum ResultValue {
     Ok,
+    // The guest called `proc_exit` with the exit code.
+    Exited(i32),
     Failed(String),
     SpawnError(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{message::Message, Process, Signal};
+
+    #[async_std::test]
+    async fn kill_with_grace_period_lets_process_clean_up() {
+        let (join, process) = crate::spawn(|_this, mailbox| async move {
+            match mailbox.pop(None).await {
+                Message::Shutdown => Ok(()),
+                _ => panic!("Unexpected message"),
+            }
+        });
+        process.send(Signal::Kill(Duration::from_secs(10)));
+        assert!(join.await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn kill_escalates_after_grace_period() {
+        let (join, process) = crate::spawn(|_this, _mailbox| async move {
+            // Ignore the shutdown request.
+            async_std::task::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        process.send(Signal::Kill(Duration::from_millis(10)));
+        let result = async_std::future::timeout(Duration::from_secs(5), join)
+            .await
+            .expect("process must be killed after the grace period");
+        assert!(result.is_err());
+    }
+
+    #[async_std::test]
+    async fn finished_process_is_not_alive() {
+        let (join, process) = crate::spawn(|_this, mailbox| async move {
+            mailbox.pop(None).await;
+            Ok(())
+        });
+        assert!(process.is_alive());
+        process.send(Signal::Message(Message::Shutdown));
+        join.await.unwrap();
+        assert!(!process.is_alive());
+    }
+}
@@ -0,0 +1,108 @@
+/*!
+Bounds how far a failure spreads over links.
+
+If a process fails, all processes linked to it fail too, and so on. Each process handles the
+[`LinkDied`](crate::Signal::LinkDied) signal in its own loop, so a cascade never recurses on the
+stack, but in a deep chain of links (or a badly built supervision tree) one failure can still tear
+down a huge number of processes.
+
+Every `LinkDied` signal carries the depth of the cascade it's part of: 0 if the process failed on
+its own or was killed, 1 if it died because of a linked process, etc. Cascades are unlimited by
+default. If a maximum depth is set on the runtime with
+[`set_max_link_cascade_depth`](crate::runtimes::wasmtime::WasmtimeRuntime::set_max_link_cascade_depth),
+a process receiving a failure with that depth or more doesn't die, the cascade is logged and the
+signal is turned into a `LinkDied` message, like for processes that don't die when a link dies.
+*/
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use async_std::channel::unbounded;
+    use uuid::Uuid;
+
+    use crate::{
+        logging::ProcessLogLevel, mailbox::MessageMailbox, message::Message,
+        post_mortem::PostMortemHooks, JoinHandle, NativeProcess, Process, Signal,
+    };
+
+    // Spawns `count` processes linked in a chain, each one returns once it receives a message.
+    fn spawn_chain(
+        count: usize,
+        max_cascade_depth: Option<u32>,
+    ) -> (Vec<JoinHandle<anyhow::Result<()>>>, Vec<NativeProcess>) {
+        (0..count)
+            .map(|_| {
+                let id = Uuid::new_v4();
+                let (signal_sender, signal_mailbox) = unbounded();
+                let message_mailbox = MessageMailbox::default();
+                let fut = {
+                    let mailbox = message_mailbox.clone();
+                    async move {
+                        match mailbox.pop(None).await {
+                            Message::LinkDied(_) => anyhow::Ok(()),
+                            _ => panic!("Unexpected message"),
+                        }
+                    }
+                };
+                let join = crate::spawn_task(crate::new(
+                    fut,
+                    id,
+                    None,
+                    signal_mailbox,
+                    message_mailbox,
+                    PostMortemHooks::default(),
+                    ProcessLogLevel::default(),
+                    None,
+                    max_cascade_depth,
+                ));
+                let process = NativeProcess {
+                    id,
+                    signal_mailbox: signal_sender,
+                };
+                (join, process)
+            })
+            .unzip()
+    }
+
+    fn link_chain(processes: &[NativeProcess]) {
+        for pair in processes.windows(2) {
+            pair[0].send(Signal::Link(None, Arc::new(pair[1].clone())));
+            pair[1].send(Signal::Link(None, Arc::new(pair[0].clone())));
+        }
+    }
+
+    #[async_std::test]
+    async fn deep_link_chain_stops_at_max_depth() {
+        const MAX_DEPTH: u32 = 16;
+        // The process at index `MAX_DEPTH + 1` receives a failure at the maximum depth.
+        let (mut handles, processes) = spawn_chain(MAX_DEPTH as usize + 3, Some(MAX_DEPTH));
+        link_chain(&processes);
+        processes[0].send(Signal::Kill(Duration::ZERO));
+
+        // The last process is never notified and keeps waiting.
+        handles.pop();
+        let results =
+            async_std::future::timeout(Duration::from_secs(10), crate::join::join_all(handles))
+                .await
+                .expect("the cascade must stop");
+        let last_killed = MAX_DEPTH as usize;
+        assert!(results[..=last_killed].iter().all(|result| result.is_err()));
+        // The failure is turned into a message instead.
+        assert!(results[last_killed + 1].is_ok());
+        assert!(processes.last().unwrap().is_alive());
+    }
+
+    #[async_std::test]
+    async fn link_chain_is_unlimited_by_default() {
+        let (handles, processes) = spawn_chain(300, None);
+        link_chain(&processes);
+        processes[0].send(Signal::Kill(Duration::ZERO));
+
+        let results =
+            async_std::future::timeout(Duration::from_secs(10), crate::join::join_all(handles))
+                .await
+                .expect("the whole chain must die");
+        assert!(results.iter().all(|result| result.is_err()));
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::{Arc, Mutex};
+
+use async_std::channel::{bounded, Sender};
+
+/// Counts the processes and background tasks of a runtime that are still running.
+///
+/// Clones share the same count. Each running process or task holds a [`LiveGuard`].
+#[derive(Clone, Default)]
+pub struct LiveTasks {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    count: usize,
+    // Notified once the count drops to zero.
+    waiters: Vec<Sender<()>>,
+}
+
+impl LiveTasks {
+    /// Counts the caller as running, until the returned guard is dropped.
+    pub fn track(&self) -> LiveGuard {
+        self.inner.lock().expect("never poisoned").count += 1;
+        LiveGuard {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.inner.lock().expect("never poisoned").count
+    }
+
+    /// Waits until no process or task is running anymore.
+    ///
+    /// Returns right away if nothing is running. A process can spawn others before finishing, so
+    /// the count only reaches zero once the whole tree of processes is done.
+    pub async fn wait_idle(&self) {
+        loop {
+            let receiver = {
+                let mut inner = self.inner.lock().expect("never poisoned");
+                if inner.count == 0 {
+                    return;
+                }
+                let (sender, receiver) = bounded(1);
+                inner.waiters.push(sender);
+                receiver
+            };
+            // Something new could start between the notification and waking up, check again.
+            let _ = receiver.recv().await;
+        }
+    }
+}
+
+/// Marks a process or task as running while it's alive.
+pub struct LiveGuard {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Drop for LiveGuard {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().expect("never poisoned");
+        inner.count -= 1;
+        if inner.count == 0 {
+            for waiter in inner.waiters.drain(..) {
+                let _ = waiter.try_send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::LiveTasks;
+
+    #[async_std::test]
+    async fn wait_idle_completes_after_last_guard() {
+        let live = LiveTasks::default();
+        live.wait_idle().await;
+
+        let first = live.track();
+        let second = live.track();
+        assert_eq!(live.count(), 2);
+        async_std::task::spawn(async move {
+            drop(first);
+            async_std::task::sleep(Duration::from_millis(10)).await;
+            drop(second);
+        });
+        async_std::future::timeout(Duration::from_secs(5), live.wait_idle())
+            .await
+            .expect("all guards are dropped");
+        assert_eq!(live.count(), 0);
+    }
+}
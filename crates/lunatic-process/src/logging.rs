@@ -0,0 +1,128 @@
+/*!
+Log verbosity of individual processes.
+
+Host functions log what a process is doing through its [`ProcessLogLevel`], instead of the global
+level of the `log` crate. This allows turning up the logs of one misbehaving process, without
+flooding the output with records of all others. A process without its own level uses the
+[default level](set_default_level).
+
+The level of a running process can be changed from the outside with a
+[`Signal::SetLogLevel`](crate::Signal::SetLogLevel) or by the process itself, and takes effect
+with the next record.
+
+Records are logged under the [`LOG_TARGET`] target. The installed logger still gets the final say,
+so it should let all records of this target through and leave the filtering to the processes.
+*/
+
+use std::{
+    fmt::Arguments,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use log::{Level, LevelFilter, Record};
+
+/// Target of all records logged by processes.
+pub const LOG_TARGET: &str = "lunatic::process";
+
+// Stored as `LevelFilter as usize + 1`, `UNSET` means "use the default level".
+const UNSET: usize = 0;
+
+static DEFAULT_LEVEL: AtomicUsize = AtomicUsize::new(UNSET);
+
+/// Sets the level of all processes that don't have their own.
+///
+/// If it's never set, the global maximum level of the `log` crate is used.
+pub fn set_default_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(encode(Some(level)), Ordering::Relaxed);
+}
+
+/// Returns the level of all processes that don't have their own.
+pub fn default_level() -> LevelFilter {
+    decode(DEFAULT_LEVEL.load(Ordering::Relaxed)).unwrap_or_else(log::max_level)
+}
+
+/// Log level of a process, shared between the process and its process loop.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessLogLevel {
+    level: Arc<AtomicUsize>,
+}
+
+impl ProcessLogLevel {
+    /// Returns the level of this process, `None` if it uses the default level.
+    pub fn get(&self) -> Option<LevelFilter> {
+        decode(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Sets the level of this process, `None` to use the default level again.
+    pub fn set(&self, level: Option<LevelFilter>) {
+        self.level.store(encode(level), Ordering::Relaxed);
+    }
+
+    /// Returns the level in effect for this process.
+    pub fn effective(&self) -> LevelFilter {
+        self.get().unwrap_or_else(default_level)
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.effective()
+    }
+
+    /// Logs the record, if `level` is enabled for this process.
+    ///
+    /// The global maximum level of the `log` crate is not checked, so a process can log more
+    /// than the rest of the application.
+    pub fn log(&self, level: Level, args: Arguments) {
+        if self.enabled(level) {
+            log::logger().log(
+                &Record::builder()
+                    .level(level)
+                    .target(LOG_TARGET)
+                    .args(args)
+                    .build(),
+            );
+        }
+    }
+}
+
+fn encode(level: Option<LevelFilter>) -> usize {
+    match level {
+        Some(level) => level as usize + 1,
+        None => UNSET,
+    }
+}
+
+fn decode(value: usize) -> Option<LevelFilter> {
+    match value {
+        1 => Some(LevelFilter::Off),
+        2 => Some(LevelFilter::Error),
+        3 => Some(LevelFilter::Warn),
+        4 => Some(LevelFilter::Info),
+        5 => Some(LevelFilter::Debug),
+        6 => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter};
+
+    use super::ProcessLogLevel;
+
+    #[test]
+    fn process_level_overrides_default() {
+        let level = ProcessLogLevel::default();
+        let shared = level.clone();
+        assert_eq!(level.get(), None);
+        shared.set(Some(LevelFilter::Trace));
+        assert_eq!(level.get(), Some(LevelFilter::Trace));
+        assert!(level.enabled(Level::Trace));
+        shared.set(Some(LevelFilter::Off));
+        assert!(!level.enabled(Level::Error));
+        shared.set(None);
+        assert_eq!(level.get(), None);
+    }
+}
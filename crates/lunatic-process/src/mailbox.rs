@@ -1,10 +1,167 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, Waker};
 
-use crate::message::Message;
+use crate::config::{MailboxLimit, MailboxOverflow};
+use crate::executor;
+use crate::message::{Message, NORMAL_PRIORITY};
+
+// Number of messages delivered while a message waits, after which its priority is raised by one.
+const AGING_STEP: u64 = 8;
+// Number of cancelled tags a mailbox remembers, older ones are forgotten first.
+const MAX_CANCELLED_TAGS: usize = 1024;
+
+type WatermarkCallback = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// Reports a mailbox that grows too long.
+///
+/// Once the number of queued messages reaches `high`, the callback is invoked with the length of
+/// the mailbox. It's not invoked again until the mailbox drained down to `low` messages, so that
+/// a mailbox hovering around `high` doesn't report over and over. Unlike a bounded mailbox no
+/// message is ever refused, the callback can shed load or raise an alert instead.
+///
+/// The callback is dispatched on the blocking thread pool, so it can send messages (also to the
+/// same process) without deadlocking the mailbox.
+#[derive(Clone)]
+pub struct Watermark {
+    high: usize,
+    low: usize,
+    callback: WatermarkCallback,
+}
+
+impl Watermark {
+    /// `low` is capped to one less than `high`.
+    pub fn new<F>(high: usize, low: usize, callback: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        Self {
+            high,
+            low: low.min(high.saturating_sub(1)),
+            callback: Arc::new(callback),
+        }
+    }
+
+    pub fn high(&self) -> usize {
+        self.high
+    }
+
+    pub fn low(&self) -> usize {
+        self.low
+    }
+}
+
+/// Storage of the messages queued in a [`MessageMailbox`], oldest first.
+///
+/// The mailbox decides which message is delivered next (tags, priorities, limits, cancelled
+/// tags), the queue only stores the messages in the order they arrived. By default messages are
+/// kept in memory (`VecDeque<Message>`). A custom queue (see
+/// [`MessageMailbox::with_queue`]) can persist them, e.g. to disk, so that the mailbox of a
+/// restarted process continues with the messages the previous one didn't receive. Messages
+/// already in the queue when the mailbox is created are delivered like any other.
+///
+/// Only data messages without resources can be persisted in a meaningful way, resources (e.g.
+/// processes and TCP streams) don't outlive the runtime.
+///
+/// Only user messages are stored in the queue, system messages are always kept in memory.
+pub trait MessageQueue: Send {
+    /// Appends a message after the newest one.
+    fn push_back(&mut self, message: Message);
+    /// Returns the message at `index`, 0 being the oldest.
+    fn get(&self, index: usize) -> Option<&Message>;
+    /// Removes the message at `index`, newer messages move one position forward.
+    fn remove(&mut self, index: usize) -> Option<Message>;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl MessageQueue for VecDeque<Message> {
+    fn push_back(&mut self, message: Message) {
+        VecDeque::push_back(self, message)
+    }
+
+    fn get(&self, index: usize) -> Option<&Message> {
+        VecDeque::get(self, index)
+    }
+
+    fn remove(&mut self, index: usize) -> Option<Message> {
+        VecDeque::remove(self, index)
+    }
+
+    fn len(&self) -> usize {
+        VecDeque::len(self)
+    }
+}
+
+/// Creates the queue of a process, from the job ID of the process if it has one (see
+/// [`ProcessConfig::set_job_id`](crate::config::ProcessConfig::set_job_id)). The job ID stays the
+/// same if a process is restarted, unlike the process ID.
+pub type MessageQueueFactory = Arc<dyn Fn(Option<&str>) -> Box<dyn MessageQueue> + Send + Sync>;
+
+/// Hands out the queues of a [`MessageQueueFactory`] to processes.
+///
+/// Only one running process owns the queue of a job. A process spawned while another process
+/// with the same job ID is still running, e.g. a child spawned with the configuration of its
+/// parent, gets a queue as if it had no job ID. The job ID is released once its process finishes,
+/// before the links are notified, so that a supervisor restarting the process gets the queue back.
+///
+/// Clones share the same owned job IDs.
+#[derive(Clone)]
+pub struct MessageQueues {
+    factory: MessageQueueFactory,
+    claimed: Arc<Mutex<HashSet<String>>>,
+}
+
+impl MessageQueues {
+    pub fn new(factory: MessageQueueFactory) -> Self {
+        Self {
+            factory,
+            claimed: Arc::default(),
+        }
+    }
+
+    /// Creates the mailbox of a process with the job ID `job_id`.
+    pub fn mailbox(&self, job_id: Option<&str>) -> MessageMailbox {
+        let claim = job_id.and_then(|job_id| {
+            let mut claimed = self.claimed.lock().expect("never poisoned");
+            if !claimed.insert(job_id.to_owned()) {
+                return None;
+            }
+            Some(JobClaim {
+                job_id: job_id.to_owned(),
+                claimed: self.claimed.clone(),
+            })
+        });
+        let queue = (self.factory)(claim.as_ref().map(|claim| claim.job_id.as_str()));
+        let mailbox = MessageMailbox::with_queue(queue);
+        mailbox
+            .inner
+            .lock()
+            .expect("only accessed by one process")
+            .job_claim = claim;
+        mailbox
+    }
+}
+
+// Job ID owned by a mailbox, released on drop.
+struct JobClaim {
+    job_id: String,
+    claimed: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Drop for JobClaim {
+    fn drop(&mut self) {
+        self.claimed
+            .lock()
+            .expect("never poisoned")
+            .remove(&self.job_id);
+    }
+}
 
 /// The `MessageMailbox` is a data structure holding all messages of a process.
 ///
@@ -12,6 +169,37 @@ use crate::message::Message;
 /// this structure. The order of messages is preserved. This struct also implements the [`Future`]
 /// trait and `pop()` operations can be awaited on if the queue is empty.
 ///
+/// System messages (see [`DataMessage::new_system`](crate::message::DataMessage::new_system)) are
+/// kept in a separate queue. They are only returned by `pop_system()`, so that framework code can
+/// handle them before user code gets to see the rest of the messages.
+///
+/// ## Priorities
+///
+/// Data messages can have a [priority](crate::message::DataMessage::priority). Messages with a
+/// higher priority are returned first, messages of the same priority in FIFO order. If no message
+/// sets a priority, the mailbox behaves as a plain FIFO queue.
+///
+/// To not starve low priority messages under a steady stream of higher priority ones, queued
+/// messages age: for every 8 messages delivered while a message is waiting, its priority is raised
+/// by one level. If priorities are equal, the older message wins. A message with the normal
+/// priority is delivered after at most `8 * MAX_PRIORITY` newer messages jumped ahead of it, plus
+/// the messages that already were ahead of it in the queue.
+///
+/// ## Watermark
+///
+/// By default a mailbox can grow without limit. A [`Watermark`] set with
+/// [`set_watermark`](Self::set_watermark) reports when it grows too long.
+///
+/// ## Limit
+///
+/// A mailbox can be limited to a number of queued messages with
+/// [`set_limit`](Self::set_limit). Messages arriving at a full mailbox are dropped according to
+/// the [`MailboxOverflow`] policy.
+///
+/// ## Storage
+///
+/// User messages are stored in a [`MessageQueue`], in memory by default.
+///
 /// ## Safety
 ///
 /// This should be cancellation safe and can be used inside `tokio::select!` statements:
@@ -26,53 +214,341 @@ struct InnerMessageMailbox {
     waker: Option<Waker>,
     tags: Option<Vec<i64>>,
     found: Option<Message>,
-    messages: VecDeque<Message>,
+    messages: Queue,
+    system_messages: Queue,
+    // Number of messages taken out of the mailbox, used to age queued messages.
+    delivered: u64,
+    // Number of queued messages with a priority above normal. If it's 0, the queues are FIFO.
+    prioritized: usize,
+    // Set if the current waiter is waiting on a system message.
+    waiting_on_system: bool,
+    // Set if the current waiter can be cancelled.
+    cancellable: bool,
+    // Set if the current waiter was cancelled, it returns without a message.
+    cancelled: bool,
+    // Tags of abandoned request/response correlations. A late message with one of this tags
+    // will be dropped on arrival.
+    cancelled_tags: CancelledTags,
+    watermark: Option<Watermark>,
+    // Set after the watermark was reported, until the mailbox drained to the low mark.
+    above_watermark: bool,
+    limit: Option<MailboxLimit>,
+    // Number of messages dropped because the mailbox was full.
+    dropped: u64,
+    // Job ID owned by the mailbox, see `MessageQueues`.
+    job_claim: Option<JobClaim>,
+}
+
+// Tags cancelled with `cancel_tag`, at most `MAX_CANCELLED_TAGS` of them. A reply that never
+// arrives would keep its tag around forever, so the oldest tags are forgotten once the limit is
+// reached. A late reply to a forgotten tag is delivered like any other message.
+#[derive(Default)]
+struct CancelledTags {
+    tags: HashSet<i64>,
+    // Tags in the order they were cancelled.
+    order: VecDeque<i64>,
+}
+
+impl CancelledTags {
+    fn insert(&mut self, tag: i64) {
+        if !self.tags.insert(tag) {
+            return;
+        }
+        self.order.push_back(tag);
+        if self.order.len() > MAX_CANCELLED_TAGS {
+            let oldest = self.order.pop_front().expect("not empty");
+            self.tags.remove(&oldest);
+        }
+    }
+
+    // Returns true if the tag was cancelled.
+    fn remove(&mut self, tag: i64) -> bool {
+        let removed = self.tags.remove(&tag);
+        if removed {
+            self.order.retain(|&cancelled| cancelled != tag);
+        }
+        removed
+    }
+
+    // Waiting on tags starts new correlations, even if they were cancelled before.
+    fn resume(&mut self, tags: Option<&[i64]>) {
+        for &tag in tags.unwrap_or_default() {
+            self.remove(tag);
+        }
+    }
+}
+
+// Messages waiting in the mailbox.
+struct Queue {
+    messages: Box<dyn MessageQueue>,
+    // Value of `delivered` when each message arrived, in the same order as `messages`.
+    arrived: VecDeque<u64>,
+}
+
+impl Queue {
+    // Messages already in the queue count as arrived before the first delivery.
+    fn new(messages: Box<dyn MessageQueue>) -> Self {
+        let arrived = std::iter::repeat_n(0, messages.len()).collect();
+        Self { messages, arrived }
+    }
+
+    fn push_back(&mut self, message: Message, arrived: u64) {
+        self.messages.push_back(message);
+        self.arrived.push_back(arrived);
+    }
+
+    fn get(&self, index: usize) -> Option<&Message> {
+        self.messages.get(index)
+    }
+
+    // Priority of the message at `index`, raised by the deliveries since it arrived.
+    fn priority(&self, index: usize, delivered: u64) -> u64 {
+        let message = self.messages.get(index).expect("must exist");
+        message.priority() as u64 + (delivered - self.arrived[index]) / AGING_STEP
+    }
+
+    fn remove(&mut self, index: usize) -> Option<Message> {
+        let message = self.messages.remove(index)?;
+        self.arrived.remove(index);
+        Some(message)
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    // Number of messages with a priority above normal.
+    fn prioritized(&self) -> usize {
+        (0..self.len())
+            .filter(|index| self.get(*index).expect("must exist").priority() > NORMAL_PRIORITY)
+            .count()
+    }
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self::new(Box::<VecDeque<Message>>::default())
+    }
+}
+
+impl InnerMessageMailbox {
+    // If a found message exists here, it means that the previous `.await` was canceled after a
+    // `wake()` call. To not lose this message it should be put back into its queue.
+    fn requeue_found(&mut self) {
+        if let Some(found) = self.found.take() {
+            self.enqueue(found);
+        }
+    }
+
+    fn enqueue(&mut self, message: Message) {
+        if message.priority() > NORMAL_PRIORITY {
+            self.prioritized += 1;
+        }
+        let arrived = self.delivered;
+        self.queue(message.is_system()).push_back(message, arrived);
+        self.check_watermark();
+    }
+
+    // Makes room for one more message if the mailbox is limited.
+    //
+    // Returns `false` if the arriving message needs to be dropped instead.
+    fn make_room(&mut self) -> bool {
+        let limit = match self.limit {
+            Some(limit) if self.messages.len() >= limit.capacity => limit,
+            _ => return true,
+        };
+        self.dropped += 1;
+        match limit.overflow {
+            MailboxOverflow::DropNewest => false,
+            MailboxOverflow::DropOldest => match self.messages.remove(0) {
+                Some(oldest) => {
+                    if oldest.priority() > NORMAL_PRIORITY {
+                        self.prioritized -= 1;
+                    }
+                    true
+                }
+                // Only possible with a capacity of 0.
+                None => false,
+            },
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len() + self.system_messages.len()
+    }
+
+    // Reports the watermark if it was reached, or re-arms it if the mailbox drained enough.
+    fn check_watermark(&mut self) {
+        let watermark = match &self.watermark {
+            Some(watermark) => watermark,
+            None => return,
+        };
+        let len = self.len();
+        if self.above_watermark {
+            self.above_watermark = len > watermark.low;
+        } else if len >= watermark.high {
+            self.above_watermark = true;
+            let callback = watermark.callback.clone();
+            executor::spawn_blocking(move || callback(len));
+        }
+    }
+
+    // Removes the next message from the queue. If `tags` are given, only messages with one of the
+    // tags are considered.
+    fn take(&mut self, system: bool, tags: Option<&[i64]>) -> Option<Message> {
+        let delivered = self.delivered;
+        let prioritized = self.prioritized > 0;
+        let queue = self.queue(system);
+        let matches = |index: &usize| match tags {
+            // Only consider messages that also have a tag.
+            Some(tags) => queue
+                .get(*index)
+                .and_then(Message::tag)
+                .is_some_and(|tag| tags.contains(&tag)),
+            None => true,
+        };
+        let index = if prioritized {
+            let mut best: Option<(usize, u64)> = None;
+            for index in (0..queue.len()).filter(matches) {
+                let priority = queue.priority(index, delivered);
+                if best.is_none_or(|(_, best)| priority > best) {
+                    best = Some((index, priority));
+                }
+            }
+            best.map(|(index, _)| index)
+        } else {
+            // All messages have the same priority and older ones are aged more, so the first one
+            // is the next.
+            (0..queue.len()).find(matches)
+        }?;
+        let message = queue.remove(index).expect("must exist");
+        if message.priority() > NORMAL_PRIORITY {
+            self.prioritized -= 1;
+        }
+        self.delivered += 1;
+        self.check_watermark();
+        Some(message)
+    }
+
+    fn queue(&mut self, system: bool) -> &mut Queue {
+        if system {
+            &mut self.system_messages
+        } else {
+            &mut self.messages
+        }
+    }
 }
 
 impl MessageMailbox {
+    /// Creates a mailbox storing user messages in `queue`. Messages already in the queue are
+    /// kept and count as the oldest ones.
+    pub fn with_queue(queue: Box<dyn MessageQueue>) -> Self {
+        let messages = Queue::new(queue);
+        let inner = InnerMessageMailbox {
+            prioritized: messages.prioritized(),
+            messages,
+            ..InnerMessageMailbox::default()
+        };
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Releases the job ID the mailbox owns, so that a new process with the same job ID gets the
+    /// job queue. Called once the process finished, see [`MessageQueues`].
+    pub fn release_job(&self) {
+        self.inner
+            .lock()
+            .expect("only accessed by one process")
+            .job_claim = None;
+    }
+
+    /// Returns the number of queued messages, user and system ones.
+    pub fn len(&self) -> usize {
+        self.inner
+            .lock()
+            .expect("only accessed by one process")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Return message in FIFO order from mailbox.
     ///
     /// If function is called with a `tags` value different from None, it will only return the first
     /// message matching any of the tags.
     ///
-    /// If no message exist, blocks until a message is received.
+    /// If no message exist, blocks until a message is received. System messages are skipped.
     pub async fn pop(&self, tags: Option<&[i64]>) -> Message {
+        self.pop_from(tags, false, false)
+            .await
+            .expect("only cancellable pops are cancelled")
+    }
+
+    /// Return system message in FIFO order from mailbox, the same way `pop` does for user
+    /// messages.
+    pub async fn pop_system(&self, tags: Option<&[i64]>) -> Message {
+        self.pop_from(tags, true, false)
+            .await
+            .expect("only cancellable pops are cancelled")
+    }
+
+    /// Same as `pop` or `pop_system`, but returns `None` if the wait is cancelled with
+    /// [`cancel_receive`](Self::cancel_receive).
+    pub async fn pop_cancellable(&self, tags: Option<&[i64]>, system: bool) -> Option<Message> {
+        self.pop_from(tags, system, true).await
+    }
+
+    async fn pop_from(
+        &self,
+        tags: Option<&[i64]>,
+        system: bool,
+        cancellable: bool,
+    ) -> Option<Message> {
         // Mailbox lock must be released before .await
         {
             let mut mailbox = self.inner.lock().expect("only accessed by one process");
+            mailbox.requeue_found();
+            // A cancel of an earlier, abandoned wait doesn't apply to this one.
+            mailbox.cancelled = false;
 
-            // If a found message exists here, it means that the previous `.await` was canceled
-            // after a `wake()` call. To not lose this message it should be put into the queue.
-            if let Some(found) = mailbox.found.take() {
-                mailbox.messages.push_back(found);
-            }
-
-            // When looking for specific tags, loop through all messages to check for it
-            if let Some(tags) = tags {
-                let index = mailbox.messages.iter().position(|x| {
-                    // Only consider messages that also have a tag.
-                    if let Some(tag) = x.tag() {
-                        tags.contains(&tag)
-                    } else {
-                        false
-                    }
-                });
-                // If message matching tags is found, remove it.
-                if let Some(index) = index {
-                    return mailbox.messages.remove(index).expect("must exist");
-                }
-            } else {
-                // If not looking for a specific tags try to pop the first message available.
-                if let Some(message) = mailbox.messages.pop_front() {
-                    return message;
-                }
+            mailbox.cancelled_tags.resume(tags);
+            if let Some(message) = mailbox.take(system, tags) {
+                return Some(message);
             }
             // Mark the tags to wait on.
             mailbox.tags = tags.map(|tags| tags.into());
+            mailbox.waiting_on_system = system;
+            mailbox.cancellable = cancellable;
         }
         self.await
     }
 
+    /// Wakes up a waiting [`pop_cancellable`](Self::pop_cancellable) without a message, it
+    /// returns `None`.
+    ///
+    /// Only a wait that is in progress is cancelled, the next one is not affected. If a message was
+    /// already handed over to the waiter, the message wins and nothing is cancelled. A message
+    /// arriving after the cancel stays in the mailbox for the next receive.
+    ///
+    /// Returns `true` if a wait was cancelled.
+    pub fn cancel_receive(&self) -> bool {
+        let mut mailbox = self.inner.lock().expect("only accessed by one process");
+        if !mailbox.cancellable || mailbox.found.is_some() {
+            return false;
+        }
+        match mailbox.waker.take() {
+            Some(waker) => {
+                mailbox.cancelled = true;
+                waker.wake();
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Similar to `pop`, but will assume right away that no message with this tags exists.
     ///
     /// Sometimes we know that the message we are waiting on can't have a particular tags already in
@@ -96,17 +572,69 @@ impl MessageMailbox {
         // Mailbox lock must be released before .await
         {
             let mut mailbox = self.inner.lock().expect("only accessed by one process");
+            mailbox.requeue_found();
 
-            // If a found message exists here, it means that the previous `.await` was canceled
-            // after a `wake()` call. To not lose this message it should be put into the queue.
-            if let Some(found) = mailbox.found.take() {
-                mailbox.messages.push_back(found);
-            }
+            mailbox.cancelled_tags.resume(tags);
 
             // Mark the tags to wait on.
             mailbox.tags = tags.map(|tags| tags.into());
+            mailbox.waiting_on_system = false;
+            mailbox.cancellable = false;
+            mailbox.cancelled = false;
         }
-        self.await
+        self.await.expect("only cancellable pops are cancelled")
+    }
+
+    /// Gives up on all messages with the tag.
+    ///
+    /// Messages with this tag that are already in the mailbox are removed and the next message
+    /// with the tag arriving later is going to be dropped instead of being put into the queue.
+    /// This is useful if a process stopped waiting on a reply (e.g. because of a timeout) and
+    /// doesn't want to keep the late reply around. Waiting on the tag again resets it. Only the
+    /// last 1024 cancelled tags are remembered, a late reply to an older one is delivered.
+    ///
+    /// This is a best-effort mechanism that only works on the local mailbox. The message could
+    /// still be in-flight or already processed by the responder, so it doesn't save any work on
+    /// the remote side.
+    ///
+    /// Returns the number of removed messages.
+    pub fn cancel_tag(&self, tag: i64) -> usize {
+        let mut mailbox = self.inner.lock().expect("only accessed by one process");
+        let mut removed = 0;
+        if mailbox.found.as_ref().and_then(|found| found.tag()) == Some(tag) {
+            mailbox.found = None;
+            removed += 1;
+        }
+        for system in [false, true] {
+            let mut prioritized = 0;
+            let queue = mailbox.queue(system);
+            let mut index = 0;
+            while let Some(message) = queue.get(index) {
+                if message.tag() != Some(tag) {
+                    index += 1;
+                    continue;
+                }
+                let message = queue.remove(index).expect("must exist");
+                if message.priority() > NORMAL_PRIORITY {
+                    prioritized += 1;
+                }
+                removed += 1;
+            }
+            mailbox.prioritized -= prioritized;
+        }
+        mailbox.check_watermark();
+        mailbox.cancelled_tags.insert(tag);
+        removed
+    }
+
+    /// Sets the watermark reported when the mailbox grows too long, `None` removes it.
+    ///
+    /// If the mailbox is already at or above the high mark, it's reported right away.
+    pub fn set_watermark(&self, watermark: Option<Watermark>) {
+        let mut mailbox = self.inner.lock().expect("only accessed by one process");
+        mailbox.watermark = watermark;
+        mailbox.above_watermark = false;
+        mailbox.check_watermark();
     }
 
     /// Pushes a message into the mailbox.
@@ -115,11 +643,21 @@ impl MessageMailbox {
     /// ready, otherwise it will push it at the end of the queue.
     pub fn push(&self, message: Message) {
         let mut mailbox = self.inner.lock().expect("only accessed by one process");
+        // Drop late messages belonging to a cancelled correlation.
+        if let Some(tag) = message.tag() {
+            if mailbox.cancelled_tags.remove(tag) {
+                return;
+            }
+        }
+        let system = message.is_system();
         // If waiting on a new message notify executor that it arrived.
         if let Some(waker) = mailbox.waker.take() {
             // If waiting on specific tags only notify if tags are matched, otherwise forward every message.
             // Note that because of the short-circuit rule in Rust it's safe to use `unwrap()` here.
-            if mailbox.tags.is_none()
+            if mailbox.waiting_on_system != system {
+                // Put the waker back if the message belongs to the other queue.
+                mailbox.waker = Some(waker);
+            } else if mailbox.tags.is_none()
                 || (message.tag().is_some()
                     && mailbox
                         .tags
@@ -136,17 +674,40 @@ impl MessageMailbox {
             }
         }
         // Otherwise put message into queue
-        mailbox.messages.push_back(message);
+        if system || mailbox.make_room() {
+            mailbox.enqueue(message);
+        }
+    }
+
+    /// Limits the number of queued messages, `None` removes the limit.
+    ///
+    /// Messages already in the mailbox are kept, even if there are more than `capacity`.
+    pub fn set_limit(&self, limit: Option<MailboxLimit>) {
+        let mut mailbox = self.inner.lock().expect("only accessed by one process");
+        mailbox.limit = limit;
+    }
+
+    /// Returns the number of messages dropped because the mailbox was full.
+    pub fn dropped(&self) -> u64 {
+        self.inner
+            .lock()
+            .expect("only accessed by one process")
+            .dropped
     }
 }
 
 impl Future for &MessageMailbox {
-    type Output = Message;
+    // `None` if the wait was cancelled.
+    type Output = Option<Message>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let mut mailbox = self.inner.lock().expect("only accessed by one process");
         if let Some(message) = mailbox.found.take() {
-            Poll::Ready(message)
+            mailbox.delivered += 1;
+            Poll::Ready(Some(message))
+        } else if mailbox.cancelled {
+            mailbox.cancelled = false;
+            Poll::Ready(None)
         } else {
             mailbox.waker = Some(cx.waker().clone());
             Poll::Pending
@@ -157,12 +718,15 @@ impl Future for &MessageMailbox {
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::VecDeque,
         future::Future,
         sync::{Arc, Mutex},
         task::{Context, Poll, Wake},
     };
 
-    use super::{Message, MessageMailbox};
+    use super::{Message, MessageMailbox, MessageQueue, Watermark, MAX_CANCELLED_TAGS};
+    use crate::config::{MailboxLimit, MailboxOverflow};
+    use crate::message::{DataMessage, MAX_PRIORITY};
 
     #[async_std::test]
     async fn no_tags_signal_message() {
@@ -238,6 +802,85 @@ mod tests {
         assert_eq!(message.tag(), Some(tag5));
     }
 
+    #[async_std::test]
+    async fn cancel_tag_drops_queued_and_late_messages() {
+        let mailbox = MessageMailbox::default();
+        mailbox.push(Message::LinkDied(Some(1)));
+        mailbox.push(Message::LinkDied(Some(2)));
+        mailbox.push(Message::LinkDied(Some(1)));
+        assert_eq!(mailbox.cancel_tag(1), 2);
+        // A late reply is dropped on arrival
+        mailbox.push(Message::LinkDied(Some(1)));
+        mailbox.push(Message::LinkDied(Some(3)));
+        let message = mailbox.pop(None).await;
+        assert_eq!(message.tag(), Some(2));
+        let message = mailbox.pop(None).await;
+        assert_eq!(message.tag(), Some(3));
+        // Only one late message is dropped
+        mailbox.cancel_tag(4);
+        mailbox.push(Message::LinkDied(Some(4)));
+        mailbox.push(Message::LinkDied(Some(4)));
+        let message = mailbox.pop(Some(&[4])).await;
+        assert_eq!(message.tag(), Some(4));
+    }
+
+    #[async_std::test]
+    async fn oldest_cancelled_tags_are_forgotten() {
+        let mailbox = MessageMailbox::default();
+        for tag in 0..=MAX_CANCELLED_TAGS as i64 {
+            mailbox.cancel_tag(tag);
+        }
+        // The first tag was forgotten, a late reply to it is delivered.
+        mailbox.push(Message::LinkDied(Some(0)));
+        mailbox.push(Message::LinkDied(Some(1)));
+        assert_eq!(mailbox.len(), 1);
+        let message = mailbox.pop(None).await;
+        assert_eq!(message.tag(), Some(0));
+    }
+
+    #[async_std::test]
+    async fn higher_priority_first_without_starving_others() {
+        let mailbox = MessageMailbox::default();
+        let message = |tag, priority| {
+            let mut message = DataMessage::new(Some(tag), 0);
+            message.priority = priority;
+            Message::Data(message)
+        };
+        mailbox.push(message(1, 0));
+        mailbox.push(message(2, 0));
+        mailbox.push(message(3, 2));
+        mailbox.push(message(4, 2));
+        assert_eq!(mailbox.pop(None).await.tag(), Some(3));
+        assert_eq!(mailbox.pop(None).await.tag(), Some(4));
+        assert_eq!(mailbox.pop(None).await.tag(), Some(1));
+
+        // A steady stream of urgent messages only delays the waiting one for a while.
+        let mut delivered = 0;
+        loop {
+            mailbox.push(message(100, MAX_PRIORITY));
+            delivered += 1;
+            if mailbox.pop(None).await.tag() == Some(2) {
+                break;
+            }
+            assert!(delivered <= 8 * MAX_PRIORITY as usize + 1);
+        }
+    }
+
+    #[async_std::test]
+    async fn system_messages_use_separate_queue() {
+        let mailbox = MessageMailbox::default();
+        mailbox.push(Message::Data(DataMessage::new_system(Some(1), 0)));
+        mailbox.push(Message::LinkDied(Some(2)));
+        mailbox.push(Message::Data(DataMessage::new_system(Some(3), 0)));
+        let message = mailbox.pop(None).await;
+        assert_eq!(message.tag(), Some(2));
+        let message = mailbox.pop_system(Some(&[3])).await;
+        assert_eq!(message.tag(), Some(3));
+        assert!(message.is_system());
+        let message = mailbox.pop_system(None).await;
+        assert_eq!(message.tag(), Some(1));
+    }
+
     #[derive(Clone)]
     struct FlagWaker(Arc<Mutex<bool>>);
     impl Wake for FlagWaker {
@@ -272,6 +915,23 @@ mod tests {
         assert!(result.is_ready());
     }
 
+    #[test]
+    fn waiting_on_user_message_ignores_system_message() {
+        let mailbox = MessageMailbox::default();
+        let waker = FlagWaker(Arc::new(Mutex::new(false)));
+        let waker_ref = waker.clone();
+        let waker = &Arc::new(waker).into();
+        let mut context = Context::from_waker(waker);
+        let fut = mailbox.pop(None);
+        let mut fut = Box::pin(fut);
+        assert!(fut.as_mut().poll(&mut context).is_pending());
+        mailbox.push(Message::Data(DataMessage::new_system(None, 0)));
+        assert!(!*waker_ref.0.lock().unwrap());
+        mailbox.push(Message::LinkDied(None));
+        assert!(*waker_ref.0.lock().unwrap());
+        assert!(fut.as_mut().poll(&mut context).is_ready());
+    }
+
     #[test]
     fn waiting_on_tag_after_none() {
         let mailbox = MessageMailbox::default();
@@ -339,4 +999,169 @@ mod tests {
             _ => panic!("Unexpected message"),
         }
     }
+
+    #[async_std::test]
+    async fn watermark_reported_once_until_drained() {
+        let mailbox = MessageMailbox::default();
+        let (sender, receiver) = async_std::channel::unbounded();
+        mailbox.set_watermark(Some(Watermark::new(3, 1, move |len| {
+            sender.try_send(len).unwrap();
+        })));
+        for tag in 0..4 {
+            mailbox.push(Message::LinkDied(Some(tag)));
+        }
+        assert_eq!(receiver.recv().await.unwrap(), 3);
+        // Hovering around the high mark doesn't report again.
+        mailbox.pop(None).await;
+        mailbox.pop(None).await;
+        mailbox.push(Message::LinkDied(None));
+        mailbox.push(Message::LinkDied(None));
+        assert!(receiver.is_empty());
+        // Draining to the low mark re-arms it.
+        for _ in 0..3 {
+            mailbox.pop(None).await;
+        }
+        mailbox.push(Message::LinkDied(None));
+        mailbox.push(Message::LinkDied(None));
+        assert_eq!(receiver.recv().await.unwrap(), 3);
+    }
+
+    #[async_std::test]
+    async fn full_mailbox_drops_by_policy() {
+        for (overflow, kept) in [
+            (MailboxOverflow::DropNewest, [1, 2]),
+            (MailboxOverflow::DropOldest, [2, 3]),
+        ] {
+            let mailbox = MessageMailbox::default();
+            mailbox.set_limit(Some(MailboxLimit {
+                capacity: 2,
+                overflow,
+            }));
+            for tag in 1..=3 {
+                mailbox.push(Message::LinkDied(Some(tag)));
+            }
+            assert_eq!(mailbox.dropped(), 1);
+            for tag in kept {
+                assert_eq!(mailbox.pop(None).await.tag(), Some(tag));
+            }
+        }
+    }
+
+    #[test]
+    fn full_mailbox_keeps_handed_over_message() {
+        let mailbox = MessageMailbox::default();
+        mailbox.set_limit(Some(MailboxLimit {
+            capacity: 1,
+            overflow: MailboxOverflow::DropOldest,
+        }));
+        let waker = &Arc::new(FlagWaker(Arc::new(Mutex::new(false)))).into();
+        let mut context = Context::from_waker(waker);
+        let mut pop = Box::pin(mailbox.pop(Some(&[1])));
+        assert!(pop.as_mut().poll(&mut context).is_pending());
+        mailbox.push(Message::LinkDied(Some(1)));
+        mailbox.push(Message::LinkDied(Some(2)));
+        mailbox.push(Message::LinkDied(Some(3)));
+        match pop.as_mut().poll(&mut context) {
+            Poll::Ready(message) => assert_eq!(message.tag(), Some(1)),
+            Poll::Pending => panic!("message must be delivered"),
+        }
+    }
+
+    #[test]
+    fn cancel_receive_races_with_message() {
+        let mailbox = MessageMailbox::default();
+        let waker = &Arc::new(FlagWaker(Arc::new(Mutex::new(false)))).into();
+        let mut context = Context::from_waker(waker);
+        // Nothing to cancel.
+        assert!(!mailbox.cancel_receive());
+        // A cancelled wait returns without a message and a later message stays queued.
+        let mut pop = Box::pin(mailbox.pop_cancellable(None, false));
+        assert!(pop.as_mut().poll(&mut context).is_pending());
+        assert!(mailbox.cancel_receive());
+        mailbox.push(Message::LinkDied(Some(1)));
+        assert!(matches!(pop.as_mut().poll(&mut context), Poll::Ready(None)));
+        drop(pop);
+        // A message handed over before the cancel wins.
+        let mut pop = Box::pin(mailbox.pop_cancellable(Some(&[2]), false));
+        assert!(pop.as_mut().poll(&mut context).is_pending());
+        mailbox.push(Message::LinkDied(Some(2)));
+        assert!(!mailbox.cancel_receive());
+        match pop.as_mut().poll(&mut context) {
+            Poll::Ready(Some(message)) => assert_eq!(message.tag(), Some(2)),
+            _ => panic!("message must be delivered"),
+        }
+        drop(pop);
+        // Non-cancellable receives are not affected.
+        let mut pop = Box::pin(mailbox.pop(Some(&[3])));
+        assert!(pop.as_mut().poll(&mut context).is_pending());
+        assert!(!mailbox.cancel_receive());
+        drop(pop);
+        // The message that arrived after the cancel is still there.
+        let mut pop = Box::pin(mailbox.pop(Some(&[1])));
+        assert!(pop.as_mut().poll(&mut context).is_ready());
+    }
+
+    // Keeps the tags of the queued messages in `journal`, like a durable queue would on disk.
+    struct Journaled {
+        messages: VecDeque<Message>,
+        journal: Arc<Mutex<Vec<i64>>>,
+    }
+
+    impl Journaled {
+        fn restore(journal: &Arc<Mutex<Vec<i64>>>) -> Box<Self> {
+            let messages = journal
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|tag| Message::Data(DataMessage::new(Some(*tag), 0)))
+                .collect();
+            Box::new(Self {
+                messages,
+                journal: journal.clone(),
+            })
+        }
+    }
+
+    impl MessageQueue for Journaled {
+        fn push_back(&mut self, message: Message) {
+            self.journal.lock().unwrap().push(message.tag().unwrap());
+            self.messages.push_back(message);
+        }
+
+        fn get(&self, index: usize) -> Option<&Message> {
+            self.messages.get(index)
+        }
+
+        fn remove(&mut self, index: usize) -> Option<Message> {
+            self.journal.lock().unwrap().remove(index);
+            self.messages.remove(index)
+        }
+
+        fn len(&self) -> usize {
+            self.messages.len()
+        }
+    }
+
+    #[async_std::test]
+    async fn custom_queue_outlives_mailbox() {
+        let journal = Arc::default();
+        let mailbox = MessageMailbox::with_queue(Journaled::restore(&journal));
+        for tag in 1..=3 {
+            mailbox.push(Message::Data(DataMessage::new(Some(tag), 0)));
+        }
+        // System messages are kept in memory.
+        mailbox.push(Message::Data(DataMessage::new_system(Some(9), 0)));
+        assert_eq!(mailbox.pop(Some(&[2])).await.tag(), Some(2));
+        assert_eq!(mailbox.len(), 3);
+        assert_eq!(*journal.lock().unwrap(), [1, 3]);
+
+        // The process restarts with a new mailbox.
+        let mailbox = MessageMailbox::with_queue(Journaled::restore(&journal));
+        assert_eq!(mailbox.len(), 2);
+        mailbox.push(Message::Data(DataMessage::new(Some(4), 0)));
+        assert_eq!(mailbox.pop(None).await.tag(), Some(1));
+        assert_eq!(mailbox.pop(None).await.tag(), Some(3));
+        assert_eq!(mailbox.pop(None).await.tag(), Some(4));
+        assert!(journal.lock().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,95 @@
+use std::{fmt::Debug, sync::Arc};
+
+use uuid::Uuid;
+
+use crate::executor;
+
+type MemoryCallback = Arc<dyn Fn(Uuid, usize) + Send + Sync>;
+
+/// Reports processes whose memory grows close to their limit.
+///
+/// Thresholds are percentages of the process' maximum memory. Each threshold is reported at most
+/// once per process, the first time the memory high-water mark of the process grows past it. The
+/// callback receives the process ID and the new memory size in bytes.
+///
+/// The callback is dispatched on the blocking thread pool, so it can't stall the process that is
+/// growing its memory.
+#[derive(Clone)]
+pub struct MemoryWatch {
+    // Sorted in ascending order
+    thresholds: Vec<u8>,
+    callback: MemoryCallback,
+}
+
+impl MemoryWatch {
+    pub fn new<F>(thresholds: impl IntoIterator<Item = u8>, callback: F) -> Self
+    where
+        F: Fn(Uuid, usize) + Send + Sync + 'static,
+    {
+        let mut thresholds: Vec<u8> = thresholds.into_iter().collect();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        Self {
+            thresholds,
+            callback: Arc::new(callback),
+        }
+    }
+
+    pub fn thresholds(&self) -> &[u8] {
+        &self.thresholds
+    }
+
+    /// Returns how many thresholds a memory of `size` bytes is past, if the limit is `max_memory`.
+    pub fn crossed(&self, size: usize, max_memory: usize) -> usize {
+        if max_memory == 0 {
+            return self.thresholds.len();
+        }
+        let percent = (size as u128 * 100 / max_memory as u128) as usize;
+        self.thresholds
+            .iter()
+            .take_while(|threshold| **threshold as usize <= percent)
+            .count()
+    }
+
+    /// Dispatches the callback without waiting for it to finish.
+    pub fn notify(&self, process_id: Uuid, size: usize) {
+        let callback = self.callback.clone();
+        executor::spawn_blocking(move || callback(process_id, size));
+    }
+}
+
+impl Debug for MemoryWatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryWatch")
+            .field("thresholds", &self.thresholds)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::MemoryWatch;
+
+    #[test]
+    fn thresholds_are_counted_by_percentage() {
+        let watch = MemoryWatch::new([90, 50, 50], |_, _| {});
+        assert_eq!(watch.thresholds(), &[50, 90]);
+        assert_eq!(watch.crossed(10, 100), 0);
+        assert_eq!(watch.crossed(50, 100), 1);
+        assert_eq!(watch.crossed(89, 100), 1);
+        assert_eq!(watch.crossed(100, 100), 2);
+    }
+
+    #[async_std::test]
+    async fn callback_is_dispatched() {
+        let (sender, receiver) = async_std::channel::unbounded();
+        let watch = MemoryWatch::new([50], move |id, size| {
+            sender.try_send((id, size)).unwrap();
+        });
+        let id = Uuid::new_v4();
+        watch.notify(id, 1024);
+        assert_eq!(receiver.recv().await.unwrap(), (id, 1024));
+    }
+}
@@ -5,26 +5,29 @@ kinds of messages, like the [`Message::LinkDied`], that is received if a linked
 */
 
 use std::{
+    collections::HashMap,
     fmt::Debug,
     io::{Read, Write},
     sync::Arc,
 };
 
-use async_std::net::{TcpStream, UdpSocket};
+use async_std::net::{TcpListener, TcpStream, UdpSocket};
 
-use crate::Process;
+use crate::{process_ref::ProcessRef, Process};
 
 /// Can be sent between processes by being embedded into a  [`Signal::Message`][0]
 ///
-/// A [`Message`] has 2 variants:
+/// A [`Message`] has 3 variants:
 /// * Data - Regular message containing a tag, buffer and resources.
 /// * LinkDied - A `LinkDied` signal that was turned into a message.
+/// * Shutdown - The process received a `Kill` signal with a grace period and should stop.
 ///
 /// [0]: crate::Signal
 #[derive(Debug)]
 pub enum Message {
     Data(DataMessage),
     LinkDied(Option<i64>),
+    Shutdown,
 }
 
 impl Message {
@@ -32,10 +35,39 @@ impl Message {
         match self {
             Message::Data(message) => message.tag,
             Message::LinkDied(tag) => *tag,
+            Message::Shutdown => None,
+        }
+    }
+
+    /// Returns the priority of the message, only data messages can have a priority above
+    /// [`NORMAL_PRIORITY`].
+    pub fn priority(&self) -> u8 {
+        match self {
+            Message::Data(message) => message.priority,
+            Message::LinkDied(_) | Message::Shutdown => NORMAL_PRIORITY,
+        }
+    }
+
+    /// Returns true for system messages, see [`DataMessage::new_system`].
+    pub fn is_system(&self) -> bool {
+        matches!(self, Message::Data(message) if message.system)
+    }
+
+    /// Returns a copy of the message for another receiver, `None` if it carries resources.
+    pub fn try_clone(&self) -> Option<Self> {
+        match self {
+            Message::Data(message) => message.try_clone().map(Message::Data),
+            Message::LinkDied(tag) => Some(Message::LinkDied(*tag)),
+            Message::Shutdown => Some(Message::Shutdown),
         }
     }
 }
 
+/// Priority of messages that didn't set one.
+pub const NORMAL_PRIORITY: u8 = 0;
+/// Highest priority a message can have.
+pub const MAX_PRIORITY: u8 = 3;
+
 /// A variant of a [`Message`] that has a buffer of data and resources attached to it.
 ///
 /// It implements the [`Read`](std::io::Read) and [`Write`](std::io::Write) traits.
@@ -46,6 +78,17 @@ pub struct DataMessage {
     pub read_ptr: usize,
     pub buffer: Vec<u8>,
     pub resources: Vec<Resource>,
+    /// System messages are kept apart from user messages by the
+    /// [`MessageMailbox`](crate::mailbox::MessageMailbox).
+    pub system: bool,
+    /// Messages with a higher priority are received first, see
+    /// [`MessageMailbox`](crate::mailbox::MessageMailbox). At most [`MAX_PRIORITY`].
+    pub priority: u8,
+    /// Process the receiver should reply to, usually the sender. The reply is expected to carry
+    /// the same tag as the request.
+    pub reply_to: Option<ProcessRef>,
+    // Tags of the outbound TCP streams in `resources`, keyed by the resource index.
+    outbound_tags: HashMap<usize, OutboundTag>,
 }
 
 impl DataMessage {
@@ -56,9 +99,41 @@ impl DataMessage {
             read_ptr: 0,
             buffer: Vec::with_capacity(buffer_capacity),
             resources: Vec::new(),
+            system: false,
+            priority: NORMAL_PRIORITY,
+            reply_to: None,
+            outbound_tags: HashMap::new(),
+        }
+    }
+
+    /// Create a new system message, only received by [`MessageMailbox::pop_system`].
+    ///
+    /// [`MessageMailbox::pop_system`]: crate::mailbox::MessageMailbox::pop_system
+    pub fn new_system(tag: Option<i64>, buffer_capacity: usize) -> Self {
+        Self {
+            system: true,
+            ..Self::new(tag, buffer_capacity)
         }
     }
 
+    /// Returns a copy of the message for another receiver, `None` if it carries resources. A
+    /// resource can only be moved to one process.
+    pub fn try_clone(&self) -> Option<Self> {
+        if !self.resources.is_empty() {
+            return None;
+        }
+        Some(Self {
+            tag: self.tag,
+            read_ptr: self.read_ptr,
+            buffer: self.buffer.clone(),
+            resources: Vec::new(),
+            system: self.system,
+            priority: self.priority,
+            reply_to: self.reply_to.clone(),
+            outbound_tags: HashMap::new(),
+        })
+    }
+
     /// Adds a process to the message and returns the index of it inside of the message
     pub fn add_process(&mut self, process: Arc<dyn Process>) -> usize {
         self.resources.push(Resource::Process(process));
@@ -71,12 +146,28 @@ impl DataMessage {
         self.resources.len() - 1
     }
 
+    /// Adds an outbound TCP stream to the message and returns the index of it inside of the
+    /// message. The tag is handed out again by [`take_tagged_tcp_stream`].
+    ///
+    /// [`take_tagged_tcp_stream`]: DataMessage::take_tagged_tcp_stream
+    pub fn add_outbound_tcp_stream(&mut self, tcp_stream: TcpStream, tag: OutboundTag) -> usize {
+        let index = self.add_tcp_stream(tcp_stream);
+        self.outbound_tags.insert(index, tag);
+        index
+    }
+
     /// Adds a UDP socket to the message and returns the index of it inside of the message
     pub fn add_udp_socket(&mut self, udp_socket: Arc<UdpSocket>) -> usize {
         self.resources.push(Resource::UdpSocket(udp_socket));
         self.resources.len() - 1
     }
 
+    /// Adds a TCP listener to the message and returns the index of it inside of the message
+    pub fn add_tcp_listener(&mut self, tcp_listener: TcpListener) -> usize {
+        self.resources.push(Resource::TcpListener(tcp_listener));
+        self.resources.len() - 1
+    }
+
     /// Takes a process from the message, but preserves the indexes of all others.
     ///
     /// If the index is out of bound or the resource is not a process the function will return
@@ -106,6 +197,7 @@ impl DataMessage {
             let resource = std::mem::replace(resource_ref, Resource::None);
             match resource {
                 Resource::TcpStream(stream) => {
+                    self.outbound_tags.remove(&index);
                     return Some(stream);
                 }
                 other => {
@@ -117,6 +209,20 @@ impl DataMessage {
         None
     }
 
+    /// Takes a TCP stream from the message together with its [`OutboundTag`], if it was added
+    /// as an outbound stream.
+    ///
+    /// If the index is out of bound or the resource is not a tcp stream the function will return
+    /// None.
+    pub fn take_tagged_tcp_stream(
+        &mut self,
+        index: usize,
+    ) -> Option<(TcpStream, Option<OutboundTag>)> {
+        let tag = self.outbound_tags.get(&index).cloned();
+        let stream = self.take_tcp_stream(index)?;
+        Some((stream, tag))
+    }
+
     /// Takes a UDP Socket from the message, but preserves the indexes of all others.
     ///
     /// If the index is out of bound or the resource is not a tcp stream the function will return
@@ -137,6 +243,26 @@ impl DataMessage {
         None
     }
 
+    /// Takes a TCP listener from the message, but preserves the indexes of all others.
+    ///
+    /// If the index is out of bound or the resource is not a tcp listener the function will
+    /// return None.
+    pub fn take_tcp_listener(&mut self, index: usize) -> Option<TcpListener> {
+        if let Some(resource_ref) = self.resources.get_mut(index) {
+            let resource = std::mem::replace(resource_ref, Resource::None);
+            match resource {
+                Resource::TcpListener(listener) => {
+                    return Some(listener);
+                }
+                other => {
+                    // Put the resource back if it's not a tcp listener and drop empty.
+                    let _ = std::mem::replace(resource_ref, other);
+                }
+            }
+        }
+        None
+    }
+
     /// Moves read pointer to index.
     pub fn seek(&mut self, index: usize) {
         self.read_ptr = index;
@@ -174,6 +300,25 @@ impl Read for DataMessage {
     }
 }
 
+/// Marks a TCP stream as an outbound connection.
+///
+/// Clones of the stream and the processes the stream is sent to share the tag, so limits can
+/// count the underlying connections instead of the stream resources referring to them.
+#[derive(Clone, Debug, Default)]
+pub struct OutboundTag(Arc<()>);
+
+impl OutboundTag {
+    /// Creates a tag for a new connection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if another copy of the tag is still alive, e.g. attached to a stream.
+    pub fn in_use(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+}
+
 /// A resource ([`WasmProcess`](crate::WasmProcess), [`TcpStream`](async_std::net::TcpStream),
 /// ...) that is attached to a [`DataMessage`].
 pub enum Resource {
@@ -181,6 +326,7 @@ pub enum Resource {
     Process(Arc<dyn Process>),
     TcpStream(TcpStream),
     UdpSocket(Arc<UdpSocket>),
+    TcpListener(TcpListener),
 }
 
 impl Debug for Resource {
@@ -190,6 +336,7 @@ impl Debug for Resource {
             Self::Process(_) => write!(f, "Process"),
             Self::TcpStream(_) => write!(f, "TcpStream"),
             Self::UdpSocket(_) => write!(f, "UdpSocket"),
+            Self::TcpListener(_) => write!(f, "TcpListener"),
         }
     }
 }
@@ -0,0 +1,191 @@
+/*!
+Counts of messages and bytes sent between pairs of processes.
+
+Accounting needs to be enabled with the `message-stats` feature, without it the module and all
+recording calls are compiled out. Even with the feature enabled, messages are only counted after
+[`start`] was called, otherwise recording is a single atomic load.
+
+The counts form a sparse matrix keyed by the sender and receiver ID, e.g. to draw a map of the
+communication between processes or find chatty pairs. Only messages sent by a process through the
+`lunatic::message` host functions are counted, signals and messages sent by the host are not.
+
+The matrix is bounded: once it holds the maximum number of pairs, messages between new pairs are
+only added to [`MessageMatrix::untracked`]. Pairs are never removed on their own, also not after
+the processes finished, use [`reset`] to start over.
+*/
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use uuid::Uuid;
+
+use crate::message::Message;
+
+// Set while accounting is running, so that recording is a single atomic load otherwise.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static MATRIX: Mutex<Option<Matrix>> = Mutex::new(None);
+
+/// Traffic from one process to another.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PairStats {
+    pub messages: u64,
+    /// Size of the data messages, resources attached to them are not counted.
+    pub bytes: u64,
+}
+
+impl PairStats {
+    fn add(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// Snapshot of the counted traffic.
+#[derive(Clone, Debug, Default)]
+pub struct MessageMatrix {
+    /// Traffic keyed by the sender and receiver ID.
+    pub pairs: HashMap<(Uuid, Uuid), PairStats>,
+    /// Traffic between pairs that didn't fit into the matrix anymore.
+    pub untracked: PairStats,
+}
+
+impl MessageMatrix {
+    pub fn get(&self, sender: Uuid, receiver: Uuid) -> Option<PairStats> {
+        self.pairs.get(&(sender, receiver)).copied()
+    }
+
+    /// Returns the `n` pairs that exchanged the most bytes, the busiest first.
+    pub fn busiest(&self, n: usize) -> Vec<((Uuid, Uuid), PairStats)> {
+        let mut pairs: Vec<_> = self
+            .pairs
+            .iter()
+            .map(|(pair, stats)| (*pair, *stats))
+            .collect();
+        pairs.sort_by_key(|(_, stats)| Reverse(stats.bytes));
+        pairs.truncate(n);
+        pairs
+    }
+}
+
+struct Matrix {
+    max_pairs: usize,
+    counts: MessageMatrix,
+}
+
+/// Starts counting, keeping at most `max_pairs` pairs. Counts collected before are kept, but the
+/// new maximum only applies to pairs added from now on.
+pub fn start(max_pairs: usize) {
+    let mut matrix = MATRIX.lock().expect("never poisoned");
+    match matrix.as_mut() {
+        Some(matrix) => matrix.max_pairs = max_pairs,
+        None => {
+            *matrix = Some(Matrix {
+                max_pairs,
+                counts: MessageMatrix::default(),
+            })
+        }
+    }
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Stops counting, the counts can still be read.
+pub fn stop() {
+    ACTIVE.store(false, Ordering::Relaxed);
+}
+
+/// Removes all counts.
+pub fn reset() {
+    if let Some(matrix) = MATRIX.lock().expect("never poisoned").as_mut() {
+        matrix.counts = MessageMatrix::default();
+    }
+}
+
+/// Returns the traffic counted so far.
+pub fn snapshot() -> MessageMatrix {
+    MATRIX
+        .lock()
+        .expect("never poisoned")
+        .as_ref()
+        .map(|matrix| matrix.counts.clone())
+        .unwrap_or_default()
+}
+
+/// Counts a message sent by the process `sender` to `receiver`.
+///
+/// Called by the host functions sending messages.
+pub fn record(sender: Uuid, receiver: Uuid, message: &Message) {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+    let bytes = match message {
+        Message::Data(data) => data.size(),
+        Message::LinkDied(_) | Message::Shutdown => 0,
+    };
+    let mut matrix = MATRIX.lock().expect("never poisoned");
+    let matrix = match matrix.as_mut() {
+        Some(matrix) => matrix,
+        None => return,
+    };
+    let counts = &mut matrix.counts;
+    if let Some(stats) = counts.pairs.get_mut(&(sender, receiver)) {
+        stats.add(bytes);
+    } else if counts.pairs.len() < matrix.max_pairs {
+        counts
+            .pairs
+            .entry((sender, receiver))
+            .or_default()
+            .add(bytes);
+    } else {
+        counts.untracked.add(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::PairStats;
+    use crate::message::{DataMessage, Message};
+
+    #[test]
+    fn pairs_beyond_the_limit_are_untracked() {
+        let message = |size| {
+            let mut data = DataMessage::new(None, size);
+            data.buffer.resize(size, 0);
+            Message::Data(data)
+        };
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        super::start(2);
+        super::reset();
+        super::record(a, b, &message(10));
+        super::record(a, b, &message(5));
+        super::record(b, a, &message(100));
+        super::record(a, c, &message(1));
+        super::stop();
+        super::record(a, b, &message(10));
+
+        let matrix = super::snapshot();
+        assert_eq!(
+            matrix.get(a, b),
+            Some(PairStats {
+                messages: 2,
+                bytes: 15
+            })
+        );
+        assert_eq!(matrix.get(a, c), None);
+        assert_eq!(
+            matrix.untracked,
+            PairStats {
+                messages: 1,
+                bytes: 1
+            }
+        );
+        assert_eq!(matrix.busiest(1)[0].0, (b, a));
+    }
+}
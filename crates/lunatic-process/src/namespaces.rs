@@ -0,0 +1,111 @@
+/*!
+Alternate import module names for host functions.
+
+Host functions are registered under fixed module names:
+
+| Module                   | Host functions                  |
+|--------------------------|---------------------------------|
+| `lunatic::error`         | Error resources                 |
+| `lunatic::process`       | Modules, configs and processes  |
+| `lunatic::message`       | Messaging                       |
+| `lunatic::networking`    | TCP, UDP and DNS                |
+| `lunatic::timer`         | Timers                          |
+| `lunatic::registry`      | Named processes                 |
+| `lunatic::version`       | Version of the runtime          |
+| `lunatic::wasi`          | WASI configuration              |
+| `wasi_snapshot_preview1` | WASI                            |
+
+Guest toolchains that use a different naming convention can still link against them, if the
+runtime is given [`ImportNamespaces`] with alternate names. The host functions stay available
+under the default names too.
+
+```
+use lunatic_process::namespaces::ImportNamespaces;
+
+let mut namespaces = ImportNamespaces::default();
+// `lunatic::process` -> `lunatic_process`, ...
+namespaces.prefix("lunatic::", "lunatic_");
+// Only `lunatic::message` -> `lunatic:message`
+namespaces.alias("lunatic::message", "lunatic:message");
+```
+*/
+
+use anyhow::Result;
+use wasmtime::Linker;
+
+/// Import module names used by the default host functions.
+pub const DEFAULT_MODULES: [&str; 9] = [
+    "lunatic::error",
+    "lunatic::process",
+    "lunatic::message",
+    "lunatic::networking",
+    "lunatic::timer",
+    "lunatic::registry",
+    "lunatic::version",
+    "lunatic::wasi",
+    "wasi_snapshot_preview1",
+];
+
+/// Additional import module names under which host functions are exposed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportNamespaces {
+    aliases: Vec<(String, String)>,
+}
+
+impl ImportNamespaces {
+    /// Exposes all host functions of `module` also under `as_module`.
+    pub fn alias(&mut self, module: &str, as_module: &str) -> &mut Self {
+        self.aliases
+            .push((module.to_string(), as_module.to_string()));
+        self
+    }
+
+    /// Exposes all [`DEFAULT_MODULES`] starting with `prefix` also under names starting with
+    /// `as_prefix` instead.
+    pub fn prefix(&mut self, prefix: &str, as_prefix: &str) -> &mut Self {
+        for module in DEFAULT_MODULES {
+            if let Some(rest) = module.strip_prefix(prefix) {
+                self.alias(module, &format!("{}{}", as_prefix, rest));
+            }
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// Returns pairs of module names and their alternate names.
+    pub fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases
+            .iter()
+            .map(|(module, as_module)| (module.as_str(), as_module.as_str()))
+    }
+
+    /// Adds the alternate names to `linker`. All host functions need to be registered already.
+    ///
+    /// Fails if an alternate name collides with an already registered host function.
+    pub fn apply<T>(&self, linker: &mut Linker<T>) -> Result<()> {
+        for (module, as_module) in self.aliases() {
+            linker.alias_module(module, as_module)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImportNamespaces;
+
+    #[test]
+    fn prefix_aliases_matching_default_modules() {
+        let mut namespaces = ImportNamespaces::default();
+        namespaces.prefix("lunatic::", "lunatic_");
+        let aliases: Vec<_> = namespaces.aliases().collect();
+        assert_eq!(aliases.len(), 8);
+        assert!(aliases.contains(&("lunatic::networking", "lunatic_networking")));
+        assert!(!aliases
+            .iter()
+            .any(|(module, _)| *module == "wasi_snapshot_preview1"));
+    }
+}
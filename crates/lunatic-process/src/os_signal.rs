@@ -0,0 +1,207 @@
+/*!
+Forwarding of OS signals to processes.
+
+Orchestrators stop a container by sending `SIGTERM` to it. Instead of terminating the runtime
+right away, a [`SignalForwarder`] turns the selected OS signals into a [`Message::Shutdown`] for
+every registered process, e.g. a top-level supervisor. The process can then stop its children
+and finish, the runtime exits once the main process is done.
+
+If none of the registered processes is alive when a signal arrives, the default action of the
+signal is performed and the runtime terminates as if nothing was forwarded.
+
+Forwarding is only supported on Unix.
+*/
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use log::debug;
+use uuid::Uuid;
+
+use crate::{message::Message, Process, Signal};
+
+/// OS signals that can be forwarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OsSignal {
+    /// `SIGTERM`
+    Terminate,
+    /// `SIGINT`, sent on Ctrl-C.
+    Interrupt,
+    /// `SIGHUP`
+    Hangup,
+    /// `SIGQUIT`
+    Quit,
+}
+
+impl OsSignal {
+    /// Parses the name of a signal, with or without the `SIG` prefix and in any case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        match name.strip_prefix("SIG").unwrap_or(&name) {
+            "TERM" => Some(Self::Terminate),
+            "INT" => Some(Self::Interrupt),
+            "HUP" => Some(Self::Hangup),
+            "QUIT" => Some(Self::Quit),
+            _ => None,
+        }
+    }
+
+    #[cfg(unix)]
+    fn raw(self) -> i32 {
+        use signal_hook::consts;
+
+        match self {
+            Self::Terminate => consts::SIGTERM,
+            Self::Interrupt => consts::SIGINT,
+            Self::Hangup => consts::SIGHUP,
+            Self::Quit => consts::SIGQUIT,
+        }
+    }
+}
+
+/// Delivers OS signals as [`Message::Shutdown`] to registered processes.
+///
+/// Clones share the same registrations. The signals are handled on a background thread, once all
+/// clones are dropped they are not forwarded anymore.
+#[derive(Clone)]
+pub struct SignalForwarder {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    signals: Vec<OsSignal>,
+    targets: Mutex<HashMap<Uuid, Arc<dyn Process>>>,
+    #[cfg(unix)]
+    handle: signal_hook::iterator::Handle,
+}
+
+impl SignalForwarder {
+    /// Starts handling `signals`.
+    pub fn new(signals: &[OsSignal]) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            let mut os_signals =
+                signal_hook::iterator::Signals::new(signals.iter().map(|signal| signal.raw()))?;
+            let inner = Arc::new(Inner {
+                signals: signals.to_vec(),
+                targets: Mutex::new(HashMap::new()),
+                handle: os_signals.handle(),
+            });
+            let weak = Arc::downgrade(&inner);
+            std::thread::Builder::new()
+                .name("lunatic-signals".to_owned())
+                .spawn(move || {
+                    for raw in os_signals.forever() {
+                        let inner = match weak.upgrade() {
+                            Some(inner) => inner,
+                            None => break,
+                        };
+                        let signal = inner.signals.iter().find(|signal| signal.raw() == raw);
+                        if let Some(signal) = signal {
+                            if inner.forward(*signal) == 0 {
+                                let _ = signal_hook::low_level::emulate_default_handler(raw);
+                            }
+                        }
+                    }
+                })?;
+            Ok(Self { inner })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = signals;
+            Err(anyhow::anyhow!(
+                "Forwarding OS signals is only supported on Unix"
+            ))
+        }
+    }
+
+    /// Returns the forwarded signals.
+    pub fn signals(&self) -> &[OsSignal] {
+        &self.inner.signals
+    }
+
+    /// Delivers the forwarded signals to `process` from now on.
+    pub fn register(&self, process: Arc<dyn Process>) {
+        let mut targets = self.inner.targets.lock().unwrap();
+        targets.insert(process.id(), process);
+    }
+
+    /// Stops delivering signals to the process. Returns `false` if it wasn't registered.
+    pub fn unregister(&self, id: Uuid) -> bool {
+        let mut targets = self.inner.targets.lock().unwrap();
+        targets.remove(&id).is_some()
+    }
+
+    /// Delivers `signal` to all registered processes, as if the OS sent it. Returns the number of
+    /// processes it was delivered to.
+    pub fn forward(&self, signal: OsSignal) -> usize {
+        self.inner.forward(signal)
+    }
+}
+
+impl Inner {
+    fn forward(&self, signal: OsSignal) -> usize {
+        let mut targets = self.targets.lock().unwrap();
+        // Registrations of finished processes are not needed anymore.
+        targets.retain(|_, process| process.is_alive());
+        for process in targets.values() {
+            debug!(
+                "Forwarding {:?} to process {} as shutdown",
+                signal,
+                process.id()
+            );
+            process.send(Signal::Message(Message::Shutdown));
+        }
+        targets.len()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.handle.close();
+    }
+}
+
+impl Debug for SignalForwarder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SignalForwarder")
+            .field("signals", &self.inner.signals)
+            .finish()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{OsSignal, SignalForwarder};
+    use crate::message::Message;
+
+    #[async_std::test]
+    async fn os_signal_is_delivered_as_shutdown() {
+        assert_eq!(OsSignal::from_name("sigterm"), Some(OsSignal::Terminate));
+        assert_eq!(OsSignal::from_name("HUP"), Some(OsSignal::Hangup));
+        assert_eq!(OsSignal::from_name("KILL"), None);
+
+        let (join, process) = crate::spawn(|_this, mailbox| async move {
+            match mailbox.pop(None).await {
+                Message::Shutdown => Ok(()),
+                _ => panic!("Unexpected message"),
+            }
+        });
+        let forwarder = SignalForwarder::new(&[OsSignal::Hangup]).unwrap();
+        forwarder.register(Arc::new(process.clone()));
+        signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+        async_std::future::timeout(Duration::from_secs(5), join)
+            .await
+            .expect("the signal must be forwarded")
+            .unwrap();
+        // The finished process isn't a target anymore.
+        assert_eq!(forwarder.forward(OsSignal::Hangup), 0);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use uuid::Uuid;
+
+use crate::{executor, DeathReason};
+
+type PostMortemHook = Arc<dyn Fn(&ProcessDeath) + Send + Sync>;
+
+/// Information about a process that finished, passed to post-mortem hooks.
+#[derive(Clone, Debug)]
+pub struct ProcessDeath {
+    pub id: Uuid,
+    /// Name of the process at the time it was spawned.
+    pub name: Option<String>,
+    pub reason: DeathReason,
+    /// Set if the process was killed by a signal or by a failing link.
+    pub killed: bool,
+    /// Time since the process started running.
+    pub uptime: Duration,
+    /// Fuel consumed by the process. Not available if it was killed.
+    pub fuel_consumed: Option<u64>,
+    /// Final size of the process memory in bytes. Not available if it was killed.
+    pub memory_size: Option<usize>,
+}
+
+/// Hooks called after every process death, no matter the reason.
+///
+/// The hooks run one after another, in the order they were added. They are dispatched on the
+/// blocking thread pool, so slow hooks don't hold up the exit of the process.
+#[derive(Clone, Default)]
+pub struct PostMortemHooks {
+    hooks: Arc<Vec<PostMortemHook>>,
+}
+
+impl PostMortemHooks {
+    pub fn add<F>(&mut self, hook: F)
+    where
+        F: Fn(&ProcessDeath) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.hooks).push(Arc::new(hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub(crate) fn dispatch(&self, death: ProcessDeath) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let hooks = self.hooks.clone();
+        executor::spawn_blocking(move || {
+            for hook in hooks.iter() {
+                hook(&death);
+            }
+        });
+    }
+}
+
+impl Debug for PostMortemHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostMortemHooks")
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use async_std::channel::unbounded;
+    use uuid::Uuid;
+
+    use super::PostMortemHooks;
+    use crate::{logging::ProcessLogLevel, mailbox::MessageMailbox, DeathReason, Signal};
+
+    #[async_std::test]
+    async fn hooks_see_normal_and_killed_deaths() {
+        let (sender, receiver) = unbounded();
+        let mut hooks = PostMortemHooks::default();
+        hooks.add(move |death| {
+            sender
+                .try_send((death.id, death.reason, death.killed))
+                .unwrap();
+        });
+
+        let finished = Uuid::new_v4();
+        let (_signals, mailbox) = unbounded();
+        let fut = async { Result::<()>::Ok(()) };
+        let process = crate::new(
+            fut,
+            finished,
+            None,
+            mailbox,
+            MessageMailbox::default(),
+            hooks.clone(),
+            ProcessLogLevel::default(),
+            None,
+            None,
+        );
+        assert!(process.await.is_ok());
+        let (id, reason, killed) = receiver.recv().await.unwrap();
+        assert_eq!(id, finished);
+        assert!(matches!(reason, DeathReason::Normal));
+        assert!(!killed);
+
+        let killed_id = Uuid::new_v4();
+        let (signals, mailbox) = unbounded();
+        let fut = async {
+            async_std::task::sleep(Duration::from_secs(60)).await;
+            Result::<()>::Ok(())
+        };
+        signals.try_send(Signal::Kill(Duration::ZERO)).unwrap();
+        let process = crate::new(
+            fut,
+            killed_id,
+            None,
+            mailbox,
+            MessageMailbox::default(),
+            hooks,
+            ProcessLogLevel::default(),
+            None,
+            None,
+        );
+        assert!(process.await.is_err());
+        let (id, reason, killed) = receiver.recv().await.unwrap();
+        assert_eq!(id, killed_id);
+        assert!(matches!(reason, DeathReason::Failure));
+        assert!(killed);
+    }
+}
@@ -0,0 +1,197 @@
+use std::{sync::Arc, time::Duration};
+
+use async_std::channel::Receiver;
+use log::LevelFilter;
+use uuid::Uuid;
+
+use crate::{
+    message::{DataMessage, Message},
+    JoinHandle, Process, Signal,
+};
+
+/// A cheaply cloneable handle to a process.
+///
+/// Wraps an `Arc<dyn Process>` and exposes the common interactions as methods, so that callers
+/// don't need to construct [`Signal`]s by hand. Use [`ProcessRef::send`] for signals without a
+/// dedicated method.
+#[derive(Clone, Debug)]
+pub struct ProcessRef(Arc<dyn Process>);
+
+impl ProcessRef {
+    pub fn new(process: Arc<dyn Process>) -> Self {
+        Self(process)
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.0.id()
+    }
+
+    /// Returns `false` if the process finished and can't receive signals anymore.
+    pub fn is_alive(&self) -> bool {
+        self.0.is_alive()
+    }
+
+    /// Delivers `message` to the mailbox of the process.
+    pub fn send_message(&self, message: Message) {
+        self.0.send(Signal::Message(message));
+    }
+
+    /// Delivers `message` to the mailbox of the process, if it's still alive.
+    ///
+    /// Returns the message back, boxed, if the process finished. The process can still die
+    /// after accepting the message, before receiving it.
+    pub fn try_send_message(&self, message: Message) -> Result<(), Box<Message>> {
+        match self.0.try_send(Signal::Message(message)) {
+            Ok(()) => Ok(()),
+            Err(signal) => match *signal {
+                Signal::Message(message) => Err(Box::new(message)),
+                _ => unreachable!("the signal is returned unchanged"),
+            },
+        }
+    }
+
+    /// Delivers a data message with a copy of `data` to the mailbox of the process.
+    pub fn send_data(&self, tag: Option<i64>, data: &[u8]) {
+        let mut message = DataMessage::new(tag, data.len());
+        message.buffer.extend_from_slice(data);
+        self.send_message(Message::Data(message));
+    }
+
+    /// Delivers all messages arriving on `receiver` to the process, e.g. to feed it events from
+    /// host IO.
+    ///
+    /// Messages take the same path as the ones sent by other processes, the receiver can't tell
+    /// them apart. A bounded channel only applies backpressure to messages that were not
+    /// forwarded yet, forwarded messages are subject to the mailbox limit of the process (see
+    /// [`MailboxLimit`](crate::config::MailboxLimit)) like all others.
+    ///
+    /// Forwarding stops once the channel is closed and empty, or the process died. Returns the
+    /// number of delivered messages.
+    pub fn forward(&self, receiver: Receiver<Message>) -> JoinHandle<usize> {
+        let process = self.clone();
+        crate::spawn_task(async move {
+            let mut delivered = 0;
+            while let Ok(message) = receiver.recv().await {
+                if !process.is_alive() {
+                    break;
+                }
+                process.send_message(message);
+                delivered += 1;
+            }
+            delivered
+        })
+    }
+
+    /// Stops the process immediately.
+    pub fn kill(&self) {
+        self.kill_with_grace(Duration::ZERO);
+    }
+
+    /// Asks the process to shut down and stops it if it's still running after `grace`.
+    pub fn kill_with_grace(&self, grace: Duration) {
+        self.0.send(Signal::Kill(grace));
+    }
+
+    /// Links the process to `other`, in both directions.
+    ///
+    /// If one of them dies, the other receives a `LinkDied` signal carrying `tag`. This is not an
+    /// atomic operation, `other` could die before processing the link and not notify this process.
+    pub fn link(&self, tag: Option<i64>, other: &ProcessRef) {
+        other.0.send(Signal::Link(tag, self.0.clone()));
+        self.0.send(Signal::Link(tag, other.0.clone()));
+    }
+
+    /// Removes the link between the process and `other`, in both directions.
+    pub fn unlink(&self, other: &ProcessRef) {
+        other.0.send(Signal::UnLink(self.0.clone()));
+        self.0.send(Signal::UnLink(other.0.clone()));
+    }
+
+    /// Sets the log level of the process, `None` switches back to the default level.
+    pub fn set_log_level(&self, level: Option<LevelFilter>) {
+        self.0.send(Signal::SetLogLevel(level));
+    }
+
+    /// Changes whether the process dies together with its failing links, or if it only receives a
+    /// `LinkDied` message.
+    pub fn die_when_link_dies(&self, value: bool) {
+        self.0.send(Signal::DieWhenLinkDies(value));
+    }
+
+    /// Sends a raw signal to the process.
+    pub fn send(&self, signal: Signal) {
+        self.0.send(signal);
+    }
+
+    pub fn inner(&self) -> &Arc<dyn Process> {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Arc<dyn Process> {
+        self.0
+    }
+}
+
+impl From<Arc<dyn Process>> for ProcessRef {
+    fn from(process: Arc<dyn Process>) -> Self {
+        Self(process)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ProcessRef;
+    use crate::message::{DataMessage, Message};
+
+    #[async_std::test]
+    async fn data_is_delivered_and_kill_stops_process() {
+        let (sender, receiver) = async_std::channel::unbounded();
+        let (join, process) = crate::spawn(|_this, mailbox| async move {
+            if let Message::Data(data) = mailbox.pop(None).await {
+                sender.send(data.buffer).await.unwrap();
+            }
+            async_std::task::sleep(std::time::Duration::from_secs(60)).await;
+            Ok(())
+        });
+        let process = ProcessRef::new(Arc::new(process));
+        process.send_data(Some(1), b"hello");
+        assert_eq!(receiver.recv().await.unwrap(), b"hello");
+        assert!(process.is_alive());
+        process.kill();
+        assert!(join.await.is_err());
+        assert!(!process.is_alive());
+        // The closed mailbox hands the message back.
+        let message = Message::Data(DataMessage::new(Some(2), 0));
+        let returned = process.try_send_message(message).unwrap_err();
+        assert_eq!(returned.tag(), Some(2));
+    }
+
+    #[async_std::test]
+    async fn forwarded_messages_reach_mailbox() {
+        let (join, process) = crate::spawn(|_this, mailbox| async move {
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                if let Message::Data(data) = mailbox.pop(None).await {
+                    received.push(data.buffer);
+                }
+            }
+            Ok(received)
+        });
+        let process = ProcessRef::new(Arc::new(process));
+        let (sender, receiver) = async_std::channel::bounded(1);
+        let forward = process.forward(receiver);
+        for data in [b"first", b"other"] {
+            let mut message = DataMessage::new(None, data.len());
+            message.buffer.extend_from_slice(data);
+            sender.send(Message::Data(message)).await.unwrap();
+        }
+        drop(sender);
+        assert_eq!(forward.await, 2);
+        assert_eq!(
+            join.await.unwrap(),
+            vec![b"first".to_vec(), b"other".to_vec()]
+        );
+    }
+}
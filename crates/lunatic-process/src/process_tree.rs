@@ -0,0 +1,129 @@
+/*!
+The parent of each running process, to count the children a process has alive.
+
+Every runtime tracks its processes in a [`ProcessTree`]. A process spawned by another process
+through the `lunatic::process` host functions is its child, detached processes included. A
+process is removed once it finished, its children stay alive but are not counted as descendants
+of their grandparent anymore.
+
+Counts are read under the same lock the tree is updated with, so they are a consistent snapshot.
+Counting the direct children is a lookup, counting all descendants walks the subtree.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use uuid::Uuid;
+
+/// Tracks the parent of each running process.
+///
+/// Clones share the same tree.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessTree {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    parents: HashMap<Uuid, Uuid>,
+    children: HashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl ProcessTree {
+    /// Records that `parent` spawned `child`.
+    ///
+    /// Called before the child starts running, so it can't finish before it's added.
+    pub fn spawned(&self, parent: Uuid, child: Uuid) {
+        let mut inner = self.inner.lock().expect("never poisoned");
+        inner.parents.insert(child, parent);
+        inner.children.entry(parent).or_default().insert(child);
+    }
+
+    /// Removes the process `id` once it finished or failed to start.
+    pub fn finished(&self, id: Uuid) {
+        let mut inner = self.inner.lock().expect("never poisoned");
+        if let Some(parent) = inner.parents.remove(&id) {
+            if let Some(siblings) = inner.children.get_mut(&parent) {
+                siblings.remove(&id);
+                if siblings.is_empty() {
+                    inner.children.remove(&parent);
+                }
+            }
+        }
+        // The orphans keep running without a parent.
+        if let Some(children) = inner.children.remove(&id) {
+            for child in children {
+                inner.parents.remove(&child);
+            }
+        }
+    }
+
+    pub fn parent(&self, id: Uuid) -> Option<Uuid> {
+        self.inner
+            .lock()
+            .expect("never poisoned")
+            .parents
+            .get(&id)
+            .copied()
+    }
+
+    /// Returns the number of alive children of `id`, with `transitive` also their children and so
+    /// on.
+    pub fn child_count(&self, id: Uuid, transitive: bool) -> u64 {
+        let inner = self.inner.lock().expect("never poisoned");
+        let children = match inner.children.get(&id) {
+            Some(children) => children,
+            None => return 0,
+        };
+        if !transitive {
+            return children.len() as u64;
+        }
+        let mut count = 0;
+        let mut pending: Vec<&Uuid> = children.iter().collect();
+        while let Some(child) = pending.pop() {
+            count += 1;
+            if let Some(grandchildren) = inner.children.get(child) {
+                pending.extend(grandchildren);
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::ProcessTree;
+
+    #[test]
+    fn counts_alive_descendants() {
+        let tree = ProcessTree::default();
+        let (root, a, b, a1, a2) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        tree.spawned(root, a);
+        tree.spawned(root, b);
+        tree.spawned(a, a1);
+        tree.spawned(a, a2);
+        assert_eq!(tree.child_count(root, false), 2);
+        assert_eq!(tree.child_count(root, true), 4);
+        assert_eq!(tree.child_count(a1, true), 0);
+        assert_eq!(tree.parent(a1), Some(a));
+
+        tree.finished(a1);
+        assert_eq!(tree.child_count(a, false), 1);
+        assert_eq!(tree.child_count(root, true), 3);
+
+        // Orphans don't count towards their grandparent.
+        tree.finished(a);
+        assert_eq!(tree.child_count(root, true), 1);
+        assert_eq!(tree.parent(a2), None);
+    }
+}
@@ -0,0 +1,123 @@
+/*!
+Per-process source of random values.
+
+Each process draws random values provided by host functions from its own [`ProcessRng`]. By
+default it's backed by the entropy source of the operating system. A process configuration can
+instead set a [`RandomSource::Seeded`] seed. Processes spawned with the same seed get the same
+sequence of random values, which allows replaying a run or writing deterministic tests. If the
+process is seeded, WASI's `random_get` is seeded from the same value too.
+
+**Seeded randomness is not secure.** Anyone who knows or guesses the seed can predict all values.
+It's intended for testing and reproducibility only, never use it for keys, tokens or anything
+else that needs to be unpredictable. The system source is the default for this reason.
+*/
+
+use rand::{rngs::OsRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+// Stream of the seeded generator used for WASI, so it doesn't repeat the host function values.
+const WASI_STREAM: u64 = 1;
+
+/// Where a process gets its random values from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RandomSource {
+    /// Entropy of the operating system, suitable for cryptography.
+    #[default]
+    System,
+    /// A pseudo-random generator with a fixed seed. **Not secure**, see the [module docs](self).
+    Seeded(u64),
+}
+
+/// Random number generator of a process.
+pub struct ProcessRng {
+    inner: Inner,
+}
+
+enum Inner {
+    System(OsRng),
+    Seeded(Box<ChaCha12Rng>),
+}
+
+impl ProcessRng {
+    pub fn new(source: RandomSource) -> Self {
+        let inner = match source {
+            RandomSource::System => Inner::System(OsRng),
+            RandomSource::Seeded(seed) => Inner::Seeded(Box::new(ChaCha12Rng::seed_from_u64(seed))),
+        };
+        Self { inner }
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        matches!(self.inner, Inner::Seeded(_))
+    }
+}
+
+impl Default for ProcessRng {
+    fn default() -> Self {
+        Self::new(RandomSource::System)
+    }
+}
+
+impl RngCore for ProcessRng {
+    fn next_u32(&mut self) -> u32 {
+        match &mut self.inner {
+            Inner::System(rng) => rng.next_u32(),
+            Inner::Seeded(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match &mut self.inner {
+            Inner::System(rng) => rng.next_u64(),
+            Inner::Seeded(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match &mut self.inner {
+            Inner::System(rng) => rng.fill_bytes(dest),
+            Inner::Seeded(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match &mut self.inner {
+            Inner::System(rng) => rng.try_fill_bytes(dest),
+            Inner::Seeded(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+/// Returns the generator WASI's `random_get` should use for `source`, `None` to keep the default.
+pub fn wasi_rng(source: RandomSource) -> Option<Box<dyn RngCore + Send + Sync>> {
+    match source {
+        RandomSource::System => None,
+        RandomSource::Seeded(seed) => {
+            let mut rng = ChaCha12Rng::seed_from_u64(seed);
+            rng.set_stream(WASI_STREAM);
+            Some(Box::new(rng))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
+
+    use super::{ProcessRng, RandomSource};
+
+    #[test]
+    fn same_seed_same_values() {
+        let mut first = ProcessRng::new(RandomSource::Seeded(1337));
+        let mut second = ProcessRng::new(RandomSource::Seeded(1337));
+        let mut other = ProcessRng::new(RandomSource::Seeded(42));
+        let values: Vec<u64> = (0..8).map(|_| first.next_u64()).collect();
+        assert_eq!(
+            values,
+            (0..8).map(|_| second.next_u64()).collect::<Vec<_>>()
+        );
+        assert_ne!(values, (0..8).map(|_| other.next_u64()).collect::<Vec<_>>());
+        assert!(!ProcessRng::default().is_seeded());
+    }
+}
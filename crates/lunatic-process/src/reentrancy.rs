@@ -0,0 +1,108 @@
+/*!
+Protection against host functions calling back into their own process.
+
+Host functions get exclusive access to the store of the calling process. If one of them called
+back into the guest, e.g. to run a callback, the guest could call another host function while the
+first one is still in progress. Host functions are not written with this in mind: they hold
+borrows of the process state or locks (e.g. of the mailbox) across the call and would deadlock or
+panic. Every process store installs [`reentrancy_hook`] to turn such calls into a trap instead.
+
+The hook counts the running host functions in [`HostCalls`], which also tells the runtime why a
+paused call is pending, see [`HostCalls::running`].
+*/
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use wasmtime::{CallHook, Trap};
+
+/// Number of host functions currently running on a store, counted by [`reentrancy_hook`].
+///
+/// Clones share the same count.
+#[derive(Clone, Debug, Default)]
+pub struct HostCalls(Arc<AtomicUsize>);
+
+impl HostCalls {
+    /// Returns `true` if a host function of the store is running.
+    ///
+    /// A call into the process that is pending while no host function runs paused because it
+    /// used up its fuel, wasm code doesn't pause otherwise.
+    pub fn running(&self) -> bool {
+        self.0.load(Ordering::Relaxed) > 0
+    }
+}
+
+/// Returns a store call hook that traps if WebAssembly code is entered while a host function of
+/// the same store is running. The running host functions are counted in `host_calls`.
+pub fn reentrancy_hook<T>(
+    host_calls: HostCalls,
+) -> impl FnMut(&mut T, CallHook) -> Result<(), Trap> + Send + Sync {
+    move |_, hook| {
+        match hook {
+            CallHook::CallingHost => {
+                host_calls.0.fetch_add(1, Ordering::Relaxed);
+            }
+            CallHook::ReturningFromHost => {
+                let _ = host_calls
+                    .0
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                        Some(count.saturating_sub(1))
+                    });
+            }
+            CallHook::CallingWasm if host_calls.running() => {
+                return Err(Trap::new(
+                    "Reentrant call: a host function tried to call back into its own process",
+                ))
+            }
+            CallHook::CallingWasm | CallHook::ReturningFromWasm => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::{Caller, Engine, Linker, Module, Store, Trap};
+
+    use super::{reentrancy_hook, HostCalls};
+
+    #[test]
+    fn calling_back_into_guest_traps() {
+        let engine = Engine::default();
+        let module = Module::new(
+            &engine,
+            r#"
+            (module
+                (import "host" "reenter" (func $reenter))
+                (func (export "run") call $reenter)
+                (func (export "noop")))
+            "#,
+        )
+        .unwrap();
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap("host", "reenter", |mut caller: Caller<()>| {
+                let run = caller.get_export("run").unwrap().into_func().unwrap();
+                run.call(&mut caller, &[], &mut [])
+                    .map_err(|error| Trap::new(error.to_string()))
+            })
+            .unwrap();
+        let mut store = Store::new(&engine, ());
+        let host_calls = HostCalls::default();
+        store.call_hook(reentrancy_hook(host_calls.clone()));
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        let run = instance
+            .get_typed_func::<(), (), _>(&mut store, "run")
+            .unwrap();
+        let error = run.call(&mut store, ()).unwrap_err();
+        assert!(error.to_string().contains("Reentrant call"));
+        // The count of host calls is balanced again, so the store is still usable afterwards.
+        assert!(!host_calls.running());
+        let noop = instance
+            .get_typed_func::<(), (), _>(&mut store, "noop")
+            .unwrap();
+        assert!(noop.call(&mut store, ()).is_ok());
+    }
+}
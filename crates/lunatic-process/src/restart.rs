@@ -0,0 +1,127 @@
+/*!
+Crash-loop protection for supervisors.
+
+A [`RestartTracker`] remembers when a child was restarted and decides if it should be restarted
+once more. Like the restart intensity of Erlang supervisors, at most `max_restarts` restarts are
+allowed within a sliding window of `within`. Restarts older than the window are forgotten, so a
+child that crashes now and then is restarted forever, while one that keeps crashing right away
+is given up on.
+
+```
+use std::time::Duration;
+
+use lunatic_process::restart::RestartTracker;
+
+let mut tracker = RestartTracker::new(3, Duration::from_secs(5));
+// The child died, check if it can be restarted.
+if tracker.should_restart() {
+    // Spawn it again.
+} else {
+    // Too many restarts in a short time, give up.
+}
+```
+
+The tracker doesn't depend on processes, it can be used for any retry logic.
+*/
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Tracks restarts inside of a sliding time window.
+#[derive(Clone, Debug)]
+pub struct RestartTracker {
+    max_restarts: usize,
+    within: Duration,
+    // Instants of the allowed restarts inside of the window, oldest first.
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartTracker {
+    pub fn new(max_restarts: usize, within: Duration) -> Self {
+        Self {
+            max_restarts,
+            within,
+            restarts: VecDeque::with_capacity(max_restarts),
+        }
+    }
+
+    pub fn max_restarts(&self) -> usize {
+        self.max_restarts
+    }
+
+    pub fn within(&self) -> Duration {
+        self.within
+    }
+
+    /// Records a restart now, if it's allowed by the policy.
+    ///
+    /// Returns `false` if `max_restarts` restarts already happened inside of the window. The
+    /// refused restart isn't recorded.
+    pub fn should_restart(&mut self) -> bool {
+        self.should_restart_at(Instant::now())
+    }
+
+    /// Same as [`should_restart`](Self::should_restart), but at the given instant.
+    ///
+    /// Instants need to be passed in increasing order.
+    pub fn should_restart_at(&mut self, now: Instant) -> bool {
+        self.expire(now);
+        if self.restarts.len() >= self.max_restarts {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+
+    /// Returns the number of restarts inside of the window ending at `now`.
+    pub fn restarts_at(&mut self, now: Instant) -> usize {
+        self.expire(now);
+        self.restarts.len()
+    }
+
+    /// Forgets all recorded restarts.
+    pub fn reset(&mut self) {
+        self.restarts.clear();
+    }
+
+    // Removes restarts that are not inside of the window ending at `now` anymore.
+    fn expire(&mut self, now: Instant) {
+        while let Some(oldest) = self.restarts.front() {
+            if now.saturating_duration_since(*oldest) < self.within {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RestartTracker;
+
+    #[test]
+    fn window_expires_old_restarts() {
+        let start = Instant::now();
+        let second = |n| start + Duration::from_secs(n);
+        let mut tracker = RestartTracker::new(3, Duration::from_secs(10));
+        // A burst uses up the whole budget.
+        assert!(tracker.should_restart_at(second(0)));
+        assert!(tracker.should_restart_at(second(0)));
+        assert!(tracker.should_restart_at(second(1)));
+        assert!(!tracker.should_restart_at(second(2)));
+        assert!(!tracker.should_restart_at(second(9)));
+        assert_eq!(tracker.restarts_at(second(9)), 3);
+        // The two restarts at 0s leave the window.
+        assert!(tracker.should_restart_at(second(10)));
+        assert!(tracker.should_restart_at(second(10)));
+        assert!(!tracker.should_restart_at(second(10)));
+        assert_eq!(tracker.restarts_at(second(30)), 0);
+
+        let mut never = RestartTracker::new(0, Duration::from_secs(10));
+        assert!(!never.should_restart_at(start));
+    }
+}
@@ -0,0 +1,130 @@
+//! On-disk cache of compiled modules.
+//!
+//! Compiled machine code is only valid for the exact engine configuration and wasmtime version it
+//! was produced with. The cache is namespaced by a fingerprint of the engine, so that artifacts of
+//! a different configuration (e.g. another optimization level or disabled SIMD) or of an older
+//! wasmtime version are never picked up:
+//!
+//! ```text
+//! <cache dir>/<engine fingerprint>/<sha256 of the wasm module>.cwasm
+//! ```
+//!
+//! On top of that, wasmtime checks the compatibility of every artifact while deserializing it.
+//! Artifacts failing this check are removed and the module is compiled again.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use log::warn;
+use sha2::{Digest, Sha256};
+use wasmtime::{Engine, Module};
+
+// The smallest valid module, compiled to fingerprint an engine.
+const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+/// Directory holding compiled modules.
+///
+/// Clones share the same hit counter.
+#[derive(Clone, Debug)]
+pub struct ModuleCache {
+    dir: PathBuf,
+    hits: Arc<AtomicU64>,
+}
+
+impl ModuleCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            hits: Arc::default(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the number of modules loaded from the cache instead of being compiled.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the directory used for artifacts of `engine`.
+    pub fn engine_dir(&self, engine: &Engine) -> Result<PathBuf> {
+        Ok(self.dir.join(engine_fingerprint(engine)?))
+    }
+}
+
+/// Returns a fingerprint of the engine configuration and the wasmtime version.
+///
+/// It's derived from an empty module compiled by `engine`. The serialized artifact contains the
+/// wasmtime version, the target, compiler flags and enabled wasm features, the same properties
+/// wasmtime checks before loading an artifact.
+pub fn engine_fingerprint(engine: &Engine) -> Result<String> {
+    let artifact = engine.precompile_module(EMPTY_MODULE)?;
+    Ok(format!("{:x}", Sha256::digest(&artifact)))
+}
+
+// Artifacts of one engine inside of the cache.
+#[derive(Clone, Debug)]
+pub(crate) struct EngineCache {
+    cache: ModuleCache,
+    dir: PathBuf,
+}
+
+impl EngineCache {
+    pub(crate) fn new(cache: ModuleCache, engine: &Engine) -> Result<Self> {
+        let dir = cache.engine_dir(engine)?;
+        fs::create_dir_all(&dir)?;
+        Ok(Self { cache, dir })
+    }
+
+    pub(crate) fn cache(&self) -> &ModuleCache {
+        &self.cache
+    }
+
+    pub(crate) fn path(&self, wasm: &[u8]) -> PathBuf {
+        self.dir.join(format!("{:x}.cwasm", Sha256::digest(wasm)))
+    }
+
+    /// Loads the compiled `wasm` module, if it's in the cache and compatible with `engine`.
+    pub(crate) fn load(&self, engine: &Engine, wasm: &[u8]) -> Option<Module> {
+        let path = self.path(wasm);
+        if !path.exists() {
+            return None;
+        }
+        // Safety: The cache directory only contains artifacts serialized by wasmtime and their
+        // compatibility with the engine is checked by `deserialize_file`.
+        match unsafe { Module::deserialize_file(engine, &path) } {
+            Ok(module) => {
+                self.cache.hits.fetch_add(1, Ordering::Relaxed);
+                Some(module)
+            }
+            Err(error) => {
+                warn!(
+                    "Discarding cached module {}, it can't be loaded: {}",
+                    path.display(),
+                    error
+                );
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    /// Stores the compiled `module` of `wasm`.
+    pub(crate) fn store(&self, wasm: &[u8], module: &Module) -> Result<()> {
+        let path = self.path(wasm);
+        // Write to a temporary file first, so that a concurrent `load` never sees a partial file.
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, module.serialize()?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
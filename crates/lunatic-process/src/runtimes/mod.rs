@@ -6,9 +6,63 @@
 //! NOTE: This traits are not used at all. Until rust supports async-traits all functions working
 //!       with a runtime will directly take `wasmtime::WasmtimeRuntime` instead of a generic.
 
+pub mod cache;
 pub mod wasmtime;
 
-pub type RawWasm = Vec<u8>;
+use std::ops::Deref;
+
+pub use memmap2::Mmap;
+
+/// The raw binary representation of a WebAssembly module.
+///
+/// Big modules don't need to be fully read into memory, they can be memory-mapped from a file
+/// instead. This avoids keeping a second copy of the module on the heap.
+pub enum RawWasm {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl RawWasm {
+    /// Returns the contents of the first custom section called `name`, or `None` if the module
+    /// doesn't have one.
+    ///
+    /// Sections are read from the raw bytes, the module doesn't need to be compiled. If the
+    /// module is malformed, only sections before the malformed part are found.
+    pub fn custom_section(&self, name: &str) -> Option<&[u8]> {
+        wasmparser::Parser::new(0)
+            .parse_all(self)
+            .map_while(|payload| payload.ok())
+            .find_map(|payload| match payload {
+                wasmparser::Payload::CustomSection(section) if section.name() == name => {
+                    Some(section.data())
+                }
+                _ => None,
+            })
+    }
+}
+
+impl Deref for RawWasm {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            RawWasm::Owned(bytes) => bytes,
+            RawWasm::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+impl From<Vec<u8>> for RawWasm {
+    fn from(bytes: Vec<u8>) -> Self {
+        RawWasm::Owned(bytes)
+    }
+}
+
+impl From<Mmap> for RawWasm {
+    fn from(mmap: Mmap) -> Self {
+        RawWasm::Mapped(mmap)
+    }
+}
 
 /// A `WasmRuntime` is a compiler that can generate runnable code from raw .wasm files.
 ///
@@ -42,3 +96,24 @@ pub trait WasmInstance {
     // Calls a wasm function by name with the specified arguments. Ignores the returned values.
     /* async fn call(&mut self, function: &str, params: Vec<Self::Param>) -> Result<()>; */
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RawWasm;
+
+    #[test]
+    fn custom_sections_are_found_by_name() {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        for (name, data) in [("build", &b"v1.2"[..]), ("manifest", b"net,fs")] {
+            // Section ID 0, size, name length, name and data.
+            module.extend([0, (1 + name.len() + data.len()) as u8, name.len() as u8]);
+            module.extend(name.as_bytes());
+            module.extend(data);
+        }
+        let module = RawWasm::from(module);
+        assert_eq!(module.custom_section("manifest"), Some(&b"net,fs"[..]));
+        assert_eq!(module.custom_section("build"), Some(&b"v1.2"[..]));
+        assert_eq!(module.custom_section("missing"), None);
+        assert_eq!(RawWasm::from(vec![1, 2, 3]).custom_section("build"), None);
+    }
+}
@@ -1,36 +1,548 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+};
 
-use anyhow::Result;
-use wasmtime::ResourceLimiter;
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use wasmtime::{ExternType, ResourceLimiter, Trap};
 
 use crate::{
+    checkpoint::CheckpointStore,
     config::{ProcessConfig, UNIT_OF_COMPUTE_IN_INSTRUCTIONS},
+    deadlock::DeadlockDetector,
+    fuel_exhausted::{FuelExhausted, FuelExhaustedHooks},
+    fuel_rate::FuelRates,
+    live::{LiveGuard, LiveTasks},
+    mailbox::{MessageQueueFactory, MessageQueues},
+    memory_watch::MemoryWatch,
+    namespaces::ImportNamespaces,
+    os_signal::SignalForwarder,
+    post_mortem::{PostMortemHooks, ProcessDeath},
+    process_tree::ProcessTree,
+    reentrancy::{reentrancy_hook, HostCalls},
     state::ProcessState,
+    step::StepMode,
+    topics::Topics,
+    topology::Topology,
+    trap_location::TrapLocation,
+    upgrade::{Upgrade, STATE_TRANSFER_EXPORT},
     ExecutionResult, ResultValue,
 };
 
-use super::RawWasm;
+use super::{
+    cache::{EngineCache, ModuleCache},
+    Mmap, RawWasm,
+};
+
+// Rough estimate of how many bytes of memory the compiler uses per byte of wasm input.
+const COMPILE_MEMORY_PER_WASM_BYTE: usize = 20;
+
+/// Limits applied to module compilation.
+///
+/// Compiling a big module takes a lot of memory. If many modules are compiled at the same time
+/// the host can run out of it. The limits are shared by all clones of a [`WasmtimeRuntime`].
+#[derive(Clone, Copy, Debug)]
+pub struct CompileLimits {
+    /// Maximum number of modules compiled at the same time. Compiles beyond this limit are
+    /// queued until a running one finishes. Defaults to the number of CPUs.
+    pub max_concurrent: usize,
+    /// Advisory memory ceiling for a single compile in bytes. The memory usage is estimated from
+    /// the size of the module and compiles estimated above the ceiling are refused. No limit by
+    /// default.
+    pub max_memory: Option<usize>,
+    /// Modules bigger than this many bytes are refused before compiling them. Defaults to
+    /// [`DEFAULT_MAX_MODULE_SIZE`].
+    pub max_module_size: usize,
+    /// Modules defining more functions than this are refused before compiling them. No limit by
+    /// default, apart from the one of the wasm validator.
+    pub max_functions: Option<u32>,
+}
+
+/// Default of [`CompileLimits::max_module_size`], 256 MiB.
+pub const DEFAULT_MAX_MODULE_SIZE: usize = 256 * 1024 * 1024;
+
+impl Default for CompileLimits {
+    fn default() -> Self {
+        let max_concurrent = std::thread::available_parallelism()
+            .map(|cpus| cpus.get())
+            .unwrap_or(1);
+        Self {
+            max_concurrent,
+            max_memory: None,
+            max_module_size: DEFAULT_MAX_MODULE_SIZE,
+            max_functions: None,
+        }
+    }
+}
+
+/// Result of [`WasmtimeRuntime::validate_module`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Imports provided by the host, in the order of the module.
+    pub resolved_imports: Vec<ImportName>,
+    pub unresolved_imports: Vec<UnresolvedImport>,
+    /// Names of all exports, in the order of the module.
+    pub exports: Vec<String>,
+}
+
+impl ValidationReport {
+    /// Returns true if all imports resolve, so the module can be spawned.
+    pub fn is_valid(&self) -> bool {
+        self.unresolved_imports.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportName {
+    pub module: String,
+    pub name: String,
+}
+
+/// An import the host can't provide.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnresolvedImport {
+    pub import: ImportName,
+    pub reason: UnresolvedReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnresolvedReason {
+    /// The host doesn't define anything under this name.
+    Missing,
+    /// The host defines it with a different type, e.g. a function with other parameters.
+    TypeMismatch,
+}
 
 #[derive(Clone)]
 pub struct WasmtimeRuntime {
     engine: wasmtime::Engine,
+    compile_limiter: Arc<CompileLimiter>,
+    compat_shims: bool,
+    capability_manifests: bool,
+    import_namespaces: ImportNamespaces,
+    module_cache: Option<EngineCache>,
+    memory_watch: Option<Arc<MemoryWatch>>,
+    deadlock_detector: Option<DeadlockDetector>,
+    signal_forwarder: Option<SignalForwarder>,
+    topology: Option<Topology>,
+    checkpoints: CheckpointStore,
+    live: LiveTasks,
+    process_tree: ProcessTree,
+    topics: Topics,
+    post_mortem_hooks: PostMortemHooks,
+    fuel_exhausted_hooks: FuelExhaustedHooks,
+    step_mode: Option<StepMode>,
+    fuel_rates: Option<FuelRates>,
+    message_queues: Option<MessageQueues>,
+    max_link_cascade_depth: Option<u32>,
 }
 
 impl WasmtimeRuntime {
     pub fn new(config: &wasmtime::Config) -> Result<Self> {
+        Self::with_compile_limits(config, CompileLimits::default())
+    }
+
+    pub fn with_compile_limits(config: &wasmtime::Config, limits: CompileLimits) -> Result<Self> {
+        if limits.max_concurrent == 0 {
+            return Err(anyhow!("At least one concurrent compile must be allowed"));
+        }
         let engine = wasmtime::Engine::new(config)?;
-        Ok(Self { engine })
+        Ok(Self {
+            engine,
+            compile_limiter: Arc::new(CompileLimiter::new(limits)),
+            compat_shims: false,
+            capability_manifests: false,
+            import_namespaces: ImportNamespaces::default(),
+            module_cache: None,
+            memory_watch: None,
+            deadlock_detector: None,
+            signal_forwarder: None,
+            topology: None,
+            checkpoints: CheckpointStore::default(),
+            live: LiveTasks::default(),
+            process_tree: ProcessTree::default(),
+            topics: Topics::default(),
+            post_mortem_hooks: PostMortemHooks::default(),
+            fuel_exhausted_hooks: FuelExhaustedHooks::default(),
+            step_mode: None,
+            fuel_rates: None,
+            message_queues: None,
+            max_link_cascade_depth: None,
+        })
+    }
+
+    pub fn engine(&self) -> &wasmtime::Engine {
+        &self.engine
+    }
+
+    /// Enables compatibility shims for host functions whose signature changed.
+    ///
+    /// Modules compiled afterwards can use the old signatures. This allows running modules built
+    /// against an older version of the host API next to new ones.
+    pub fn set_compat_shims(&mut self, enabled: bool) {
+        self.compat_shims = enabled;
+    }
+
+    pub fn compat_shims(&self) -> bool {
+        self.compat_shims
+    }
+
+    /// Restricts processes spawned afterwards to the capabilities their module declares in a
+    /// manifest. The format of the manifest and the capabilities depend on the
+    /// [`ProcessState`], modules without a manifest are not restricted.
+    pub fn set_capability_manifests(&mut self, enforced: bool) {
+        self.capability_manifests = enforced;
+    }
+
+    pub fn capability_manifests(&self) -> bool {
+        self.capability_manifests
+    }
+
+    /// Exposes host functions also under the alternate import names of `namespaces`, for
+    /// modules compiled afterwards with [`compile_module`](Self::compile_module).
+    pub fn set_import_namespaces(&mut self, namespaces: ImportNamespaces) {
+        self.import_namespaces = namespaces;
+    }
+
+    pub fn import_namespaces(&self) -> &ImportNamespaces {
+        &self.import_namespaces
+    }
+
+    /// Caches modules compiled with [`compile_module_cached`](Self::compile_module_cached) in
+    /// the directory of `cache`, see [`super::cache`].
+    ///
+    /// Fails if the cache directory can't be created.
+    pub fn set_module_cache(&mut self, cache: Option<ModuleCache>) -> Result<()> {
+        self.module_cache = match cache {
+            Some(cache) => Some(EngineCache::new(cache, &self.engine)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    pub fn module_cache(&self) -> Option<&ModuleCache> {
+        self.module_cache.as_ref().map(EngineCache::cache)
+    }
+
+    /// Reports processes spawned afterwards whose memory grows past the thresholds of the
+    /// `watch`. Not all [`ProcessState`]s support it.
+    pub fn set_memory_watch(&mut self, watch: Option<MemoryWatch>) {
+        self.memory_watch = watch.map(Arc::new);
+    }
+
+    pub fn memory_watch(&self) -> Option<&MemoryWatch> {
+        self.memory_watch.as_deref()
+    }
+
+    /// Tracks processes spawned afterwards that block on their mailbox with the `detector`. Not
+    /// all [`ProcessState`]s support it.
+    pub fn set_deadlock_detector(&mut self, detector: Option<DeadlockDetector>) {
+        self.deadlock_detector = detector;
+    }
+
+    pub fn deadlock_detector(&self) -> Option<&DeadlockDetector> {
+        self.deadlock_detector.as_ref()
+    }
+
+    /// Lets processes opt into receiving the OS signals handled by `forwarder`.
+    pub fn set_signal_forwarder(&mut self, forwarder: Option<SignalForwarder>) {
+        self.signal_forwarder = forwarder;
+    }
+
+    pub fn signal_forwarder(&self) -> Option<&SignalForwarder> {
+        self.signal_forwarder.as_ref()
+    }
+
+    /// Records processes spawned afterwards and their links in `topology`.
+    pub fn set_topology(&mut self, topology: Option<Topology>) {
+        self.topology = topology;
+    }
+
+    pub fn topology(&self) -> Option<&Topology> {
+        self.topology.as_ref()
+    }
+
+    /// Stores the checkpoints of processes spawned afterwards in `checkpoints`. By default each
+    /// runtime has its own store.
+    pub fn set_checkpoint_store(&mut self, checkpoints: CheckpointStore) {
+        self.checkpoints = checkpoints;
+    }
+
+    pub fn checkpoints(&self) -> &CheckpointStore {
+        &self.checkpoints
+    }
+
+    /// Counts a background task (e.g. a timer) as running, until the returned guard is dropped.
+    ///
+    /// Processes spawned through the runtime are counted automatically.
+    pub fn track_task(&self) -> LiveGuard {
+        self.live.track()
+    }
+
+    /// Returns the number of processes and background tasks that are still running.
+    pub fn live_count(&self) -> usize {
+        self.live.count()
+    }
+
+    /// Waits until all processes spawned through the runtime exited and all tracked background
+    /// tasks finished.
+    ///
+    /// Sockets and other resources are owned by processes and closed once their process exits.
+    /// Timers can outlive the process that created them and are waited on until they fire or
+    /// get canceled.
+    pub async fn wait_idle(&self) {
+        self.live.wait_idle().await
+    }
+
+    /// Returns the parents of the running processes, see [`crate::process_tree`].
+    pub fn process_tree(&self) -> &ProcessTree {
+        &self.process_tree
+    }
+
+    /// Returns the pub/sub topics of the processes, see [`crate::topics`].
+    pub fn topics(&self) -> &Topics {
+        &self.topics
+    }
+
+    /// Calls `hook` each time a process spawned afterwards dies, no matter the reason. Hooks are
+    /// called in the order they were added.
+    pub fn add_post_mortem_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&ProcessDeath) + Send + Sync + 'static,
+    {
+        self.post_mortem_hooks.add(hook);
+    }
+
+    pub fn post_mortem_hooks(&self) -> &PostMortemHooks {
+        &self.post_mortem_hooks
+    }
+
+    /// Calls `hook` each time a process spawned afterwards runs out of fuel, see
+    /// [`FuelExhaustedHooks`].
+    pub fn add_fuel_exhausted_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&FuelExhausted) + Send + Sync + 'static,
+    {
+        self.fuel_exhausted_hooks.add(hook);
+    }
+
+    pub fn fuel_exhausted_hooks(&self) -> &FuelExhaustedHooks {
+        &self.fuel_exhausted_hooks
+    }
+
+    /// Runs processes instantiated afterwards in step mode, see [`crate::step`].
+    pub fn set_step_mode(&mut self, mode: Option<StepMode>) {
+        self.step_mode = mode;
+    }
+
+    pub fn step_mode(&self) -> Option<&StepMode> {
+        self.step_mode.as_ref()
+    }
+
+    // Returns how often and how much fuel is injected into the store of a process with the fuel
+    // limit `max_fuel`.
+    pub(crate) fn fuel_injections(&self, max_fuel: Option<u64>) -> (u64, u64) {
+        match (max_fuel, &self.step_mode) {
+            (Some(max_fuel), None) => (max_fuel, UNIT_OF_COMPUTE_IN_INSTRUCTIONS),
+            // If no limit is specified use maximum
+            (None, None) => (u64::MAX, UNIT_OF_COMPUTE_IN_INSTRUCTIONS),
+            // In step mode the same fuel is injected in steps.
+            (Some(max_fuel), Some(step)) => {
+                let fuel = max_fuel.saturating_mul(UNIT_OF_COMPUTE_IN_INSTRUCTIONS);
+                ((fuel / step.instructions()).max(1), step.instructions())
+            }
+            (None, Some(step)) => (u64::MAX, step.instructions()),
+        }
+    }
+
+    /// Tracks the fuel consumption rate of processes spawned afterwards in `rates`, see
+    /// [`crate::fuel_rate`].
+    pub fn set_fuel_rates(&mut self, rates: Option<FuelRates>) {
+        self.fuel_rates = rates;
+    }
+
+    pub fn fuel_rates(&self) -> Option<&FuelRates> {
+        self.fuel_rates.as_ref()
+    }
+
+    /// Stores the user messages of processes spawned afterwards in queues created by `factory`
+    /// instead of in memory, see [`MessageQueue`](crate::mailbox::MessageQueue).
+    pub fn set_message_queues(&mut self, factory: Option<MessageQueueFactory>) {
+        self.message_queues = factory.map(MessageQueues::new);
+    }
+
+    pub fn message_queues(&self) -> Option<&MessageQueues> {
+        self.message_queues.as_ref()
+    }
+
+    /// Stops cascades of link failures between processes spawned afterwards at `depth`, see
+    /// [`crate::link_cascade`]. By default cascades are unlimited.
+    pub fn set_max_link_cascade_depth(&mut self, depth: Option<u32>) {
+        self.max_link_cascade_depth = depth;
+    }
+
+    pub fn max_link_cascade_depth(&self) -> Option<u32> {
+        self.max_link_cascade_depth
+    }
+
+    pub fn compile_limits(&self) -> CompileLimits {
+        self.compile_limiter.limits
+    }
+
+    /// Returns how many compiles had to wait in the queue because of the concurrency limit.
+    pub fn throttled_compiles(&self) -> u64 {
+        self.compile_limiter.throttled.load(Ordering::Relaxed)
     }
 
     /// Compiles a wasm module to machine code and performs type-checking on host functions.
+    ///
+    /// Modules above the [size or function limits](CompileLimits) are refused before they reach
+    /// the compiler. If the maximum number of concurrent compiles is reached, this call blocks
+    /// until one of them finishes. Async code should run it on a blocking thread, e.g. with
+    /// [`executor::unblock`](crate::executor::unblock).
     pub fn compile_module<T>(&self, data: RawWasm) -> Result<WasmtimeCompiledModule<T>>
     where
         T: ProcessState,
     {
-        let module = wasmtime::Module::new(&self.engine, data.as_slice())?;
-        let mut linker = wasmtime::Linker::new(&self.engine);
-        // Register host functions to linker.
-        <T as ProcessState>::register(&mut linker)?;
+        self.compile_module_with_namespaces(data, &self.import_namespaces)
+    }
+
+    /// Compiles a wasm module, exposing the host functions also under the alternate import names
+    /// of `namespaces` instead of the ones set on the runtime.
+    pub fn compile_module_with_namespaces<T>(
+        &self,
+        data: RawWasm,
+        namespaces: &ImportNamespaces,
+    ) -> Result<WasmtimeCompiledModule<T>>
+    where
+        T: ProcessState,
+    {
+        self.check_compile_limits(&data)?;
+        let _permit = self.compile_limiter.acquire();
+        let module = wasmtime::Module::new(&self.engine, &*data)?;
+        self.link_module(data, module, namespaces)
+    }
+
+    /// Compiles a wasm module and resolves its imports against the host functions of `T`, without
+    /// keeping the compiled module.
+    ///
+    /// Fails only if the module can't be compiled (e.g. it isn't valid wasm), imports that don't
+    /// resolve are listed in the report. Compile limits apply as for
+    /// [`compile_module`](Self::compile_module).
+    pub fn validate_module<T>(&self, data: &RawWasm) -> Result<ValidationReport>
+    where
+        T: ProcessState,
+    {
+        self.check_compile_limits(data)?;
+        let module = {
+            let _permit = self.compile_limiter.acquire();
+            wasmtime::Module::new(&self.engine, &**data)?
+        };
+        let linker = self.linker::<T>(&module, &self.import_namespaces)?;
+        let mut store = wasmtime::Store::new(&self.engine, T::default());
+        let mut report = ValidationReport::default();
+        for import in module.imports() {
+            let name = ImportName {
+                module: import.module().to_owned(),
+                name: import.name().to_owned(),
+            };
+            let reason = match linker.get_by_import(&mut store, &import) {
+                None => Some(UnresolvedReason::Missing),
+                Some(defined) => match (import.ty(), defined.ty(&store)) {
+                    (ExternType::Func(expected), ExternType::Func(found)) if expected == found => {
+                        None
+                    }
+                    _ => Some(UnresolvedReason::TypeMismatch),
+                },
+            };
+            match reason {
+                None => report.resolved_imports.push(name),
+                Some(reason) => report.unresolved_imports.push(UnresolvedImport {
+                    import: name,
+                    reason,
+                }),
+            }
+        }
+        report.exports = module
+            .exports()
+            .map(|export| export.name().to_owned())
+            .collect();
+        Ok(report)
+    }
+
+    // Refuses modules that are too large, define too many functions or are estimated to use more
+    // memory to compile than the limit allows. Only the section headers are read, the module
+    // isn't validated.
+    fn check_compile_limits(&self, data: &RawWasm) -> Result<()> {
+        let limits = &self.compile_limiter.limits;
+        if data.len() > limits.max_module_size {
+            return Err(anyhow!(
+                "Module too large: {} bytes, above the limit of {} bytes",
+                data.len(),
+                limits.max_module_size
+            ));
+        }
+        if let Some(max_functions) = limits.max_functions {
+            let functions = defined_functions(data);
+            if functions > max_functions {
+                return Err(anyhow!(
+                    "Module defines {} functions, above the limit of {}",
+                    functions,
+                    max_functions
+                ));
+            }
+        }
+        if let Some(max_memory) = limits.max_memory {
+            let estimate = data.len().saturating_mul(COMPILE_MEMORY_PER_WASM_BYTE);
+            if estimate > max_memory {
+                return Err(anyhow!(
+                    "Compiling the module is estimated to use {} bytes of memory, above the limit of {} bytes",
+                    estimate,
+                    max_memory
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles a wasm module, or loads it from the module cache if it was compiled before with
+    /// the same engine configuration.
+    ///
+    /// Without a module cache this is the same as [`compile_module`](Self::compile_module).
+    /// Failing to write the cache is only logged.
+    pub fn compile_module_cached<T>(&self, data: RawWasm) -> Result<WasmtimeCompiledModule<T>>
+    where
+        T: ProcessState,
+    {
+        let cache = match &self.module_cache {
+            Some(cache) => cache,
+            None => return self.compile_module(data),
+        };
+        if let Some(module) = cache.load(&self.engine, &data) {
+            return self.link_module(data, module, &self.import_namespaces);
+        }
+        let compiled = self.compile_module(data)?;
+        if let Err(error) = cache.store(compiled.source(), &compiled.inner.module) {
+            warn!("Failed to cache compiled module: {}", error);
+        }
+        Ok(compiled)
+    }
+
+    // Resolves the host functions imported by the compiled `module`.
+    fn link_module<T>(
+        &self,
+        data: RawWasm,
+        module: wasmtime::Module,
+        namespaces: &ImportNamespaces,
+    ) -> Result<WasmtimeCompiledModule<T>>
+    where
+        T: ProcessState,
+    {
+        let linker = self.linker(&module, namespaces)?;
         // The `default_state` and `store` are just used for resolving host functions that are not
         // owned by any particular `Store`. The "real" instance state and store are created inside
         // the `instantiate` function.
@@ -43,36 +555,220 @@ impl WasmtimeRuntime {
         Ok(compiled_module)
     }
 
+    // Returns a linker with all host functions available to `module`.
+    fn linker<T>(
+        &self,
+        module: &wasmtime::Module,
+        namespaces: &ImportNamespaces,
+    ) -> Result<wasmtime::Linker<T>>
+    where
+        T: ProcessState,
+    {
+        let mut linker = wasmtime::Linker::new(&self.engine);
+        // Register host functions to linker.
+        <T as ProcessState>::register(&mut linker)?;
+        if self.compat_shims {
+            // The shims replace the current host functions with the same name.
+            linker.allow_shadowing(true);
+            <T as ProcessState>::register_compat(&mut linker, module)?;
+            linker.allow_shadowing(false);
+        }
+        namespaces.apply(&mut linker)?;
+        Ok(linker)
+    }
+
+    /// Compiles a memory-mapped wasm module.
+    ///
+    /// The mapping is kept alive as the source of the compiled module, so the module is never
+    /// copied onto the heap. The caller must make sure that the underlying file isn't modified
+    /// while the compiled module exists.
+    pub fn compile_module_from_mmap<T>(&self, mmap: Mmap) -> Result<WasmtimeCompiledModule<T>>
+    where
+        T: ProcessState,
+    {
+        self.compile_module(RawWasm::Mapped(mmap))
+    }
+
+    /// Compiles multiple modules in parallel, respecting the compile limits of the runtime.
+    ///
+    /// The results are returned in the same order as the modules were passed in.
+    pub fn compile_modules<T>(
+        &self,
+        modules: Vec<RawWasm>,
+    ) -> Vec<Result<WasmtimeCompiledModule<T>>>
+    where
+        T: ProcessState,
+    {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = modules
+                .into_iter()
+                .map(|data| scope.spawn(move || self.compile_module(data)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| match handle.join() {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow!("Module compilation panicked")),
+                })
+                .collect()
+        })
+    }
+
+    /// Instantiates the module with `state` and marks the state as initialized.
+    ///
+    /// If the module has a start function, it runs as part of the instantiation, before the state
+    /// is initialized and before any exported function can be called. A trap in the start
+    /// function fails the instantiation with an "Initialization failed" error.
+    ///
+    /// If the process config asks for it, the memory is
+    /// [prefaulted](WasmtimeInstance::prefault_memory) afterwards.
     pub async fn instantiate<T>(
         &self,
         compiled_module: &WasmtimeCompiledModule<T>,
         state: T,
     ) -> Result<WasmtimeInstance<T>>
     where
-        T: ProcessState + Send + ResourceLimiter,
+        T: ProcessState + Send + ResourceLimiter + 'static,
+    {
+        self.instantiate_with_fuel_used(compiled_module, state, 0)
+            .await
+            .map_err(|(error, _)| error)
+    }
+
+    /// Instantiates the module a process upgrades its code to, see [`crate::upgrade`].
+    ///
+    /// Unlike [`instantiate`](Self::instantiate) the state is handed back if it fails, so that
+    /// the process can keep running its old code. The `fuel_used` by the old code is charged to
+    /// the new store, it keeps counting towards the fuel limit of the process.
+    pub async fn instantiate_upgrade<T>(
+        &self,
+        compiled_module: &WasmtimeCompiledModule<T>,
+        state: T,
+        fuel_used: u64,
+    ) -> Result<WasmtimeInstance<T>, (anyhow::Error, T)>
+    where
+        T: ProcessState + Send + ResourceLimiter + 'static,
+    {
+        self.instantiate_with_fuel_used(compiled_module, state, fuel_used)
+            .await
+    }
+
+    async fn instantiate_with_fuel_used<T>(
+        &self,
+        compiled_module: &WasmtimeCompiledModule<T>,
+        state: T,
+        fuel_used: u64,
+    ) -> Result<WasmtimeInstance<T>, (anyhow::Error, T)>
+    where
+        T: ProcessState + Send + ResourceLimiter + 'static,
     {
         let max_fuel = state.config().get_max_fuel();
+        let prefault_memory = state.config().get_prefault_memory();
         let mut store = wasmtime::Store::new(&self.engine, state);
         // Set limits of the store
         store.limiter(|state| state);
+        // Trap instead of deadlocking if a host function calls back into the process
+        let host_calls = HostCalls::default();
+        store.call_hook(reentrancy_hook(host_calls.clone()));
         // Trap if out of fuel
         store.out_of_fuel_trap();
         // Define maximum fuel
-        match max_fuel {
-            Some(max_fuel) => {
-                store.out_of_fuel_async_yield(max_fuel, UNIT_OF_COMPUTE_IN_INSTRUCTIONS)
+        let (injections, fuel_per_injection) = self.fuel_injections(max_fuel);
+        // Only processes with a limit can run out of fuel.
+        let fuel_limit = max_fuel.map(|_| injections.saturating_mul(fuel_per_injection));
+        let injections = injections.saturating_sub(fuel_used / fuel_per_injection);
+        store.out_of_fuel_async_yield(injections, fuel_per_injection);
+        if fuel_used > 0 {
+            // Counts the fuel as consumed, without changing the fuel left in the store. Wasmtime
+            // refuses to consume the last unit of fuel, one unit more is added for it.
+            let charged = store
+                .add_fuel(fuel_used.saturating_add(1))
+                .and_then(|()| store.consume_fuel(fuel_used));
+            if let Err(error) = charged {
+                return Err((error, store.into_data()));
             }
-            // If no limit is specified use maximum
-            None => store.out_of_fuel_async_yield(u64::MAX, UNIT_OF_COMPUTE_IN_INSTRUCTIONS),
-        };
+        }
         // Create instance
-        let instance = compiled_module
+        let instance = match compiled_module
             .instantiator()
             .instantiate_async(&mut store)
-            .await?;
+            .await
+        {
+            Ok(instance) => instance,
+            // Imports are already checked by the instantiator, only the start function can trap.
+            Err(error) if error.is::<Trap>() => {
+                let error = error.context("Initialization failed, the start function trapped");
+                return Err((error, store.into_data()));
+            }
+            Err(error) => return Err((error, store.into_data())),
+        };
         // Mark state as initialized
         store.data_mut().initialize();
-        Ok(WasmtimeInstance { store, instance })
+        let mut instance = WasmtimeInstance {
+            store,
+            instance,
+            step_mode: self.step_mode.clone(),
+            fuel_rates: self.fuel_rates.clone(),
+            host_calls,
+            fuel_limit,
+            fuel_exhausted_hooks: self.fuel_exhausted_hooks.clone(),
+        };
+        if let Some(size) = prefault_memory {
+            if let Err(error) = instance.prefault_memory(size) {
+                return Err((error, instance.into_data()));
+            }
+        }
+        Ok(instance)
+    }
+}
+
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+// Smallest page size of the supported platforms, bigger pages are just touched more than once.
+const TOUCH_STRIDE: usize = 4096;
+
+// Counting semaphore bounding the number of concurrent compiles.
+struct CompileLimiter {
+    limits: CompileLimits,
+    running: Mutex<usize>,
+    finished: Condvar,
+    throttled: AtomicU64,
+}
+
+impl CompileLimiter {
+    fn new(limits: CompileLimits) -> Self {
+        Self {
+            limits,
+            running: Mutex::new(0),
+            finished: Condvar::new(),
+            throttled: AtomicU64::new(0),
+        }
+    }
+
+    fn acquire(&self) -> CompilePermit<'_> {
+        let mut running = self.running.lock().expect("never poisoned");
+        if *running >= self.limits.max_concurrent {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Compile throttled, {} compiles already running (limit {})",
+                *running, self.limits.max_concurrent
+            );
+            while *running >= self.limits.max_concurrent {
+                running = self.finished.wait(running).expect("never poisoned");
+            }
+        }
+        *running += 1;
+        CompilePermit { limiter: self }
+    }
+}
+
+struct CompilePermit<'a> {
+    limiter: &'a CompileLimiter,
+}
+
+impl<'a> Drop for CompilePermit<'a> {
+    fn drop(&mut self) {
+        *self.limiter.running.lock().expect("never poisoned") -= 1;
+        self.limiter.finished.notify_one();
     }
 }
 
@@ -84,6 +780,8 @@ pub struct WasmtimeCompiledModuleInner<T> {
     source: RawWasm,
     module: wasmtime::Module,
     instance_pre: wasmtime::InstancePre<T>,
+    // Opaque key-value pairs attached by the embedder.
+    metadata: Mutex<HashMap<String, String>>,
 }
 
 impl<T> WasmtimeCompiledModule<T> {
@@ -96,18 +794,82 @@ impl<T> WasmtimeCompiledModule<T> {
             source,
             module,
             instance_pre,
+            metadata: Mutex::default(),
         });
         Self { inner }
     }
 
+    /// Attaches the metadata entry `key` to the module, replacing an existing value.
+    ///
+    /// Metadata is not interpreted by lunatic, embedders can use it to keep a version, author or
+    /// policy next to the module. It's shared by all clones of the module.
+    pub fn set_metadata<K: Into<String>, V: Into<String>>(&self, key: K, value: V) {
+        self.inner
+            .metadata
+            .lock()
+            .expect("never poisoned")
+            .insert(key.into(), value.into());
+    }
+
+    /// Returns the metadata entry `key`, if it's set.
+    pub fn metadata(&self, key: &str) -> Option<String> {
+        self.inner
+            .metadata
+            .lock()
+            .expect("never poisoned")
+            .get(key)
+            .cloned()
+    }
+
+    /// Returns a copy of all metadata entries.
+    pub fn all_metadata(&self) -> HashMap<String, String> {
+        self.inner.metadata.lock().expect("never poisoned").clone()
+    }
+
     pub fn exports(&self) -> impl ExactSizeIterator<Item = wasmtime::ExportType<'_>> {
         self.inner.module.exports()
     }
 
+    /// Returns true if the module exports a function called `name`.
+    pub fn has_function(&self, name: &str) -> bool {
+        matches!(
+            self.inner.module.get_export(name),
+            Some(wasmtime::ExternType::Func(_))
+        )
+    }
+
     pub fn source(&self) -> &RawWasm {
         &self.inner.source
     }
 
+    /// Returns the contents of the custom section called `name` of the module, see
+    /// [`RawWasm::custom_section`].
+    pub fn custom_section(&self, name: &str) -> Option<&[u8]> {
+        self.inner.source.custom_section(name)
+    }
+
+    /// Returns the size of the compiled image in bytes.
+    ///
+    /// The image holds the machine code, data segments and metadata of the module. Its size is
+    /// close to the length of the serialized module.
+    pub fn code_size(&self) -> usize {
+        self.inner.module.image_range().len()
+    }
+
+    /// Returns an estimate of the memory kept alive by this module in bytes.
+    ///
+    /// This is the [`code_size`](Self::code_size) plus the size of the wasm source if it's held on
+    /// the heap. Memory-mapped sources are backed by their file and are not counted. Allocations
+    /// made by wasmtime for type information and the linked host functions are small in
+    /// comparison and are not included either.
+    pub fn memory_size(&self) -> usize {
+        let source = match &self.inner.source {
+            RawWasm::Owned(bytes) => bytes.len(),
+            RawWasm::Mapped(_) => 0,
+        };
+        self.code_size() + source
+    }
+
     pub fn instantiator(&self) -> &wasmtime::InstancePre<T> {
         &self.inner.instance_pre
     }
@@ -121,64 +883,221 @@ impl<T> Clone for WasmtimeCompiledModule<T> {
     }
 }
 
-pub struct WasmtimeInstance<T>
-where
-    T: Send,
-{
+pub struct WasmtimeInstance<T> {
     store: wasmtime::Store<T>,
     instance: wasmtime::Instance,
+    step_mode: Option<StepMode>,
+    fuel_rates: Option<FuelRates>,
+    host_calls: HostCalls,
+    // Total fuel of the process in instructions, `None` without a limit.
+    fuel_limit: Option<u64>,
+    fuel_exhausted_hooks: FuelExhaustedHooks,
 }
 
 impl<T> WasmtimeInstance<T>
 where
-    T: Send,
+    T: ProcessState + Send,
 {
-    pub async fn call(mut self, function: &str, params: Vec<wasmtime::Val>) -> ExecutionResult<T> {
-        let entry = self.instance.get_func(&mut self.store, function);
+    /// Grows the exported memory to at least `size` bytes and touches each page of the first
+    /// `size` bytes, so the process doesn't stall on page faults later. This trades startup time
+    /// and resident memory for predictable latency.
+    ///
+    /// Growing goes through the resource limiter of the process, it fails if `size` is above the
+    /// memory limit of the process or the maximum of the module's memory. The contents are kept
+    /// and a bigger memory isn't shrunk.
+    pub fn prefault_memory(&mut self, size: usize) -> Result<()> {
+        let memory = self
+            .instance
+            .get_memory(&mut self.store, "memory")
+            .ok_or_else(|| anyhow!("Can't prefault memory, the module doesn't export one"))?;
+        let current = memory.data_size(&self.store);
+        if size > current {
+            let pages = (size - current).div_ceil(WASM_PAGE_SIZE) as u64;
+            memory
+                .grow(&mut self.store, pages)
+                .with_context(|| format!("Can't prefault {} bytes of memory", size))?;
+        }
+        for page in memory.data_mut(&mut self.store)[..size].chunks_mut(TOUCH_STRIDE) {
+            // Writing forces the OS to back the page, `black_box` keeps the write from being
+            // optimized out.
+            page[0] = std::hint::black_box(page[0]);
+        }
+        Ok(())
+    }
 
-        if entry.is_none() {
-            return ExecutionResult {
-                state: self.store.into_data(),
-                result: ResultValue::SpawnError(format!("Function '{}' not found", function)),
-            };
+    /// Transfers the state of the old code to this instance during an upgrade, see
+    /// [`crate::upgrade`].
+    pub(crate) async fn transfer_state(&mut self, state: &[u8]) -> Result<()> {
+        if state.is_empty() {
+            return Ok(());
         }
+        let transfer = self
+            .instance
+            .get_typed_func::<u32, u32, _>(&mut self.store, STATE_TRANSFER_EXPORT)?;
+        let buffer = transfer
+            .call_async(&mut self.store, state.len() as u32)
+            .await?;
+        let memory = self
+            .instance
+            .get_memory(&mut self.store, "memory")
+            .ok_or_else(|| anyhow!("The new module doesn't export a memory"))?;
+        memory
+            .write(&mut self.store, buffer as usize, state)
+            .context("The state buffer is outside of the new memory")?;
+        Ok(())
+    }
 
-        let result = entry
-            .unwrap()
-            .call_async(&mut self.store, &params, &mut [])
-            .await;
+    /// Returns the state of the process, dropping the instance.
+    pub fn into_data(self) -> T {
+        self.store.into_data()
+    }
 
-        ExecutionResult {
-            state: self.store.into_data(),
-            result: match result {
-                Ok(()) => ResultValue::Ok,
-                Err(err) => {
-                    // If the trap is a result of calling `proc_exit(0)`, treat it as an no-error finish.
-                    match err.downcast_ref::<wasmtime::Trap>() {
-                        Some(trap) => {
-                            if trap.i32_exit_status().is_some()
-                                && trap.i32_exit_status().unwrap() == 0
-                            {
-                                ResultValue::Ok
-                            } else {
-                                ResultValue::Failed(trap.to_string())
-                            }
-                        }
+    /// Calls `function` and drives the process until it finishes.
+    ///
+    /// If the process upgrades itself (see [`crate::upgrade`]), the old code is unwound and the
+    /// process continues with the function of the new instance. The store of the old code is
+    /// dropped.
+    pub async fn call(mut self, function: &str, params: Vec<wasmtime::Val>) -> ExecutionResult<T> {
+        let mut entry = match self.instance.get_func(&mut self.store, function) {
+            Some(entry) => entry,
+            None => {
+                return ExecutionResult {
+                    state: self.store.into_data(),
+                    result: ResultValue::SpawnError(format!("Function '{}' not found", function)),
+                    fuel_consumed: None,
+                    memory_size: None,
+                    trap_location: None,
+                }
+            }
+        };
+        let mut params = params;
+        // Each fuel yield of the process injects one step or one unit of compute.
+        let fuel_per_yield = match &self.step_mode {
+            Some(step) => step.instructions(),
+            None => UNIT_OF_COMPUTE_IN_INSTRUCTIONS,
+        };
+        let fuel_meter = self.fuel_rates.as_ref().map(|rates| {
+            rates.track(
+                self.store.data().id(),
+                self.store.fuel_consumed().unwrap_or(0),
+            )
+        });
+        let result = loop {
+            let process_id = self.store.data().id();
+            // The fuel consumed so far and the fuel left, the call yields once it's used up.
+            let fuel_at_yield =
+                self.store.fuel_consumed().unwrap_or(0) + self.store.consume_fuel(0).unwrap_or(0);
+            let host_calls = self.host_calls.clone();
+            let call = entry.call_async(&mut self.store, &params, &mut []);
+            let result = match (&self.step_mode, &fuel_meter) {
+                (Some(step), Some(meter)) => {
+                    let call = step.drive(process_id, fuel_at_yield, host_calls.clone(), call);
+                    meter
+                        .drive(fuel_at_yield, fuel_per_yield, host_calls, call)
+                        .await
+                }
+                (Some(step), None) => {
+                    step.drive(process_id, fuel_at_yield, host_calls, call)
+                        .await
+                }
+                (None, Some(meter)) => {
+                    meter
+                        .drive(fuel_at_yield, fuel_per_yield, host_calls, call)
+                        .await
+                }
+                (None, None) => call.await,
+            };
+            // Counts the fuel consumed since the last yield.
+            if let (Some(meter), Some(fuel_consumed)) = (&fuel_meter, self.store.fuel_consumed()) {
+                meter.record(fuel_consumed);
+            }
+            let upgrade = match result {
+                Err(_) => self.store.data_mut().take_pending_upgrade(),
+                Ok(()) => None,
+            };
+            match upgrade {
+                Some(Upgrade::Ready { instance, function }) => {
+                    self = instance;
+                    entry = self
+                        .instance
+                        .get_func(&mut self.store, &function)
+                        .expect("checked by the upgrade");
+                    params = Vec::new();
+                }
+                _ => break result,
+            }
+        };
+
+        let fuel_consumed = self.store.fuel_consumed();
+        // The out of fuel trap can't be told apart from other traps, but a failed process that
+        // used up all of its fuel could only have failed because of it.
+        if let (Err(_), Some(consumed), Some(limit)) = (&result, fuel_consumed, self.fuel_limit) {
+            if consumed >= limit {
+                self.fuel_exhausted_hooks.dispatch(FuelExhausted {
+                    id: self.store.data().id(),
+                    fuel_consumed: consumed,
+                    fuel_limit: limit,
+                });
+            }
+        }
+        let memory_size = self
+            .instance
+            .get_memory(&mut self.store, "memory")
+            .map(|memory| memory.data_size(&self.store));
+        let mut trap_location = None;
+        let result = match result {
+            Ok(()) => ResultValue::Ok,
+            Err(err) => {
+                // If the trap is a result of calling `proc_exit`, treat it as a clean exit.
+                match err.downcast_ref::<wasmtime::Trap>() {
+                    Some(trap) => match trap.i32_exit_status() {
+                        Some(code) => ResultValue::Exited(code),
                         None => {
-                            ResultValue::Failed("Can't downcast trap to wasmtime::Trap".to_string())
+                            trap_location = TrapLocation::from_trap(trap);
+                            ResultValue::Failed(trap.to_string())
                         }
+                    },
+                    None => {
+                        ResultValue::Failed("Can't downcast trap to wasmtime::Trap".to_string())
                     }
                 }
-            },
+            }
+        };
+        ExecutionResult {
+            state: self.store.into_data(),
+            fuel_consumed,
+            memory_size,
+            result,
+            trap_location,
         }
     }
 }
 
+// Returns the number of functions the module defines, from the header of the function section.
+// Malformed modules count as 0, they fail compilation anyway.
+fn defined_functions(data: &[u8]) -> u32 {
+    wasmparser::Parser::new(0)
+        .parse_all(data)
+        .map_while(|payload| payload.ok())
+        .find_map(|payload| match payload {
+            wasmparser::Payload::FunctionSection(functions) => Some(functions.get_count()),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// Returns the wasmtime configuration used by the `lunatic` binary.
+///
+/// Modules are compiled with Cranelift, the only compiler available in the wasmtime version used
+/// by lunatic. The Winch baseline compiler, which trades code quality for compile speed, requires
+/// a newer wasmtime.
 pub fn default_config() -> wasmtime::Config {
     let mut config = wasmtime::Config::new();
     config
         .async_support(true)
         .debug_info(false)
+        // Symbolicate traps with the DWARF info of modules that have it, for `TrapLocation`
+        .wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Enable)
         // The behavior of fuel running out is defined on the Store
         .consume_fuel(true)
         .wasm_reference_types(true)
@@ -192,3 +1111,106 @@ pub fn default_config() -> wasmtime::Config {
         .static_memory_forced(true);
     config
 }
+
+/// Native profiler that generated machine code is reported to.
+///
+/// Without a profiler, native profilers only see anonymous JIT code. With one, the compiled
+/// guest functions show up under their names:
+///
+/// * [`Profiler::JitDump`] writes a `jit-<pid>.dump` file into the working directory, which
+///   `perf inject --jit` merges into a `perf record` taken with `-k mono`. Only supported on
+///   Linux.
+/// * [`Profiler::VTune`] reports the code to a running Intel VTune collector through the ITT
+///   API. Only supported on Linux with x86 CPUs.
+///
+/// Building a configuration with a profiler that isn't supported on the platform fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profiler {
+    #[default]
+    None,
+    JitDump,
+    VTune,
+}
+
+/// Builder of the wasmtime configuration, starting from [`default_config`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WasmtimeConfigBuilder {
+    profiler: Profiler,
+    threads: bool,
+}
+
+impl WasmtimeConfigBuilder {
+    pub fn profiler(mut self, profiler: Profiler) -> Self {
+        self.profiler = profiler;
+        self
+    }
+
+    /// Enables the [threads proposal](https://github.com/webassembly/threads), disabled by
+    /// default.
+    ///
+    /// This lets modules compiled with atomics run, e.g. multithreaded libraries used from a
+    /// single thread. Atomic instructions work on the regular memory of the process. Shared
+    /// memories are still refused when compiling, the wasmtime version used doesn't implement
+    /// them yet, so modules need to be linked without `--shared-memory`.
+    ///
+    /// A process stays a single thread of execution, it can't start other threads. Atomic
+    /// instructions consume fuel like any other instruction, and the fuel limit of the process
+    /// covers everything it runs. Use processes instead of threads to run code in parallel, each
+    /// with its own fuel.
+    pub fn wasm_threads(mut self, enable: bool) -> Self {
+        self.threads = enable;
+        self
+    }
+
+    /// Returns the configuration, or an error if the profiler can't be used on this platform.
+    pub fn build(self) -> Result<wasmtime::Config> {
+        let mut config = default_config();
+        let strategy = match self.profiler {
+            Profiler::None => wasmtime::ProfilingStrategy::None,
+            Profiler::JitDump => wasmtime::ProfilingStrategy::JitDump,
+            Profiler::VTune => wasmtime::ProfilingStrategy::VTune,
+        };
+        config.profiler(strategy)?;
+        config.wasm_threads(self.threads);
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::{CompileLimiter, CompileLimits, Profiler, WasmtimeConfigBuilder, WasmtimeRuntime};
+
+    #[test]
+    fn config_builder_defaults_to_no_profiler() {
+        let builder = WasmtimeConfigBuilder::default();
+        assert_eq!(builder.profiler, Profiler::None);
+        let config = builder.profiler(Profiler::None).build().unwrap();
+        assert!(WasmtimeRuntime::new(&config).is_ok());
+    }
+
+    #[test]
+    fn compiles_above_limit_are_queued() {
+        let limiter = CompileLimiter::new(CompileLimits {
+            max_concurrent: 1,
+            ..CompileLimits::default()
+        });
+        let permit = limiter.acquire();
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| {
+                let _permit = limiter.acquire();
+                let running = *limiter.running.lock().unwrap();
+                running
+            });
+            while limiter.throttled.load(Ordering::Relaxed) == 0 {
+                std::thread::yield_now();
+            }
+            assert!(!waiting.is_finished());
+            drop(permit);
+            assert_eq!(waiting.join().unwrap(), 1);
+        });
+        assert_eq!(*limiter.running.lock().unwrap(), 0);
+        assert_eq!(limiter.throttled.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,170 @@
+/*!
+A global tap recording every signal delivered to a process.
+
+Tracing needs to be enabled with the `signal-trace` feature, without it the tap and all recording
+calls are compiled out. Even with the feature enabled, events are only collected while there is
+at least one subscriber.
+
+Each [`SignalEvent`] contains the time the signal was sent, the target process and the source
+process, if the signal carries it. Message contents are not recorded, only their tag and size.
+The events can be used to reconstruct the interaction between processes, e.g. the sequence of
+events leading to a deadlock.
+
+## Example:
+
+```ignore
+let events = lunatic_process::signal_trace::subscribe();
+// ... run processes ...
+while let Ok(event) = events.try_recv() {
+    println!("{:?}", event);
+}
+```
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use async_std::channel::{unbounded, Receiver, Sender};
+use log::LevelFilter;
+use uuid::Uuid;
+
+use crate::{message::Message, DeathReason, Signal};
+
+// Set while there are subscribers, so that recording is a single atomic load otherwise.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static SUBSCRIBERS: Mutex<Vec<Sender<SignalEvent>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone)]
+pub struct SignalEvent {
+    pub time: SystemTime,
+    pub source: Option<Uuid>,
+    pub target: Uuid,
+    pub kind: SignalEventKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SignalEventKind {
+    Message { tag: Option<i64>, size: usize },
+    Kill(Duration),
+    DieWhenLinkDies(bool),
+    Link(Option<i64>),
+    UnLink,
+    LinkDied(Option<i64>, DeathReason),
+    SetLogLevel(Option<LevelFilter>),
+    Detach,
+    CancelReceive,
+    // The target process finished. Not a signal, but recorded to show deaths of processes
+    // without links.
+    Died(DeathReason),
+}
+
+/// Returns a receiver getting all signal events from now on.
+///
+/// Dropping the receiver unsubscribes it. The channel is unbounded, if the receiver isn't drained
+/// the events will pile up in memory.
+pub fn subscribe() -> Receiver<SignalEvent> {
+    let (sender, receiver) = unbounded();
+    let mut subscribers = SUBSCRIBERS.lock().expect("never poisoned");
+    subscribers.push(sender);
+    ACTIVE.store(true, Ordering::Relaxed);
+    receiver
+}
+
+// Records a signal sent to the process `target`.
+pub(crate) fn record(target: Uuid, signal: &Signal) {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+    let (source, kind) = match signal {
+        Signal::Message(message) => {
+            let size = match message {
+                Message::Data(data) => data.size(),
+                Message::LinkDied(_) | Message::Shutdown => 0,
+            };
+            let kind = SignalEventKind::Message {
+                tag: message.tag(),
+                size,
+            };
+            (None, kind)
+        }
+        Signal::Kill(grace) => (None, SignalEventKind::Kill(*grace)),
+        Signal::DieWhenLinkDies(value) => (None, SignalEventKind::DieWhenLinkDies(*value)),
+        Signal::Link(tag, process) => (Some(process.id()), SignalEventKind::Link(*tag)),
+        Signal::UnLink(process) => (Some(process.id()), SignalEventKind::UnLink),
+        Signal::LinkDied(id, tag, reason, _) => {
+            (Some(*id), SignalEventKind::LinkDied(*tag, *reason))
+        }
+        Signal::SetLogLevel(level) => (None, SignalEventKind::SetLogLevel(*level)),
+        Signal::Detach => (None, SignalEventKind::Detach),
+        Signal::CancelReceive => (None, SignalEventKind::CancelReceive),
+    };
+    publish(SignalEvent {
+        time: SystemTime::now(),
+        source,
+        target,
+        kind,
+    });
+}
+
+// Records the death of the process `target`.
+pub(crate) fn record_death(target: Uuid, reason: DeathReason) {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+    publish(SignalEvent {
+        time: SystemTime::now(),
+        source: None,
+        target,
+        kind: SignalEventKind::Died(reason),
+    });
+}
+
+fn publish(event: SignalEvent) {
+    let mut subscribers = SUBSCRIBERS.lock().expect("never poisoned");
+    // Unsubscribe dropped receivers.
+    subscribers.retain(|subscriber| subscriber.try_send(event.clone()).is_ok());
+    if subscribers.is_empty() {
+        ACTIVE.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SignalEventKind;
+    use crate::{Process, Signal};
+
+    #[async_std::test]
+    async fn signals_and_deaths_are_recorded() {
+        let events = super::subscribe();
+        let (join, process) = crate::spawn(|_this, _mailbox| async move {
+            async_std::task::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        process.send(Signal::DieWhenLinkDies(false));
+        process.send(Signal::Kill(Duration::ZERO));
+        let _ = join.await;
+
+        // Other tests can run at the same time, only look at events of this process.
+        let mut kinds = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if event.target == process.id() {
+                kinds.push(event.kind);
+            }
+        }
+        assert!(matches!(
+            kinds[..],
+            [
+                SignalEventKind::DieWhenLinkDies(false),
+                SignalEventKind::Kill(_),
+                SignalEventKind::Died(crate::DeathReason::Failure)
+            ]
+        ));
+    }
+}
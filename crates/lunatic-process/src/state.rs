@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use async_std::channel::{Receiver, Sender};
@@ -8,9 +9,13 @@ use uuid::Uuid;
 use wasmtime::Linker;
 
 use crate::{
-    config::ProcessConfig,
+    config::{ProcessConfig, ReloadableConfig},
+    deadlock::WaitGuard,
+    extensions::Extensions,
+    logging::ProcessLogLevel,
     mailbox::MessageMailbox,
     runtimes::wasmtime::{WasmtimeCompiledModule, WasmtimeRuntime},
+    upgrade::Upgrade,
     Process, Signal,
 };
 
@@ -34,6 +39,14 @@ pub trait ProcessState: Sized + Default {
 
     /// Register all host functions to the linker.
     fn register(linker: &mut Linker<Self>) -> Result<()>;
+    /// Register compatibility shims for host functions that changed, if `module` was built
+    /// against an older version of them. Only called if the shims are enabled on the runtime.
+    ///
+    /// The linker allows shadowing while the shims are registered, so they can replace the
+    /// current host functions with the same name.
+    fn register_compat(_linker: &mut Linker<Self>, _module: &wasmtime::Module) -> Result<()> {
+        Ok(())
+    }
     /// Marks a wasm instance as initialized
     fn initialize(&mut self);
     /// Returns true if the instance was initialized
@@ -45,13 +58,61 @@ pub trait ProcessState: Sized + Default {
     fn module(&self) -> &WasmtimeCompiledModule<Self>;
     /// Returns the process configuration
     fn config(&self) -> &Arc<Self::Config>;
+    /// Returns the reloadable configuration the process follows, if any.
+    fn config_source(&self) -> Option<&ReloadableConfig<Self::Config>> {
+        None
+    }
+    /// Makes the process follow `source`: processes it spawns without an explicit configuration
+    /// get the latest configuration of `source` instead of the one of this process, and follow
+    /// it too. The configuration of this process doesn't change.
+    fn set_config_source(&mut self, _source: Option<ReloadableConfig<Self::Config>>) {}
+    /// Returns the values the embedder attached to the process, `None` if the state doesn't
+    /// support extensions.
+    fn extensions(&self) -> Option<&Extensions> {
+        None
+    }
+    /// Attaches `extensions` to the process, see [`Extensions`]. Processes it spawns inherit
+    /// them.
+    fn set_extensions(&mut self, _extensions: Extensions) {}
 
     // Returns ID
     fn id(&self) -> Uuid;
+    /// Returns the moment the process state was created, right before the process is spawned.
+    fn spawned_at(&self) -> Instant;
+    /// Returns for how long the process has existed, measured with a monotonic clock.
+    fn uptime(&self) -> Duration {
+        self.spawned_at().elapsed()
+    }
+    /// Returns the human-readable label of the process, it doesn't need to be unique.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+    /// Sets the label of the process. Logs of the process loop use the label the process had
+    /// when it was spawned.
+    fn set_name(&mut self, _name: Option<String>) {}
+    /// Marks the process as blocked on its mailbox for the deadlock detector of the runtime, until
+    /// the returned guard is dropped. Returns `None` if the runtime has no detector, if the
+    /// process has pending timers or if the state doesn't support it.
+    fn wait_guard(&self) -> Option<WaitGuard> {
+        None
+    }
     // Returns signal mailbox
     fn signal_mailbox(&self) -> &(Sender<Signal>, Receiver<Signal>);
     // Returns message mailbox
     fn message_mailbox(&self) -> &MessageMailbox;
+    /// Returns the log level host functions use for this process, see [`crate::logging`].
+    fn log_level(&self) -> &ProcessLogLevel;
+    /// Sets the notifier of [`spawn_wasm_and_await_ready`](crate::wasm::spawn_wasm_and_await_ready),
+    /// before the process is spawned. States that don't keep it can't report readiness, waiting
+    /// on them only ends with the process or the ready timeout.
+    fn set_ready_notifier(&mut self, _notifier: Sender<()>) {}
+    /// Takes the notifier out of the state, so that readiness is only reported once.
+    fn take_ready_notifier(&mut self) -> Option<Sender<()>> {
+        None
+    }
+    /// Sets the upgrade of the process' code, see [`crate::upgrade`].
+    fn set_pending_upgrade(&mut self, upgrade: Upgrade<Self>);
+    fn take_pending_upgrade(&mut self) -> Option<Upgrade<Self>>;
 
     // Config resources
     fn config_resources(&self) -> &ConfigResources<Self::Config>;
@@ -0,0 +1,147 @@
+/*!
+Step mode for debugging guest code.
+
+With a [`StepMode`] set on the runtime, processes spawned afterwards pause after every
+`instructions` executed wasm instructions and call the step hook. The hook sees how far the
+process got and decides if it continues or gets killed, which allows a debugger or a test to
+follow the progression of a process in fine detail.
+
+Steps reuse the fuel machinery, the process yields each time the fuel of one step is used up, the
+same way it regularly yields after each unit of compute. Fuel charged for host calls counts
+towards the step too. Waiting in a host function (e.g. on a message or on IO) isn't a step, only
+pauses outside of host functions are. Stepping with small intervals is very slow and should only
+be used for debugging.
+
+The hook runs on the thread driving the process, while the process is paused. It should return
+quickly.
+*/
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use uuid::Uuid;
+use wasmtime::Trap;
+
+use crate::reentrancy::HostCalls;
+
+type StepHook = Arc<dyn Fn(&StepInfo) -> StepAction + Send + Sync>;
+
+/// State of a paused process, passed to the step hook.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepInfo {
+    pub process_id: Uuid,
+    /// Number of pauses of the current call, starting at 1. The first pause happens before the
+    /// call executed any instruction.
+    pub step: u64,
+    /// Fuel consumed by the process when it paused, in instructions. The process pauses once the
+    /// fuel of the store is used up, the value is exact up to the instructions of the last basic
+    /// block.
+    pub fuel_consumed: u64,
+}
+
+/// Decision of the step hook.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepAction {
+    Continue,
+    /// The process fails with a trap.
+    Kill,
+}
+
+/// Pauses processes after a fixed number of instructions and calls a hook.
+#[derive(Clone)]
+pub struct StepMode {
+    instructions: u64,
+    hook: StepHook,
+}
+
+impl StepMode {
+    /// Calls `hook` after every `instructions` executed instructions, at least 1.
+    pub fn new<F>(instructions: u64, hook: F) -> Self
+    where
+        F: Fn(&StepInfo) -> StepAction + Send + Sync + 'static,
+    {
+        Self {
+            instructions: instructions.max(1),
+            hook: Arc::new(hook),
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Drives `call` of the process `process_id`, calling the hook after each step.
+    ///
+    /// `fuel_at_yield` is the fuel the store consumed once the fuel it has left is used up, where
+    /// the first pause happens. `host_calls` are the running host functions of the store.
+    pub(crate) fn drive<F>(
+        &self,
+        process_id: Uuid,
+        fuel_at_yield: u64,
+        host_calls: HostCalls,
+        call: F,
+    ) -> Stepped<F>
+    where
+        F: Future<Output = anyhow::Result<()>>,
+    {
+        Stepped {
+            mode: self.clone(),
+            process_id,
+            steps: 0,
+            fuel_at_yield,
+            host_calls,
+            call: Box::pin(call),
+        }
+    }
+}
+
+impl Debug for StepMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StepMode")
+            .field("instructions", &self.instructions)
+            .finish()
+    }
+}
+
+// A call of a process in step mode.
+pub(crate) struct Stepped<F> {
+    mode: StepMode,
+    process_id: Uuid,
+    steps: u64,
+    // Fuel consumed by the store at the next fuel yield.
+    fuel_at_yield: u64,
+    host_calls: HostCalls,
+    call: Pin<Box<F>>,
+}
+
+impl<F> Future for Stepped<F>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    type Output = anyhow::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let poll = self.call.as_mut().poll(cx);
+        // Outside of host functions the process only pauses if it used up its fuel.
+        if poll.is_pending() && !self.host_calls.running() {
+            self.steps += 1;
+            let info = StepInfo {
+                process_id: self.process_id,
+                step: self.steps,
+                fuel_consumed: self.fuel_at_yield,
+            };
+            // Each fuel yield injects the fuel of one step, the process pauses again once it's
+            // used up.
+            self.fuel_at_yield += self.mode.instructions;
+            if (self.mode.hook)(&info) == StepAction::Kill {
+                return Poll::Ready(Err(Trap::new("Process killed by the step hook").into()));
+            }
+        }
+        poll
+    }
+}
@@ -0,0 +1,104 @@
+/*!
+Named topics processes can subscribe to, to receive all messages published to the topic.
+
+Every runtime has its own [`Topics`]. Processes use them through the `lunatic::message::subscribe`,
+`unsubscribe` and `publish` host functions.
+
+## Guarantees
+
+* Each subscriber gets a copy of the message, in its mailbox like any other message.
+* Messages published by the same process arrive at each subscriber in the order they were
+  published (per-publisher FIFO). There is no order between messages of different publishers.
+* Only the subscribers at the time of publishing get the message. There is no backlog, a process
+  subscribing later doesn't receive earlier messages.
+* A publisher subscribed to the topic gets its own messages.
+* Subscriptions end when the process finishes.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use uuid::Uuid;
+
+use crate::{message::Message, process_ref::ProcessRef};
+
+/// Subscribers of each topic.
+///
+/// Clones share the same topics.
+#[derive(Clone, Debug, Default)]
+pub struct Topics {
+    inner: Arc<Mutex<HashMap<String, HashMap<Uuid, ProcessRef>>>>,
+}
+
+impl Topics {
+    /// Subscribes `process` to `topic`, returns `false` if it already was.
+    pub fn subscribe(&self, topic: &str, process: ProcessRef) -> bool {
+        let mut topics = self.inner.lock().expect("never poisoned");
+        topics
+            .entry(topic.to_owned())
+            .or_default()
+            .insert(process.id(), process)
+            .is_none()
+    }
+
+    /// Unsubscribes the process `id` from `topic`, returns `false` if it wasn't subscribed.
+    pub fn unsubscribe(&self, topic: &str, id: Uuid) -> bool {
+        let mut topics = self.inner.lock().expect("never poisoned");
+        let subscribers = match topics.get_mut(topic) {
+            Some(subscribers) => subscribers,
+            None => return false,
+        };
+        let removed = subscribers.remove(&id).is_some();
+        if subscribers.is_empty() {
+            topics.remove(topic);
+        }
+        removed
+    }
+
+    /// Unsubscribes the process `id` from all topics, once it finished.
+    pub fn unsubscribe_all(&self, id: Uuid) {
+        let mut topics = self.inner.lock().expect("never poisoned");
+        topics.retain(|_, subscribers| {
+            subscribers.remove(&id);
+            !subscribers.is_empty()
+        });
+    }
+
+    /// Returns the number of processes subscribed to `topic`.
+    pub fn subscribers(&self, topic: &str) -> usize {
+        let topics = self.inner.lock().expect("never poisoned");
+        topics.get(topic).map_or(0, HashMap::len)
+    }
+
+    /// Delivers a copy of `message` to each subscriber of `topic` and returns the IDs of the
+    /// processes it was delivered to.
+    ///
+    /// Messages with resources can't be copied, they are not delivered to anyone.
+    pub fn publish(&self, topic: &str, message: &Message) -> Vec<Uuid> {
+        let mut topics = self.inner.lock().expect("never poisoned");
+        let subscribers = match topics.get_mut(topic) {
+            Some(subscribers) => subscribers,
+            None => return Vec::new(),
+        };
+        let mut delivered = Vec::with_capacity(subscribers.len());
+        // Delivering under the lock keeps the order of messages from the same publisher.
+        subscribers.retain(|id, process| {
+            let copy = match message.try_clone() {
+                Some(copy) => copy,
+                None => return true,
+            };
+            // A subscriber that finished, but wasn't cleaned up yet.
+            if process.try_send_message(copy).is_err() {
+                return false;
+            }
+            delivered.push(*id);
+            true
+        });
+        if subscribers.is_empty() {
+            topics.remove(topic);
+        }
+        delivered
+    }
+}
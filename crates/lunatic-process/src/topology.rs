@@ -0,0 +1,220 @@
+/*!
+A graph of the running processes and the links between them.
+
+If a [`Topology`] is installed on the runtime, every process spawned afterwards reports itself
+and its links to it. A [`TopologyGraph`] snapshot can be serialized for a debugging dashboard or
+rendered with Graphviz through [`TopologyGraph::to_dot`].
+
+## Consistency
+
+The snapshot is a best-effort cut, not a stop-the-world view. Each process updates its own entry
+while handling its signals, so:
+
+* A link that was requested, but not processed by the process yet, is missing.
+* A link is recorded separately by both sides, for a short time only one of the edges can be in
+  the graph.
+* A process that just died is listed until its loop finished cleaning up.
+
+Only the processes of this node are included.
+*/
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Tracks the processes of a runtime and their links.
+///
+/// Clones share the same graph.
+#[derive(Clone, Default)]
+pub struct Topology {
+    inner: Arc<Mutex<HashMap<Uuid, Node>>>,
+}
+
+struct Node {
+    name: Option<String>,
+    spawned: Instant,
+    // Linked processes and the tag this process receives if they die.
+    links: HashMap<Uuid, Option<i64>>,
+}
+
+/// A snapshot of the processes and links.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopologyGraph {
+    pub processes: Vec<ProcessNode>,
+    pub links: Vec<LinkEdge>,
+}
+
+/// A running process.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessNode {
+    pub id: Uuid,
+    /// Name of the process at the time it was spawned.
+    pub name: Option<String>,
+    pub uptime_ms: u64,
+}
+
+/// A link, as seen by the process `from`.
+///
+/// Links are bidirectional, so once both sides processed the link there is also an edge in the
+/// other direction.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkEdge {
+    pub from: Uuid,
+    pub to: Uuid,
+    /// Tag of the message `from` receives if `to` dies.
+    pub tag: Option<i64>,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current graph, ordered by process ID.
+    pub fn snapshot(&self) -> TopologyGraph {
+        let nodes = self.inner.lock().expect("never poisoned");
+        let mut graph = TopologyGraph::default();
+        for (id, node) in nodes.iter() {
+            graph.processes.push(ProcessNode {
+                id: *id,
+                name: node.name.clone(),
+                uptime_ms: node.spawned.elapsed().as_millis() as u64,
+            });
+            graph
+                .links
+                .extend(node.links.iter().map(|(to, tag)| LinkEdge {
+                    from: *id,
+                    to: *to,
+                    tag: *tag,
+                }));
+        }
+        graph.processes.sort_by_key(|process| process.id);
+        graph.links.sort_by_key(|link| (link.from, link.to));
+        graph
+    }
+
+    pub(crate) fn spawned(&self, id: Uuid, name: Option<String>) {
+        let node = Node {
+            name,
+            spawned: Instant::now(),
+            links: HashMap::new(),
+        };
+        self.inner.lock().expect("never poisoned").insert(id, node);
+    }
+
+    pub(crate) fn linked(&self, id: Uuid, to: Uuid, tag: Option<i64>) {
+        if let Some(node) = self.inner.lock().expect("never poisoned").get_mut(&id) {
+            node.links.insert(to, tag);
+        }
+    }
+
+    pub(crate) fn unlinked(&self, id: Uuid, to: Uuid) {
+        if let Some(node) = self.inner.lock().expect("never poisoned").get_mut(&id) {
+            node.links.remove(&to);
+        }
+    }
+
+    pub(crate) fn unlinked_all(&self, id: Uuid) {
+        if let Some(node) = self.inner.lock().expect("never poisoned").get_mut(&id) {
+            node.links.clear();
+        }
+    }
+
+    pub(crate) fn finished(&self, id: Uuid) {
+        self.inner.lock().expect("never poisoned").remove(&id);
+    }
+}
+
+impl TopologyGraph {
+    /// Renders the graph in the Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph lunatic {\n");
+        for process in &self.processes {
+            let label = match &process.name {
+                Some(name) => format!("{}\\n{}", escape_dot(name), process.id),
+                None => process.id.to_string(),
+            };
+            let _ = writeln!(dot, "    \"{}\" [label=\"{}\"];", process.id, label);
+        }
+        for link in &self.links {
+            let _ = match link.tag {
+                Some(tag) => writeln!(
+                    dot,
+                    "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                    link.from, link.to, tag
+                ),
+                None => writeln!(dot, "    \"{}\" -> \"{}\";", link.from, link.to),
+            };
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// Escapes a process name for a quoted DOT string. Line breaks are dropped, the label puts the ID
+// on its own line.
+fn escape_dot(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' | '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{LinkEdge, Topology};
+
+    #[test]
+    fn snapshot_contains_processes_and_links() {
+        let topology = Topology::new();
+        let (parent, child) = (Uuid::new_v4(), Uuid::new_v4());
+        topology.spawned(parent, Some("supervisor".to_owned()));
+        topology.spawned(child, None);
+        topology.linked(parent, child, None);
+        topology.linked(child, parent, Some(7));
+        // Links of unknown processes are ignored.
+        topology.linked(Uuid::new_v4(), child, None);
+
+        let graph = topology.snapshot();
+        assert_eq!(graph.processes.len(), 2);
+        assert_eq!(graph.links.len(), 2);
+        assert!(graph.links.contains(&LinkEdge {
+            from: child,
+            to: parent,
+            tag: Some(7)
+        }));
+        let dot = graph.to_dot();
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"7\"];", child, parent)));
+        assert!(dot.contains("supervisor"));
+
+        topology.unlinked(parent, child);
+        topology.finished(child);
+        let graph = topology.snapshot();
+        assert_eq!(graph.processes.len(), 1);
+        assert_eq!(graph.processes[0].name.as_deref(), Some("supervisor"));
+        assert!(graph.links.is_empty());
+    }
+
+    #[test]
+    fn dot_escapes_names() {
+        let topology = Topology::new();
+        let id = Uuid::new_v4();
+        topology.spawned(id, Some("a\\b \"c\"\nd\r".to_owned()));
+        let dot = topology.snapshot().to_dot();
+        assert!(dot.contains(&format!("[label=\"a\\\\b \\\"c\\\"d\\n{}\"];", id)));
+    }
+}
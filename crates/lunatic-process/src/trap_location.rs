@@ -0,0 +1,115 @@
+/*!
+Where in the guest code a process trapped.
+
+The innermost frame of the trap's backtrace is the faulting instruction. Its offset in the module
+is always known, the source file and line only if the module contains DWARF debug info and the
+engine was configured to parse it (see
+[`WasmBacktraceDetails`](wasmtime::WasmBacktraceDetails), enabled in
+[`default_config`](crate::runtimes::wasmtime::default_config)). Modules built in release mode
+usually don't carry debug info, the location then falls back to the function and offset.
+*/
+
+use std::fmt::Display;
+
+/// The faulting instruction of a trap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrapLocation {
+    pub module_name: Option<String>,
+    pub func_index: u32,
+    pub func_name: Option<String>,
+    /// Offset of the instruction from the start of the module binary.
+    pub module_offset: Option<usize>,
+    /// Offset of the instruction from the start of the function body.
+    pub func_offset: Option<usize>,
+    /// Source location, only available with debug info.
+    pub source: Option<SourceLocation>,
+}
+
+/// A position in the source code, from the DWARF debug info.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl TrapLocation {
+    /// Returns the location of the innermost wasm frame, `None` if the trap has no backtrace,
+    /// e.g. because it was created by a host function outside of any guest call.
+    pub fn from_trap(trap: &wasmtime::Trap) -> Option<Self> {
+        let frame = trap.trace()?.first()?;
+        // Inlined functions produce multiple symbols, the first one is the innermost.
+        let source = frame.symbols().first().and_then(|symbol| {
+            if symbol.file().is_none() && symbol.line().is_none() {
+                return None;
+            }
+            Some(SourceLocation {
+                file: symbol.file().map(str::to_owned),
+                line: symbol.line(),
+                column: symbol.column(),
+            })
+        });
+        Some(Self {
+            module_name: frame.module_name().map(str::to_owned),
+            func_index: frame.func_index(),
+            func_name: frame.func_name().map(str::to_owned),
+            module_offset: frame.module_offset(),
+            func_offset: frame.func_offset(),
+            source,
+        })
+    }
+}
+
+impl Display for TrapLocation {
+    // e.g. `src/main.rs:12:5 in main (wasm function 3, module offset 0x1a2)`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(source) = &self.source {
+            write!(f, "{}", source.file.as_deref().unwrap_or("<unknown>"))?;
+            if let Some(line) = source.line {
+                write!(f, ":{}", line)?;
+                if let Some(column) = source.column {
+                    write!(f, ":{}", column)?;
+                }
+            }
+            write!(f, " in ")?;
+        }
+        if let Some(name) = &self.func_name {
+            write!(f, "{} ", name)?;
+        }
+        write!(f, "(wasm function {}", self.func_index)?;
+        if let Some(module_offset) = self.module_offset {
+            write!(f, ", module offset {:#x}", module_offset)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceLocation, TrapLocation};
+
+    #[test]
+    fn display_falls_back_to_offsets() {
+        let mut location = TrapLocation {
+            module_name: None,
+            func_index: 3,
+            func_name: Some("main".to_owned()),
+            module_offset: Some(0x1a2),
+            func_offset: Some(4),
+            source: None,
+        };
+        assert_eq!(
+            location.to_string(),
+            "main (wasm function 3, module offset 0x1a2)"
+        );
+        location.source = Some(SourceLocation {
+            file: Some("src/main.rs".to_owned()),
+            line: Some(12),
+            column: Some(5),
+        });
+        assert_eq!(
+            location.to_string(),
+            "src/main.rs:12:5 in main (wasm function 3, module offset 0x1a2)"
+        );
+    }
+}
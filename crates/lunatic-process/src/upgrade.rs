@@ -0,0 +1,150 @@
+/*!
+Live upgrades of the code of a running process.
+
+A process requests an upgrade with the `lunatic::process::upgrade` host function, naming the
+module and the function it continues with. Nothing changes right away, the old code finishes
+handling its current message. The upgrade happens at the next inter-message boundary, when the
+process calls `lunatic::message::receive`:
+
+1. The new module is instantiated in a new store that takes over the process state. The process
+   ID, mailbox, links, registered name and all resources stay the same, the mailbox keeps
+   all messages that didn't arrive yet.
+2. The state is transferred (see below).
+3. The old code is unwound, its store and memory are freed. The process continues with the new
+   function, which can't take parameters or return values, and consumes the unchanged mailbox.
+
+The fuel the process used so far still counts towards its fuel limit, and the memory limit of the
+process applies to the new instance. The old instance is only freed once the transfer succeeded,
+so for the duration of the transfer both memories exist.
+
+## State transfer
+
+The memory of the new instance starts out fresh. With the upgrade request the old code names a
+region of its memory holding its state. At the boundary the host copies the region, as it is at
+that moment, and calls the function [`STATE_TRANSFER_EXPORT`] of the new instance with its
+length. The function has the type `(i32) -> i32`, it returns the address of a buffer in its
+own memory the host writes the state to. The new entry function finds it there afterwards. An
+empty region skips the transfer, the new module doesn't need to export the function then.
+
+## Failures
+
+If the new module can't be instantiated, the transfer function is missing or traps, or the
+returned buffer is outside of its memory, the upgrade is dropped and the old code keeps running:
+the receive continues as if no upgrade had been requested and the failure is logged.
+*/
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use wasmtime::{Caller, ResourceLimiter, Trap};
+
+use crate::{
+    runtimes::wasmtime::{WasmtimeCompiledModule, WasmtimeInstance},
+    state::ProcessState,
+};
+
+/// Function of the new module that receives the state of the old code, see the
+/// [module documentation](self).
+pub const STATE_TRANSFER_EXPORT: &str = "lunatic_upgrade_state";
+
+/// Upgrade of a process, see [`ProcessState::set_pending_upgrade`].
+pub enum Upgrade<T> {
+    /// Requested by the process, performed at the next inter-message boundary.
+    Requested {
+        module: WasmtimeCompiledModule<T>,
+        /// Function of `module` the process continues with.
+        function: String,
+        /// Address and length of the state in the memory of the old code.
+        state: (u32, u32),
+    },
+    /// Performed, the process continues with `function` of `instance` once the old code is
+    /// unwound.
+    Ready {
+        instance: WasmtimeInstance<T>,
+        function: String,
+    },
+}
+
+/// Performs the upgrade the process requested, if any.
+///
+/// Called at the inter-message boundary, before a receive waits for the next message. Returns a
+/// trap unwinding the old code if the upgrade succeeded. If there is nothing to upgrade or the
+/// upgrade failed, it returns `Ok` and the old code keeps running.
+pub async fn upgrade_at_boundary<T>(caller: &mut Caller<'_, T>) -> Result<(), Trap>
+where
+    T: ProcessState + Send + ResourceLimiter + 'static,
+{
+    let (module, function, state) = match caller.data_mut().take_pending_upgrade() {
+        Some(Upgrade::Requested {
+            module,
+            function,
+            state,
+        }) => (module, function, state),
+        // Only requested upgrades can be pending while the old code runs.
+        Some(ready @ Upgrade::Ready { .. }) => {
+            caller.data_mut().set_pending_upgrade(ready);
+            return Ok(());
+        }
+        None => return Ok(()),
+    };
+    match upgrade(caller, &module, state).await {
+        Ok(instance) => {
+            // The store of the old code keeps a placeholder state from now on, carrying the new
+            // instance until the old code is unwound.
+            caller
+                .data_mut()
+                .set_pending_upgrade(Upgrade::Ready { instance, function });
+            Err(Trap::new(
+                "lunatic::process::upgrade: Unwinding the old code",
+            ))
+        }
+        Err(error) => {
+            warn!(
+                "Process {} keeps running the old code, upgrade failed: {:?}",
+                caller.data().id(),
+                error
+            );
+            Ok(())
+        }
+    }
+}
+
+// Moves the process state into a new instance of `module` and transfers the old state region.
+// The process state is back in the old store if it fails.
+async fn upgrade<T>(
+    caller: &mut Caller<'_, T>,
+    module: &WasmtimeCompiledModule<T>,
+    (state_ptr, state_len): (u32, u32),
+) -> Result<WasmtimeInstance<T>>
+where
+    T: ProcessState + Send + ResourceLimiter + 'static,
+{
+    let old_memory = caller
+        .get_export("memory")
+        .and_then(|memory| memory.into_memory())
+        .ok_or_else(|| anyhow!("The old code doesn't export a memory"))?;
+    let state = old_memory
+        .data(&caller)
+        .get(state_ptr as usize..state_ptr as usize + state_len as usize)
+        .ok_or_else(|| anyhow!("The state region is outside of the old memory"))?
+        .to_vec();
+    let fuel_used = caller.fuel_consumed().unwrap_or(0);
+    let runtime = caller.data().runtime().clone();
+    let process_state = std::mem::take(caller.data_mut());
+    let mut instance = match runtime
+        .instantiate_upgrade(module, process_state, fuel_used)
+        .await
+    {
+        Ok(instance) => instance,
+        Err((error, process_state)) => {
+            *caller.data_mut() = process_state;
+            return Err(error);
+        }
+    };
+    match instance.transfer_state(&state).await {
+        Ok(()) => Ok(instance),
+        Err(error) => {
+            *caller.data_mut() = instance.into_data();
+            Err(error)
+        }
+    }
+}
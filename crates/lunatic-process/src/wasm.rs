@@ -1,13 +1,16 @@
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use async_std::task::JoinHandle;
+use anyhow::{anyhow, Result};
+use async_std::channel::bounded;
 use log::trace;
 use wasmtime::{ResourceLimiter, Val};
 
+use crate::config::ProcessConfig;
+use crate::message::Message;
 use crate::runtimes::wasmtime::{WasmtimeCompiledModule, WasmtimeRuntime};
 use crate::state::ProcessState;
-use crate::{Process, Signal, WasmProcess};
+use crate::{executor, JoinHandle, Process, Signal, WasmProcess};
 
 /// Spawns a new wasm process from a compiled module.
 ///
@@ -18,6 +21,21 @@ use crate::{Process, Signal, WasmProcess};
 /// After it's spawned the process will keep running in the background. A process can be killed
 /// with `Signal::Kill` signal. If you would like to block until the process is finished you can
 /// `.await` on the returned `JoinHandle<()>`.
+///
+/// An `init_message` is put into the mailbox of the process before it starts running. It's
+/// always the first message the process receives, ahead of any message sent to it after the
+/// spawn. The link to the parent is established before the process starts too, so a `LinkDied`
+/// message caused by the link can only arrive after the init message.
+///
+/// If the module doesn't export `function`, the fallback entry of the process configuration is
+/// used instead (see [`ProcessConfig::set_fallback_entry`]). If neither exists, an error is
+/// returned right away and the process is never started.
+///
+/// Dependencies of host functions (e.g. a database pool) can be attached to the `state` with
+/// [`ProcessState::set_extensions`], processes spawned by this one inherit them.
+///
+/// The start function of the module runs before the spawn returns. If it traps, the process is
+/// never started and an "Initialization failed" error is returned instead.
 pub async fn spawn_wasm<S>(
     runtime: WasmtimeRuntime,
     module: WasmtimeCompiledModule<S>,
@@ -25,20 +43,48 @@ pub async fn spawn_wasm<S>(
     function: &str,
     params: Vec<Val>,
     link: Option<(Option<i64>, Arc<dyn Process>)>,
+    init_message: Option<Message>,
 ) -> Result<(JoinHandle<Result<S>>, Arc<dyn Process>)>
 where
     S: ProcessState + Send + ResourceLimiter + 'static,
 {
+    let function = entry_function(&module, &state, function)?;
     let id = state.id();
-    trace!("Spawning process: {}", id);
+    let name = state.name().map(str::to_owned);
+    trace!("Spawning process: {} {:?}", id, name);
 
     let signal_mailbox = state.signal_mailbox().clone();
     let message_mailbox = state.message_mailbox().clone();
+    let log_level = state.log_level().clone();
+    if let Some(message) = init_message {
+        // Nobody else can reach the mailbox yet, so the message is guaranteed to be first.
+        message_mailbox.push(message);
+    }
 
+    // Counted as running until the process loop finishes.
+    let live = runtime.track_task();
     let instance = runtime.instantiate(&module, state).await?;
-    let function = function.to_string();
     let fut = async move { instance.call(&function, params).await };
-    let child_process = crate::new(fut, id, signal_mailbox.1, message_mailbox);
+    let child_process = crate::new(
+        fut,
+        id,
+        name,
+        signal_mailbox.1,
+        message_mailbox,
+        runtime.post_mortem_hooks().clone(),
+        log_level,
+        runtime.topology().cloned(),
+        runtime.max_link_cascade_depth(),
+    );
+    let process_tree = runtime.process_tree().clone();
+    let topics = runtime.topics().clone();
+    let child_process = async move {
+        let result = child_process.await;
+        process_tree.finished(id);
+        topics.unsubscribe_all(id);
+        drop(live);
+        result
+    };
     let child_process_handle = WasmProcess::new(id, signal_mailbox.0.clone());
 
     // **Child link guarantees**:
@@ -78,6 +124,156 @@ where
 
     // Spawn a background process
     trace!("Process size: {}", std::mem::size_of_val(&child_process));
-    let join = async_std::task::spawn(child_process);
+    let join = crate::spawn_task(child_process);
     Ok((join, Arc::new(child_process_handle)))
 }
+
+// Returns the function the process should start with, checked before anything is instantiated.
+fn entry_function<S: ProcessState>(
+    module: &WasmtimeCompiledModule<S>,
+    state: &S,
+    function: &str,
+) -> Result<String> {
+    if module.has_function(function) {
+        return Ok(function.to_string());
+    }
+    match state.config().get_fallback_entry() {
+        Some(fallback) if module.has_function(fallback) => Ok(fallback.to_string()),
+        Some(fallback) => Err(anyhow!(
+            "Function '{}' not found, the fallback '{}' doesn't exist either",
+            function,
+            fallback
+        )),
+        None => Err(anyhow!("Function '{}' not found", function)),
+    }
+}
+
+/// Spawns a new wasm process from synchronous code, like the `main` function of a CLI.
+///
+/// Same as [`spawn_wasm`], but the spawn is driven to completion on the current thread. It only
+/// blocks until the process is instantiated and the handle is ready, not until the process
+/// finishes. The process itself still runs as a background task of the installed
+/// [executor](crate::executor), so an async runtime needs to be available. The default
+/// `async_std` executor starts its worker threads on demand, other executors (e.g. tokio) need to
+/// be running already.
+///
+/// Must not be called from inside of an async task, it would block the executor thread.
+#[allow(clippy::type_complexity)]
+pub fn spawn_wasm_blocking<S>(
+    runtime: WasmtimeRuntime,
+    module: WasmtimeCompiledModule<S>,
+    state: S,
+    function: &str,
+    params: Vec<Val>,
+    link: Option<(Option<i64>, Arc<dyn Process>)>,
+    init_message: Option<Message>,
+) -> Result<(JoinHandle<Result<S>>, Arc<dyn Process>)>
+where
+    S: ProcessState + Send + ResourceLimiter + 'static,
+{
+    async_std::task::block_on(spawn_wasm(
+        runtime,
+        module,
+        state,
+        function,
+        params,
+        link,
+        init_message,
+    ))
+}
+
+/// Spawns a new wasm process and waits until it reports that it's ready.
+///
+/// Same as [`spawn_wasm`], but it only returns once the process called the
+/// `lunatic::process::ready` host function. A service process can call it after it finished its
+/// initialization (loaded data, bound a port, ...), so that the spawner doesn't race against
+/// the startup.
+///
+/// If the process isn't ready within `ready_timeout`, it's unlinked from the spawner and killed,
+/// and a [`ReadyTimeout`] error is returned. The spawner doesn't get a `LinkDied` signal for it.
+/// Without a timeout, or with one too large to compute a deadline from, it waits until the
+/// process is ready. An error is also returned if the process finishes before it's ready.
+#[allow(clippy::too_many_arguments)]
+pub async fn spawn_wasm_and_await_ready<S>(
+    runtime: WasmtimeRuntime,
+    module: WasmtimeCompiledModule<S>,
+    mut state: S,
+    function: &str,
+    params: Vec<Val>,
+    link: Option<(Option<i64>, Arc<dyn Process>)>,
+    init_message: Option<Message>,
+    ready_timeout: Option<Duration>,
+) -> Result<(JoinHandle<Result<S>>, Arc<dyn Process>)>
+where
+    S: ProcessState + Send + ResourceLimiter + 'static,
+{
+    let (notifier, ready) = bounded(1);
+    state.set_ready_notifier(notifier);
+    let spawner = link.as_ref().map(|(_, spawner)| spawner.clone());
+    let (mut join, process) =
+        spawn_wasm(runtime, module, state, function, params, link, init_message).await?;
+    let ready_timeout =
+        ready_timeout.filter(|&timeout| Instant::now().checked_add(timeout).is_some());
+    let timed_out = async {
+        match ready_timeout {
+            Some(timeout) => executor::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        biased;
+        Ok(()) = ready.recv() => Ok((join, process)),
+        result = &mut join => match result {
+            Ok(_) => Err(anyhow!("Process finished before it was ready")),
+            Err(error) => Err(error.context("Process failed before it was ready")),
+        },
+        _ = timed_out => {
+            // Signals are handled in order, the link is gone before the process dies.
+            if let Some(spawner) = spawner {
+                process.send(Signal::UnLink(spawner.clone()));
+                spawner.send(Signal::UnLink(process.clone()));
+            }
+            process.send(Signal::Kill(Duration::ZERO));
+            Err(ReadyTimeout(ready_timeout.unwrap_or_default()).into())
+        }
+    }
+}
+
+/// Error of [`spawn_wasm_and_await_ready`] if the process wasn't ready in time, to tell it apart
+/// from a process failing during its startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadyTimeout(pub Duration);
+
+impl std::fmt::Display for ReadyTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Process wasn't ready after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ReadyTimeout {}
+
+/// Spawns a detached process from a compiled module.
+///
+/// Unlike a process spawned by [`spawn_wasm`] without a link, a detached process can't be linked
+/// afterwards either. `Link` and `LinkDied` signals are ignored by it, so the death of the
+/// spawner or any other process never takes it down and its own death is never reported to
+/// anyone. It keeps running until it finishes or is killed directly.
+pub async fn spawn_wasm_detached<S>(
+    runtime: WasmtimeRuntime,
+    module: WasmtimeCompiledModule<S>,
+    state: S,
+    function: &str,
+    params: Vec<Val>,
+    init_message: Option<Message>,
+) -> Result<(JoinHandle<Result<S>>, Arc<dyn Process>)>
+where
+    S: ProcessState + Send + ResourceLimiter + 'static,
+{
+    // Handled before the process starts running, like the link in `spawn_wasm`.
+    state
+        .signal_mailbox()
+        .0
+        .try_send(Signal::Detach)
+        .expect("receiver must exist at this point");
+    spawn_wasm(runtime, module, state, function, params, None, init_message).await
+}
@@ -0,0 +1,46 @@
+//! Installs the global executor, so it runs in its own test binary and doesn't affect the unit
+//! tests.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use lunatic_process::executor::{self, AsyncStdExecutor, BoxFuture, Executor};
+
+// Counts the tasks spawned onto it.
+#[derive(Clone, Default)]
+struct CountingExecutor {
+    spawned: Arc<AtomicUsize>,
+}
+
+impl Executor for CountingExecutor {
+    fn spawn(&self, future: BoxFuture) {
+        self.spawned.fetch_add(1, Ordering::SeqCst);
+        AsyncStdExecutor.spawn(future);
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        self.spawned.fetch_add(1, Ordering::SeqCst);
+        AsyncStdExecutor.spawn_blocking(f);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture {
+        AsyncStdExecutor.sleep(duration)
+    }
+}
+
+#[async_std::test]
+async fn tasks_run_on_installed_executor() {
+    let installed = CountingExecutor::default();
+    let spawned = installed.spawned.clone();
+    assert!(executor::set_executor(installed).is_ok());
+    assert!(executor::set_executor(AsyncStdExecutor).is_err());
+
+    assert_eq!(executor::spawn(async { 42 }).await, 42);
+    assert_eq!(executor::unblock(|| 43).await, 43);
+    assert_eq!(spawned.load(Ordering::SeqCst), 2);
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use lunatic_common_api::{get_memory, IntoTrap};
+use lunatic_process::fuel::{charge_host_call, HostCallCategory};
 use lunatic_process::state::ProcessState;
 use lunatic_process_api::ProcessCtx;
 use wasmtime::Trap;
@@ -24,6 +25,7 @@ fn put<T: ProcessState + ProcessCtx<T>>(
     name_str_len: u32,
     process_id: u64,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Registry)?;
     let process = caller
         .data_mut()
         .process_resources_mut()
@@ -53,6 +55,7 @@ fn get<T: ProcessState + ProcessCtx<T>>(
     name_str_len: u32,
     process_id_ptr: u32,
 ) -> Result<u32, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Registry)?;
     let memory = get_memory(&mut caller)?;
     let (memory_slice, state) = memory.data_and_store_mut(&mut caller);
     let name = memory_slice
@@ -87,6 +90,7 @@ fn remove<T: ProcessState + ProcessCtx<T>>(
     name_str_ptr: u32,
     name_str_len: u32,
 ) -> Result<(), Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Registry)?;
     let memory = get_memory(&mut caller)?;
     let (memory_slice, state) = memory.data_and_store_mut(&mut caller);
     let name = memory_slice
@@ -1,10 +1,15 @@
 use std::{
     any::Any,
+    collections::VecDeque,
     fmt::{Display, Formatter},
     io::{Cursor, IoSlice, IoSliceMut, SeekFrom, Write},
+    pin::Pin,
     sync::{Arc, Mutex, RwLock},
+    task::{Context, Poll, Waker},
 };
 
+use futures_core::Stream as FuturesStream;
+
 use wasi_common::{
     file::{Advice, FdFlags, FileType, Filestat},
     Error, ErrorExt, SystemTimeSpec, WasiFile,
@@ -13,19 +18,223 @@ use wasi_common::{
 // This signature looks scary, but it just means that the vector holding all output streams
 // is rarely extended and often accessed (`RwLock`). The `Mutex` is necessary to allow
 // parallel writes for independent processes, it doesn't have any contention.
-type StdOutVec = Arc<RwLock<Vec<Mutex<Cursor<Vec<u8>>>>>>;
+type StdOutVec = Arc<RwLock<Vec<Stream>>>;
+
+/// Standard stream a write to a capture came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamKind {
+    Stdout,
+    Stderr,
+}
+
+impl Display for StreamKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            StreamKind::Stdout => write!(f, "stdout"),
+            StreamKind::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// Output of one standard stream, see [`StdoutCapture::records`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputRecord {
+    pub kind: StreamKind,
+    pub content: String,
+}
+
+#[derive(Debug)]
+struct Stream {
+    // Name of the process writing to the stream
+    label: Option<String>,
+    buffer: Mutex<Buffer>,
+    // Subscribers receiving the output as it's written
+    subscriptions: Mutex<Subscriptions>,
+}
+
+#[derive(Debug, Default)]
+struct Buffer {
+    content: Cursor<Vec<u8>>,
+    // Kind and start of each run of writes from the same standard stream, in the order of
+    // `content`.
+    records: Vec<(StreamKind, usize)>,
+}
+
+impl Buffer {
+    fn is_empty(&self) -> bool {
+        self.content.get_ref().is_empty()
+    }
+
+    // Tags the content written since `start` with `kind`.
+    fn tag(&mut self, kind: StreamKind, start: usize) {
+        if start == self.content.get_ref().len() {
+            return;
+        }
+        match self.records.last() {
+            Some(&(last, _)) if last == kind => {}
+            _ => self.records.push((kind, start)),
+        }
+    }
+
+    fn records(&self) -> Vec<OutputRecord> {
+        let content = self.content.get_ref();
+        self.records
+            .iter()
+            .enumerate()
+            .map(|(i, &(kind, start))| {
+                let end = self
+                    .records
+                    .get(i + 1)
+                    .map_or(content.len(), |&(_, end)| end);
+                OutputRecord {
+                    kind,
+                    content: String::from_utf8_lossy(&content[start..end]).to_string(),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Stream {
+    fn new() -> Self {
+        Self {
+            label: None,
+            buffer: Mutex::new(Buffer::default()),
+            subscriptions: Mutex::new(Subscriptions::default()),
+        }
+    }
+
+    // Needs to be called while holding the lock on `buffer`, so that the subscribers see the
+    // output in the same order as the buffer.
+    fn publish(&self, chunk: &[u8]) {
+        if chunk.is_empty() {
+            return;
+        }
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        // Forget subscriptions whose `OutputStream` was dropped.
+        subscriptions
+            .list
+            .retain(|subscription| Arc::strong_count(subscription) > 1);
+        for subscription in subscriptions.list.iter() {
+            subscription.lock().unwrap().push(chunk);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Subscriptions {
+    list: Vec<Arc<Mutex<Subscription>>>,
+    closed: bool,
+}
+
+// Output buffered for one `OutputStream`.
+#[derive(Debug)]
+struct Subscription {
+    chunks: VecDeque<Vec<u8>>,
+    // Sum of the chunk lengths, at most `capacity`
+    buffered: usize,
+    capacity: usize,
+    dropped: u64,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl Subscription {
+    fn push(&mut self, chunk: &[u8]) {
+        self.chunks.push_back(chunk.to_vec());
+        self.buffered += chunk.len();
+        // The writing process never waits on a slow consumer, the oldest output is dropped
+        // instead.
+        while self.buffered > self.capacity {
+            let excess = self.buffered - self.capacity;
+            let oldest = self
+                .chunks
+                .front_mut()
+                .expect("buffered bytes are in chunks");
+            let dropped = if oldest.len() <= excess {
+                self.chunks.pop_front().unwrap().len()
+            } else {
+                oldest.drain(..excess);
+                excess
+            };
+            self.buffered -= dropped;
+            self.dropped += dropped as u64;
+        }
+        self.wake();
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        self.wake();
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Output of a captured stream, yielding chunks as they are written.
+///
+/// The stream ends with `None` once the process writing to it exited and all output was
+/// consumed. While the process is running and there is no new output, it's just pending.
+///
+/// At most `capacity` bytes are buffered for a consumer that can't keep up, older output is
+/// dropped. [`OutputStream::dropped_bytes`] returns how much was lost this way.
+#[derive(Debug)]
+pub struct OutputStream {
+    subscription: Arc<Mutex<Subscription>>,
+}
+
+impl OutputStream {
+    /// Returns the number of bytes that were dropped, because the consumer was too slow.
+    pub fn dropped_bytes(&self) -> u64 {
+        self.subscription.lock().unwrap().dropped
+    }
+}
+
+impl FuturesStream for OutputStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        let mut subscription = self.subscription.lock().unwrap();
+        if let Some(chunk) = subscription.chunks.pop_front() {
+            subscription.buffered -= chunk.len();
+            Poll::Ready(Some(chunk))
+        } else if subscription.closed {
+            Poll::Ready(None)
+        } else {
+            subscription.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
 
 /// `StdoutCapture` holds the standard output from multiple processes.
 ///
 /// The most common pattern of usage is to capture together the output from a starting process
 /// and all sub-processes. E.g. Hide output of sub-processes during testing.
+///
+/// Each write is tagged with the standard stream it came from, the kind of the `StdoutCapture`
+/// it went through (see [`StdoutCapture::records`]). Stdout and stderr can be captured together,
+/// by giving stderr a handle to the same streams created with [`StdoutCapture::with_kind`], or
+/// separately by giving each of them its own `StdoutCapture`, created with
+/// [`StdoutCapture::new`] and [`StdoutCapture::new_stderr`]. Streams of sub-processes keep the
+/// kind of the parent's handle they were derived from.
+///
+/// Output isn't buffered, every write is visible in the stream's content right away. Flushing it
+/// from the guest with `fd_sync` always succeeds and has nothing left to do.
 #[derive(Clone, Debug)]
 pub struct StdoutCapture {
     writers: StdOutVec,
     // Index of the stdout currently in use by a process
     index: usize,
+    // Tag of the writes through this handle
+    kind: StreamKind,
 }
 
+// Handles are equal if they write to the same stream, whatever their kind is.
 impl PartialEq for StdoutCapture {
     fn eq(&self, other: &Self) -> bool {
         Arc::ptr_eq(&self.writers, &other.writers) && self.index == other.index
@@ -47,10 +256,17 @@ impl Display for StdoutCapture {
             write!(f, "{}", self.content()).unwrap();
         } else {
             for (i, stream) in streams.iter().enumerate() {
-                writeln!(f, " --- process {} stdout ---", i).unwrap();
-                let stream = stream.lock().unwrap();
-                let content = String::from_utf8_lossy(stream.get_ref()).to_string();
-                write!(f, "{}", content).unwrap();
+                let records = stream.buffer.lock().unwrap().records();
+                for record in records {
+                    match &stream.label {
+                        Some(label) => {
+                            writeln!(f, " --- process {} ({}) {} ---", i, label, record.kind)
+                                .unwrap()
+                        }
+                        None => writeln!(f, " --- process {} {} ---", i, record.kind).unwrap(),
+                    }
+                    write!(f, "{}", record.content).unwrap();
+                }
             }
         }
         Ok(())
@@ -58,54 +274,131 @@ impl Display for StdoutCapture {
 }
 
 impl StdoutCapture {
-    // Create a new `StdoutCapture` with one stream inside.
+    // Create a new `StdoutCapture` with one stdout stream inside.
     pub fn new() -> Self {
+        Self::new_with_kind(StreamKind::Stdout)
+    }
+
+    // Create a new `StdoutCapture` with one stderr stream inside.
+    pub fn new_stderr() -> Self {
+        Self::new_with_kind(StreamKind::Stderr)
+    }
+
+    fn new_with_kind(kind: StreamKind) -> Self {
         Self {
-            writers: Arc::new(RwLock::new(vec![Mutex::new(Cursor::new(Vec::new()))])),
+            writers: Arc::new(RwLock::new(vec![Stream::new()])),
             index: 0,
+            kind,
+        }
+    }
+
+    /// Returns a handle to the same stream, tagging its writes with `kind`.
+    pub fn with_kind(&self, kind: StreamKind) -> Self {
+        Self {
+            writers: self.writers.clone(),
+            index: self.index,
+            kind,
         }
     }
 
+    /// Returns the kind writes through this handle are tagged with.
+    pub fn kind(&self) -> StreamKind {
+        self.kind
+    }
+
     /// Returns `true` if this is the only reference to the outputs.
     pub fn only_reference(&self) -> bool {
         Arc::strong_count(&self.writers) == 1
     }
 
+    /// Sets the process name shown in front of the stream's content.
+    pub fn set_label(&self, label: Option<String>) {
+        let mut streams = RwLock::write(&self.writers).unwrap();
+        streams[self.index].label = label;
+    }
+
     /// Returns a clone of `StdoutCapture` pointing to the next stream
     pub fn next(&self) -> Self {
         let index = {
             let mut writers = RwLock::write(&self.writers).unwrap();
-            // If the stream already exists don't add a new one, e.g. stdout & stderr share the same stream.
-            writers.push(Mutex::new(Cursor::new(Vec::new())));
+            writers.push(Stream::new());
             writers.len() - 1
         };
         Self {
             writers: self.writers.clone(),
             index,
+            kind: self.kind,
         }
     }
 
     /// Returns true if all streams are empty
     pub fn is_empty(&self) -> bool {
         let streams = RwLock::read(&self.writers).unwrap();
-        streams.iter().all(|stream| {
-            let stream = stream.lock().unwrap();
-            stream.get_ref().is_empty()
-        })
+        streams
+            .iter()
+            .all(|stream| stream.buffer.lock().unwrap().is_empty())
     }
 
     /// Returns stream's content
     pub fn content(&self) -> String {
         let streams = RwLock::read(&self.writers).unwrap();
-        let stream = streams[self.index].lock().unwrap();
-        String::from_utf8_lossy(stream.get_ref()).to_string()
+        let stream = streams[self.index].buffer.lock().unwrap();
+        String::from_utf8_lossy(stream.content.get_ref()).to_string()
+    }
+
+    /// Returns the stream's content split into the runs of writes from the same standard stream.
+    pub fn records(&self) -> Vec<OutputRecord> {
+        let streams = RwLock::read(&self.writers).unwrap();
+        let buffer = streams[self.index].buffer.lock().unwrap();
+        buffer.records()
     }
 
     /// Add string to end of the stream
     pub fn push_str(&self, content: &str) {
         let streams = RwLock::read(&self.writers).unwrap();
-        let mut stream = streams[self.index].lock().unwrap();
-        write!(stream, "{}", content).unwrap();
+        let stream = &streams[self.index];
+        let mut buffer = stream.buffer.lock().unwrap();
+        let start = buffer.content.get_ref().len();
+        write!(buffer.content, "{}", content).unwrap();
+        buffer.tag(self.kind, start);
+        stream.publish(content.as_bytes());
+    }
+
+    /// Returns the stream's output as an async stream of chunks, buffering at most `capacity`
+    /// bytes for a slow consumer.
+    ///
+    /// The content written so far is yielded first, followed by all new output.
+    pub fn subscribe(&self, capacity: usize) -> OutputStream {
+        let streams = RwLock::read(&self.writers).unwrap();
+        let stream = &streams[self.index];
+        let buffer = stream.buffer.lock().unwrap();
+        let mut subscriptions = stream.subscriptions.lock().unwrap();
+        let mut subscription = Subscription {
+            chunks: VecDeque::new(),
+            buffered: 0,
+            capacity,
+            dropped: 0,
+            closed: subscriptions.closed,
+            waker: None,
+        };
+        if !buffer.is_empty() {
+            subscription.push(buffer.content.get_ref());
+        }
+        let subscription = Arc::new(Mutex::new(subscription));
+        subscriptions.list.push(subscription.clone());
+        OutputStream { subscription }
+    }
+
+    /// Marks the stream as finished, because the process writing to it exited.
+    ///
+    /// Subscribers end after they consumed the remaining output. The content is kept.
+    pub fn close(&self) {
+        let streams = RwLock::read(&self.writers).unwrap();
+        let mut subscriptions = streams[self.index].subscriptions.lock().unwrap();
+        subscriptions.closed = true;
+        for subscription in subscriptions.list.drain(..) {
+            subscription.lock().unwrap().close();
+        }
     }
 }
 
@@ -114,6 +407,7 @@ impl WasiFile for StdoutCapture {
     fn as_any(&self) -> &dyn Any {
         self
     }
+    // Writes are unbuffered, so there is nothing to flush.
     async fn datasync(&mut self) -> Result<(), Error> {
         Ok(())
     }
@@ -162,8 +456,12 @@ impl WasiFile for StdoutCapture {
     }
     async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
         let streams = RwLock::read(&self.writers).unwrap();
-        let mut stream = streams[self.index].lock().unwrap();
-        let n = stream.write_vectored(bufs)?;
+        let stream = &streams[self.index];
+        let mut buffer = stream.buffer.lock().unwrap();
+        let start = buffer.content.get_ref().len();
+        let n = buffer.content.write_vectored(bufs)?;
+        buffer.tag(self.kind, start);
+        stream.publish(&buffer.content.get_ref()[start..]);
         Ok(n.try_into()?)
     }
     async fn write_vectored_at<'a>(
@@ -203,3 +501,75 @@ impl WasiFile for StdoutCapture {
         Err(Error::badf())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use async_std::stream::StreamExt;
+
+    use super::{OutputRecord, StdoutCapture, StreamKind};
+
+    #[async_std::test]
+    async fn subscribers_receive_output_until_closed() {
+        let capture = StdoutCapture::new();
+        capture.push_str("before ");
+        let mut output = capture.subscribe(1024);
+        let mut slow = capture.subscribe(4);
+        capture.push_str("after");
+        capture.close();
+
+        assert_eq!(output.next().await, Some(b"before ".to_vec()));
+        assert_eq!(output.next().await, Some(b"after".to_vec()));
+        assert_eq!(output.next().await, None);
+        // Only the last 4 bytes fit into the buffer of the slow consumer.
+        assert_eq!(slow.next().await, Some(b"fter".to_vec()));
+        assert_eq!(slow.next().await, None);
+        assert_eq!(slow.dropped_bytes(), 8);
+        assert_eq!(capture.content(), "before after");
+    }
+
+    #[test]
+    fn writes_are_tagged_with_their_stream() {
+        let stdout = StdoutCapture::new();
+        let stderr = stdout.with_kind(StreamKind::Stderr);
+        assert_eq!(stdout, stderr);
+        stdout.push_str("a");
+        stdout.push_str("b");
+        stderr.push_str("error");
+        stdout.push_str("c");
+        stderr.push_str("");
+        let record = |kind, content: &str| OutputRecord {
+            kind,
+            content: content.to_owned(),
+        };
+        assert_eq!(
+            stdout.records(),
+            vec![
+                record(StreamKind::Stdout, "ab"),
+                record(StreamKind::Stderr, "error"),
+                record(StreamKind::Stdout, "c"),
+            ]
+        );
+        assert_eq!(stdout.content(), "aberrorc");
+
+        // Streams of sub-processes keep the kind of the handle they were derived from.
+        let child = stderr.next();
+        assert_eq!(child.kind(), StreamKind::Stderr);
+        child.push_str("child");
+        assert_eq!(child.records(), vec![record(StreamKind::Stderr, "child")]);
+        assert_eq!(
+            stdout.to_string(),
+            " --- process 0 stdout ---\nab --- process 0 stderr ---\nerror --- process 0 stdout ---\nc --- process 1 stderr ---\nchild"
+        );
+    }
+
+    #[test]
+    fn separate_captures_keep_their_kind() {
+        let (stdout, stderr) = (StdoutCapture::new(), StdoutCapture::new_stderr());
+        assert_ne!(stdout, stderr);
+        stdout.push_str("out");
+        stderr.push_str("err");
+        assert_eq!(stdout.records()[0].kind, StreamKind::Stdout);
+        assert_eq!(stderr.records()[0].kind, StreamKind::Stderr);
+        assert_eq!(stderr.content(), "err");
+    }
+}
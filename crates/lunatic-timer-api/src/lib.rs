@@ -1,3 +1,5 @@
+pub mod test_timers;
+
 use std::{
     cmp::Ordering,
     collections::BinaryHeap,
@@ -9,8 +11,13 @@ use anyhow::Result;
 use async_std::task::JoinHandle;
 use hash_map_id::HashMapId;
 use lunatic_common_api::IntoTrap;
-use lunatic_process::{state::ProcessState, Signal};
+use lunatic_process::{
+    fuel::{charge_host_call, HostCallCategory},
+    process_ref::ProcessRef,
+    state::ProcessState,
+};
 use lunatic_process_api::ProcessCtx;
+use test_timers::TestTimers;
 use wasmtime::{Caller, Linker, Trap};
 
 #[derive(Debug)]
@@ -39,17 +46,35 @@ impl PartialEq for HeapValue {
 
 impl Eq for HeapValue {}
 
+#[derive(Debug)]
+pub enum Timer {
+    // Timer task waiting on the wall-clock
+    Task(JoinHandle<()>),
+    // ID of the timer inside `TestTimers`
+    Test(u64),
+}
+
 #[derive(Debug, Default)]
 pub struct TimerResources {
-    hash_map: HashMapId<JoinHandle<()>>,
+    hash_map: HashMapId<Timer>,
     heap: BinaryHeap<HeapValue>,
+    test_timers: Option<TestTimers>,
 }
 
 impl TimerResources {
+    /// Schedules new timers of the process on the virtual clock of `test_timers`.
+    pub fn set_test_timers(&mut self, test_timers: Option<TestTimers>) {
+        self.test_timers = test_timers;
+    }
+
+    pub fn test_timers(&self) -> Option<&TestTimers> {
+        self.test_timers.as_ref()
+    }
+
     pub fn add(&mut self, handle: JoinHandle<()>, target_time: Instant) -> u64 {
         self.cleanup_expired_timers();
 
-        let id = self.hash_map.add(handle);
+        let id = self.hash_map.add(Timer::Task(handle));
         self.heap.push(HeapValue {
             instant: target_time,
             key: id,
@@ -74,9 +99,61 @@ impl TimerResources {
         }
     }
 
-    pub fn remove(&mut self, id: u64) -> Option<JoinHandle<()>> {
+    // Test timers are not tracked in the heap, they are removed on cancel or when the
+    // resources are dropped.
+    pub fn add_test(&mut self, test_id: u64) -> u64 {
+        self.hash_map.add(Timer::Test(test_id))
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Timer> {
         self.hash_map.remove(id)
     }
+
+    /// Removes the timer and stops it from firing.
+    ///
+    /// Returns `false` if no timer with this ID exists or it already fired.
+    pub async fn cancel(&mut self, id: u64) -> bool {
+        match self.remove(id) {
+            Some(Timer::Task(timer_handle)) => {
+                timer_handle.cancel().await;
+                true
+            }
+            Some(Timer::Test(test_id)) => self
+                .test_timers()
+                .map(|test_timers| test_timers.cancel(test_id))
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Returns the IDs of timers that could still fire. Test timers are always considered pending.
+    pub fn pending_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        let now = Instant::now();
+        let tasks = self
+            .heap
+            .iter()
+            .filter(move |timer| timer.instant > now && self.hash_map.get(timer.key).is_some())
+            .map(|timer| timer.key);
+        let tests = self
+            .hash_map
+            .ids()
+            .filter(|id| matches!(self.hash_map.get(*id), Some(Timer::Test(_))));
+        tasks.chain(tests)
+    }
+
+    /// Returns `true` if a timer could still fire. Test timers are always considered pending.
+    pub fn has_pending(&self) -> bool {
+        let now = Instant::now();
+        let waiting = self
+            .heap
+            .iter()
+            .any(|timer| timer.instant > now && self.hash_map.get(timer.key).is_some());
+        waiting
+            || self
+                .hash_map
+                .values()
+                .any(|timer| matches!(timer, Timer::Test(_)))
+    }
 }
 
 pub trait TimerCtx {
@@ -104,6 +181,7 @@ fn send_after<T: ProcessState + ProcessCtx<T> + TimerCtx>(
     process_id: u64,
     delay: u64,
 ) -> Result<u64, Trap> {
+    charge_host_call(&mut caller, HostCallCategory::Timer)?;
     let message = caller
         .data_mut()
         .message_scratch_area()
@@ -113,16 +191,26 @@ fn send_after<T: ProcessState + ProcessCtx<T> + TimerCtx>(
         .data_mut()
         .process_resources_mut()
         .get(process_id)
-        .or_trap("lunatic::message::send_after")?
-        .clone();
+        .or_trap("lunatic::message::send_after")?;
+    let process = ProcessRef::new(process.clone());
+
+    let delay = Duration::from_millis(delay);
+    if let Some(test_timers) = caller.data().timer_resources().test_timers() {
+        let test_id = test_timers.schedule(delay, move || process.send_message(message));
+        let id = caller.data_mut().timer_resources_mut().add_test(test_id);
+        return Ok(id);
+    }
 
-    let target_time = Instant::now() + Duration::from_millis(delay);
+    let target_time = Instant::now() + delay;
+    // The runtime isn't idle while the timer is pending, dropped if the timer is canceled.
+    let live = caller.data().runtime().track_task();
     let timer_handle = async_std::task::spawn(async move {
+        let _live = live;
         let duration_remaining = target_time - Instant::now();
         if duration_remaining != Duration::ZERO {
             async_std::task::sleep(duration_remaining).await;
         }
-        process.send(Signal::Message(message));
+        process.send_message(message);
     });
 
     let id = caller
@@ -145,13 +233,12 @@ fn cancel_timer<T: ProcessState + TimerCtx + Send>(
     timer_id: u64,
 ) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_> {
     Box::new(async move {
-        let timer_handle = caller.data_mut().timer_resources_mut().remove(timer_id);
-        match timer_handle {
-            Some(timer_handle) => {
-                timer_handle.cancel().await;
-                Ok(1)
-            }
-            None => Ok(0),
-        }
+        charge_host_call(&mut caller, HostCallCategory::Timer)?;
+        let canceled = caller
+            .data_mut()
+            .timer_resources_mut()
+            .cancel(timer_id)
+            .await;
+        Ok(canceled as u32)
     })
 }
@@ -0,0 +1,206 @@
+/*!
+Virtual time for testing code that depends on timers.
+
+By default timers are backed by the wall-clock and a test needs to actually wait for them to
+fire. If [`TestTimers`] are installed into the [`TimerResources`](crate::TimerResources) of a
+process, timers created by the process are scheduled on a virtual clock instead. Virtual time
+only moves forward when the test calls [`TestTimers::advance`], which synchronously fires all
+timers that became due, in order.
+
+Only processes that got the `TestTimers` installed use virtual time, sub-processes keep using
+the wall-clock.
+*/
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+type Action = Box<dyn FnMut() + Send>;
+
+/// A virtual clock driving timers.
+///
+/// Clones share the same clock and timers.
+#[derive(Clone, Default)]
+pub struct TestTimers {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Virtual time passed since the creation of the clock
+    now: Duration,
+    next_id: u64,
+    // Due time and ID of scheduled timers, timers due at the same time fire in creation order.
+    queue: BinaryHeap<Reverse<(Duration, u64)>>,
+    timers: HashMap<u64, Timer>,
+}
+
+struct Timer {
+    interval: Option<Duration>,
+    action: Action,
+}
+
+impl TestTimers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Virtual time passed since the creation of the clock.
+    pub fn now(&self) -> Duration {
+        self.inner.lock().expect("never poisoned").now
+    }
+
+    /// Number of timers that didn't fire or get canceled yet.
+    pub fn pending(&self) -> usize {
+        self.inner.lock().expect("never poisoned").timers.len()
+    }
+
+    /// Runs `action` once, after `delay` of virtual time passes.
+    pub fn schedule<F>(&self, delay: Duration, action: F) -> u64
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut action = Some(action);
+        let action = move || {
+            if let Some(action) = action.take() {
+                action()
+            }
+        };
+        self.insert(delay, None, Box::new(action))
+    }
+
+    /// Runs `action` every `interval` of virtual time, until the timer is canceled.
+    ///
+    /// ## Panics
+    ///
+    /// If the interval is zero.
+    pub fn schedule_interval<F>(&self, interval: Duration, action: F) -> u64
+    where
+        F: FnMut() + Send + 'static,
+    {
+        assert!(!interval.is_zero(), "interval can't be zero");
+        self.insert(interval, Some(interval), Box::new(action))
+    }
+
+    /// Cancels the timer. Returns `false` if the timer already fired or was canceled.
+    pub fn cancel(&self, id: u64) -> bool {
+        // The queue entry is skipped once it comes up.
+        let mut inner = self.inner.lock().expect("never poisoned");
+        inner.timers.remove(&id).is_some()
+    }
+
+    /// Moves virtual time forward and fires all timers that become due, in order.
+    ///
+    /// A recurring timer fires once for each interval that fits into the advanced time. Returns
+    /// the number of times timers fired.
+    pub fn advance(&self, by: Duration) -> usize {
+        let target = self.now() + by;
+        let mut fired = 0;
+        loop {
+            // Don't hold the lock while running the action, it may schedule new timers.
+            let (id, mut timer) = {
+                let mut inner = self.inner.lock().expect("never poisoned");
+                match inner.queue.peek() {
+                    Some(Reverse((due, _))) if *due <= target => {
+                        let Reverse((due, id)) = inner.queue.pop().expect("peeked");
+                        inner.now = due;
+                        match inner.timers.remove(&id) {
+                            Some(timer) => (id, timer),
+                            // Canceled
+                            None => continue,
+                        }
+                    }
+                    _ => {
+                        inner.now = target;
+                        return fired;
+                    }
+                }
+            };
+            (timer.action)();
+            fired += 1;
+            if let Some(interval) = timer.interval {
+                let mut inner = self.inner.lock().expect("never poisoned");
+                let due = inner.now + interval;
+                inner.queue.push(Reverse((due, id)));
+                inner.timers.insert(id, timer);
+            }
+        }
+    }
+
+    fn insert(&self, delay: Duration, interval: Option<Duration>, action: Action) -> u64 {
+        let mut inner = self.inner.lock().expect("never poisoned");
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let due = inner.now + delay;
+        inner.queue.push(Reverse((due, id)));
+        inner.timers.insert(id, Timer { interval, action });
+        id
+    }
+}
+
+impl Debug for TestTimers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.lock().expect("never poisoned");
+        f.debug_struct("TestTimers")
+            .field("now", &inner.now)
+            .field("pending", &inner.timers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::TestTimers;
+
+    #[test]
+    fn due_timers_fire_in_order() {
+        let timers = TestTimers::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for (name, delay) in [("c", 30), ("a", 10), ("b", 20), ("d", 40)] {
+            let log = log.clone();
+            timers.schedule(Duration::from_millis(delay), move || {
+                log.lock().unwrap().push(name)
+            });
+        }
+        assert_eq!(timers.advance(Duration::from_millis(5)), 0);
+        assert_eq!(timers.advance(Duration::from_millis(25)), 3);
+        assert_eq!(*log.lock().unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(timers.now(), Duration::from_millis(30));
+        assert_eq!(timers.pending(), 1);
+    }
+
+    #[test]
+    fn recurring_timer_fires_for_each_interval() {
+        let timers = TestTimers::new();
+        let count = Arc::new(Mutex::new(0));
+        let counter = count.clone();
+        let id = timers.schedule_interval(Duration::from_millis(100), move || {
+            *counter.lock().unwrap() += 1
+        });
+        // Jumps past multiple intervals at once.
+        assert_eq!(timers.advance(Duration::from_millis(350)), 3);
+        // The next firing is at 400ms, not 450ms.
+        assert_eq!(timers.advance(Duration::from_millis(50)), 1);
+        assert!(timers.cancel(id));
+        assert_eq!(timers.advance(Duration::from_secs(10)), 0);
+        assert_eq!(*count.lock().unwrap(), 4);
+    }
+
+    #[test]
+    fn canceled_timer_does_not_fire() {
+        let timers = TestTimers::new();
+        let id = timers.schedule(Duration::from_millis(10), || panic!("canceled"));
+        assert!(timers.cancel(id));
+        assert!(!timers.cancel(id));
+        assert_eq!(timers.advance(Duration::from_millis(20)), 0);
+    }
+}
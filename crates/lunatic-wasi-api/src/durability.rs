@@ -0,0 +1,283 @@
+/*!
+Durability of files written through WASI.
+
+Writes to files go directly to the OS, but the OS keeps them in its page cache and only writes
+them out to the disk later. A guest can force its data to the disk with `fd_sync` and
+`fd_datasync`, errors of those calls are returned to the guest. If a process is configured with
+[`WriteDurability::SyncOnClose`], the host also syncs each file that was opened for writing once
+the guest closes it.
+
+**Syncing is expensive.** Each sync waits for the disk to confirm the write, which takes from
+hundreds of microseconds on fast SSDs to tens of milliseconds on spinning disks or network
+storage. Guests closing many small files under `SyncOnClose` will be bound by the disk latency
+instead of the throughput. Guests that only need some of their files to be durable should stay
+with [`WriteDurability::Buffered`] and call `fd_sync` on them.
+
+Closing a file has no way to report errors back to the guest, a failed sync-on-close is only
+logged. The sync runs on a blocking thread of the executor after the file is closed, so closing
+doesn't block the process. Guests that need to know whether their data is durable must call `fd_sync` before
+closing the file. `SyncOnClose` is only supported on unix platforms.
+*/
+
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut, SeekFrom};
+use std::path::PathBuf;
+
+use log::warn;
+#[cfg(unix)]
+use lunatic_process::executor;
+use serde::{Deserialize, Serialize};
+use wasi_common::dir::{ReaddirCursor, ReaddirEntity};
+use wasi_common::file::{Advice, FdFlags, FileType, Filestat, OFlags};
+use wasi_common::{Error, SystemTimeSpec, WasiDir, WasiFile};
+
+/// How durable writes to files of a process are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteDurability {
+    /// Files are only synced when the guest asks for it.
+    #[default]
+    Buffered,
+    /// Files opened for writing are also synced when they are closed.
+    SyncOnClose,
+}
+
+/// A directory that wraps all files opened for writing in a [`SyncOnCloseFile`].
+pub struct SyncOnCloseDir {
+    inner: Box<dyn WasiDir>,
+}
+
+impl SyncOnCloseDir {
+    pub fn new(inner: Box<dyn WasiDir>) -> Self {
+        Self { inner }
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiDir for SyncOnCloseDir {
+    // Exposing the wrapped directory keeps `rename` and `hard_link` between directories working,
+    // they downcast the target directory.
+    fn as_any(&self) -> &dyn Any {
+        self.inner.as_any()
+    }
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        let file = self
+            .inner
+            .open_file(symlink_follow, path, oflags, read, write, fdflags)
+            .await?;
+        if write {
+            Ok(Box::new(SyncOnCloseFile { inner: file }))
+        } else {
+            Ok(file)
+        }
+    }
+    async fn open_dir(&self, symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        let dir = self.inner.open_dir(symlink_follow, path).await?;
+        Ok(Box::new(SyncOnCloseDir::new(dir)))
+    }
+    async fn create_dir(&self, path: &str) -> Result<(), Error> {
+        self.inner.create_dir(path).await
+    }
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        self.inner.readdir(cursor).await
+    }
+    async fn symlink(&self, old_path: &str, new_path: &str) -> Result<(), Error> {
+        self.inner.symlink(old_path, new_path).await
+    }
+    async fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        self.inner.remove_dir(path).await
+    }
+    async fn unlink_file(&self, path: &str) -> Result<(), Error> {
+        self.inner.unlink_file(path).await
+    }
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.inner.read_link(path).await
+    }
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.inner.get_filestat().await
+    }
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        self.inner.get_path_filestat(path, follow_symlinks).await
+    }
+    async fn rename(
+        &self,
+        path: &str,
+        dest_dir: &dyn WasiDir,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        self.inner.rename(path, dest_dir, dest_path).await
+    }
+    async fn hard_link(
+        &self,
+        path: &str,
+        target_dir: &dyn WasiDir,
+        target_path: &str,
+    ) -> Result<(), Error> {
+        self.inner.hard_link(path, target_dir, target_path).await
+    }
+    async fn set_times(
+        &self,
+        path: &str,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+        follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        self.inner
+            .set_times(path, atime, mtime, follow_symlinks)
+            .await
+    }
+}
+
+/// A file that is synced to the disk when it's dropped.
+pub struct SyncOnCloseFile {
+    inner: Box<dyn WasiFile>,
+}
+
+impl Drop for SyncOnCloseFile {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(fd) = self.inner.pollable() {
+            // The duplicate keeps the file open until it's synced.
+            let fd = match rustix::io::dup(fd) {
+                Ok(fd) => fd,
+                Err(error) => {
+                    warn!("Failed to sync file on close: {}", error);
+                    return;
+                }
+            };
+            executor::spawn_blocking(move || {
+                if let Err(error) = rustix::fs::fsync(&fd) {
+                    warn!("Failed to sync file on close: {}", error);
+                }
+            });
+        }
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for SyncOnCloseFile {
+    fn as_any(&self) -> &dyn Any {
+        self.inner.as_any()
+    }
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        self.inner.get_filetype().await
+    }
+    #[cfg(unix)]
+    fn pollable(&self) -> Option<rustix::fd::BorrowedFd<'_>> {
+        self.inner.pollable()
+    }
+    fn isatty(&mut self) -> bool {
+        self.inner.isatty()
+    }
+    async fn datasync(&mut self) -> Result<(), Error> {
+        self.inner.datasync().await
+    }
+    async fn sync(&mut self) -> Result<(), Error> {
+        self.inner.sync().await
+    }
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        self.inner.get_fdflags().await
+    }
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        self.inner.set_fdflags(flags).await
+    }
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        self.inner.get_filestat().await
+    }
+    async fn set_filestat_size(&mut self, size: u64) -> Result<(), Error> {
+        self.inner.set_filestat_size(size).await
+    }
+    async fn advise(&mut self, offset: u64, len: u64, advice: Advice) -> Result<(), Error> {
+        self.inner.advise(offset, len, advice).await
+    }
+    async fn allocate(&mut self, offset: u64, len: u64) -> Result<(), Error> {
+        self.inner.allocate(offset, len).await
+    }
+    async fn set_times(
+        &mut self,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        self.inner.set_times(atime, mtime).await
+    }
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        self.inner.read_vectored(bufs).await
+    }
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.inner.read_vectored_at(bufs, offset).await
+    }
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        self.inner.write_vectored(bufs).await
+    }
+    async fn write_vectored_at<'a>(
+        &mut self,
+        bufs: &[IoSlice<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.inner.write_vectored_at(bufs, offset).await
+    }
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        self.inner.seek(pos).await
+    }
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.inner.peek(buf).await
+    }
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        self.inner.num_ready_bytes().await
+    }
+    async fn readable(&self) -> Result<(), Error> {
+        self.inner.readable().await
+    }
+    async fn writable(&self) -> Result<(), Error> {
+        self.inner.writable().await
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::IoSlice;
+
+    use wasi_common::file::{FdFlags, OFlags};
+    use wasi_common::WasiDir;
+    use wasmtime_wasi::{ambient_authority, Dir};
+
+    use super::SyncOnCloseDir;
+
+    #[async_std::test]
+    async fn written_files_are_synced_on_close() {
+        let path = std::env::temp_dir().join(format!("lunatic-durability-{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        let dir = Dir::open_ambient_dir(&path, ambient_authority()).unwrap();
+        let dir = SyncOnCloseDir::new(Box::new(wasmtime_wasi::sync::dir::Dir::from_cap_std(dir)));
+
+        let mut file = dir
+            .open_file(false, "data", OFlags::CREATE, false, true, FdFlags::empty())
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"durable")])
+            .await
+            .unwrap();
+        // Closing doesn't wait on the sync, but the file stays intact.
+        drop(file);
+        assert_eq!(std::fs::read(path.join("data")).unwrap(), b"durable");
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -1,4 +1,10 @@
+pub mod durability;
+pub mod temp_dir;
+
+use std::path::Path;
+
 use anyhow::Result;
+use durability::{SyncOnCloseDir, WriteDurability};
 use lunatic_common_api::{get_memory, IntoTrap};
 use lunatic_process::state::ProcessState;
 use lunatic_stdout_capture::StdoutCapture;
@@ -10,6 +16,7 @@ pub fn build_wasi(
     args: Option<&Vec<String>>,
     envs: Option<&Vec<(String, String)>>,
     dirs: &[String],
+    durability: WriteDurability,
 ) -> Result<WasiCtx> {
     let mut wasi = WasiCtxBuilder::new().inherit_stdio();
     if let Some(envs) = envs {
@@ -18,17 +25,47 @@ pub fn build_wasi(
     if let Some(args) = args {
         wasi = wasi.args(args)?;
     }
+    if durability == WriteDurability::Buffered {
+        for preopen_dir_path in dirs {
+            let preopen_dir = Dir::open_ambient_dir(preopen_dir_path, ambient_authority())?;
+            wasi = wasi.preopened_dir(preopen_dir, preopen_dir_path)?;
+        }
+        return Ok(wasi.build());
+    }
+    let mut wasi = wasi.build();
     for preopen_dir_path in dirs {
         let preopen_dir = Dir::open_ambient_dir(preopen_dir_path, ambient_authority())?;
-        wasi = wasi.preopened_dir(preopen_dir, preopen_dir_path)?;
+        let preopen_dir = wasmtime_wasi::sync::dir::Dir::from_cap_std(preopen_dir);
+        let preopen_dir = SyncOnCloseDir::new(Box::new(preopen_dir));
+        wasi.push_preopened_dir(Box::new(preopen_dir), preopen_dir_path)?;
     }
-    Ok(wasi.build())
+    Ok(wasi)
+}
+
+/// Preopens the host directory `dir` under the path `guest_path` of the guest.
+pub fn preopen_dir_at(
+    wasi: &mut WasiCtx,
+    dir: &Path,
+    guest_path: &str,
+    durability: WriteDurability,
+) -> Result<()> {
+    let dir = Dir::open_ambient_dir(dir, ambient_authority())?;
+    let dir = wasmtime_wasi::sync::dir::Dir::from_cap_std(dir);
+    if durability == WriteDurability::Buffered {
+        wasi.push_preopened_dir(Box::new(dir), guest_path)?;
+    } else {
+        let dir = SyncOnCloseDir::new(Box::new(dir));
+        wasi.push_preopened_dir(Box::new(dir), guest_path)?;
+    }
+    Ok(())
 }
 
 pub trait LunaticWasiConfigCtx {
     fn add_environment_variable(&mut self, key: String, value: String);
     fn add_command_line_argument(&mut self, argument: String);
     fn preopen_dir(&mut self, dir: String);
+    fn write_durability(&self) -> WriteDurability;
+    fn set_write_durability(&mut self, durability: WriteDurability);
 }
 
 pub trait LunaticWasiCtx {
@@ -64,6 +101,16 @@ where
         add_command_line_argument,
     )?;
     linker.func_wrap("lunatic::wasi", "config_preopen_dir", preopen_dir)?;
+    linker.func_wrap(
+        "lunatic::wasi",
+        "config_set_write_durability",
+        set_write_durability,
+    )?;
+    linker.func_wrap(
+        "lunatic::wasi",
+        "config_get_write_durability",
+        get_write_durability,
+    )?;
 
     Ok(())
 }
@@ -178,3 +225,62 @@ where
         .preopen_dir(dir);
     Ok(())
 }
+
+// Sets the durability of file writes for processes spawned from this configuration.
+//
+// Durability values:
+// * 0 - Buffered, files are only synced when the guest calls `fd_sync` or `fd_datasync`
+// * 1 - Sync on close, files opened for writing are also synced when they are closed
+//
+// Syncing files is expensive, see the `durability` module for details.
+//
+// Traps:
+// * If the config ID doesn't exist.
+// * If the durability value is unknown.
+fn set_write_durability<T>(
+    mut caller: Caller<T>,
+    config_id: u64,
+    durability: u32,
+) -> Result<(), Trap>
+where
+    T: ProcessState,
+    T::Config: LunaticWasiConfigCtx,
+{
+    let durability = match durability {
+        0 => WriteDurability::Buffered,
+        1 => WriteDurability::SyncOnClose,
+        _ => {
+            return Err(Trap::new(
+                "lunatic::wasi::config_set_write_durability: Unknown durability",
+            ))
+        }
+    };
+    caller
+        .data_mut()
+        .config_resources_mut()
+        .get_mut(config_id)
+        .or_trap("lunatic::wasi::config_set_write_durability: Config ID doesn't exist")?
+        .set_write_durability(durability);
+    Ok(())
+}
+
+// Returns the durability of file writes of a configuration, see `config_set_write_durability`.
+//
+// Traps:
+// * If the config ID doesn't exist.
+fn get_write_durability<T>(caller: Caller<T>, config_id: u64) -> Result<u32, Trap>
+where
+    T: ProcessState,
+    T::Config: LunaticWasiConfigCtx,
+{
+    let durability = caller
+        .data()
+        .config_resources()
+        .get(config_id)
+        .or_trap("lunatic::wasi::config_get_write_durability: Config ID doesn't exist")?
+        .write_durability();
+    Ok(match durability {
+        WriteDurability::Buffered => 0,
+        WriteDurability::SyncOnClose => 1,
+    })
+}
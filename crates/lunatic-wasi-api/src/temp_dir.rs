@@ -0,0 +1,57 @@
+/*!
+Private scratch directories of processes.
+
+A process configured with a temp directory gets its own, empty directory on the host, preopened
+into its WASI context under a fixed guest path (e.g. `/tmp`). The directory is created inside of
+the host's temp directory and named after the process ID, so processes never see each other's
+files or the rest of the host's temp directory.
+
+The directory is removed together with everything inside of it once the [`ProcessTempDir`] is
+dropped. It's owned by the process state, which is dropped when the process finishes, no matter
+if it returned, trapped or was killed. The removal can take a while for large directories, so it
+runs on a blocking thread of the executor and the directory might still exist for a moment after
+the drop.
+*/
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use log::warn;
+use lunatic_process::executor;
+
+/// Scratch directory on the host, removed on drop.
+#[derive(Debug)]
+pub struct ProcessTempDir {
+    path: PathBuf,
+}
+
+impl ProcessTempDir {
+    /// Creates the directory `lunatic-<process_id>` inside of the host's temp directory.
+    pub fn create(process_id: &str) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("lunatic-{}", process_id));
+        fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ProcessTempDir {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        executor::spawn_blocking(move || {
+            if let Err(error) = fs::remove_dir_all(&path) {
+                warn!(
+                    "Failed to remove temp directory {}: {}",
+                    path.display(),
+                    error
+                );
+            }
+        });
+    }
+}
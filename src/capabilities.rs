@@ -0,0 +1,184 @@
+/*!
+Capability manifests embedded in modules.
+
+A module can declare the capabilities it needs in a custom section called
+`lunatic.capabilities`, as a list of names separated by whitespace or commas:
+
+```text
+spawn_processes, filesystem
+```
+
+If manifests are enforced on the runtime (see
+[`WasmtimeRuntime::set_capability_manifests`](lunatic_process::runtimes::wasmtime::WasmtimeRuntime::set_capability_manifests)),
+processes spawned from a module with a manifest only keep the declared capabilities of their
+config. Spawning fails if the manifest asks for a capability the config doesn't grant. Modules
+without a manifest keep the whole config.
+*/
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+
+use anyhow::{anyhow, bail, Result};
+use lunatic_process::{config::ProcessConfig, runtimes::RawWasm};
+use lunatic_process_api::ProcessConfigCtx;
+
+use crate::DefaultProcessConfig;
+
+/// Name of the custom section holding the manifest.
+pub const MANIFEST_SECTION: &str = "lunatic.capabilities";
+
+/// A permission of a process that can be declared in a manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Capability {
+    CompileModules,
+    CreateConfigs,
+    SpawnProcesses,
+    /// Access to the preopened directories and the temp directory.
+    Filesystem,
+}
+
+impl Capability {
+    /// Parses the name used in manifests.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "compile_modules" => Some(Self::CompileModules),
+            "create_configs" => Some(Self::CreateConfigs),
+            "spawn_processes" => Some(Self::SpawnProcesses),
+            "filesystem" => Some(Self::Filesystem),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::CompileModules => "compile_modules",
+            Self::CreateConfigs => "create_configs",
+            Self::SpawnProcesses => "spawn_processes",
+            Self::Filesystem => "filesystem",
+        }
+    }
+
+    fn granted_by(self, config: &DefaultProcessConfig) -> bool {
+        match self {
+            Self::CompileModules => config.can_compile_modules(),
+            Self::CreateConfigs => config.can_create_configs(),
+            Self::SpawnProcesses => config.can_spawn_processes(),
+            Self::Filesystem => {
+                !config.preopened_dirs().is_empty() || config.get_temp_dir().is_some()
+            }
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The capabilities a module declares.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CapabilityManifest {
+    capabilities: BTreeSet<Capability>,
+}
+
+impl CapabilityManifest {
+    /// Parses the contents of the manifest section. Fails on unknown capabilities.
+    pub fn parse(manifest: &[u8]) -> Result<Self> {
+        let manifest = std::str::from_utf8(manifest)
+            .map_err(|_| anyhow!("Capability manifest is not valid UTF-8"))?;
+        let capabilities = manifest
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                Capability::from_name(name)
+                    .ok_or_else(|| anyhow!("Unknown capability `{}` in manifest", name))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { capabilities })
+    }
+
+    /// Reads the manifest of `module`, `None` if it doesn't have one.
+    pub fn from_module(module: &RawWasm) -> Result<Option<Self>> {
+        module
+            .custom_section(MANIFEST_SECTION)
+            .map(Self::parse)
+            .transpose()
+    }
+
+    pub fn capabilities(&self) -> impl Iterator<Item = Capability> + '_ {
+        self.capabilities.iter().copied()
+    }
+
+    /// Returns `config` restricted to the declared capabilities.
+    ///
+    /// Fails, listing the offending capabilities, if the manifest declares capabilities that
+    /// `config` doesn't grant.
+    pub fn restrict(&self, config: &DefaultProcessConfig) -> Result<DefaultProcessConfig> {
+        let forbidden: Vec<_> = self
+            .capabilities()
+            .filter(|capability| !capability.granted_by(config))
+            .map(Capability::name)
+            .collect();
+        if !forbidden.is_empty() {
+            bail!(
+                "Module requests capabilities the process config doesn't grant: {}",
+                forbidden.join(", ")
+            );
+        }
+        let declared = |capability| self.capabilities.contains(&capability);
+        let mut config = config.clone();
+        if !declared(Capability::CompileModules) {
+            config.set_can_compile_modules(false);
+        }
+        if !declared(Capability::CreateConfigs) {
+            config.set_can_create_configs(false);
+        }
+        if !declared(Capability::SpawnProcesses) {
+            config.set_can_spawn_processes(false);
+        }
+        if !declared(Capability::Filesystem) {
+            config.set_preopened_dirs(Vec::new());
+            config.set_temp_dir(None);
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lunatic_process_api::ProcessConfigCtx;
+
+    use super::{Capability, CapabilityManifest};
+    use crate::DefaultProcessConfig;
+
+    #[test]
+    fn manifest_restricts_config() {
+        let manifest = CapabilityManifest::parse(b"spawn_processes,\n filesystem").unwrap();
+        assert_eq!(
+            manifest.capabilities().collect::<Vec<_>>(),
+            [Capability::SpawnProcesses, Capability::Filesystem]
+        );
+        assert!(CapabilityManifest::parse(b"spawn_processes network").is_err());
+
+        let host = DefaultProcessConfig::builder()
+            .can_compile_modules(true)
+            .can_spawn_processes(true)
+            .preopen_dir("/data")
+            .build()
+            .unwrap();
+        let config = manifest.restrict(&host).unwrap();
+        assert!(config.can_spawn_processes());
+        assert!(!config.can_compile_modules());
+        assert_eq!(config.preopened_dirs(), ["/data"]);
+
+        let greedy = CapabilityManifest::parse(b"create_configs filesystem").unwrap();
+        let error = greedy
+            .restrict(&DefaultProcessConfig::default())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Module requests capabilities the process config doesn't grant: create_configs, filesystem"
+        );
+    }
+}
@@ -1,26 +1,61 @@
 use std::fmt::Debug;
+use std::time::Duration;
 
-use lunatic_process::config::ProcessConfig;
+use anyhow::{bail, Result};
+
+use lunatic_networking_api::bandwidth::BandwidthLimit;
+use lunatic_networking_api::{NetworkingConfigCtx, DEFAULT_MAX_TCP_LINGER, MAX_TCP_LINGER_SECS};
+use lunatic_process::config::{IdleReceiveTimeout, MailboxLimit, ProcessConfig};
+use lunatic_process::fuel::{HostCallCategory, HostCallCosts};
+use lunatic_process::random::RandomSource;
 use lunatic_process_api::ProcessConfigCtx;
+use lunatic_wasi_api::durability::WriteDurability;
 use lunatic_wasi_api::LunaticWasiConfigCtx;
 use serde::{Deserialize, Serialize};
 
+/// Configuration of processes.
+///
+/// Prefer creating it with [`DefaultProcessConfig::builder`], which validates the options. The
+/// setters are kept for changing an existing configuration, e.g. from host functions.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct DefaultProcessConfig {
     // Maximum amount of memory that can be used by processes in bytes
     max_memory: usize,
     // Maximum amount of compute expressed in units of 100k instructions.
     max_fuel: Option<u64>,
+    // Fuel charged for host calls, in instructions
+    host_call_costs: HostCallCosts,
+    // Longest time a process can stay blocked on a receive
+    idle_receive_timeout: Option<IdleReceiveTimeout>,
+    // Source of random values, seeded only for reproducible runs
+    random_source: RandomSource,
+    // Entry function used if the requested one doesn't exist
+    fallback_entry: Option<String>,
+    // Job checkpoints are saved under
+    job_id: Option<String>,
+    // Memory grown and touched on instantiation
+    prefault_memory: Option<usize>,
+    // Maximum number of queued messages
+    mailbox_limit: Option<MailboxLimit>,
     // Can this process compile new WebAssembly modules
     can_compile_modules: bool,
     // Can this process create new configurations
     can_create_configs: bool,
     // Can this process spawn sub-processes
     can_spawn_processes: bool,
+    // Throughput cap shared by all sockets of the process
+    bandwidth_limit: Option<BandwidthLimit>,
+    // Maximum number of open connections made with `tcp_connect`
+    max_outbound_connections: Option<usize>,
+    // Longest linger of TCP streams
+    max_tcp_linger: Duration,
     // WASI configs
     preopened_dirs: Vec<String>,
+    // Guest path of the private temp directory
+    temp_dir: Option<String>,
     command_line_arguments: Vec<String>,
     environment_variables: Vec<(String, String)>,
+    write_durability: WriteDurability,
 }
 
 impl Debug for DefaultProcessConfig {
@@ -28,9 +63,21 @@ impl Debug for DefaultProcessConfig {
         f.debug_struct("EnvConfig")
             .field("max_memory", &self.max_memory)
             .field("max_fuel", &self.max_fuel)
+            .field("host_call_costs", &self.host_call_costs)
+            .field("idle_receive_timeout", &self.idle_receive_timeout)
+            .field("random_source", &self.random_source)
+            .field("fallback_entry", &self.fallback_entry)
+            .field("job_id", &self.job_id)
+            .field("prefault_memory", &self.prefault_memory)
+            .field("mailbox_limit", &self.mailbox_limit)
+            .field("bandwidth_limit", &self.bandwidth_limit)
+            .field("max_outbound_connections", &self.max_outbound_connections)
+            .field("max_tcp_linger", &self.max_tcp_linger)
+            .field("temp_dir", &self.temp_dir)
             .field("preopened_dirs", &self.preopened_dirs)
             .field("args", &self.command_line_arguments)
             .field("envs", &self.environment_variables)
+            .field("write_durability", &self.write_durability)
             .finish()
     }
 }
@@ -51,6 +98,71 @@ impl ProcessConfig for DefaultProcessConfig {
     fn get_max_memory(&self) -> usize {
         self.max_memory
     }
+
+    fn set_host_call_cost(&mut self, category: HostCallCategory, cost: u64) {
+        self.host_call_costs.set(category, cost);
+    }
+
+    fn get_host_call_cost(&self, category: HostCallCategory) -> u64 {
+        self.host_call_costs.get(category)
+    }
+
+    fn set_idle_receive_timeout(&mut self, timeout: Option<IdleReceiveTimeout>) {
+        self.idle_receive_timeout = timeout;
+    }
+
+    fn get_idle_receive_timeout(&self) -> Option<IdleReceiveTimeout> {
+        self.idle_receive_timeout
+    }
+
+    fn set_random_source(&mut self, source: RandomSource) {
+        self.random_source = source;
+    }
+
+    fn get_random_source(&self) -> RandomSource {
+        self.random_source
+    }
+
+    fn set_fallback_entry(&mut self, entry: Option<String>) {
+        self.fallback_entry = entry;
+    }
+
+    fn get_fallback_entry(&self) -> Option<&str> {
+        self.fallback_entry.as_deref()
+    }
+
+    fn set_mailbox_limit(&mut self, limit: Option<MailboxLimit>) {
+        self.mailbox_limit = limit;
+    }
+
+    fn get_mailbox_limit(&self) -> Option<MailboxLimit> {
+        self.mailbox_limit
+    }
+
+    fn set_job_id(&mut self, job_id: Option<String>) {
+        self.job_id = job_id;
+    }
+
+    fn get_job_id(&self) -> Option<&str> {
+        self.job_id.as_deref()
+    }
+
+    fn set_prefault_memory(&mut self, size: Option<usize>) {
+        self.prefault_memory = size;
+    }
+
+    fn get_prefault_memory(&self) -> Option<usize> {
+        self.prefault_memory
+    }
+
+    /// See [`ProcessTempDir`](lunatic_wasi_api::temp_dir::ProcessTempDir).
+    fn set_temp_dir(&mut self, guest_path: Option<String>) {
+        self.temp_dir = guest_path;
+    }
+
+    fn get_temp_dir(&self) -> Option<&str> {
+        self.temp_dir.as_deref()
+    }
 }
 
 impl LunaticWasiConfigCtx for DefaultProcessConfig {
@@ -65,9 +177,48 @@ impl LunaticWasiConfigCtx for DefaultProcessConfig {
     fn preopen_dir(&mut self, dir: String) {
         self.preopened_dirs.push(dir);
     }
+
+    fn write_durability(&self) -> WriteDurability {
+        self.write_durability
+    }
+
+    fn set_write_durability(&mut self, durability: WriteDurability) {
+        self.write_durability = durability;
+    }
+}
+
+impl NetworkingConfigCtx for DefaultProcessConfig {
+    fn bandwidth_limit(&self) -> Option<BandwidthLimit> {
+        self.bandwidth_limit
+    }
+
+    fn set_bandwidth_limit(&mut self, limit: Option<BandwidthLimit>) {
+        self.bandwidth_limit = limit;
+    }
+
+    fn max_outbound_connections(&self) -> Option<usize> {
+        self.max_outbound_connections
+    }
+
+    fn set_max_outbound_connections(&mut self, max: Option<usize>) {
+        self.max_outbound_connections = max;
+    }
+
+    fn max_tcp_linger(&self) -> Duration {
+        self.max_tcp_linger
+    }
+
+    fn set_max_tcp_linger(&mut self, max: Duration) {
+        self.max_tcp_linger = max;
+    }
 }
 
 impl DefaultProcessConfig {
+    /// Returns a builder starting from the [default](ProcessConfigBuilder) options.
+    pub fn builder() -> ProcessConfigBuilder {
+        ProcessConfigBuilder::default()
+    }
+
     pub fn preopened_dirs(&self) -> &[String] {
         &self.preopened_dirs
     }
@@ -77,6 +228,10 @@ impl DefaultProcessConfig {
         self.preopened_dirs.push(dir.into())
     }
 
+    pub fn set_preopened_dirs(&mut self, dirs: Vec<String>) {
+        self.preopened_dirs = dirs;
+    }
+
     pub fn set_command_line_arguments(&mut self, args: Vec<String>) {
         self.command_line_arguments = args;
     }
@@ -125,12 +280,296 @@ impl Default for DefaultProcessConfig {
         Self {
             max_memory: u32::MAX as usize, // = 4 GB
             max_fuel: None,
+            host_call_costs: HostCallCosts::default(),
+            idle_receive_timeout: None,
+            random_source: RandomSource::default(),
+            fallback_entry: None,
+            job_id: None,
+            prefault_memory: None,
+            mailbox_limit: None,
             can_compile_modules: false,
             can_create_configs: false,
             can_spawn_processes: false,
+            bandwidth_limit: None,
+            max_outbound_connections: None,
+            max_tcp_linger: DEFAULT_MAX_TCP_LINGER,
             preopened_dirs: vec![],
+            temp_dir: None,
             command_line_arguments: vec![],
             environment_variables: vec![],
+            write_durability: WriteDurability::default(),
         }
     }
 }
+
+/// Smallest memory limit a process can run with, one WebAssembly page.
+pub const MIN_MEMORY: usize = 64 * 1024;
+
+/// Builder of [`DefaultProcessConfig`], checking the options in [`build`](Self::build).
+///
+/// Defaults:
+///
+/// | Option                     | Default                      |
+/// |----------------------------|------------------------------|
+/// | `max_memory`               | 4 GB                         |
+/// | `max_fuel`                 | unlimited                    |
+/// | `host_call_cost`           | 0 for all categories         |
+/// | `idle_receive_timeout`     | none                         |
+/// | `random_source`            | [`RandomSource::System`]     |
+/// | `fallback_entry`           | none                         |
+/// | `job_id`                   | none                         |
+/// | `prefault_memory`          | none                         |
+/// | `mailbox_limit`            | unlimited                    |
+/// | `can_compile_modules`      | `false`                      |
+/// | `can_create_configs`       | `false`                      |
+/// | `can_spawn_processes`      | `false`                      |
+/// | `bandwidth_limit`          | unlimited                    |
+/// | `max_outbound_connections` | unlimited                    |
+/// | `max_tcp_linger`           | 5 seconds                    |
+/// | `write_durability`         | [`WriteDurability::default`] |
+/// | `temp_dir`                 | none                         |
+/// | WASI dirs, args & envs     | empty                        |
+#[derive(Clone, Debug, Default)]
+pub struct ProcessConfigBuilder {
+    config: DefaultProcessConfig,
+}
+
+impl ProcessConfigBuilder {
+    /// Maximum amount of memory in bytes, at least [`MIN_MEMORY`].
+    pub fn max_memory(mut self, max_memory: usize) -> Self {
+        self.config.max_memory = max_memory;
+        self
+    }
+
+    /// Maximum amount of compute in units of 100k instructions, `None` for unlimited. Can't be 0.
+    pub fn max_fuel(mut self, max_fuel: Option<u64>) -> Self {
+        self.config.max_fuel = max_fuel;
+        self
+    }
+
+    pub fn host_call_cost(mut self, category: HostCallCategory, cost: u64) -> Self {
+        self.config.host_call_costs.set(category, cost);
+        self
+    }
+
+    /// Longest time a process can stay blocked on a receive. The timeout can't be zero.
+    pub fn idle_receive_timeout(mut self, timeout: Option<IdleReceiveTimeout>) -> Self {
+        self.config.idle_receive_timeout = timeout;
+        self
+    }
+
+    pub fn random_source(mut self, source: RandomSource) -> Self {
+        self.config.random_source = source;
+        self
+    }
+
+    /// Function to start processes with, if the one they are spawned with doesn't exist.
+    pub fn fallback_entry<S: Into<String>>(mut self, entry: Option<S>) -> Self {
+        self.config.fallback_entry = entry.map(Into::into);
+        self
+    }
+
+    /// Job the processes save their checkpoints under, so that a respawned process can resume.
+    /// It can't be empty.
+    pub fn job_id<S: Into<String>>(mut self, job_id: Option<S>) -> Self {
+        self.config.job_id = job_id.map(Into::into);
+        self
+    }
+
+    /// Grows the memory of processes to `size` bytes and touches it right after instantiation,
+    /// so they don't stall on page faults later. Can't be above `max_memory`.
+    pub fn prefault_memory(mut self, size: Option<usize>) -> Self {
+        self.config.prefault_memory = size;
+        self
+    }
+
+    /// Maximum number of queued messages and what to drop if it's reached. The capacity can't
+    /// be 0.
+    pub fn mailbox_limit(mut self, limit: Option<MailboxLimit>) -> Self {
+        self.config.mailbox_limit = limit;
+        self
+    }
+
+    pub fn can_compile_modules(mut self, can: bool) -> Self {
+        self.config.can_compile_modules = can;
+        self
+    }
+
+    pub fn can_create_configs(mut self, can: bool) -> Self {
+        self.config.can_create_configs = can;
+        self
+    }
+
+    pub fn can_spawn_processes(mut self, can: bool) -> Self {
+        self.config.can_spawn_processes = can;
+        self
+    }
+
+    pub fn bandwidth_limit(mut self, limit: Option<BandwidthLimit>) -> Self {
+        self.config.bandwidth_limit = limit;
+        self
+    }
+
+    /// Maximum number of outbound TCP connections a process can have open at the same time,
+    /// `None` for unlimited. Can't be 0.
+    pub fn max_outbound_connections(mut self, max: Option<usize>) -> Self {
+        self.config.max_outbound_connections = max;
+        self
+    }
+
+    /// Longest linger processes can set on TCP streams. Dropping a stream can block a host
+    /// thread for this long, the maximum depends on the platform.
+    pub fn max_tcp_linger(mut self, max: Duration) -> Self {
+        self.config.max_tcp_linger = max;
+        self
+    }
+
+    /// Grant access to the given directory.
+    pub fn preopen_dir<S: Into<String>>(mut self, dir: S) -> Self {
+        self.config.preopened_dirs.push(dir.into());
+        self
+    }
+
+    /// Gives each process an empty temp directory, preopened under `guest_path` (e.g. `/tmp`).
+    /// It's created on spawn and removed when the process finishes.
+    pub fn temp_dir<S: Into<String>>(mut self, guest_path: Option<S>) -> Self {
+        self.config.temp_dir = guest_path.map(Into::into);
+        self
+    }
+
+    pub fn command_line_arguments(mut self, args: Vec<String>) -> Self {
+        self.config.command_line_arguments = args;
+        self
+    }
+
+    pub fn environment_variables(mut self, envs: Vec<(String, String)>) -> Self {
+        self.config.environment_variables = envs;
+        self
+    }
+
+    pub fn write_durability(mut self, durability: WriteDurability) -> Self {
+        self.config.write_durability = durability;
+        self
+    }
+
+    /// Returns the configuration, or an error if an option is invalid.
+    pub fn build(self) -> Result<DefaultProcessConfig> {
+        let config = self.config;
+        if config.max_memory < MIN_MEMORY {
+            bail!(
+                "max_memory of {} bytes is less than one page ({} bytes)",
+                config.max_memory,
+                MIN_MEMORY
+            );
+        }
+        if config.max_fuel == Some(0) {
+            bail!("max_fuel can't be 0, use `None` for unlimited fuel");
+        }
+        if config.fallback_entry.as_deref() == Some("") {
+            bail!("fallback_entry can't be empty, use `None` to disable it");
+        }
+        if config.job_id.as_deref() == Some("") {
+            bail!("job_id can't be empty, use `None` to disable checkpoints");
+        }
+        if let Some(size) = config.prefault_memory {
+            if size > config.max_memory {
+                bail!(
+                    "prefault_memory of {} bytes is above max_memory of {} bytes",
+                    size,
+                    config.max_memory
+                );
+            }
+        }
+        if config.max_outbound_connections == Some(0) {
+            bail!("max_outbound_connections can't be 0, use `None` for unlimited connections");
+        }
+        if config.max_tcp_linger.as_secs() > MAX_TCP_LINGER_SECS {
+            bail!(
+                "max_tcp_linger can't exceed {} seconds on this platform",
+                MAX_TCP_LINGER_SECS
+            );
+        }
+        if config.temp_dir.as_deref() == Some("") {
+            bail!("temp_dir can't be empty, use `None` to disable it");
+        }
+        if config
+            .mailbox_limit
+            .is_some_and(|limit| limit.capacity == 0)
+        {
+            bail!("mailbox_limit capacity can't be 0, use `None` for an unlimited mailbox");
+        }
+        if let Some(idle) = config.idle_receive_timeout {
+            if idle.timeout.is_zero() {
+                bail!("idle_receive_timeout can't be zero, use `None` to disable it");
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use lunatic_networking_api::MAX_TCP_LINGER_SECS;
+    use lunatic_process::config::{
+        IdleReceivePolicy, IdleReceiveTimeout, MailboxLimit, MailboxOverflow, ProcessConfig,
+    };
+    use lunatic_process_api::ProcessConfigCtx;
+
+    use super::{DefaultProcessConfig, MIN_MEMORY};
+
+    #[test]
+    fn builder_rejects_invalid_options() {
+        let config = DefaultProcessConfig::builder()
+            .max_memory(MIN_MEMORY)
+            .max_fuel(Some(1))
+            .can_spawn_processes(true)
+            .build()
+            .unwrap();
+        assert_eq!(config.get_max_memory(), MIN_MEMORY);
+        assert_eq!(config.get_max_fuel(), Some(1));
+        assert!(config.can_spawn_processes());
+        assert!(!config.can_compile_modules());
+
+        assert!(DefaultProcessConfig::builder().build().is_ok());
+        assert!(DefaultProcessConfig::builder()
+            .max_memory(MIN_MEMORY - 1)
+            .build()
+            .is_err());
+        assert!(DefaultProcessConfig::builder()
+            .max_fuel(Some(0))
+            .build()
+            .is_err());
+        assert!(DefaultProcessConfig::builder()
+            .max_outbound_connections(Some(0))
+            .build()
+            .is_err());
+        assert!(DefaultProcessConfig::builder()
+            .max_tcp_linger(Duration::from_secs(MAX_TCP_LINGER_SECS + 1))
+            .build()
+            .is_err());
+        assert!(DefaultProcessConfig::builder()
+            .mailbox_limit(Some(MailboxLimit {
+                capacity: 0,
+                overflow: MailboxOverflow::DropNewest,
+            }))
+            .build()
+            .is_err());
+        assert!(DefaultProcessConfig::builder()
+            .fallback_entry(Some(""))
+            .build()
+            .is_err());
+        assert!(DefaultProcessConfig::builder()
+            .job_id(Some(""))
+            .build()
+            .is_err());
+        assert!(DefaultProcessConfig::builder()
+            .idle_receive_timeout(Some(IdleReceiveTimeout {
+                timeout: Duration::ZERO,
+                policy: IdleReceivePolicy::Kill,
+            }))
+            .build()
+            .is_err());
+    }
+}
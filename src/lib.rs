@@ -30,9 +30,19 @@ Wasm modules, checkout out the [lunatic crate](https://crates.io/crates/lunatic)
 TODO
 */
 
+mod capabilities;
 mod config;
+mod resources;
 mod state;
-
-pub use config::DefaultProcessConfig;
-pub use lunatic_process::{spawn, wasm::spawn_wasm, Finished, Process, Signal, WasmProcess};
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
+pub use capabilities::{Capability, CapabilityManifest, MANIFEST_SECTION};
+pub use config::{DefaultProcessConfig, ProcessConfigBuilder, MIN_MEMORY};
+pub use lunatic_process::{
+    spawn,
+    wasm::{spawn_wasm, spawn_wasm_and_await_ready, spawn_wasm_blocking, spawn_wasm_detached},
+    Finished, Process, Signal, WasmProcess,
+};
+pub use resources::ResourceKind;
 pub use state::DefaultProcessState;
@@ -7,7 +7,7 @@ use dashmap::DashMap;
 use lunatic_process::{runtimes, state::ProcessState};
 use lunatic_process_api::ProcessConfigCtx;
 use lunatic_runtime::{spawn_wasm, DefaultProcessConfig, DefaultProcessState};
-use lunatic_stdout_capture::StdoutCapture;
+use lunatic_stdout_capture::{StdoutCapture, StreamKind};
 use lunatic_wasi_api::LunaticWasiCtx;
 
 pub(crate) async fn test() -> Result<()> {
@@ -101,7 +101,7 @@ pub(crate) async fn test() -> Result<()> {
     let path = args.value_of("wasm").unwrap();
     let path = Path::new(path);
     let module = fs::read(path)?;
-    let module = runtime.compile_module::<DefaultProcessState>(module)?;
+    let module = runtime.compile_module::<DefaultProcessState>(module.into())?;
 
     let filter = args.value_of("filter").unwrap_or_default();
 
@@ -211,7 +211,7 @@ pub(crate) async fn test() -> Result<()> {
         let no_capture = args.is_present("nocapture");
         if !no_capture {
             state.set_stdout(stdout.clone());
-            state.set_stderr(stdout.clone());
+            state.set_stderr(stdout.with_kind(StreamKind::Stderr));
         }
 
         let (task, _) = spawn_wasm(
@@ -221,6 +221,7 @@ pub(crate) async fn test() -> Result<()> {
             &test_function.wasm_export_name,
             Vec::new(),
             None,
+            None,
         )
         .await
         .context(format!(
@@ -1,15 +1,32 @@
-use std::{env, fs, path::Path, sync::Arc};
+use std::{env, fs, path::Path, sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
 use clap::{crate_version, Arg, Command};
 
 use dashmap::DashMap;
-use lunatic_process::{runtimes, state::ProcessState};
-use lunatic_process_api::ProcessConfigCtx;
+use log::LevelFilter;
+use lunatic_process::{
+    deadlock::{DeadlockAction, DeadlockDetector},
+    logging,
+    namespaces::ImportNamespaces,
+    os_signal::{OsSignal, SignalForwarder},
+    runtimes::{
+        self,
+        cache::ModuleCache,
+        wasmtime::{Profiler, WasmtimeConfigBuilder},
+    },
+    state::ProcessState,
+};
 use lunatic_runtime::{spawn_wasm, DefaultProcessConfig, DefaultProcessState};
 
 pub(crate) async fn execute() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let env = || env_logger::Env::default().default_filter_or("warn");
+    // Processes start with the level given by the environment, but can turn it up later. Their
+    // records are filtered by the process itself, not by the logger.
+    logging::set_default_level(env_logger::Builder::from_env(env()).build().filter());
+    env_logger::Builder::from_env(env())
+        .filter_module(logging::LOG_TARGET, LevelFilter::Trace)
+        .init();
 
     // Parse command line arguments
     let args = Command::new("lunatic")
@@ -22,6 +39,64 @@ pub(crate) async fn execute() -> Result<()> {
                 .multiple_occurrences(true)
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("compat_shims")
+                .long("compat-shims")
+                .help("Allow modules built against an older host API to load"),
+        )
+        .arg(
+            Arg::new("capability_manifests")
+                .long("capability-manifests")
+                .help("Restrict processes to the capabilities declared in their module"),
+        )
+        .arg(
+            Arg::new("import_namespace")
+                .long("import-namespace")
+                .value_name("MODULE=ALIAS")
+                .help("Also expose the host functions of MODULE under the import module ALIAS")
+                .multiple_occurrences(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("module_cache")
+                .long("module-cache")
+                .value_name("DIRECTORY")
+                .help("Keep compiled modules in the given directory to speed up later runs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("deadlock_scan")
+                .long("deadlock-scan")
+                .value_name("SECONDS")
+                .help("Report processes waiting on each other's messages, scanning every SECONDS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("kill_deadlocked")
+                .long("kill-deadlocked")
+                .help("Kill processes found by --deadlock-scan")
+                .requires("deadlock_scan"),
+        )
+        .arg(
+            Arg::new("forward_signal")
+                .long("forward-signal")
+                .value_name("SIGNAL")
+                .help("Deliver SIGNAL to the main process as a shutdown message (Unix only)")
+                .possible_values(["SIGTERM", "SIGINT", "SIGHUP", "SIGQUIT"])
+                .multiple_occurrences(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("profiler")
+                .long("profiler")
+                .value_name("PROFILER")
+                .help("Report generated code to a native profiler (Linux only)")
+                .possible_values(["jitdump", "vtune"])
+                .takes_value(true),
+        )
+        .arg(Arg::new("wasm_threads").long("wasm-threads").help(
+            "Enable atomics from the wasm threads proposal (shared memories are not supported)",
+        ))
         .arg(
             Arg::new("bench")
                 .long("bench")
@@ -46,12 +121,6 @@ pub(crate) async fn execute() -> Result<()> {
         )
         .get_matches();
 
-    let mut config = DefaultProcessConfig::default();
-    // Allow initial process to compile modules, create configurations and spawn sub-processes
-    config.set_can_compile_modules(true);
-    config.set_can_create_configs(true);
-    config.set_can_spawn_processes(true);
-
     // Path to wasm file
     let path = args.value_of("wasm").unwrap();
     let path = Path::new(path);
@@ -68,38 +137,93 @@ pub(crate) async fn execute() -> Result<()> {
     if args.is_present("bench") {
         wasi_args.push("--bench".to_owned());
     }
-    config.set_command_line_arguments(wasi_args);
-
-    // Inherit environment variables
-    config.set_environment_variables(env::vars().collect());
 
-    // Always preopen the current dir
-    config.preopen_dir(".");
+    let mut config = DefaultProcessConfig::builder()
+        // Allow initial process to compile modules, create configurations and spawn sub-processes
+        .can_compile_modules(true)
+        .can_create_configs(true)
+        .can_spawn_processes(true)
+        .command_line_arguments(wasi_args)
+        // Inherit environment variables
+        .environment_variables(env::vars().collect())
+        // Always preopen the current dir
+        .preopen_dir(".");
     if let Some(dirs) = args.values_of("dir") {
         for dir in dirs {
-            config.preopen_dir(dir);
+            config = config.preopen_dir(dir);
         }
     }
+    let config = config.build()?;
 
     // Create wasmtime runtime
-    let wasmtime_config = runtimes::wasmtime::default_config();
-    let runtime = runtimes::wasmtime::WasmtimeRuntime::new(&wasmtime_config)?;
+    let profiler = match args.value_of("profiler") {
+        Some("jitdump") => Profiler::JitDump,
+        Some("vtune") => Profiler::VTune,
+        _ => Profiler::None,
+    };
+    let wasmtime_config = WasmtimeConfigBuilder::default()
+        .profiler(profiler)
+        .wasm_threads(args.is_present("wasm_threads"))
+        .build()
+        .context("The profiler isn't supported on this platform")?;
+    let mut runtime = runtimes::wasmtime::WasmtimeRuntime::new(&wasmtime_config)?;
+    runtime.set_compat_shims(args.is_present("compat_shims"));
+    runtime.set_capability_manifests(args.is_present("capability_manifests"));
+    if let Some(aliases) = args.values_of("import_namespace") {
+        let mut namespaces = ImportNamespaces::default();
+        for alias in aliases {
+            let (module, as_module) = alias
+                .split_once('=')
+                .with_context(|| format!("Invalid --import-namespace `{}`", alias))?;
+            namespaces.alias(module, as_module);
+        }
+        runtime.set_import_namespaces(namespaces);
+    }
+    if let Some(dir) = args.value_of("module_cache") {
+        runtime.set_module_cache(Some(ModuleCache::new(dir)))?;
+    }
+    if let Some(seconds) = args.value_of("deadlock_scan") {
+        let seconds: f64 = seconds
+            .parse()
+            .context("Invalid --deadlock-scan interval")?;
+        // `Duration::from_secs_f64` panics on negative, infinite and overflowing values, and a
+        // zero interval would scan all the time.
+        let seconds = Some(seconds)
+            .filter(|seconds| *seconds > 0.0 && *seconds <= u64::MAX as f64)
+            .context("The --deadlock-scan interval must be a positive number of seconds")?;
+        let action = if args.is_present("kill_deadlocked") {
+            DeadlockAction::Kill
+        } else {
+            DeadlockAction::Report
+        };
+        // Detected cycles are logged by the detector itself.
+        let detector = DeadlockDetector::new(Duration::from_secs_f64(seconds), action, |_| {});
+        runtime.set_deadlock_detector(Some(detector));
+    }
+    if let Some(signals) = args.values_of("forward_signal") {
+        let signals: Vec<OsSignal> = signals.filter_map(OsSignal::from_name).collect();
+        runtime.set_signal_forwarder(Some(SignalForwarder::new(&signals)?));
+    }
 
     // Spawn main process
     let module = fs::read(path)?;
 
-    let module = runtime.compile_module::<DefaultProcessState>(module)?;
+    let module = runtime.compile_module_cached::<DefaultProcessState>(module.into())?;
 
     let registry = Arc::new(DashMap::new());
     let state =
-        DefaultProcessState::new(runtime.clone(), module.clone(), Arc::new(config), registry)
-            .unwrap();
-    let (task, _) = spawn_wasm(runtime, module, state, "_start", Vec::new(), None)
+        DefaultProcessState::new(runtime.clone(), module.clone(), Arc::new(config), registry)?;
+    let forwarder = runtime.signal_forwarder().cloned();
+    let (task, process) = spawn_wasm(runtime, module, state, "_start", Vec::new(), None, None)
         .await
         .context(format!(
             "Failed to spawn process from {}::_start()",
             path.to_string_lossy()
         ))?;
+    // Other processes can opt in themselves, the main process receives the signals by default.
+    if let Some(forwarder) = forwarder {
+        forwarder.register(process);
+    }
     // Wait on the main process to finish
     task.await.map(|_| ())
 }
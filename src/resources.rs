@@ -0,0 +1,233 @@
+use std::{convert::TryFrom, future::Future};
+
+use anyhow::Result;
+use lunatic_common_api::{get_memory, IntoTrap};
+use lunatic_error_api::ErrorCtx;
+use lunatic_networking_api::NetworkingCtx;
+use lunatic_process::{
+    fuel::{charge_host_call, HostCallCategory},
+    state::ProcessState,
+};
+use lunatic_process_api::ProcessCtx;
+use lunatic_timer_api::TimerCtx;
+use wasmtime::{Caller, Linker, Trap};
+
+/// Kinds of resources a process can hold.
+///
+/// Resource IDs are only unique inside of one kind, so the kind needs to be passed together with
+/// the ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResourceKind {
+    Config,
+    Module,
+    Process,
+    Timer,
+    DnsIterator,
+    TcpListener,
+    TcpStream,
+    UdpSocket,
+    Error,
+}
+
+impl TryFrom<u32> for ResourceKind {
+    type Error = ();
+
+    fn try_from(kind: u32) -> Result<Self, ()> {
+        match kind {
+            0 => Ok(Self::Config),
+            1 => Ok(Self::Module),
+            2 => Ok(Self::Process),
+            3 => Ok(Self::Timer),
+            4 => Ok(Self::DnsIterator),
+            5 => Ok(Self::TcpListener),
+            6 => Ok(Self::TcpStream),
+            7 => Ok(Self::UdpSocket),
+            8 => Ok(Self::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<ResourceKind> for u32 {
+    fn from(kind: ResourceKind) -> u32 {
+        kind as u32
+    }
+}
+
+/// Returns all open resources of the process, ordered by kind and ID.
+///
+/// Timers that already fired are not included.
+pub(crate) fn open_resources<T>(state: &T) -> Vec<(ResourceKind, u64)>
+where
+    T: ProcessState + ProcessCtx<T> + TimerCtx + NetworkingCtx + ErrorCtx,
+{
+    let mut resources = Vec::new();
+    let mut push = |kind: ResourceKind, ids: &mut dyn Iterator<Item = u64>| {
+        resources.extend(ids.map(|id| (kind, id)));
+    };
+    push(ResourceKind::Config, &mut state.config_resources().ids());
+    push(ResourceKind::Module, &mut state.module_resources().ids());
+    push(ResourceKind::Process, &mut state.process_resources().ids());
+    push(
+        ResourceKind::Timer,
+        &mut state.timer_resources().pending_ids(),
+    );
+    push(ResourceKind::DnsIterator, &mut state.dns_resources().ids());
+    push(
+        ResourceKind::TcpListener,
+        &mut state.tcp_listener_resources().ids(),
+    );
+    push(
+        ResourceKind::TcpStream,
+        &mut state.tcp_stream_resources().ids(),
+    );
+    push(ResourceKind::UdpSocket, &mut state.udp_resources().ids());
+    push(ResourceKind::Error, &mut state.error_resources().ids());
+    resources.sort_unstable();
+    resources
+}
+
+/// Closes the resource and frees its slot in the resources of the process.
+///
+/// Returns `false` if no resource of this kind with the ID exists. Later uses of the ID by the
+/// guest are treated like uses of an ID that never existed, the host functions trap.
+pub(crate) async fn close_resource<T>(state: &mut T, kind: ResourceKind, id: u64) -> bool
+where
+    T: ProcessState + ProcessCtx<T> + TimerCtx + NetworkingCtx + ErrorCtx,
+{
+    match kind {
+        ResourceKind::Config => state.config_resources_mut().remove(id).is_some(),
+        ResourceKind::Module => state.module_resources_mut().remove(id).is_some(),
+        ResourceKind::Process => state.process_resources_mut().remove(id).is_some(),
+        ResourceKind::Timer => state.timer_resources_mut().cancel(id).await,
+        ResourceKind::DnsIterator => state.dns_resources_mut().remove(id).is_some(),
+        ResourceKind::TcpListener => {
+            lunatic_networking_api::close_tcp_listener(state, id).is_some()
+        }
+        ResourceKind::TcpStream => lunatic_networking_api::close_tcp_stream(state, id).is_some(),
+        ResourceKind::UdpSocket => lunatic_networking_api::close_udp_socket(state, id).is_some(),
+        ResourceKind::Error => state.error_resources_mut().remove(id).is_some(),
+    }
+}
+
+/// Closes all open resources of the process and returns how many were closed.
+pub(crate) async fn close_all_resources<T>(state: &mut T) -> usize
+where
+    T: ProcessState + ProcessCtx<T> + TimerCtx + NetworkingCtx + ErrorCtx,
+{
+    let mut closed = 0;
+    for (kind, id) in open_resources(state) {
+        if close_resource(state, kind, id).await {
+            closed += 1;
+        }
+    }
+    closed
+}
+
+// Register the resource APIs to the linker
+pub(crate) fn register<T>(linker: &mut Linker<T>) -> Result<()>
+where
+    T: ProcessState + ProcessCtx<T> + TimerCtx + NetworkingCtx + ErrorCtx + Send + 'static,
+{
+    linker.func_wrap2_async("lunatic::process", "resource_close", resource_close)?;
+    linker.func_wrap("lunatic::process", "resource_list", resource_list)?;
+    linker.func_wrap0_async("lunatic::process", "resource_close_all", resource_close_all)?;
+    Ok(())
+}
+
+// Closes a resource of any kind and frees its slot in the resources of the process.
+//
+// This is the same as calling the `drop_*` (or `cancel_timer`) function of the kind, but it
+// doesn't trap if the resource doesn't exist. Closing the same resource twice is reported on the
+// second call.
+//
+// Kinds:
+// * 0 - Configuration
+// * 1 - Module
+// * 2 - Process
+// * 3 - Timer
+// * 4 - DNS iterator
+// * 5 - TCP listener
+// * 6 - TCP stream
+// * 7 - UDP socket
+// * 8 - Error
+//
+// Returns:
+// * 0 if the resource was closed
+// * 1 if no resource of this kind with the ID exists, it was never created or is already closed
+//
+// A timer that already fired can be reported either way.
+//
+// Traps:
+// * If the kind is unknown.
+fn resource_close<T>(
+    mut caller: Caller<T>,
+    kind: u32,
+    id: u64,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + ProcessCtx<T> + TimerCtx + NetworkingCtx + ErrorCtx + Send,
+{
+    Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Process)?;
+        let kind = ResourceKind::try_from(kind)
+            .map_err(|_| Trap::new("lunatic::process::resource_close: Unknown resource kind"))?;
+        let closed = close_resource(caller.data_mut(), kind, id).await;
+        Ok(if closed { 0 } else { 1 })
+    })
+}
+
+// Size of one entry written by `resource_list`.
+const RESOURCE_ENTRY_SIZE: u32 = 12;
+
+// Lists the open resources of the process.
+//
+// For each resource a 12 byte entry is written to the buffer, the kind (same numbering as in
+// `resource_close`) as a little endian u32 followed by the ID as a little endian u64. Entries are
+// ordered by kind and ID. If the buffer is too small, only the first `buffer_len / 12` entries
+// are written and the call can be retried with a bigger buffer. WASI file descriptors are not
+// included.
+//
+// Returns:
+// * The number of open resources, also if not all of them fit into the buffer.
+//
+// Traps:
+// * If any memory outside the guest heap space is referenced.
+fn resource_list<T>(mut caller: Caller<T>, buffer_ptr: u32, buffer_len: u32) -> Result<u32, Trap>
+where
+    T: ProcessState + ProcessCtx<T> + TimerCtx + NetworkingCtx + ErrorCtx,
+{
+    charge_host_call(&mut caller, HostCallCategory::Process)?;
+    let resources = open_resources(caller.data());
+    let fits = (buffer_len / RESOURCE_ENTRY_SIZE) as usize;
+    let mut buffer = Vec::with_capacity(fits.min(resources.len()) * RESOURCE_ENTRY_SIZE as usize);
+    for (kind, id) in resources.iter().take(fits) {
+        buffer.extend_from_slice(&u32::from(*kind).to_le_bytes());
+        buffer.extend_from_slice(&id.to_le_bytes());
+    }
+    let memory = get_memory(&mut caller)?;
+    memory
+        .write(&mut caller, buffer_ptr as usize, &buffer)
+        .or_trap("lunatic::process::resource_list")?;
+    Ok(resources.len() as u32)
+}
+
+// Closes all open resources of the process.
+//
+// Each resource is closed like with `resource_close`. Using any of the IDs afterwards is the same
+// as using an ID that never existed.
+//
+// Returns:
+// * The number of closed resources.
+fn resource_close_all<T>(
+    mut caller: Caller<T>,
+) -> Box<dyn Future<Output = Result<u32, Trap>> + Send + '_>
+where
+    T: ProcessState + ProcessCtx<T> + TimerCtx + NetworkingCtx + ErrorCtx + Send,
+{
+    Box::new(async move {
+        charge_host_call(&mut caller, HostCallCategory::Process)?;
+        let closed = close_all_resources(caller.data_mut()).await;
+        Ok(closed as u32)
+    })
+}
@@ -1,5 +1,7 @@
 use std::fmt::Debug;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Result;
 use async_std::channel::{unbounded, Receiver, Sender};
@@ -9,29 +11,44 @@ use hash_map_id::HashMapId;
 use lunatic_error_api::{ErrorCtx, ErrorResource};
 use lunatic_networking_api::dns::DnsIterator;
 use lunatic_networking_api::NetworkingCtx;
-use lunatic_process::config::ProcessConfig;
+use lunatic_process::config::{ProcessConfig, ReloadableConfig};
+use lunatic_process::deadlock::WaitGuard;
+use lunatic_process::extensions::Extensions;
+use lunatic_process::logging::ProcessLogLevel;
+use lunatic_process::random::{self, ProcessRng};
 use lunatic_process::runtimes::wasmtime::{WasmtimeCompiledModule, WasmtimeRuntime};
 use lunatic_process::state::{ConfigResources, ProcessState};
-use lunatic_process::{mailbox::MessageMailbox, message::Message, Process, Signal};
+use lunatic_process::upgrade::Upgrade;
+use lunatic_process::{mailbox::MessageMailbox, message::Message, Process, Signal, WasmProcess};
 use lunatic_process_api::ProcessCtx;
 use lunatic_stdout_capture::StdoutCapture;
 use lunatic_timer_api::{TimerCtx, TimerResources};
-use lunatic_wasi_api::{build_wasi, LunaticWasiCtx};
+use lunatic_wasi_api::{
+    build_wasi, preopen_dir_at, temp_dir::ProcessTempDir, LunaticWasiConfigCtx, LunaticWasiCtx,
+};
 use uuid::Uuid;
 use wasmtime::{Linker, ResourceLimiter};
 use wasmtime_wasi::WasiCtx;
 
+use crate::capabilities::CapabilityManifest;
+use crate::resources::{self, ResourceKind};
 use crate::DefaultProcessConfig;
 
 pub struct DefaultProcessState {
     // Process id
     id: Uuid,
+    // Human-readable label used in diagnostics
+    name: Option<String>,
     // The WebAssembly runtime
     runtime: Option<WasmtimeRuntime>,
     // The module that this process was spawned from
     module: Option<WasmtimeCompiledModule<Self>>,
     // The process configuration
     config: Arc<DefaultProcessConfig>,
+    // Runtime-wide config used for spawned processes instead of `config`, if followed
+    config_source: Option<ReloadableConfig<DefaultProcessConfig>>,
+    // Values attached by the embedder, inherited by spawned processes
+    extensions: Extensions,
     // A space that can be used to temporarily store messages when sending or receiving them.
     // Messages can contain resources that need to be added across multiple host. Likewise,
     // receiving messages is done in two steps, first the message size is returned to allow the
@@ -42,6 +59,12 @@ pub struct DefaultProcessState {
     signal_mailbox: (Sender<Signal>, Receiver<Signal>),
     // Messages sent to the process
     message_mailbox: MessageMailbox,
+    // Verbosity of logs about this process, shared with the process loop
+    log_level: ProcessLogLevel,
+    // Notifies the spawner waiting for the process to be ready
+    ready_notifier: Option<Sender<()>>,
+    // Code to continue with after the process upgraded itself
+    pending_upgrade: Option<Upgrade<DefaultProcessState>>,
     // Resources
     resources: Resources,
     // WASI
@@ -50,10 +73,18 @@ pub struct DefaultProcessState {
     wasi_stdout: Option<StdoutCapture>,
     // WASI stderr stream
     wasi_stderr: Option<StdoutCapture>,
+    // Private temp directory, removed when the state is dropped
+    temp_dir: Option<ProcessTempDir>,
     // Set to true if the WASM module has been instantiated
     initialized: bool,
+    // When the state was created, measured with a monotonic clock
+    spawned_at: Instant,
+    // Number of memory watch thresholds the memory already grew past
+    memory_thresholds_crossed: usize,
     // Shared process registry
     registry: Arc<DashMap<String, Arc<dyn Process>>>,
+    // Source of random values for host functions
+    rng: ProcessRng,
 }
 
 impl ProcessState for DefaultProcessState {
@@ -67,26 +98,65 @@ impl ProcessState for DefaultProcessState {
     ) -> Result<Self> {
         // TODO: Switch to new_v1() for distributed Lunatic to assure uniqueness across nodes.
         let id = Uuid::new_v4();
+        // Modules with a manifest only get the capabilities they declare.
+        let mut config = config;
+        if runtime.capability_manifests() {
+            if let Some(manifest) = CapabilityManifest::from_module(module.source())? {
+                config = Arc::new(manifest.restrict(&config)?);
+            }
+        }
         let signal_mailbox = unbounded::<Signal>();
-        let message_mailbox = MessageMailbox::default();
+        let message_mailbox = match runtime.message_queues() {
+            Some(queues) => queues.mailbox(config.get_job_id()),
+            None => MessageMailbox::default(),
+        };
+        message_mailbox.set_limit(config.get_mailbox_limit());
+        let mut wasi = build_wasi(
+            Some(config.command_line_arguments()),
+            Some(config.environment_variables()),
+            config.preopened_dirs(),
+            config.write_durability(),
+        )?;
+        if let Some(rng) = random::wasi_rng(config.get_random_source()) {
+            wasi.random = rng;
+        }
+        let temp_dir = match config.get_temp_dir() {
+            Some(guest_path) => {
+                let temp_dir = ProcessTempDir::create(&id.to_string())?;
+                preopen_dir_at(
+                    &mut wasi,
+                    temp_dir.path(),
+                    guest_path,
+                    config.write_durability(),
+                )?;
+                Some(temp_dir)
+            }
+            None => None,
+        };
         let state = Self {
             id,
+            name: None,
             runtime: Some(runtime),
             module: Some(module),
             config: config.clone(),
+            config_source: None,
+            extensions: Extensions::default(),
             message: None,
             signal_mailbox,
             message_mailbox,
+            log_level: ProcessLogLevel::default(),
+            ready_notifier: None,
+            pending_upgrade: None,
             resources: Resources::default(),
-            wasi: build_wasi(
-                Some(config.command_line_arguments()),
-                Some(config.environment_variables()),
-                config.preopened_dirs(),
-            )?,
+            wasi,
             wasi_stdout: None,
             wasi_stderr: None,
+            temp_dir,
             initialized: false,
+            spawned_at: Instant::now(),
+            memory_thresholds_crossed: 0,
             registry,
+            rng: ProcessRng::new(config.get_random_source()),
         };
         Ok(state)
     }
@@ -100,6 +170,12 @@ impl ProcessState for DefaultProcessState {
         lunatic_version_api::register(linker)?;
         lunatic_wasi_api::register(linker)?;
         lunatic_registry_api::register(linker)?;
+        crate::resources::register(linker)?;
+        Ok(())
+    }
+
+    fn register_compat(linker: &mut Linker<Self>, module: &wasmtime::Module) -> Result<()> {
+        lunatic_networking_api::compat::register_compat(linker, module)?;
         Ok(())
     }
 
@@ -119,6 +195,22 @@ impl ProcessState for DefaultProcessState {
         &self.config
     }
 
+    fn config_source(&self) -> Option<&ReloadableConfig<DefaultProcessConfig>> {
+        self.config_source.as_ref()
+    }
+
+    fn set_config_source(&mut self, source: Option<ReloadableConfig<DefaultProcessConfig>>) {
+        self.config_source = source;
+    }
+
+    fn extensions(&self) -> Option<&Extensions> {
+        Some(&self.extensions)
+    }
+
+    fn set_extensions(&mut self, extensions: Extensions) {
+        self.extensions = extensions;
+    }
+
     fn module(&self) -> &WasmtimeCompiledModule<Self> {
         self.module.as_ref().unwrap()
     }
@@ -127,6 +219,40 @@ impl ProcessState for DefaultProcessState {
         self.id
     }
 
+    fn spawned_at(&self) -> Instant {
+        self.spawned_at
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: Option<String>) {
+        // Prefix the captured output with the new name.
+        if let Some(stdout) = &self.wasi_stdout {
+            stdout.set_label(name.clone());
+        }
+        if let Some(stderr) = &self.wasi_stderr {
+            stderr.set_label(name.clone());
+        }
+        self.name = name;
+    }
+
+    fn wait_guard(&self) -> Option<WaitGuard> {
+        let detector = self.runtime.as_ref()?.deadlock_detector()?;
+        if self.resources.timers.has_pending() {
+            return None;
+        }
+        let peers = self
+            .resources
+            .processes
+            .values()
+            .map(|process| process.id())
+            .collect();
+        let this = WasmProcess::new(self.id, self.signal_mailbox.0.clone());
+        Some(detector.waiting(Arc::new(this), peers))
+    }
+
     fn signal_mailbox(&self) -> &(Sender<Signal>, Receiver<Signal>) {
         &self.signal_mailbox
     }
@@ -135,6 +261,26 @@ impl ProcessState for DefaultProcessState {
         &self.message_mailbox
     }
 
+    fn log_level(&self) -> &ProcessLogLevel {
+        &self.log_level
+    }
+
+    fn set_ready_notifier(&mut self, notifier: Sender<()>) {
+        self.ready_notifier = Some(notifier);
+    }
+
+    fn take_ready_notifier(&mut self) -> Option<Sender<()>> {
+        self.ready_notifier.take()
+    }
+
+    fn set_pending_upgrade(&mut self, upgrade: Upgrade<Self>) {
+        self.pending_upgrade = Some(upgrade);
+    }
+
+    fn take_pending_upgrade(&mut self) -> Option<Upgrade<Self>> {
+        self.pending_upgrade.take()
+    }
+
     fn config_resources(&self) -> &ConfigResources<<DefaultProcessState as ProcessState>::Config> {
         &self.resources.configs
     }
@@ -157,27 +303,66 @@ impl Default for DefaultProcessState {
         let message_mailbox = MessageMailbox::default();
         Self {
             id: Uuid::new_v4(),
+            name: None,
             runtime: None,
             module: None,
             config: Arc::new(config.clone()),
+            config_source: None,
+            extensions: Extensions::default(),
             message: None,
             signal_mailbox,
             message_mailbox,
+            log_level: ProcessLogLevel::default(),
+            ready_notifier: None,
+            pending_upgrade: None,
             resources: Resources::default(),
             wasi: build_wasi(
                 Some(config.command_line_arguments()),
                 Some(config.environment_variables()),
                 config.preopened_dirs(),
+                config.write_durability(),
             )
             .unwrap(),
             wasi_stdout: None,
             wasi_stderr: None,
+            temp_dir: None,
             initialized: false,
+            spawned_at: Instant::now(),
+            memory_thresholds_crossed: 0,
             registry: Arc::new(DashMap::new()),
+            rng: ProcessRng::default(),
         }
     }
 }
 
+impl DefaultProcessState {
+    /// Host path of the private temp directory of the process, if it has one.
+    pub fn temp_dir(&self) -> Option<&Path> {
+        self.temp_dir.as_ref().map(ProcessTempDir::path)
+    }
+
+    /// Returns the kinds and IDs of all resources the process holds.
+    ///
+    /// Sockets, timers, handles to other processes, modules, configurations and errors are
+    /// included. Files opened through WASI are managed by WASI and not part of the list.
+    pub fn open_resources(&self) -> Vec<(ResourceKind, u64)> {
+        resources::open_resources(self)
+    }
+
+    /// Force-closes a resource of the process.
+    ///
+    /// Returns `false` if it doesn't exist. If the guest still uses the ID afterwards, the host
+    /// function traps the same way as for an ID that never existed.
+    pub async fn close_resource(&mut self, kind: ResourceKind, id: u64) -> bool {
+        resources::close_resource(self, kind, id).await
+    }
+
+    /// Force-closes all resources of the process and returns how many were closed.
+    pub async fn close_all_resources(&mut self) -> usize {
+        resources::close_all_resources(self).await
+    }
+}
+
 impl Debug for DefaultProcessState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("State")
@@ -186,10 +371,34 @@ impl Debug for DefaultProcessState {
     }
 }
 
+// Subscribers of the captured output end once the process is gone.
+impl Drop for DefaultProcessState {
+    fn drop(&mut self) {
+        if let Some(stdout) = &self.wasi_stdout {
+            stdout.close();
+        }
+        if let Some(stderr) = &self.wasi_stderr {
+            stderr.close();
+        }
+    }
+}
+
 // Limit the maximum memory of the process depending on the environment it was spawned in.
 impl ResourceLimiter for DefaultProcessState {
     fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> bool {
-        desired <= self.config().get_max_memory()
+        let max_memory = self.config().get_max_memory();
+        if desired > max_memory {
+            return false;
+        }
+        // Memory never shrinks, so `desired` is always the new high-water mark.
+        if let Some(watch) = self.runtime.as_ref().and_then(|r| r.memory_watch()) {
+            let crossed = watch.crossed(desired, max_memory);
+            if crossed > self.memory_thresholds_crossed {
+                self.memory_thresholds_crossed = crossed;
+                watch.notify(self.id, desired);
+            }
+        }
+        true
     }
 
     fn table_growing(&mut self, _current: u32, desired: u32, _maximum: Option<u32>) -> bool {
@@ -248,6 +457,10 @@ impl ProcessCtx<DefaultProcessState> for DefaultProcessState {
     fn process_resources_mut(&mut self) -> &mut lunatic_process_api::ProcessResources {
         &mut self.resources.processes
     }
+
+    fn rng(&mut self) -> &mut ProcessRng {
+        &mut self.rng
+    }
 }
 
 impl NetworkingCtx for DefaultProcessState {
@@ -259,6 +472,32 @@ impl NetworkingCtx for DefaultProcessState {
         &mut self.resources.tcp_listeners
     }
 
+    fn tcp_listener_filters(&self) -> &lunatic_networking_api::TcpListenerFilters {
+        &self.resources.tcp_listener_filters
+    }
+
+    fn tcp_listener_filters_mut(&mut self) -> &mut lunatic_networking_api::TcpListenerFilters {
+        &mut self.resources.tcp_listener_filters
+    }
+
+    fn tcp_listener_idle_timeouts(&self) -> &lunatic_networking_api::TcpListenerIdleTimeouts {
+        &self.resources.tcp_listener_idle_timeouts
+    }
+
+    fn tcp_listener_idle_timeouts_mut(
+        &mut self,
+    ) -> &mut lunatic_networking_api::TcpListenerIdleTimeouts {
+        &mut self.resources.tcp_listener_idle_timeouts
+    }
+
+    fn tcp_stream_activity(&self) -> &lunatic_networking_api::TcpStreamActivity {
+        &self.resources.tcp_stream_activity
+    }
+
+    fn tcp_stream_activity_mut(&mut self) -> &mut lunatic_networking_api::TcpStreamActivity {
+        &mut self.resources.tcp_stream_activity
+    }
+
     fn tcp_stream_resources(&self) -> &lunatic_networking_api::TcpStreamResources {
         &self.resources.tcp_streams
     }
@@ -267,6 +506,30 @@ impl NetworkingCtx for DefaultProcessState {
         &mut self.resources.tcp_streams
     }
 
+    fn outbound_tcp_streams(&self) -> &lunatic_networking_api::OutboundTcpStreams {
+        &self.resources.outbound_tcp_streams
+    }
+
+    fn outbound_tcp_streams_mut(&mut self) -> &mut lunatic_networking_api::OutboundTcpStreams {
+        &mut self.resources.outbound_tcp_streams
+    }
+
+    fn tcp_stream_shutdowns(&self) -> &lunatic_networking_api::TcpStreamShutdowns {
+        &self.resources.tcp_stream_shutdowns
+    }
+
+    fn tcp_stream_shutdowns_mut(&mut self) -> &mut lunatic_networking_api::TcpStreamShutdowns {
+        &mut self.resources.tcp_stream_shutdowns
+    }
+
+    fn tcp_listener_drains(&self) -> &lunatic_networking_api::drain::TcpListenerDrains {
+        &self.resources.tcp_listener_drains
+    }
+
+    fn tcp_listener_drains_mut(&mut self) -> &mut lunatic_networking_api::drain::TcpListenerDrains {
+        &mut self.resources.tcp_listener_drains
+    }
+
     fn udp_resources(&self) -> &lunatic_networking_api::UdpResources {
         &self.resources.udp_sockets
     }
@@ -282,6 +545,22 @@ impl NetworkingCtx for DefaultProcessState {
     fn dns_resources_mut(&mut self) -> &mut lunatic_networking_api::DnsResources {
         &mut self.resources.dns_iterators
     }
+
+    fn udp_multicast_groups(&self) -> &lunatic_networking_api::UdpMulticastGroups {
+        &self.resources.udp_multicast_groups
+    }
+
+    fn udp_multicast_groups_mut(&mut self) -> &mut lunatic_networking_api::UdpMulticastGroups {
+        &mut self.resources.udp_multicast_groups
+    }
+
+    fn network_stats(&self) -> &lunatic_networking_api::bandwidth::NetworkStats {
+        &self.resources.network_stats
+    }
+
+    fn network_stats_mut(&mut self) -> &mut lunatic_networking_api::bandwidth::NetworkStats {
+        &mut self.resources.network_stats
+    }
 }
 
 impl TimerCtx for DefaultProcessState {
@@ -305,12 +584,14 @@ impl LunaticWasiCtx for DefaultProcessState {
 
     // Redirect the stdout stream
     fn set_stdout(&mut self, stdout: StdoutCapture) {
+        stdout.set_label(self.name.clone());
         self.wasi_stdout = Some(stdout.clone());
         self.wasi.set_stdout(Box::new(stdout));
     }
 
     // Redirect the stderr stream
     fn set_stderr(&mut self, stderr: StdoutCapture) {
+        stderr.set_label(self.name.clone());
         self.wasi_stderr = Some(stderr.clone());
         self.wasi.set_stderr(Box::new(stderr));
     }
@@ -332,21 +613,36 @@ pub(crate) struct Resources {
     pub(crate) timers: TimerResources,
     pub(crate) dns_iterators: HashMapId<DnsIterator>,
     pub(crate) tcp_listeners: HashMapId<TcpListener>,
+    pub(crate) tcp_listener_filters: lunatic_networking_api::TcpListenerFilters,
+    pub(crate) tcp_listener_idle_timeouts: lunatic_networking_api::TcpListenerIdleTimeouts,
     pub(crate) tcp_streams: HashMapId<TcpStream>,
+    pub(crate) tcp_stream_activity: lunatic_networking_api::TcpStreamActivity,
+    pub(crate) outbound_tcp_streams: lunatic_networking_api::OutboundTcpStreams,
+    pub(crate) tcp_stream_shutdowns: lunatic_networking_api::TcpStreamShutdowns,
+    pub(crate) tcp_listener_drains: lunatic_networking_api::drain::TcpListenerDrains,
     pub(crate) udp_sockets: HashMapId<Arc<UdpSocket>>,
+    pub(crate) udp_multicast_groups: lunatic_networking_api::UdpMulticastGroups,
+    pub(crate) network_stats: lunatic_networking_api::bandwidth::NetworkStats,
     pub(crate) errors: HashMapId<anyhow::Error>,
 }
 
+#[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use lunatic_process::message::{DataMessage, Message};
+    use lunatic_process::runtimes::wasmtime::{default_config, WasmtimeRuntime};
+    use lunatic_process::state::ProcessState;
+    use lunatic_process::wasm::spawn_wasm;
+    use lunatic_process::Signal;
+
+    use crate::state::DefaultProcessState;
+    use crate::test_util::{DeathReason, TestRuntime};
+    use crate::DefaultProcessConfig;
+
     #[async_std::test]
     async fn import_filter_signature_matches() {
-        use crate::state::DefaultProcessState;
-        use crate::DefaultProcessConfig;
-        use lunatic_process::runtimes::wasmtime::WasmtimeRuntime;
-        use lunatic_process::state::ProcessState;
-        use lunatic_process::wasm::spawn_wasm;
-        use std::sync::Arc;
-
         // The default configuration includes both, the "lunatic::*" and "wasi_*" namespaces.
         let config = DefaultProcessConfig::default();
 
@@ -356,14 +652,1635 @@ mod tests {
         let runtime = WasmtimeRuntime::new(&wasmtime_config).unwrap();
 
         let raw_module = wat::parse_file("./wat/all_imports.wat").unwrap();
-        let module = runtime.compile_module(raw_module).unwrap();
+        let module = runtime.compile_module(raw_module.into()).unwrap();
         let registry = Arc::new(dashmap::DashMap::new());
         let state =
             DefaultProcessState::new(runtime.clone(), module.clone(), Arc::new(config), registry)
                 .unwrap();
 
-        spawn_wasm(runtime, module, state, "hello", Vec::new(), None)
+        spawn_wasm(runtime, module, state, "hello", Vec::new(), None, None)
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn guest_compiles_module() {
+        let config = DefaultProcessConfig::builder()
+            .can_compile_modules(true)
+            .build()
+            .unwrap();
+        let runtime = TestRuntime::new().unwrap().with_process_config(config);
+        // Compiles the empty module at 0 and traps unless it succeeds.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::process" "compile_module"
+                        (func $compile (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "\00asm\01\00\00\00")
+                    (func (export "run")
+                        (if (call $compile (i32.const 0) (i32.const 8) (i32.const 16))
+                            (then unreachable))))
+                "#,
+            )
+            .unwrap();
+        let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        assert!(matches!(
+            process.finished().await.reason,
+            DeathReason::Normal
+        ));
+    }
+
+    #[async_std::test]
+    async fn init_message_is_received_first() {
+        let runtime = TestRuntime::new().unwrap();
+        // Traps if the first message doesn't have the tag 42.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (import "lunatic::message" "get_tag" (func $get_tag (result i64)))
+                    (memory (export "memory") 1)
+                    (func (export "run")
+                        (if (i32.ne (call $receive (i32.const 0) (i32.const 0) (i32.const 5000))
+                                    (i32.const 0))
+                            (then unreachable))
+                        (if (i64.ne (call $get_tag) (i64.const 42))
+                            (then unreachable))))
+                "#,
+            )
+            .unwrap();
+        let state = runtime.state(&module).unwrap();
+        let init = Message::Data(DataMessage::new(Some(42), 0));
+        let (join, process) = spawn_wasm(
+            runtime.runtime().clone(),
+            module,
+            state,
+            "run",
+            Vec::new(),
+            None,
+            Some(init),
+        )
+        .await
+        .unwrap();
+        process.send(Signal::Message(Message::Data(DataMessage::new(Some(7), 0))));
+        assert!(join.await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn missing_entry_fails_spawn_or_uses_fallback() {
+        let runtime = TestRuntime::new().unwrap();
+        let module = runtime
+            .compile(r#"(module (func (export "main")))"#)
+            .unwrap();
+        let error = runtime
+            .spawn(&module, "run", Vec::new())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Function 'run' not found");
+
+        let config = DefaultProcessConfig::builder()
+            .fallback_entry(Some("main"))
+            .build()
+            .unwrap();
+        let runtime = runtime.with_process_config(config);
+        let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        assert!(matches!(
+            process.finished().await.reason,
+            DeathReason::Normal
+        ));
+    }
+
+    #[async_std::test]
+    async fn trap_in_start_function_fails_spawn() {
+        let runtime = TestRuntime::new().unwrap();
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (func $init unreachable)
+                    (start $init)
+                    (func (export "run")))
+                "#,
+            )
+            .unwrap();
+        let error = runtime
+            .spawn(&module, "run", Vec::new())
             .await
+            .err()
             .unwrap();
+        assert!(error.to_string().starts_with("Initialization failed"));
+        // No process was started, so the entry function never ran.
+        assert_eq!(runtime.runtime().live_count(), 0);
+    }
+
+    #[async_std::test]
+    async fn detached_process_ignores_links() {
+        use lunatic_process::wasm::spawn_wasm_detached;
+        use uuid::Uuid;
+
+        let runtime = TestRuntime::new().unwrap();
+        // Waits on a message.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "run")
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 5000)))))
+                "#,
+            )
+            .unwrap();
+        let state = runtime.state(&module).unwrap();
+        let (join, process) = spawn_wasm_detached(
+            runtime.runtime().clone(),
+            module,
+            state,
+            "run",
+            Vec::new(),
+            None,
+        )
+        .await
+        .unwrap();
+        // A failing link would kill a process that isn't detached.
+        let (_, other) = lunatic_process::spawn(|_, _| async { Ok::<(), anyhow::Error>(()) });
+        process.send(Signal::Link(Some(1), Arc::new(other)));
+        process.send(Signal::LinkDied(
+            Uuid::new_v4(),
+            Some(1),
+            DeathReason::Failure,
+            0,
+        ));
+        process.send(Signal::Message(Message::Data(DataMessage::new(None, 0))));
+        assert!(join.await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn temp_dir_is_removed_after_process_finishes() {
+        let runtime = TestRuntime::new().unwrap();
+        // `run` traps unless the temp directory is preopened as `/tmp`.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "wasi_snapshot_preview1" "fd_prestat_get"
+                        (func $fd_prestat_get (param i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "run")
+                        (if (call $fd_prestat_get (i32.const 3) (i32.const 0))
+                            (then unreachable))
+                        (if (i32.ne (i32.load (i32.const 4)) (i32.const 4))
+                            (then unreachable)))
+                    (func (export "crash") unreachable))
+                "#,
+            )
+            .unwrap();
+        let config = DefaultProcessConfig::builder()
+            .temp_dir(Some("/tmp"))
+            .build()
+            .unwrap();
+        let runtime = runtime.with_process_config(config);
+        for (function, success) in [("run", true), ("crash", false)] {
+            let state = runtime.state(&module).unwrap();
+            let dir = state.temp_dir().unwrap().to_owned();
+            assert!(dir.is_dir());
+            assert!(dir.to_string_lossy().contains(&state.id().to_string()));
+            let (join, _) = spawn_wasm(
+                runtime.runtime().clone(),
+                module.clone(),
+                state,
+                function,
+                Vec::new(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+            assert_eq!(join.await.is_ok(), success);
+            // The directory is removed on a blocking thread.
+            let removed = async {
+                while dir.exists() {
+                    async_std::task::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            async_std::future::timeout(Duration::from_secs(5), removed)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[async_std::test]
+    async fn step_mode_calls_hook_and_can_kill() {
+        use lunatic_process::step::{StepAction, StepInfo, StepMode};
+        use std::sync::Mutex;
+
+        // Waits on a receive that times out, which isn't a step, and counts down from 1000.
+        let raw_module = wat::parse_str(
+            r#"
+            (module
+                (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (local $i i32)
+                    (if (i32.ne (call $receive (i32.const 0) (i32.const 0) (i32.const 20))
+                            (i32.const 9027))
+                        (then unreachable))
+                    (local.set $i (i32.const 1000))
+                    (loop $continue
+                        (local.set $i (i32.sub (local.get $i) (i32.const 1)))
+                        (br_if $continue (local.get $i)))))
+            "#,
+        )
+        .unwrap();
+        for kill_at in [None, Some(3)] {
+            let steps: Arc<Mutex<Vec<StepInfo>>> = Arc::default();
+            let recorded = steps.clone();
+            let mut runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+            runtime.set_step_mode(Some(StepMode::new(100, move |info| {
+                recorded.lock().unwrap().push(*info);
+                if Some(info.step) == kill_at {
+                    StepAction::Kill
+                } else {
+                    StepAction::Continue
+                }
+            })));
+            let runtime = TestRuntime::with_runtime(runtime).unwrap();
+            let module = runtime.compile(&raw_module).unwrap();
+            let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+            let id = process.id();
+            let reason = process.finished().await.reason;
+            let steps = steps.lock().unwrap();
+            match kill_at {
+                // The loop executes a few thousand instructions.
+                None => {
+                    assert!(matches!(reason, DeathReason::Normal));
+                    assert!(steps.len() > 10);
+                }
+                Some(kill_at) => {
+                    assert!(matches!(reason, DeathReason::Failure));
+                    assert_eq!(steps.len() as u64, kill_at);
+                }
+            }
+            for (i, info) in steps.iter().enumerate() {
+                assert_eq!(info.process_id, id);
+                assert_eq!(info.step, i as u64 + 1);
+                assert_eq!(info.fuel_consumed, i as u64 * 100);
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn topology_contains_linked_processes() {
+        use lunatic_process::topology::{LinkEdge, Topology};
+
+        let mut runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        let topology = Topology::new();
+        runtime.set_topology(Some(topology.clone()));
+        let runtime = TestRuntime::with_runtime(runtime).unwrap();
+        // Waits for the shutdown message.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "run")
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))))
+                "#,
+            )
+            .unwrap();
+        let parent = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        let child = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        child.process().link(Some(7), parent.process());
+        // Both ends of the link are recorded once both processes handled the link signal.
+        let graph = async_std::future::timeout(Duration::from_secs(5), async {
+            loop {
+                let graph = topology.snapshot();
+                if graph.processes.len() == 2 && graph.links.len() == 2 {
+                    break graph;
+                }
+                async_std::task::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(graph.links.contains(&LinkEdge {
+            from: child.id(),
+            to: parent.id(),
+            tag: Some(7),
+        }));
+        assert!(graph.links.contains(&LinkEdge {
+            from: parent.id(),
+            to: child.id(),
+            tag: Some(7),
+        }));
+
+        child.send(Signal::Message(Message::Shutdown));
+        assert!(matches!(child.finished().await.reason, DeathReason::Normal));
+        parent.send(Signal::Message(Message::Shutdown));
+        assert!(matches!(
+            parent.finished().await.reason,
+            DeathReason::Normal
+        ));
+        assert_eq!(topology.snapshot(), Default::default());
+    }
+
+    #[async_std::test]
+    async fn respawned_process_resumes_from_checkpoint() {
+        let config = DefaultProcessConfig::builder()
+            .job_id(Some("job-1"))
+            .build()
+            .unwrap();
+        let runtime = TestRuntime::new().unwrap().with_process_config(config);
+        // Without a checkpoint it saves "abc" and traps, like a crashing job. Otherwise it checks
+        // that the checkpoint is "abc".
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::process" "checkpoint_save"
+                        (func $save (param i32 i32) (result i32)))
+                    (import "lunatic::process" "checkpoint_load"
+                        (func $load (param i32 i32) (result i64)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "abc")
+                    (func (export "run")
+                        (if (i64.eq (call $load (i32.const 16) (i32.const 0)) (i64.const -1))
+                            (then
+                                (if (call $save (i32.const 0) (i32.const 3)) (then unreachable))
+                                unreachable))
+                        (if (i64.ne (call $load (i32.const 16) (i32.const 8)) (i64.const 3))
+                            (then unreachable))
+                        (if (i32.ne (i32.load (i32.const 16)) (i32.const 0x636261))
+                            (then unreachable))))
+                "#,
+            )
+            .unwrap();
+        for success in [false, true] {
+            let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+            let reason = process.finished().await.reason;
+            assert_eq!(matches!(reason, DeathReason::Normal), success);
+        }
+        let checkpoints = runtime.runtime().checkpoints();
+        assert_eq!(checkpoints.load("job-1").as_deref(), Some(&b"abc"[..]));
+        assert!(checkpoints.load("job-2").is_none());
+    }
+
+    #[async_std::test]
+    async fn reloaded_config_applies_to_new_processes_only() {
+        use lunatic_process::config::ReloadableConfig;
+
+        let runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        // Spawns a child without an explicit config, both save a checkpoint under their job.
+        let raw_module = wat::parse_str(
+            r#"
+            (module
+                (import "lunatic::process" "spawn"
+                    (func $spawn (param i64 i64 i64 i32 i32 i32 i32 i32) (result i32)))
+                (import "lunatic::process" "checkpoint_save"
+                    (func $save (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "child")
+                (func (export "run")
+                    (if (call $spawn (i64.const 0) (i64.const -1) (i64.const -1)
+                            (i32.const 0) (i32.const 5) (i32.const 0) (i32.const 0) (i32.const 16))
+                        (then unreachable))
+                    (if (call $save (i32.const 0) (i32.const 3)) (then unreachable)))
+                (func (export "child")
+                    (if (call $save (i32.const 0) (i32.const 5)) (then unreachable))))
+            "#,
+        )
+        .unwrap();
+        let module = runtime.compile_module(raw_module.into()).unwrap();
+        let config = |job_id| {
+            DefaultProcessConfig::builder()
+                .can_spawn_processes(true)
+                .job_id(Some(job_id))
+                .build()
+                .unwrap()
+        };
+        let source = ReloadableConfig::new(config("old"));
+        let registry = Arc::new(dashmap::DashMap::new());
+        let mut state =
+            DefaultProcessState::new(runtime.clone(), module.clone(), source.load(), registry)
+                .unwrap();
+        state.set_config_source(Some(source.clone()));
+        source.store(config("new"));
+
+        let (join, _) = spawn_wasm(
+            runtime.clone(),
+            module.clone(),
+            state,
+            "run",
+            Vec::new(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        join.await.unwrap();
+        // The running process kept its config, the child got the reloaded one.
+        assert_eq!(
+            runtime.checkpoints().load("old").as_deref(),
+            Some(&b"chi"[..])
+        );
+        let mut child_checkpoint = None;
+        for _ in 0..100 {
+            child_checkpoint = runtime.checkpoints().load("new");
+            if child_checkpoint.is_some() {
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(child_checkpoint.as_deref(), Some(&b"child"[..]));
+    }
+
+    #[async_std::test]
+    async fn busy_process_has_fuel_rate() {
+        use lunatic_process::fuel_rate::FuelRates;
+
+        let mut runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        let rates = FuelRates::new(Duration::from_secs(60));
+        runtime.set_fuel_rates(Some(rates.clone()));
+        let runtime = TestRuntime::with_runtime(runtime).unwrap();
+        // Counts down from the parameter, then waits for the shutdown message.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive"
+                        (func $receive (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "run") (param $n i32)
+                        (loop $busy
+                            (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                            (br_if $busy (local.get $n)))
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))))
+                "#,
+            )
+            .unwrap();
+        let mut processes = Vec::new();
+        for iterations in [1, 1_000_000] {
+            let params = vec![wasmtime::Val::I32(iterations)];
+            processes.push(runtime.spawn(&module, "run", params).await.unwrap());
+        }
+        let (idle, busy) = (processes[0].id(), processes[1].id());
+        async_std::future::timeout(Duration::from_secs(5), async {
+            while rates.rate(busy).unwrap_or(0) == 0 {
+                async_std::task::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(rates.rate(idle), Some(0));
+        assert_eq!(rates.snapshot()[0].0, busy);
+
+        for process in processes {
+            process.send(Signal::Message(Message::Shutdown));
+            process.finished().await;
+        }
+        assert_eq!(rates.rate(busy), None);
+        assert!(rates.snapshot().is_empty());
+    }
+
+    #[test]
+    fn validation_reports_unresolved_imports() {
+        use lunatic_process::runtimes::wasmtime::{ImportName, UnresolvedImport, UnresolvedReason};
+
+        let runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        let raw_module = wat::parse_str(
+            r#"
+            (module
+                (import "lunatic::process" "id" (func (param i64 i32)))
+                (import "lunatic::process" "sleep_ms" (func (param i32 i32)))
+                (import "lunatic::nothing" "here" (func))
+                (memory (export "memory") 1)
+                (func (export "run")))
+            "#,
+        )
+        .unwrap();
+        let report = runtime
+            .validate_module::<DefaultProcessState>(&raw_module.into())
+            .unwrap();
+        assert!(!report.is_valid());
+        let import = |module: &str, name: &str| ImportName {
+            module: module.to_owned(),
+            name: name.to_owned(),
+        };
+        assert_eq!(report.resolved_imports, [import("lunatic::process", "id")]);
+        assert_eq!(
+            report.unresolved_imports,
+            [
+                UnresolvedImport {
+                    import: import("lunatic::process", "sleep_ms"),
+                    reason: UnresolvedReason::TypeMismatch,
+                },
+                UnresolvedImport {
+                    import: import("lunatic::nothing", "here"),
+                    reason: UnresolvedReason::Missing,
+                },
+            ]
+        );
+        assert_eq!(report.exports, ["memory", "run"]);
+        // Invalid wasm fails the validation itself.
+        assert!(runtime
+            .validate_module::<DefaultProcessState>(&vec![0, 1, 2].into())
+            .is_err());
+    }
+
+    #[test]
+    fn capability_manifest_is_enforced() {
+        use lunatic_process_api::ProcessConfigCtx;
+
+        let mut runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        runtime.set_capability_manifests(true);
+        let raw_module = wat::parse_str(
+            r#"
+            (module
+                (@custom "lunatic.capabilities" "create_configs")
+                (func (export "run")))
+            "#,
+        )
+        .unwrap();
+        let runtime = TestRuntime::with_runtime(runtime).unwrap();
+        let module = runtime.compile(raw_module).unwrap();
+        let state = |config| runtime.clone().with_process_config(config).state(&module);
+
+        let error = state(DefaultProcessConfig::default()).unwrap_err();
+        assert!(error.to_string().ends_with("doesn't grant: create_configs"));
+        let config = DefaultProcessConfig::builder()
+            .can_create_configs(true)
+            .can_spawn_processes(true)
+            .build()
+            .unwrap();
+        let state = state(config).unwrap();
+        assert!(state.config().can_create_configs());
+        assert!(!state.config().can_spawn_processes());
+    }
+
+    #[async_std::test]
+    async fn resources_can_be_listed_and_force_closed() {
+        use crate::ResourceKind;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let runtime = TestRuntime::new().unwrap();
+        // Opens a config and a TCP stream, checks the list and closes everything. With
+        // `use_closed` set, the stream is dropped once more after it was force-closed.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::networking" "tcp_connect"
+                        (func $connect (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+                    (import "lunatic::networking" "drop_tcp_stream" (func $drop (param i64)))
+                    (import "lunatic::process" "create_config" (func $create_config (result i64)))
+                    (import "lunatic::process" "resource_list" (func $list (param i32 i32) (result i32)))
+                    (import "lunatic::process" "resource_close_all" (func $close_all (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "\7f\00\00\01")
+                    (func (export "run") (param $port i32) (param $use_closed i32)
+                        (local $stream i64)
+                        (drop (call $create_config))
+                        (if (call $connect (i32.const 4) (i32.const 0) (local.get $port)
+                                (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 8))
+                            (then unreachable))
+                        (local.set $stream (i64.load (i32.const 8)))
+                        ;; Only room for the first entry.
+                        (if (i32.ne (call $list (i32.const 16) (i32.const 20)) (i32.const 2))
+                            (then unreachable))
+                        (if (i32.ne (i32.load (i32.const 16)) (i32.const 0)) (then unreachable))
+                        (if (i32.ne (call $list (i32.const 16) (i32.const 24)) (i32.const 2))
+                            (then unreachable))
+                        (if (i32.ne (i32.load (i32.const 28)) (i32.const 6)) (then unreachable))
+                        (if (i64.ne (i64.load (i32.const 32)) (local.get $stream))
+                            (then unreachable))
+                        (if (i32.ne (call $close_all) (i32.const 2)) (then unreachable))
+                        (if (call $list (i32.const 16) (i32.const 24)) (then unreachable))
+                        (if (local.get $use_closed) (then (call $drop (local.get $stream))))))
+                "#,
+            )
+            .unwrap();
+        let config = DefaultProcessConfig::builder()
+            .can_create_configs(true)
+            .build()
+            .unwrap();
+        let runtime = runtime.with_process_config(config.clone());
+        for use_closed in [false, true] {
+            let state = runtime.state(&module).unwrap();
+            let (join, _) = spawn_wasm(
+                runtime.runtime().clone(),
+                module.clone(),
+                state,
+                "run",
+                vec![
+                    wasmtime::Val::I32(port as i32),
+                    wasmtime::Val::I32(use_closed as i32),
+                ],
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+            match join.await {
+                Ok(_) => assert!(!use_closed),
+                Err(error) => {
+                    assert!(use_closed, "{:?}", error);
+                    assert!(format!("{:?}", error).contains("drop_tcp_stream"));
+                }
+            }
+        }
+
+        // The same from the host side.
+        let mut state = runtime.state(&module).unwrap();
+        let first = state.config_resources_mut().add(config.clone());
+        let second = state.config_resources_mut().add(config);
+        assert_eq!(
+            state.open_resources(),
+            vec![
+                (ResourceKind::Config, first),
+                (ResourceKind::Config, second)
+            ]
+        );
+        assert!(state.close_resource(ResourceKind::Config, first).await);
+        assert!(!state.close_resource(ResourceKind::Config, first).await);
+        assert_eq!(state.close_all_resources().await, 1);
+        assert!(state.open_resources().is_empty());
+    }
+
+    #[async_std::test]
+    async fn resource_close_detects_double_close() {
+        let config = DefaultProcessConfig::builder()
+            .can_create_configs(true)
+            .build()
+            .unwrap();
+        let runtime = TestRuntime::new().unwrap().with_process_config(config);
+        // Traps unless the config is closed once and the second close reports it.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::process" "create_config"
+                        (func $create_config (result i64)))
+                    (import "lunatic::process" "resource_close"
+                        (func $close (param i32 i64) (result i32)))
+                    (func (export "run") (local $config i64)
+                        (local.set $config (call $create_config))
+                        (if (i32.ne (call $close (i32.const 0) (local.get $config)) (i32.const 0))
+                            (then unreachable))
+                        (if (i32.ne (call $close (i32.const 0) (local.get $config)) (i32.const 1))
+                            (then unreachable))
+                        (if (i32.ne (call $close (i32.const 8) (i64.const 42)) (i32.const 1))
+                            (then unreachable))))
+                "#,
+            )
+            .unwrap();
+        let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        assert!(matches!(
+            process.finished().await.reason,
+            DeathReason::Normal
+        ));
+    }
+
+    #[test]
+    fn spawn_from_sync_code() {
+        use lunatic_process::wasm::spawn_wasm_blocking;
+
+        let runtime = TestRuntime::new().unwrap();
+        let module = runtime
+            .compile(r#"(module (func (export "run")))"#)
+            .unwrap();
+        let state = runtime.state(&module).unwrap();
+        let (join, _) = spawn_wasm_blocking(
+            runtime.runtime().clone(),
+            module,
+            state,
+            "run",
+            Vec::new(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(async_std::task::block_on(join).is_ok());
+    }
+
+    #[async_std::test]
+    async fn spawn_awaits_ready_or_times_out() {
+        use lunatic_process::wasm::spawn_wasm_and_await_ready;
+
+        let runtime = TestRuntime::new().unwrap();
+        // Both entry functions keep running until they receive a message.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::process" "ready" (func $ready))
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "ready")
+                        (call $ready)
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0))))
+                    (func (export "never_ready")
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))))
+                "#,
+            )
+            .unwrap();
+        let timeout = Duration::from_secs(5);
+        let state = runtime.state(&module).unwrap();
+        let (join, process) = spawn_wasm_and_await_ready(
+            runtime.runtime().clone(),
+            module.clone(),
+            state,
+            "ready",
+            Vec::new(),
+            None,
+            None,
+            Some(timeout),
+        )
+        .await
+        .unwrap();
+        process.send(Signal::Kill(Duration::ZERO));
+        assert!(join.await.is_err());
+
+        let state = runtime.state(&module).unwrap();
+        let error = spawn_wasm_and_await_ready(
+            runtime.runtime().clone(),
+            module,
+            state,
+            "never_ready",
+            Vec::new(),
+            None,
+            None,
+            Some(Duration::from_millis(50)),
+        )
+        .await
+        .err()
+        .expect("the process never gets ready");
+        assert!(error.to_string().starts_with("Process wasn't ready"));
+        assert!(error.is::<lunatic_process::wasm::ReadyTimeout>());
+        // The process that didn't get ready is killed.
+        while runtime.runtime().live_count() > 0 {
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[async_std::test]
+    async fn upgrade_keeps_mailbox_and_id() {
+        use lunatic_process_api::ProcessCtx;
+
+        let runtime = TestRuntime::new().unwrap();
+        // Handles one message and requests the upgrade to `next` of module 0 with the state
+        // "abc", after failing to request invalid ones. Traps if the next receive returns.
+        let old = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (import "lunatic::process" "upgrade"
+                        (func $upgrade (param i64 i32 i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "nextmissing")
+                    (data (i32.const 32) "abc")
+                    (func (export "run")
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))
+                        (if (i32.ne (call $upgrade (i64.const 0) (i32.const 4) (i32.const 7)
+                                        (i32.const 0) (i32.const 0))
+                                    (i32.const 1))
+                            (then unreachable))
+                        (if (call $upgrade (i64.const 0) (i32.const 0) (i32.const 4)
+                                (i32.const 32) (i32.const 3))
+                            (then unreachable))
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))
+                        unreachable))
+                "#,
+            )
+            .unwrap();
+        // Traps unless it got the state and the second message is still in the mailbox.
+        let new = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "lunatic_upgrade_state") (param $len i32) (result i32)
+                        (if (i32.ne (local.get $len) (i32.const 3)) (then unreachable))
+                        (i32.const 64))
+                    (func (export "next")
+                        (if (i32.ne (i32.load (i32.const 64)) (i32.const 0x636261))
+                            (then unreachable))
+                        (if (i32.ne (call $receive (i32.const 0) (i32.const 0) (i32.const 1000))
+                                    (i32.const 0))
+                            (then unreachable))))
+                "#,
+            )
+            .unwrap();
+        let mut state = runtime.state(&old).unwrap();
+        assert_eq!(state.module_resources_mut().add(new), 0);
+        let (join, process) = spawn_wasm(
+            runtime.runtime().clone(),
+            old,
+            state,
+            "run",
+            Vec::new(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        for _ in 0..2 {
+            process.send(Signal::Message(Message::Data(DataMessage::new(None, 0))));
+        }
+        let state = join.await.unwrap();
+        assert_eq!(state.id(), process.id());
+    }
+
+    #[async_std::test]
+    async fn failed_upgrade_keeps_old_code() {
+        use lunatic_process_api::ProcessCtx;
+
+        let runtime = TestRuntime::new().unwrap();
+        // Requests upgrades to module 0, which has no state transfer function, and to module 1,
+        // whose transfer function traps. The receive continues with the old code.
+        let old = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (import "lunatic::process" "upgrade"
+                        (func $upgrade (param i64 i32 i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "next")
+                    (func (export "run")
+                        (if (i32.ne (call $upgrade (i64.const 0) (i32.const 0) (i32.const 4)
+                                        (i32.const 0) (i32.const 4))
+                                    (i32.const 2))
+                            (then unreachable))
+                        (if (call $upgrade (i64.const 1) (i32.const 0) (i32.const 4)
+                                (i32.const 0) (i32.const 4))
+                            (then unreachable))
+                        (if (i32.ne (call $receive (i32.const 0) (i32.const 0) (i32.const 1000))
+                                    (i32.const 0))
+                            (then unreachable))))
+                "#,
+            )
+            .unwrap();
+        let without_transfer = runtime
+            .compile(
+                r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "next")))
+            "#,
+            )
+            .unwrap();
+        let failing_transfer = runtime
+            .compile(
+                r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "lunatic_upgrade_state") (param i32) (result i32) unreachable)
+                (func (export "next") unreachable))
+            "#,
+            )
+            .unwrap();
+        let mut state = runtime.state(&old).unwrap();
+        assert_eq!(state.module_resources_mut().add(without_transfer), 0);
+        assert_eq!(state.module_resources_mut().add(failing_transfer), 1);
+        let (join, process) = spawn_wasm(
+            runtime.runtime().clone(),
+            old,
+            state,
+            "run",
+            Vec::new(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        process.send(Signal::Message(Message::Data(DataMessage::new(None, 0))));
+        let state = join.await.unwrap();
+        assert_eq!(state.id(), process.id());
+    }
+
+    #[async_std::test]
+    async fn log_level_changes_while_running() {
+        use log::LevelFilter;
+
+        let runtime = TestRuntime::new().unwrap();
+        // Waits on a message.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "run")
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 5000)))))
+                "#,
+            )
+            .unwrap();
+        let state = runtime.state(&module).unwrap();
+        let log_level = state.log_level().clone();
+        assert_eq!(log_level.get(), None);
+        let (join, process) = spawn_wasm(
+            runtime.runtime().clone(),
+            module,
+            state,
+            "run",
+            Vec::new(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        process.send(Signal::SetLogLevel(Some(LevelFilter::Trace)));
+        process.send(Signal::Message(Message::Data(DataMessage::new(None, 0))));
+        assert!(join.await.is_ok());
+        assert_eq!(log_level.get(), Some(LevelFilter::Trace));
+    }
+
+    #[async_std::test]
+    async fn captured_output_stream_ends_with_process() {
+        use async_std::stream::StreamExt;
+        use lunatic_stdout_capture::StdoutCapture;
+        use lunatic_wasi_api::LunaticWasiCtx;
+
+        let runtime = TestRuntime::new().unwrap();
+        // Writes "hello" to stdout.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "wasi_snapshot_preview1" "fd_write"
+                        (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 16) "hello")
+                    (func (export "run")
+                        (i32.store (i32.const 0) (i32.const 16))
+                        (i32.store (i32.const 4) (i32.const 5))
+                        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+                "#,
+            )
+            .unwrap();
+        let mut state = runtime.state(&module).unwrap();
+        let stdout = StdoutCapture::new();
+        state.set_stdout(stdout.clone());
+        let mut output = stdout.subscribe(1024);
+        let (join, _) = spawn_wasm(
+            runtime.runtime().clone(),
+            module,
+            state,
+            "run",
+            Vec::new(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        // The execution result holds on to the state, the stream ends after it's dropped.
+        assert!(join.await.is_ok());
+        assert_eq!(output.next().await, Some(b"hello".to_vec()));
+        assert_eq!(output.next().await, None);
+    }
+
+    // Calls `proc_exit(code)` from a WASI guest and returns the execution result.
+    async fn proc_exit(code: i32) -> lunatic_process::ExecutionResult<DefaultProcessState> {
+        let runtime = TestRuntime::new().unwrap();
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                    (memory (export "memory") 1)
+                    (func (export "exit") (param i32)
+                        local.get 0
+                        call $proc_exit))
+                "#,
+            )
+            .unwrap();
+        let state = runtime.state(&module).unwrap();
+        let instance = runtime.runtime().instantiate(&module, state).await.unwrap();
+        instance.call("exit", vec![wasmtime::Val::I32(code)]).await
+    }
+
+    #[test]
+    fn compat_shims_load_old_signatures() {
+        // `tcp_bind` before the backlog and before the flags parameter was added.
+        for params in ["i32 i32 i32 i32 i32 i32", "i32 i32 i32 i32 i32 i32 i32"] {
+            let raw_module = wat::parse_str(format!(
+                r#"
+                (module
+                    (import "lunatic::networking" "tcp_bind"
+                        (func (param {}) (result i32))))
+                "#,
+                params
+            ))
+            .unwrap();
+
+            let mut runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+            assert!(runtime
+                .compile_module::<DefaultProcessState>(raw_module.clone().into())
+                .is_err());
+            runtime.set_compat_shims(true);
+            assert!(runtime
+                .compile_module::<DefaultProcessState>(raw_module.into())
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn import_namespaces_expose_alternate_names() {
+        use lunatic_process::namespaces::ImportNamespaces;
+
+        let raw_module = wat::parse_str(
+            r#"
+            (module
+                (import "lunatic_process" "id" (func (param i64 i32)))
+                (import "lunatic::process" "this" (func (result i64))))
+            "#,
+        )
+        .unwrap();
+
+        let runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        assert!(runtime
+            .compile_module::<DefaultProcessState>(raw_module.clone().into())
+            .is_err());
+        let mut namespaces = ImportNamespaces::default();
+        namespaces.prefix("lunatic::", "lunatic_");
+        assert!(runtime
+            .compile_module_with_namespaces::<DefaultProcessState>(raw_module.into(), &namespaces)
+            .is_ok());
+    }
+
+    #[test]
+    fn module_cache_is_invalidated_by_engine_config() {
+        use lunatic_process::runtimes::cache::ModuleCache;
+        use std::fs;
+
+        let dir = std::env::temp_dir().join(format!("lunatic-cache-{}", uuid::Uuid::new_v4()));
+        let raw_module = wat::parse_str(r#"(module (func (export "hello")))"#).unwrap();
+        let runtime = |config: &wasmtime::Config| {
+            let mut runtime = WasmtimeRuntime::new(config).unwrap();
+            runtime
+                .set_module_cache(Some(ModuleCache::new(&dir)))
+                .unwrap();
+            runtime
+        };
+        let artifact = |runtime: &WasmtimeRuntime, engine_dir: &std::path::Path| {
+            runtime
+                .compile_module_cached::<DefaultProcessState>(raw_module.clone().into())
+                .unwrap();
+            let entries: Vec<_> = fs::read_dir(engine_dir).unwrap().collect();
+            assert_eq!(entries.len(), 1);
+            entries[0].as_ref().unwrap().path()
+        };
+
+        let config = default_config();
+        let default = runtime(&config);
+        let default_dir = default
+            .module_cache()
+            .unwrap()
+            .engine_dir(default.engine())
+            .unwrap();
+        let cached = artifact(&default, &default_dir);
+        let hits = || default.module_cache().unwrap().hits();
+        assert_eq!(hits(), 0);
+        // The second compile is served from the cache.
+        assert_eq!(artifact(&default, &default_dir), cached);
+        assert_eq!(hits(), 1);
+
+        // Another opt level is compatible, but it still gets its own artifacts.
+        let mut no_opt = config.clone();
+        no_opt.cranelift_opt_level(wasmtime::OptLevel::None);
+        let no_opt = runtime(&no_opt);
+        let no_opt_dir = no_opt
+            .module_cache()
+            .unwrap()
+            .engine_dir(no_opt.engine())
+            .unwrap();
+        assert_ne!(no_opt_dir, default_dir);
+        artifact(&no_opt, &no_opt_dir);
+
+        let mut no_simd = config;
+        no_simd.wasm_simd(false);
+        let no_simd = runtime(&no_simd);
+        let no_simd_dir = no_simd
+            .module_cache()
+            .unwrap()
+            .engine_dir(no_simd.engine())
+            .unwrap();
+        assert_ne!(no_simd_dir, default_dir);
+        // Even if an incompatible artifact ends up in the directory, it's not loaded.
+        fs::copy(&cached, no_simd_dir.join(cached.file_name().unwrap())).unwrap();
+        let path = artifact(&no_simd, &no_simd_dir);
+        assert_ne!(fs::read(&path).unwrap(), fs::read(&cached).unwrap());
+        assert_eq!(no_simd.module_cache().unwrap().hits(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_modules_are_refused_before_compiling() {
+        use lunatic_process::runtimes::wasmtime::CompileLimits;
+
+        let raw_module = wat::parse_str("(module (func) (func))").unwrap();
+        let size = raw_module.len();
+        let compile = |limits: CompileLimits| {
+            let runtime = WasmtimeRuntime::with_compile_limits(&default_config(), limits).unwrap();
+            runtime
+                .compile_module::<DefaultProcessState>(raw_module.clone().into())
+                .map(|_| ())
+        };
+        assert!(compile(CompileLimits {
+            max_module_size: size,
+            max_functions: Some(2),
+            ..CompileLimits::default()
+        })
+        .is_ok());
+        let error = compile(CompileLimits {
+            max_module_size: size - 1,
+            ..CompileLimits::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Module too large: {} bytes, above the limit of {} bytes",
+                size,
+                size - 1
+            )
+        );
+        let error = compile(CompileLimits {
+            max_functions: Some(1),
+            ..CompileLimits::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Module defines 2 functions, above the limit of 1"
+        );
+    }
+
+    #[async_std::test]
+    async fn memory_is_prefaulted_within_limit() {
+        use lunatic_process::config::ProcessConfig;
+
+        let runtime = TestRuntime::new().unwrap();
+        // Traps unless the memory was grown from 1 to 3 pages.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (memory (export "memory") 1)
+                    (func (export "run")
+                        (if (i32.ne (memory.size) (i32.const 3)) (then unreachable))))
+                "#,
+            )
+            .unwrap();
+        let page = 64 * 1024;
+        let config = DefaultProcessConfig::builder()
+            .max_memory(4 * page)
+            .prefault_memory(Some(2 * page + 1))
+            .build()
+            .unwrap();
+        let process = runtime
+            .clone()
+            .with_process_config(config.clone())
+            .spawn(&module, "run", Vec::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            process.finished().await.reason,
+            DeathReason::Normal
+        ));
+
+        // The memory limit still applies if the builder is bypassed.
+        let mut config = config;
+        config.set_prefault_memory(Some(5 * page));
+        assert!(runtime
+            .with_process_config(config)
+            .spawn(&module, "run", Vec::new())
+            .await
+            .is_err());
+        assert!(DefaultProcessConfig::builder()
+            .max_memory(4 * page)
+            .prefault_memory(Some(5 * page))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn compiled_module_reports_memory_size() {
+        let runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        let raw_module = wat::parse_file("./wat/all_imports.wat").unwrap();
+        let source_size = raw_module.len();
+        let module = runtime
+            .compile_module::<DefaultProcessState>(raw_module.into())
+            .unwrap();
+        assert!(module.code_size() > 0);
+        assert_eq!(module.memory_size(), module.code_size() + source_size);
+    }
+
+    #[test]
+    fn compiled_module_metadata_is_shared_by_clones() {
+        let runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        let raw_module = wat::parse_str("(module)").unwrap();
+        let module = runtime
+            .compile_module::<DefaultProcessState>(raw_module.into())
+            .unwrap();
+        let registered = module.clone();
+        module.set_metadata("version", "1.0.0");
+        module.set_metadata("version", "1.1.0");
+        module.set_metadata("author", "lunatic");
+        assert_eq!(registered.metadata("version").as_deref(), Some("1.1.0"));
+        assert_eq!(registered.metadata("license"), None);
+        assert_eq!(registered.all_metadata().len(), 2);
+    }
+
+    #[async_std::test]
+    async fn proc_exit_zero_is_clean_exit() {
+        let result = proc_exit(0).await;
+        assert!(result.failure().is_none());
+        assert_eq!(result.exit_code(), Some(0));
+        assert!(result.is_success());
+    }
+
+    #[async_std::test]
+    async fn proc_exit_non_zero_is_not_a_failure() {
+        let result = proc_exit(3).await;
+        assert!(result.failure().is_none());
+        assert_eq!(result.exit_code(), Some(3));
+        assert!(!result.is_success());
+    }
+
+    #[async_std::test]
+    async fn trap_location_points_to_faulting_function() {
+        let runtime = TestRuntime::new().unwrap();
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (func $inner unreachable)
+                    (func (export "run") call $inner))
+                "#,
+            )
+            .unwrap();
+        let state = runtime.state(&module).unwrap();
+        let instance = runtime.runtime().instantiate(&module, state).await.unwrap();
+        let result = instance.call("run", Vec::new()).await;
+        assert!(result.failure().is_some());
+        let location = result.trap_location().unwrap();
+        assert_eq!(location.func_index, 0);
+        assert_eq!(location.func_name.as_deref(), Some("inner"));
+        assert!(location.module_offset.is_some());
+        // No DWARF in the module, only the raw offsets are known.
+        assert!(location.source.is_none());
+    }
+
+    #[async_std::test]
+    async fn fuel_exhausted_hook_reports_consumed_fuel() {
+        use lunatic_process::config::UNIT_OF_COMPUTE_IN_INSTRUCTIONS;
+
+        let mut runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        let (sender, receiver) = async_std::channel::unbounded();
+        runtime.add_fuel_exhausted_hook(move |exhausted| {
+            sender.try_send(exhausted.clone()).unwrap();
+        });
+        let config = DefaultProcessConfig::builder()
+            .max_fuel(Some(1))
+            .build()
+            .unwrap();
+        let runtime = TestRuntime::with_runtime(runtime)
+            .unwrap()
+            .with_process_config(config);
+        let module = runtime
+            .compile(r#"(module (func (export "run") (loop $forever (br $forever))))"#)
+            .unwrap();
+        let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        let id = process.id();
+        assert!(matches!(
+            process.finished().await.reason,
+            DeathReason::Failure
+        ));
+
+        let exhausted = async_std::future::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(exhausted.id, id);
+        assert_eq!(exhausted.fuel_limit, UNIT_OF_COMPUTE_IN_INSTRUCTIONS);
+        assert!(exhausted.fuel_consumed >= exhausted.fuel_limit);
+    }
+
+    #[async_std::test]
+    async fn host_call_costs_use_up_fuel() {
+        use lunatic_process::config::UNIT_OF_COMPUTE_IN_INSTRUCTIONS;
+        use lunatic_process::fuel::HostCallCategory;
+
+        let mut runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        let (sender, receiver) = async_std::channel::unbounded();
+        runtime.add_fuel_exhausted_hook(move |exhausted| {
+            sender.try_send(exhausted.clone()).unwrap();
+        });
+        let config = DefaultProcessConfig::builder()
+            .max_fuel(Some(5))
+            .host_call_cost(
+                HostCallCategory::Process,
+                2 * UNIT_OF_COMPUTE_IN_INSTRUCTIONS,
+            )
+            .build()
+            .unwrap();
+        let runtime = TestRuntime::with_runtime(runtime)
+            .unwrap()
+            .with_process_config(config);
+        // Each call costs two slices of fuel, the process can pay for two of them. The second
+        // call must leave less than a slice.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::process" "fuel_remaining" (func $fuel_remaining (result i64)))
+                    (func (export "run")
+                        (drop (call $fuel_remaining))
+                        (if (i64.ge_u (call $fuel_remaining) (i64.const 100000))
+                            (then unreachable))
+                        (drop (call $fuel_remaining))
+                        unreachable))
+                "#,
+            )
+            .unwrap();
+        let state = runtime.state(&module).unwrap();
+        let instance = runtime.runtime().instantiate(&module, state).await.unwrap();
+        let result = instance.call("run", Vec::new()).await;
+        let failure = result.failure().unwrap();
+        assert!(
+            failure.contains("Process ran out of fuel paying for a host call"),
+            "{}",
+            failure
+        );
+
+        let exhausted = async_std::future::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(exhausted.fuel_limit, 5 * UNIT_OF_COMPUTE_IN_INSTRUCTIONS);
+        assert!(exhausted.fuel_consumed >= exhausted.fuel_limit);
+    }
+
+    #[test]
+    fn atomics_need_wasm_threads() {
+        use lunatic_process::runtimes::wasmtime::WasmtimeConfigBuilder;
+
+        let raw_module = wat::parse_str(
+            r#"
+            (module
+                (memory 1)
+                (func (export "run") (result i32)
+                    (i32.atomic.rmw.add (i32.const 0) (i32.const 1))))
+            "#,
+        )
+        .unwrap();
+        let runtime = |threads| {
+            let config = WasmtimeConfigBuilder::default()
+                .wasm_threads(threads)
+                .build()
+                .unwrap();
+            WasmtimeRuntime::new(&config).unwrap()
+        };
+        assert!(runtime(false)
+            .compile_module::<DefaultProcessState>(raw_module.clone().into())
+            .is_err());
+        assert!(runtime(true)
+            .compile_module::<DefaultProcessState>(raw_module.into())
+            .is_ok());
+    }
+
+    #[async_std::test]
+    async fn child_count_includes_grandchildren() {
+        let runtime = TestRuntime::new().unwrap();
+        // `run` spawns `mid` and `leaf`, `mid` spawns another `leaf`. All of them wait for the
+        // shutdown message once done.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::process" "spawn"
+                        (func $spawn (param i64 i64 i64 i32 i32 i32 i32 i32) (result i32)))
+                    (import "lunatic::process" "child_count" (func $child_count (param i32) (result i64)))
+                    (import "lunatic::process" "sleep_ms" (func $sleep_ms (param i64)))
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "mid")
+                    (data (i32.const 8) "leaf")
+                    (func $spawn_leaf
+                        (if (call $spawn (i64.const 0) (i64.const -1) (i64.const -1)
+                                (i32.const 8) (i32.const 4) (i32.const 0) (i32.const 0) (i32.const 32))
+                            (then unreachable)))
+                    (func $wait
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0))))
+                    (func (export "run")
+                        (if (call $spawn (i64.const 0) (i64.const -1) (i64.const -1)
+                                (i32.const 0) (i32.const 3) (i32.const 0) (i32.const 0) (i32.const 32))
+                            (then unreachable))
+                        (call $spawn_leaf)
+                        (if (i64.ne (call $child_count (i32.const 0)) (i64.const 2))
+                            (then unreachable))
+                        ;; The grandchild is spawned concurrently by `mid`.
+                        (loop $until_spawned
+                            (if (i64.ne (call $child_count (i32.const 1)) (i64.const 3))
+                                (then
+                                    (call $sleep_ms (i64.const 1))
+                                    (br $until_spawned))))
+                        (call $wait))
+                    (func (export "mid")
+                        (call $spawn_leaf)
+                        (call $wait))
+                    (func (export "leaf")
+                        (call $wait)))
+                "#,
+            )
+            .unwrap();
+        let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        let tree = runtime.runtime().process_tree().clone();
+        let root = process.id();
+        async_std::future::timeout(Duration::from_secs(5), async {
+            while tree.child_count(root, true) != 3 {
+                async_std::task::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(tree.child_count(root, false), 2);
+
+        process.send(Signal::Message(Message::Shutdown));
+        let death = async_std::future::timeout(Duration::from_secs(5), process.finished())
+            .await
+            .unwrap();
+        assert!(matches!(death.reason, DeathReason::Normal));
+        assert_eq!(tree.child_count(root, true), 0);
+    }
+
+    #[async_std::test]
+    async fn guest_spawns_and_awaits_ready() {
+        let runtime = TestRuntime::new().unwrap();
+        // `run` waits for `ready` to get ready and times out waiting for `never_ready`.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::process" "spawn_and_await_ready"
+                        (func $spawn (param i64 i64 i64 i32 i32 i32 i32 i64 i32) (result i32)))
+                    (import "lunatic::process" "ready" (func $ready))
+                    (import "lunatic::process" "sleep_ms" (func $sleep_ms (param i64)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "ready")
+                    (data (i32.const 8) "never_ready")
+                    (func (export "run")
+                        (if (i32.ne (call $spawn (i64.const 0) (i64.const -1) (i64.const -1)
+                                (i32.const 0) (i32.const 5) (i32.const 0) (i32.const 0)
+                                (i64.const 5000) (i32.const 32))
+                                (i32.const 0))
+                            (then unreachable))
+                        (if (i32.ne (call $spawn (i64.const 0) (i64.const -1) (i64.const -1)
+                                (i32.const 8) (i32.const 11) (i32.const 0) (i32.const 0)
+                                (i64.const 20) (i32.const 32))
+                                (i32.const 2))
+                            (then unreachable)))
+                    (func (export "ready")
+                        (call $sleep_ms (i64.const 10))
+                        (call $ready))
+                    (func (export "never_ready")
+                        (call $sleep_ms (i64.const 5000))))
+                "#,
+            )
+            .unwrap();
+        let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        assert!(matches!(
+            process.finished().await.reason,
+            DeathReason::Normal
+        ));
+    }
+
+    #[async_std::test]
+    async fn linked_spawner_survives_ready_timeout() {
+        let runtime = TestRuntime::new().unwrap();
+        // `run` times out waiting for a linked `never_ready` and keeps running afterwards. The
+        // largest timeout is accepted and waits for `ready`.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::process" "spawn_and_await_ready"
+                        (func $spawn (param i64 i64 i64 i32 i32 i32 i32 i64 i32) (result i32)))
+                    (import "lunatic::process" "ready" (func $ready))
+                    (import "lunatic::process" "sleep_ms" (func $sleep_ms (param i64)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "ready")
+                    (data (i32.const 8) "never_ready")
+                    (func (export "run")
+                        (if (i32.ne (call $spawn (i64.const 1) (i64.const -1) (i64.const -1)
+                                (i32.const 8) (i32.const 11) (i32.const 0) (i32.const 0)
+                                (i64.const 20) (i32.const 32))
+                                (i32.const 2))
+                            (then unreachable))
+                        (call $sleep_ms (i64.const 100))
+                        (if (i32.ne (call $spawn (i64.const 1) (i64.const -1) (i64.const -1)
+                                (i32.const 0) (i32.const 5) (i32.const 0) (i32.const 0)
+                                (i64.const -1) (i32.const 32))
+                                (i32.const 0))
+                            (then unreachable)))
+                    (func (export "ready")
+                        (call $ready)
+                        (call $sleep_ms (i64.const 5000)))
+                    (func (export "never_ready")
+                        (call $sleep_ms (i64.const 5000))))
+                "#,
+            )
+            .unwrap();
+        let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        assert!(matches!(
+            process.finished().await.reason,
+            DeathReason::Normal
+        ));
+    }
+
+    #[async_std::test]
+    async fn job_queue_is_owned_by_one_process() {
+        use lunatic_process::mailbox::MessageQueue;
+        use std::collections::VecDeque;
+        use std::sync::Mutex;
+
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let mut runtime = WasmtimeRuntime::new(&default_config()).unwrap();
+        let factory_requested = requested.clone();
+        runtime.set_message_queues(Some(Arc::new(move |job_id: Option<&str>| {
+            factory_requested
+                .lock()
+                .unwrap()
+                .push(job_id.map(str::to_owned));
+            Box::new(VecDeque::new()) as Box<dyn MessageQueue>
+        })));
+        let config = DefaultProcessConfig::builder()
+            .job_id(Some("job"))
+            .build()
+            .unwrap();
+        let runtime = TestRuntime::with_runtime(runtime)
+            .unwrap()
+            .with_process_config(config);
+        // Waits for the shutdown message.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive" (func $receive (param i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "main")
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))))
+                "#,
+            )
+            .unwrap();
+
+        let parent = runtime.spawn(&module, "main", Vec::new()).await.unwrap();
+        // A child spawned with the configuration of the parent doesn't share the job queue.
+        drop(runtime.state(&module).unwrap());
+        parent.send(Signal::Message(Message::Shutdown));
+        assert!(matches!(
+            parent.finished().await.reason,
+            DeathReason::Normal
+        ));
+        // The job queue is released once the process finished.
+        let _restarted = runtime.state(&module).unwrap();
+        assert_eq!(
+            *requested.lock().unwrap(),
+            vec![Some("job".to_owned()), None, Some("job".to_owned())]
+        );
+    }
+
+    #[async_std::test]
+    async fn state_applies_mailbox_limit() {
+        use lunatic_process::config::{MailboxLimit, MailboxOverflow};
+
+        let config = DefaultProcessConfig::builder()
+            .mailbox_limit(Some(MailboxLimit {
+                capacity: 2,
+                overflow: MailboxOverflow::DropNewest,
+            }))
+            .build()
+            .unwrap();
+        let runtime = TestRuntime::new().unwrap().with_process_config(config);
+        let module = runtime
+            .compile(r#"(module (func (export "main")))"#)
+            .unwrap();
+        let state = runtime.state(&module).unwrap();
+        let mailbox = state.message_mailbox();
+        for tag in 0..3 {
+            mailbox.push(Message::LinkDied(Some(tag)));
+        }
+        assert_eq!(mailbox.len(), 2);
+        assert_eq!(mailbox.dropped(), 1);
+        assert_eq!(mailbox.pop(None).await.tag(), Some(0));
     }
 }
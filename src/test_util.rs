@@ -0,0 +1,258 @@
+/*!
+Helpers for tests spawning processes, enabled with the `test-util` feature and in the tests of
+this crate.
+
+A [`TestRuntime`] compiles modules from WAT or wasm and spawns processes with
+[`DefaultProcessState`]. Each [`TestProcess`] captures its stdout and stderr and reports how it
+finished:
+
+```no_run
+# async fn example() -> anyhow::Result<()> {
+use lunatic_runtime::test_util::{DeathReason, TestRuntime};
+
+let runtime = TestRuntime::new()?;
+let module = runtime.compile(r#"(module (func (export "run")))"#)?;
+let process = runtime.spawn(&module, "run", Vec::new()).await?;
+let death = process.finished().await;
+assert!(matches!(death.reason, DeathReason::Normal));
+# Ok(())
+# }
+```
+*/
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_std::channel::{bounded, Receiver, Sender};
+use dashmap::DashMap;
+use lunatic_process::{
+    process_ref::ProcessRef,
+    runtimes::wasmtime::{default_config, WasmtimeCompiledModule, WasmtimeRuntime},
+    state::ProcessState,
+    wasm::spawn_wasm,
+    JoinHandle, Process, Signal,
+};
+use lunatic_stdout_capture::StdoutCapture;
+use lunatic_wasi_api::LunaticWasiCtx;
+use uuid::Uuid;
+use wasmtime::Val;
+
+use crate::{DefaultProcessConfig, DefaultProcessState};
+
+pub use lunatic_process::{post_mortem::ProcessDeath, DeathReason};
+
+/// A runtime for tests, see the [module documentation](self).
+///
+/// Clones share the runtime and the registry, so they can spawn processes from the same modules
+/// with a different configuration.
+#[derive(Clone)]
+pub struct TestRuntime {
+    runtime: WasmtimeRuntime,
+    config: Arc<DefaultProcessConfig>,
+    registry: Arc<DashMap<String, Arc<dyn Process>>>,
+    // Notified once the process with the ID died.
+    deaths: Arc<DashMap<Uuid, Sender<ProcessDeath>>>,
+}
+
+impl TestRuntime {
+    /// Creates a runtime with the default configuration. Processes get the default process
+    /// configuration, except that they can spawn other processes.
+    pub fn new() -> Result<Self> {
+        Self::with_runtime(WasmtimeRuntime::new(&default_config())?)
+    }
+
+    /// Wraps an already configured `runtime`, e.g. one with compile limits.
+    pub fn with_runtime(mut runtime: WasmtimeRuntime) -> Result<Self> {
+        let deaths: Arc<DashMap<Uuid, Sender<ProcessDeath>>> = Arc::default();
+        let notify = deaths.clone();
+        runtime.add_post_mortem_hook(move |death| {
+            if let Some((_, sender)) = notify.remove(&death.id) {
+                let _ = sender.try_send(death.clone());
+            }
+        });
+        let config = DefaultProcessConfig::builder()
+            .can_spawn_processes(true)
+            .build()?;
+        Ok(Self {
+            runtime,
+            config: Arc::new(config),
+            registry: Arc::default(),
+            deaths,
+        })
+    }
+
+    /// Spawns processes afterwards with `config`.
+    pub fn with_process_config(mut self, config: DefaultProcessConfig) -> Self {
+        self.config = Arc::new(config);
+        self
+    }
+
+    pub fn runtime(&self) -> &WasmtimeRuntime {
+        &self.runtime
+    }
+
+    /// Compiles a module from the WAT text or the wasm binary in `source`.
+    pub fn compile<S: AsRef<[u8]>>(
+        &self,
+        source: S,
+    ) -> Result<WasmtimeCompiledModule<DefaultProcessState>> {
+        let wasm = wat::parse_bytes(source.as_ref())?;
+        self.runtime.compile_module(wasm.into_owned().into())
+    }
+
+    /// Creates the state a process spawned from `module` starts with, without spawning it.
+    pub fn state(
+        &self,
+        module: &WasmtimeCompiledModule<DefaultProcessState>,
+    ) -> Result<DefaultProcessState> {
+        DefaultProcessState::new(
+            self.runtime.clone(),
+            module.clone(),
+            self.config.clone(),
+            self.registry.clone(),
+        )
+    }
+
+    /// Spawns a process calling `function` of `module` with `params`.
+    pub async fn spawn(
+        &self,
+        module: &WasmtimeCompiledModule<DefaultProcessState>,
+        function: &str,
+        params: Vec<Val>,
+    ) -> Result<TestProcess> {
+        let mut state = self.state(module)?;
+        let (stdout, stderr) = (StdoutCapture::new(), StdoutCapture::new_stderr());
+        state.set_stdout(stdout.clone());
+        state.set_stderr(stderr.clone());
+        // Registered before spawning, the process could die right away.
+        let (sender, death) = bounded(1);
+        let id = state.id();
+        self.deaths.insert(id, sender);
+        let spawned = spawn_wasm(
+            self.runtime.clone(),
+            module.clone(),
+            state,
+            function,
+            params,
+            None,
+            None,
+        )
+        .await;
+        let (join, process) = match spawned {
+            Ok(spawned) => spawned,
+            Err(error) => {
+                self.deaths.remove(&id);
+                return Err(error);
+            }
+        };
+        Ok(TestProcess {
+            process: ProcessRef::new(process),
+            join,
+            death,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+/// A process spawned by a [`TestRuntime`].
+pub struct TestProcess {
+    process: ProcessRef,
+    join: JoinHandle<Result<DefaultProcessState>>,
+    death: Receiver<ProcessDeath>,
+    stdout: StdoutCapture,
+    stderr: StdoutCapture,
+}
+
+impl TestProcess {
+    pub fn id(&self) -> Uuid {
+        self.process.id()
+    }
+
+    pub fn process(&self) -> &ProcessRef {
+        &self.process
+    }
+
+    /// Sends a data message with a copy of `data` to the process.
+    pub fn send_data(&self, tag: Option<i64>, data: &[u8]) {
+        self.process.send_data(tag, data);
+    }
+
+    pub fn send(&self, signal: Signal) {
+        self.process.send(signal);
+    }
+
+    /// Returns what the process wrote to stdout so far.
+    pub fn stdout(&self) -> String {
+        self.stdout.content()
+    }
+
+    /// Returns what the process wrote to stderr so far.
+    pub fn stderr(&self) -> String {
+        self.stderr.content()
+    }
+
+    /// Waits until the process finished and returns how it died.
+    pub async fn finished(self) -> ProcessDeath {
+        let _ = self.join.await;
+        self.death
+            .recv()
+            .await
+            .expect("the post-mortem hook reports every death")
+    }
+
+    /// Waits until the process finished and returns how it died, together with everything it
+    /// wrote to stdout.
+    pub async fn finished_with_stdout(self) -> (ProcessDeath, String) {
+        let stdout = self.stdout.clone();
+        let death = self.finished().await;
+        (death, stdout.content())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use lunatic_process::message::Message;
+    use lunatic_process::Signal;
+
+    use super::{DeathReason, TestRuntime};
+
+    #[async_std::test]
+    async fn reports_exit_and_captures_stdout() {
+        let runtime = TestRuntime::new().unwrap();
+        // Echos the first message to stdout, exits with code 3 on the shutdown message.
+        let module = runtime
+            .compile(
+                r#"
+                (module
+                    (import "lunatic::message" "receive"
+                        (func $receive (param i32 i32 i32) (result i32)))
+                    (import "lunatic::message" "read_data"
+                        (func $read_data (param i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "fd_write"
+                        (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                    (memory (export "memory") 1)
+                    (func (export "run")
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))
+                        (i32.store (i32.const 0) (i32.const 100))
+                        (i32.store (i32.const 4) (call $read_data (i32.const 100) (i32.const 64)))
+                        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))
+                        (call $proc_exit (i32.const 3))))
+                "#,
+            )
+            .unwrap();
+        let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+        process.send_data(None, b"hello\n");
+        process.send(Signal::Message(Message::Shutdown));
+        let (death, stdout) =
+            async_std::future::timeout(Duration::from_secs(5), process.finished_with_stdout())
+                .await
+                .unwrap();
+        assert!(matches!(death.reason, DeathReason::Exited(3)));
+        assert_eq!(stdout, "hello\n");
+    }
+}
@@ -0,0 +1,238 @@
+//! Messaging host functions, exercised by processes of the runtime.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use lunatic_process::message::{DataMessage, Message};
+use lunatic_process::process_ref::ProcessRef;
+use lunatic_runtime::test_util::{DeathReason, TestRuntime};
+use wasmtime::Val;
+
+#[async_std::test]
+async fn reply_goes_to_reply_to_process() {
+    let runtime = TestRuntime::new().unwrap();
+    // Answers a request with the same tag, traps unless `try_send` returns **expected**.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::message" "receive"
+                    (func $receive (param i32 i32 i32) (result i32)))
+                (import "lunatic::message" "get_tag" (func $get_tag (result i64)))
+                (import "lunatic::message" "take_reply_to"
+                    (func $take_reply_to (param i32) (result i32)))
+                (import "lunatic::message" "create_data" (func $create_data (param i64 i64)))
+                (import "lunatic::message" "try_send" (func $try_send (param i64) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "run") (param $expected i32)
+                    (local $tag i64)
+                    (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))
+                    (local.set $tag (call $get_tag))
+                    (if (call $take_reply_to (i32.const 8))
+                        (then unreachable))
+                    (call $create_data (local.get $tag) (i64.const 0))
+                    (if (i32.ne (call $try_send (i64.load (i32.const 8))) (local.get $expected))
+                        (then unreachable))))
+            "#,
+        )
+        .unwrap();
+    for alive in [true, false] {
+        let (requester_join, requester) = lunatic_process::spawn(move |_, mailbox| async move {
+            if alive {
+                assert_eq!(mailbox.pop(Some(&[42])).await.tag(), Some(42));
+            }
+            Ok::<(), anyhow::Error>(())
+        });
+        let requester = ProcessRef::new(Arc::new(requester));
+        let mut requester_join = Some(requester_join);
+        if !alive {
+            requester_join.take().unwrap().await.unwrap();
+            assert!(!requester.is_alive());
+        }
+
+        let expected = if alive { 0 } else { 1 };
+        let responder = runtime
+            .spawn(&module, "run", vec![Val::I32(expected)])
+            .await
+            .unwrap();
+        let mut request = DataMessage::new(Some(42), 0);
+        request.reply_to = Some(requester.clone());
+        responder.process().send_message(Message::Data(request));
+        assert!(matches!(
+            responder.finished().await.reason,
+            DeathReason::Normal
+        ));
+        if let Some(requester_join) = requester_join {
+            requester_join.await.unwrap();
+        }
+    }
+}
+
+#[async_std::test]
+async fn receive_times_out_without_losing_later_message() {
+    let runtime = TestRuntime::new().unwrap();
+    // Traps unless the first receive times out and the second one gets the message.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::message" "receive"
+                    (func $receive (param i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (if (i32.ne (call $receive (i32.const 0) (i32.const 0) (i32.const 10))
+                                (i32.const 9027))
+                        (then unreachable))
+                    (if (i32.ne (call $receive (i32.const 0) (i32.const 0) (i32.const 0))
+                                (i32.const 0))
+                        (then unreachable))))
+            "#,
+        )
+        .unwrap();
+    let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+    async_std::task::sleep(Duration::from_millis(100)).await;
+    process.send_data(None, &[]);
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+}
+
+#[async_std::test]
+async fn send_multi_reports_finished_receivers() {
+    let runtime = TestRuntime::new().unwrap();
+    // `run` receives three processes and sends "hi" to all of them, the first two print it.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::message" "receive"
+                    (func $receive (param i32 i32 i32) (result i32)))
+                (import "lunatic::message" "take_process"
+                    (func $take_process (param i64) (result i64)))
+                (import "lunatic::message" "create_data" (func $create_data (param i64 i64)))
+                (import "lunatic::message" "write_data"
+                    (func $write_data (param i32 i32) (result i32)))
+                (import "lunatic::message" "read_data"
+                    (func $read_data (param i32 i32) (result i32)))
+                (import "lunatic::message" "send_multi"
+                    (func $send_multi (param i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "hi")
+                ;; Writes the data of the received message to stdout.
+                (func $print
+                    (i32.store (i32.const 88) (i32.const 100))
+                    (i32.store (i32.const 92) (call $read_data (i32.const 100) (i32.const 8)))
+                    (drop (call $fd_write (i32.const 1) (i32.const 88) (i32.const 1) (i32.const 80))))
+                (func (export "run")
+                    (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))
+                    (i64.store (i32.const 16) (call $take_process (i64.const 0)))
+                    (i64.store (i32.const 24) (call $take_process (i64.const 1)))
+                    (i64.store (i32.const 32) (call $take_process (i64.const 2)))
+                    (call $create_data (i64.const 0) (i64.const 2))
+                    (drop (call $write_data (i32.const 0) (i32.const 2)))
+                    (if (i32.ne (call $send_multi (i32.const 16) (i32.const 3) (i32.const 48))
+                            (i32.const 2))
+                        (then unreachable))
+                    (if (i32.ne (i32.load8_u (i32.const 48)) (i32.const 0)) (then unreachable))
+                    (if (i32.ne (i32.load8_u (i32.const 49)) (i32.const 0)) (then unreachable))
+                    (if (i32.ne (i32.load8_u (i32.const 50)) (i32.const 1)) (then unreachable)))
+                (func (export "sink")
+                    (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))
+                    (call $print))
+                (func (export "noop")))
+            "#,
+        )
+        .unwrap();
+    let a = runtime.spawn(&module, "sink", Vec::new()).await.unwrap();
+    let b = runtime.spawn(&module, "sink", Vec::new()).await.unwrap();
+    let finished = runtime.spawn(&module, "noop", Vec::new()).await.unwrap();
+    let finished_process = finished.process().inner().clone();
+    finished.finished().await;
+
+    let mut processes = DataMessage::new(None, 0);
+    processes.add_process(a.process().inner().clone());
+    processes.add_process(b.process().inner().clone());
+    processes.add_process(finished_process);
+    let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+    process.process().send_message(Message::Data(processes));
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+    for sink in [a, b] {
+        let (death, stdout) = sink.finished_with_stdout().await;
+        assert!(matches!(death.reason, DeathReason::Normal));
+        assert_eq!(stdout, "hi");
+    }
+}
+
+#[async_std::test]
+async fn published_messages_reach_subscribers() {
+    let runtime = TestRuntime::new().unwrap();
+    // `sink` subscribes and prints the first message, `run` publishes.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::message" "receive"
+                    (func $receive (param i32 i32 i32) (result i32)))
+                (import "lunatic::message" "create_data" (func $create_data (param i64 i64)))
+                (import "lunatic::message" "write_data"
+                    (func $write_data (param i32 i32) (result i32)))
+                (import "lunatic::message" "read_data"
+                    (func $read_data (param i32 i32) (result i32)))
+                (import "lunatic::message" "subscribe"
+                    (func $subscribe (param i32 i32) (result i32)))
+                (import "lunatic::message" "publish"
+                    (func $publish (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "news")
+                (data (i32.const 8) "hi")
+                ;; Writes the data of the received message to stdout.
+                (func $print
+                    (i32.store (i32.const 88) (i32.const 100))
+                    (i32.store (i32.const 92) (call $read_data (i32.const 100) (i32.const 8)))
+                    (drop (call $fd_write (i32.const 1) (i32.const 88) (i32.const 1) (i32.const 80))))
+                (func (export "run")
+                    (call $create_data (i64.const 0) (i64.const 2))
+                    (drop (call $write_data (i32.const 8) (i32.const 2)))
+                    (if (i32.ne (call $publish (i32.const 0) (i32.const 4)) (i32.const 2))
+                        (then unreachable)))
+                (func (export "sink")
+                    (if (call $subscribe (i32.const 0) (i32.const 4)) (then unreachable))
+                    (if (i32.eqz (call $subscribe (i32.const 0) (i32.const 4)))
+                        (then unreachable))
+                    (drop (call $receive (i32.const 0) (i32.const 0) (i32.const 0)))
+                    (call $print)))
+            "#,
+        )
+        .unwrap();
+    let a = runtime.spawn(&module, "sink", Vec::new()).await.unwrap();
+    let b = runtime.spawn(&module, "sink", Vec::new()).await.unwrap();
+    let topics = runtime.runtime().topics();
+    async_std::future::timeout(Duration::from_secs(5), async {
+        while topics.subscribers("news") != 2 {
+            async_std::task::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    let process = runtime.spawn(&module, "run", Vec::new()).await.unwrap();
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+    for sink in [a, b] {
+        let (death, stdout) = sink.finished_with_stdout().await;
+        assert!(matches!(death.reason, DeathReason::Normal));
+        assert_eq!(stdout, "hi");
+    }
+    // Finished processes are unsubscribed.
+    assert_eq!(topics.subscribers("news"), 0);
+}
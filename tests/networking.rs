@@ -0,0 +1,492 @@
+//! Networking host functions, exercised by processes of the runtime.
+
+#![cfg(target_os = "linux")]
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use lunatic_networking_api::DEFAULT_MAX_TCP_LINGER;
+use lunatic_runtime::test_util::{DeathReason, TestRuntime};
+use lunatic_runtime::DefaultProcessConfig;
+use wasmtime::Val;
+
+// Connects to `port`, sets the linger and drops the stream. Traps unless `tcp_set_linger`
+// returns `expected`.
+const SET_LINGER: &str = r#"
+    (module
+        (import "lunatic::networking" "tcp_connect"
+            (func $connect (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+        (import "lunatic::networking" "tcp_set_linger"
+            (func $set_linger (param i64 i64 i32) (result i32)))
+        (import "lunatic::networking" "drop_tcp_stream" (func $drop_stream (param i64)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "\7f\00\00\01")
+        (func (export "run") (param $port i32) (param $linger i64) (param $expected i32)
+            (if (call $connect (i32.const 4) (i32.const 0) (local.get $port)
+                    (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 8))
+                (then unreachable))
+            (if (i32.ne (call $set_linger (i64.load (i32.const 8)) (local.get $linger)
+                        (i32.const 16))
+                    (local.get $expected))
+                (then unreachable))
+            (call $drop_stream (i64.load (i32.const 8)))))
+    "#;
+
+async fn set_linger(runtime: &TestRuntime, linger: i64, expected: i32) -> DeathReason {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let module = runtime.compile(SET_LINGER).unwrap();
+    let params = vec![Val::I32(port as i32), Val::I64(linger), Val::I32(expected)];
+    let process = runtime.spawn(&module, "run", params).await.unwrap();
+    process.finished().await.reason
+}
+
+#[async_std::test]
+async fn zero_linger_resets_connection_on_drop() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let peer = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.read(&mut [0; 8]).unwrap_err().kind()
+    });
+    let runtime = TestRuntime::new().unwrap();
+    let module = runtime.compile(SET_LINGER).unwrap();
+    let params = vec![Val::I32(port as i32), Val::I64(0), Val::I32(0)];
+    let process = runtime.spawn(&module, "run", params).await.unwrap();
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+    assert_eq!(peer.join().unwrap(), std::io::ErrorKind::ConnectionReset);
+
+    // Only -1 disables the linger.
+    assert!(matches!(
+        set_linger(&runtime, -2, 0).await,
+        DeathReason::Failure
+    ));
+}
+
+#[async_std::test]
+async fn read_exact_reports_partial_reads() {
+    // Reads 4 bytes with a timeout of 200ms, traps unless the call returns `expected` and
+    // reports the 2 bytes the peer sent.
+    let module = r#"
+        (module
+            (import "lunatic::networking" "tcp_connect"
+                (func $connect (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+            (import "lunatic::networking" "tcp_read_exact"
+                (func $read_exact (param i64 i32 i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\7f\00\00\01")
+            (func (export "run") (param $port i32) (param $expected i32)
+                (if (call $connect (i32.const 4) (i32.const 0) (local.get $port)
+                        (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 8))
+                    (then unreachable))
+                (if (i32.ne (call $read_exact (i64.load (i32.const 8)) (i32.const 32)
+                            (i32.const 4) (i32.const 200) (i32.const 16) (i32.const 24))
+                        (local.get $expected))
+                    (then unreachable))
+                (if (i64.ne (i64.load (i32.const 16)) (i64.const 2)) (then unreachable))
+                (if (i32.ne (i32.load16_u (i32.const 32)) (i32.const 0x6261))
+                    (then unreachable))))
+    "#;
+    let runtime = TestRuntime::new().unwrap();
+    let module = runtime.compile(module).unwrap();
+    // The peer keeps the connection open without sending more (timeout), or resets it (error).
+    for (reset, expected) in [(false, 9027), (true, 1)] {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"ab").unwrap();
+            std::thread::sleep(Duration::from_millis(if reset { 50 } else { 400 }));
+            if reset {
+                socket2::SockRef::from(&stream)
+                    .set_linger(Some(Duration::ZERO))
+                    .unwrap();
+            }
+        });
+        let params = vec![Val::I32(port as i32), Val::I32(expected)];
+        let process = runtime.spawn(&module, "run", params).await.unwrap();
+        assert!(matches!(
+            process.finished().await.reason,
+            DeathReason::Normal
+        ));
+        peer.join().unwrap();
+    }
+}
+
+#[async_std::test]
+async fn peer_addr_of_connected_stream() {
+    // Connects to `port` and traps unless the peer address is 127.0.0.1:`port`.
+    let module = r#"
+        (module
+            (import "lunatic::networking" "tcp_connect"
+                (func $connect (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+            (import "lunatic::networking" "tcp_peer_addr"
+                (func $peer_addr (param i64 i32) (result i32)))
+            (import "lunatic::networking" "resolve_next"
+                (func $resolve_next (param i64 i32 i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\7f\00\00\01")
+            (func (export "run") (param $port i32)
+                (if (call $connect (i32.const 4) (i32.const 0) (local.get $port)
+                        (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 8))
+                    (then unreachable))
+                (if (call $peer_addr (i64.load (i32.const 8)) (i32.const 16))
+                    (then unreachable))
+                (if (call $resolve_next (i64.load (i32.const 16)) (i32.const 24)
+                        (i32.const 32) (i32.const 48) (i32.const 52) (i32.const 56))
+                    (then unreachable))
+                (if (i32.ne (i32.load (i32.const 24)) (i32.const 4)) (then unreachable))
+                (if (i32.ne (i32.load (i32.const 32)) (i32.load (i32.const 0)))
+                    (then unreachable))
+                (if (i32.ne (i32.load16_u (i32.const 48)) (local.get $port))
+                    (then unreachable))
+                ;; The iterator has just one element.
+                (if (i32.eqz (call $resolve_next (i64.load (i32.const 16)) (i32.const 24)
+                        (i32.const 32) (i32.const 48) (i32.const 52) (i32.const 56)))
+                    (then unreachable))))
+    "#;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let peer = std::thread::spawn(move || listener.accept().unwrap());
+    let runtime = TestRuntime::new().unwrap();
+    let module = runtime.compile(module).unwrap();
+    let process = runtime
+        .spawn(&module, "run", vec![Val::I32(port as i32)])
+        .await
+        .unwrap();
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+    peer.join().unwrap();
+}
+
+#[async_std::test]
+async fn linger_is_capped_by_the_config() {
+    let runtime = TestRuntime::new().unwrap();
+    let max = DEFAULT_MAX_TCP_LINGER.as_secs() as i64;
+    assert!(matches!(
+        set_linger(&runtime, max, 0).await,
+        DeathReason::Normal
+    ));
+    assert!(matches!(
+        set_linger(&runtime, max + 1, 1).await,
+        DeathReason::Normal
+    ));
+}
+
+#[async_std::test]
+async fn outbound_connections_are_limited() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = DefaultProcessConfig::builder()
+        .max_outbound_connections(Some(1))
+        .build()
+        .unwrap();
+    let runtime = TestRuntime::new().unwrap().with_process_config(config);
+    // Traps unless the second connection is refused and a third one succeeds after the first
+    // one is dropped.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::networking" "tcp_connect"
+                    (func $connect (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+                (import "lunatic::networking" "drop_tcp_stream" (func $drop (param i64)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\7f\00\00\01")
+                (func $connect_to (param $port i32) (result i32)
+                    (call $connect (i32.const 4) (i32.const 0) (local.get $port)
+                        (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 8)))
+                (func (export "run") (param $port i32)
+                    (local $first i64)
+                    (if (call $connect_to (local.get $port)) (then unreachable))
+                    (local.set $first (i64.load (i32.const 8)))
+                    (if (i32.ne (call $connect_to (local.get $port)) (i32.const 1))
+                        (then unreachable))
+                    (call $drop (local.get $first))
+                    (if (call $connect_to (local.get $port)) (then unreachable))))
+            "#,
+        )
+        .unwrap();
+    let process = runtime
+        .spawn(&module, "run", vec![Val::I32(port as i32)])
+        .await
+        .unwrap();
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+}
+
+#[async_std::test]
+async fn cloned_or_sent_connections_stay_limited() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = DefaultProcessConfig::builder()
+        .max_outbound_connections(Some(1))
+        .build()
+        .unwrap();
+    let runtime = TestRuntime::new().unwrap().with_process_config(config);
+    // Traps unless a second connection is refused after cloning the first one and dropping
+    // the original, and while the clone is in a message sent to the process itself. A new
+    // connection succeeds once the clone is taken back and dropped.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::networking" "tcp_connect"
+                    (func $connect (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+                (import "lunatic::networking" "clone_tcp_stream"
+                    (func $clone (param i64) (result i64)))
+                (import "lunatic::networking" "drop_tcp_stream" (func $drop (param i64)))
+                (import "lunatic::process" "this" (func $this (result i64)))
+                (import "lunatic::message" "create_data" (func $create_data (param i64 i64)))
+                (import "lunatic::message" "push_tcp_stream"
+                    (func $push (param i64) (result i64)))
+                (import "lunatic::message" "take_tcp_stream"
+                    (func $take (param i64) (result i64)))
+                (import "lunatic::message" "send" (func $send (param i64)))
+                (import "lunatic::message" "receive"
+                    (func $receive (param i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\7f\00\00\01")
+                (func $connect_to (param $port i32) (result i32)
+                    (call $connect (i32.const 4) (i32.const 0) (local.get $port)
+                        (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 8)))
+                (func (export "run") (param $port i32)
+                    (local $first i64)
+                    (local $clone i64)
+                    (if (call $connect_to (local.get $port)) (then unreachable))
+                    (local.set $first (i64.load (i32.const 8)))
+                    (local.set $clone (call $clone (local.get $first)))
+                    (call $drop (local.get $first))
+                    (if (i32.ne (call $connect_to (local.get $port)) (i32.const 1))
+                        (then unreachable))
+                    (call $create_data (i64.const 0) (i64.const 0))
+                    (drop (call $push (local.get $clone)))
+                    (call $send (call $this))
+                    (if (i32.ne (call $connect_to (local.get $port)) (i32.const 1))
+                        (then unreachable))
+                    (if (call $receive (i32.const 0) (i32.const 0) (i32.const 5000))
+                        (then unreachable))
+                    (local.set $clone (call $take (i64.const 0)))
+                    (if (i32.ne (call $connect_to (local.get $port)) (i32.const 1))
+                        (then unreachable))
+                    (call $drop (local.get $clone))
+                    (if (call $connect_to (local.get $port)) (then unreachable))))
+            "#,
+        )
+        .unwrap();
+    let process = runtime
+        .spawn(&module, "run", vec![Val::I32(port as i32)])
+        .await
+        .unwrap();
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+}
+
+#[async_std::test]
+async fn read_exact_fills_buffer_until_eof() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // The data arrives in two chunks, followed by EOF.
+    let peer = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"ab").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        stream.write_all(b"cdef").unwrap();
+    });
+    let runtime = TestRuntime::new().unwrap();
+    // Traps unless the first call returns all 4 requested bytes and the second one the
+    // 2 bytes left before EOF.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::networking" "tcp_connect"
+                    (func $connect (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+                (import "lunatic::networking" "tcp_read_exact"
+                    (func $read_exact (param i64 i32 i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\7f\00\00\01")
+                (func (export "run") (param $port i32)
+                    (local $stream i64)
+                    (if (call $connect (i32.const 4) (i32.const 0) (local.get $port)
+                            (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 8))
+                        (then unreachable))
+                    (local.set $stream (i64.load (i32.const 8)))
+                    (if (call $read_exact (local.get $stream) (i32.const 32) (i32.const 4)
+                            (i32.const 0) (i32.const 16) (i32.const 24))
+                        (then unreachable))
+                    (if (i64.ne (i64.load (i32.const 16)) (i64.const 4)) (then unreachable))
+                    (if (i32.ne (i32.load (i32.const 32)) (i32.const 0x64636261))
+                        (then unreachable))
+                    (if (call $read_exact (local.get $stream) (i32.const 32) (i32.const 4)
+                            (i32.const 0) (i32.const 16) (i32.const 24))
+                        (then unreachable))
+                    (if (i64.ne (i64.load (i32.const 16)) (i64.const 2)) (then unreachable))
+                    (if (i32.ne (i32.load16_u (i32.const 32)) (i32.const 0x6665))
+                        (then unreachable))))
+            "#,
+        )
+        .unwrap();
+    let process = runtime
+        .spawn(&module, "run", vec![Val::I32(port as i32)])
+        .await
+        .unwrap();
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+    peer.join().unwrap();
+}
+
+#[async_std::test]
+async fn shut_down_directions_fail_cleanly() {
+    let runtime = TestRuntime::new().unwrap();
+    // Writes "ping", shuts down the direction and checks the results of writing and reading
+    // afterwards. A half-closed stream can still read the "pong" answer.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::networking" "tcp_connect"
+                    (func $connect (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+                (import "lunatic::networking" "tcp_write_vectored"
+                    (func $write (param i64 i32 i32 i32 i32) (result i32)))
+                (import "lunatic::networking" "tcp_read_exact"
+                    (func $read_exact (param i64 i32 i32 i32 i32 i32) (result i32)))
+                (import "lunatic::networking" "tcp_shutdown"
+                    (func $shutdown (param i64 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\7f\00\00\01")
+                (data (i32.const 48) "ping")
+                (data (i32.const 64) "\30\00\00\00\04\00\00\00")
+                (func (export "run") (param $port i32) (param $direction i32)
+                        (param $write_result i32) (param $read_result i32)
+                    (local $stream i64)
+                    (if (call $connect (i32.const 4) (i32.const 0) (local.get $port)
+                            (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 8))
+                        (then unreachable))
+                    (local.set $stream (i64.load (i32.const 8)))
+                    (if (call $write (local.get $stream) (i32.const 64) (i32.const 1)
+                            (i32.const 0) (i32.const 16))
+                        (then unreachable))
+                    (if (call $shutdown (local.get $stream) (local.get $direction)
+                            (i32.const 24))
+                        (then unreachable))
+                    (if (i32.ne (call $write (local.get $stream) (i32.const 64) (i32.const 1)
+                                (i32.const 0) (i32.const 16))
+                            (local.get $write_result))
+                        (then unreachable))
+                    (if (i32.ne (call $read_exact (local.get $stream) (i32.const 32)
+                                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 24))
+                            (local.get $read_result))
+                        (then unreachable))
+                    (if (i32.eqz (local.get $read_result))
+                        (then (if (i32.ne (i32.load (i32.const 32)) (i32.const 0x676e6f70))
+                            (then unreachable))))))
+            "#,
+        )
+        .unwrap();
+    // (direction, write result, read result, received by the peer, succeeds)
+    let cases: [(i32, i32, i32, &[u8], bool); 4] = [
+        (0, 0, 1, b"pingping", true),
+        (1, 1, 0, b"ping", true),
+        (2, 1, 1, b"ping", true),
+        // Unknown direction
+        (3, 0, 0, b"ping", false),
+    ];
+    for (direction, write_result, read_result, received, success) in cases {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            let _ = stream.write_all(b"pong");
+            received
+        });
+        let params = [port as i32, direction, write_result, read_result];
+        let process = runtime
+            .spawn(
+                &module,
+                "run",
+                params.iter().copied().map(Val::I32).collect(),
+            )
+            .await
+            .unwrap();
+        let reason = process.finished().await.reason;
+        assert_eq!(
+            matches!(reason, DeathReason::Normal),
+            success,
+            "direction {}",
+            direction
+        );
+        assert_eq!(peer.join().unwrap(), received, "direction {}", direction);
+    }
+}
+
+#[async_std::test]
+async fn drained_listener_notifies_once_connections_close() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let runtime = TestRuntime::new().unwrap();
+    // Traps unless the drain message only arrives after the accepted stream is dropped.
+    let module = runtime
+        .compile(
+            r#"
+            (module
+                (import "lunatic::networking" "tcp_bind"
+                    (func $bind (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+                (import "lunatic::networking" "tcp_accept"
+                    (func $accept (param i64 i32 i32) (result i32)))
+                (import "lunatic::networking" "tcp_listener_drain"
+                    (func $drain (param i64 i64)))
+                (import "lunatic::networking" "drop_tcp_stream"
+                    (func $drop_stream (param i64)))
+                (import "lunatic::message" "receive"
+                    (func $receive (param i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\7f\00\00\01")
+                (func (export "run") (param $port i32)
+                    (if (call $bind (i32.const 4) (i32.const 0) (local.get $port)
+                            (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)
+                            (i32.const 8))
+                        (then unreachable))
+                    (if (call $accept (i64.load (i32.const 8)) (i32.const 16) (i32.const 24))
+                        (then unreachable))
+                    (call $drain (i64.load (i32.const 8)) (i64.const 7))
+                    (if (i32.ne (call $receive (i32.const 0) (i32.const 0) (i32.const 200))
+                                (i32.const 9027))
+                        (then unreachable))
+                    (call $drop_stream (i64.load (i32.const 16)))
+                    (if (i32.ne (call $receive (i32.const 0) (i32.const 0) (i32.const 0))
+                                (i32.const 0))
+                        (then unreachable))))
+            "#,
+        )
+        .unwrap();
+    let process = runtime
+        .spawn(&module, "run", vec![Val::I32(port as i32)])
+        .await
+        .unwrap();
+    async_std::task::sleep(Duration::from_millis(100)).await;
+    let _accepted = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    async_std::task::sleep(Duration::from_millis(100)).await;
+    // The listener is closed as soon as the drain starts.
+    assert!(std::net::TcpStream::connect(("127.0.0.1", port)).is_err());
+    assert!(matches!(
+        process.finished().await.reason,
+        DeathReason::Normal
+    ));
+}
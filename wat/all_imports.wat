@@ -6,6 +6,8 @@
     (import "lunatic::error" "drop" (func (param i64)))
 
     (import "lunatic::message" "create_data" (func (param i64 i64)))
+    (import "lunatic::message" "create_system_data" (func (param i64 i64)))
+    (import "lunatic::message" "set_priority" (func (param i32)))
     (import "lunatic::message" "write_data" (func (param i32 i32) (result i32)))
     (import "lunatic::message" "read_data" (func (param i32 i32) (result i32)))
     (import "lunatic::message" "seek_data" (func (param i64)))
@@ -17,9 +19,21 @@
     (import "lunatic::message" "take_tcp_stream" (func (param i64) (result i64)))
     (import "lunatic::message" "push_udp_socket" (func (param i64) (result i64)))
     (import "lunatic::message" "take_udp_socket" (func (param i64) (result i64)))
+    (import "lunatic::message" "push_tcp_listener" (func (param i64) (result i64)))
+    (import "lunatic::message" "take_tcp_listener" (func (param i64) (result i64)))
     (import "lunatic::message" "send" (func (param i64)))
+    (import "lunatic::message" "send_to_named" (func (param i32 i32) (result i32)))
+    (import "lunatic::message" "try_send" (func (param i64) (result i32)))
+    (import "lunatic::message" "send_multi" (func (param i32 i32 i32) (result i32)))
+    (import "lunatic::message" "subscribe" (func (param i32 i32) (result i32)))
+    (import "lunatic::message" "unsubscribe" (func (param i32 i32) (result i32)))
+    (import "lunatic::message" "publish" (func (param i32 i32) (result i32)))
+    (import "lunatic::message" "set_reply_to" (func (param i64)))
+    (import "lunatic::message" "take_reply_to" (func (param i32) (result i32)))
     (import "lunatic::message" "send_receive_skip_search" (func (param i64 i32) (result i32)))
     (import "lunatic::message" "receive" (func (param i32 i32 i32) (result i32)))
+    (import "lunatic::message" "receive_system" (func (param i32 i32 i32) (result i32)))
+    (import "lunatic::message" "cancel_tag" (func (param i64) (result i64)))
 
     (import "lunatic::timer" "send_after" (func (param i64 i64) (result i64)))
     (import "lunatic::timer" "cancel_timer" (func (param i64) (result i32)))
@@ -27,19 +41,31 @@
     (import "lunatic::networking" "resolve" (func (param i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "drop_dns_iterator" (func (param i64)))
     (import "lunatic::networking" "resolve_next" (func (param i64 i32 i32 i32 i32 i32) (result i32)))
-    (import "lunatic::networking" "tcp_bind" (func (param i32 i32 i32 i32 i32 i32) (result i32)))
+    (import "lunatic::networking" "tcp_bind" (func (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "drop_tcp_listener" (func (param i64)))
     (import "lunatic::networking" "tcp_local_addr" (func (param i64 i32) (result i32)))
+    (import "lunatic::networking" "tcp_listener_queue_depth" (func (param i64 i32) (result i32)))
     (import "lunatic::networking" "tcp_accept" (func (param i64 i32 i32) (result i32)))
+    (import "lunatic::networking" "tcp_listener_allow" (func (param i64 i32 i32 i32)))
+    (import "lunatic::networking" "tcp_listener_clear_allowlist" (func (param i64)))
+    (import "lunatic::networking" "tcp_listener_rejected_count" (func (param i64) (result i64)))
+    (import "lunatic::networking" "tcp_listener_set_idle_timeout" (func (param i64 i64 i64)))
+    (import "lunatic::networking" "tcp_listener_drain" (func (param i64 i64)))
     (import "lunatic::networking" "tcp_connect" (func (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "drop_tcp_stream" (func (param i64)))
     (import "lunatic::networking" "clone_tcp_stream" (func (param i64) (result i64)))
     (import "lunatic::networking" "tcp_write_vectored" (func (param i64 i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "tcp_read" (func (param i64 i32 i32 i32 i32) (result i32)))
+    (import "lunatic::networking" "tcp_read_exact" (func (param i64 i32 i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "tcp_flush" (func (param i64 i32) (result i32)))
+    (import "lunatic::networking" "tcp_set_linger" (func (param i64 i64 i32) (result i32)))
+    (import "lunatic::networking" "tcp_shutdown" (func (param i64 i32 i32) (result i32)))
+    (import "lunatic::networking" "stream_flush" (func (param i64 i32) (result i32)))
+    (import "lunatic::networking" "stream_close" (func (param i64 i32) (result i32)))
     (import "lunatic::networking" "udp_bind" (func (param i32 i32 i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "drop_udp_socket" (func (param i64)))
     (import "lunatic::networking" "udp_local_addr" (func (param i64 i32) (result i32)))
+    (import "lunatic::networking" "tcp_peer_addr" (func (param i64 i32) (result i32)))
     (import "lunatic::networking" "udp_receive" (func (param i64 i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "udp_receive_from" (func (param i64 i32 i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "udp_connect" (func (param i64 i32 i32 i32 i32 i32 i32 i32) (result i32)))
@@ -48,8 +74,23 @@
     (import "lunatic::networking" "get_udp_socket_broadcast" (func (param i64) (result i32)))
     (import "lunatic::networking" "set_udp_socket_ttl" (func (param i64 i32)))
     (import "lunatic::networking" "get_udp_socket_ttl" (func (param i64) (result i32)))
+    (import "lunatic::networking" "udp_join_multicast_v4" (func (param i64 i32 i32 i32) (result i32)))
+    (import "lunatic::networking" "udp_leave_multicast_v4" (func (param i64 i32 i32 i32) (result i32)))
+    (import "lunatic::networking" "udp_join_multicast_v6" (func (param i64 i32 i32 i32) (result i32)))
+    (import "lunatic::networking" "udp_leave_multicast_v6" (func (param i64 i32 i32 i32) (result i32)))
+    (import "lunatic::networking" "set_udp_socket_multicast_ttl_v4" (func (param i64 i32 i32) (result i32)))
+    (import "lunatic::networking" "get_udp_socket_multicast_ttl_v4" (func (param i64) (result i32)))
+    (import "lunatic::networking" "set_udp_socket_multicast_loop" (func (param i64 i32 i32 i32) (result i32)))
+    (import "lunatic::networking" "get_udp_socket_multicast_loop" (func (param i64 i32) (result i32)))
     (import "lunatic::networking" "udp_send_to" (func (param i64 i32 i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
     (import "lunatic::networking" "udp_send" (func (param i64 i32 i32 i32 i32) (result i32)))
+    (import "lunatic::networking" "bytes_sent" (func (result i64)))
+    (import "lunatic::networking" "bytes_received" (func (result i64)))
+    (import "lunatic::networking" "config_set_bandwidth_limit" (func (param i64 i64 i64)))
+    (import "lunatic::networking" "config_set_max_outbound_connections" (func (param i64 i64)))
+    (import "lunatic::networking" "config_get_max_outbound_connections" (func (param i64) (result i64)))
+    (import "lunatic::networking" "config_get_bandwidth_limit" (func (param i64) (result i64)))
+    (import "lunatic::networking" "config_get_bandwidth_burst" (func (param i64) (result i64)))
 
     (import "lunatic::process" "compile_module" (func (param i32 i32 i32) (result i32)))
     (import "lunatic::process" "drop_module" (func (param i64)))
@@ -59,6 +100,13 @@
     (import "lunatic::process" "config_get_max_memory" (func (param i64) (result i64)))
     (import "lunatic::process" "config_set_max_fuel" (func (param i64 i64)))
     (import "lunatic::process" "config_get_max_fuel" (func (param i64) (result i64)))
+    (import "lunatic::process" "config_set_host_call_cost" (func (param i64 i32 i64)))
+    (import "lunatic::process" "config_get_host_call_cost" (func (param i64 i32) (result i64)))
+    (import "lunatic::process" "config_set_idle_receive_timeout" (func (param i64 i64 i32)))
+    (import "lunatic::process" "config_get_idle_receive_timeout" (func (param i64) (result i64)))
+    (import "lunatic::process" "config_get_idle_receive_policy" (func (param i64) (result i32)))
+    (import "lunatic::process" "config_set_random_seed" (func (param i64 i64)))
+    (import "lunatic::process" "config_use_system_random" (func (param i64)))
     (import "lunatic::process" "config_can_compile_modules" (func (param i64) (result i32)))
     (import "lunatic::process" "config_set_can_compile_modules" (func (param i64 i32)))
     (import "lunatic::process" "config_can_create_configs" (func (param i64) (result i32)))
@@ -75,6 +123,29 @@
     (import "lunatic::process" "link" (func (param i64 i64)))
     (import "lunatic::process" "unlink" (func (param i64)))
     (import "lunatic::process" "kill" (func (param i64)))
+    (import "lunatic::process" "kill_with_grace" (func (param i64 i64)))
+    (import "lunatic::process" "set_log_level" (func (param i64 i32)))
+    (import "lunatic::process" "fuel_remaining" (func (result i64)))
+    (import "lunatic::process" "memory_available" (func (result i64)))
+    (import "lunatic::process" "uptime_ms" (func (result i64)))
+    (import "lunatic::process" "ready" (func))
+    (import "lunatic::process" "upgrade" (func (param i64 i32 i32 i32 i32) (result i32)))
+    (import "lunatic::process" "resource_close" (func (param i32 i64) (result i32)))
+    (import "lunatic::process" "resource_list" (func (param i32 i32) (result i32)))
+    (import "lunatic::process" "resource_close_all" (func (result i32)))
+    (import "lunatic::process" "spawn_named" (func (param i64 i64 i64 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+    (import "lunatic::process" "spawn_detached" (func (param i64 i64 i32 i32 i32 i32 i32) (result i32)))
+    (import "lunatic::process" "spawn_and_await_ready" (func (param i64 i64 i64 i32 i32 i32 i32 i64 i32) (result i32)))
+    (import "lunatic::process" "set_name" (func (param i32 i32)))
+    (import "lunatic::process" "forward_os_signals" (func (param i32) (result i32)))
+    (import "lunatic::process" "checkpoint_save" (func (param i32 i32) (result i32)))
+    (import "lunatic::process" "checkpoint_load" (func (param i32 i32) (result i64)))
+    (import "lunatic::process" "checkpoint_delete" (func (result i32)))
+    (import "lunatic::process" "name_size" (func (result i32)))
+    (import "lunatic::process" "name" (func (param i32)))
+    (import "lunatic::process" "random_u64" (func (result i64)))
+    (import "lunatic::process" "random_bytes" (func (param i32 i32)))
+    (import "lunatic::process" "child_count" (func (param i32) (result i64)))
 
     (import "lunatic::version" "major" (func (result i32)))
     (import "lunatic::version" "minor" (func (result i32)))
@@ -83,6 +154,8 @@
     (import "lunatic::wasi" "config_add_environment_variable" (func (param i64 i32 i32 i32 i32)))
     (import "lunatic::wasi" "config_add_command_line_argument" (func (param i64 i32 i32)))
     (import "lunatic::wasi" "config_preopen_dir" (func (param i64 i32 i32)))
+    (import "lunatic::wasi" "config_set_write_durability" (func (param i64 i32)))
+    (import "lunatic::wasi" "config_get_write_durability" (func (param i64) (result i32)))
 
     (import "lunatic::registry" "put" (func (param i32 i32 i64)))
     (import "lunatic::registry" "get" (func (param i32 i32 i32) (result i32)))